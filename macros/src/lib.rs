@@ -2,7 +2,10 @@ use proc_macro::TokenStream;
 use quote::{format_ident, quote, ToTokens};
 use syn::{parse_macro_input, Data, DeriveInput, ExprPath};
 
-#[proc_macro_derive(JsonErrorResponse, attributes(status_code))]
+/// Implements `IntoResponse` for an error enum. Every variant needs a `#[status_code(...)]`. A
+/// variant with a single field can be marked with `#[field_errors]` to add the field, converted
+/// into `Vec<FieldError>`, to the response.
+#[proc_macro_derive(JsonErrorResponse, attributes(status_code, field_errors))]
 pub fn derive_error_response(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
 
@@ -33,11 +36,32 @@ pub fn derive_error_response(input: TokenStream) -> TokenStream {
                         );
                         args.to_token_stream()
                     });
+                let has_field_errors = variant
+                    .attrs
+                    .iter()
+                    .any(|attr| attr.meta.path().is_ident("field_errors"));
+                if has_field_errors {
+                    assert!(
+                        variant.fields.len() == 1,
+                        "#[field_errors] is only allowed on variants with a single field"
+                    );
+                    return quote! {
+                        #name::#ident(field_errors) => (
+                            #status_code,
+                            Json(ErrorResponse {
+                                error_message,
+                                field_errors: field_errors.into(),
+                                ..Default::default()
+                            }),
+                        )
+                            .into_response()
+                    };
+                }
                 quote! {
                     #name::#ident #fields => (
                         #status_code,
                         Json(ErrorResponse {
-                            error_message: self.to_string(),
+                            error_message,
                             ..Default::default()
                         }),
                     )
@@ -47,6 +71,7 @@ pub fn derive_error_response(input: TokenStream) -> TokenStream {
             let expanded = quote! {
                 impl IntoResponse for #name {
                     fn into_response(self) -> axum::response::Response {
+                        let error_message = self.to_string();
                         match self {
                             #(#match_arms),*
                         }
@@ -0,0 +1,2 @@
+-- Scopes limit what a static access token can be used for
+ALTER TABLE auth.token ADD COLUMN IF NOT EXISTS scopes text[] DEFAULT '{}' NOT NULL;
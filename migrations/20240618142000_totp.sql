@@ -0,0 +1,17 @@
+-- TOTP secret is set on enrolment, totp_enabled_at once the enrolment is confirmed. The secret is
+-- encrypted like the signing keys.
+ALTER TABLE auth.user ADD COLUMN IF NOT EXISTS totp_secret bytea;
+ALTER TABLE auth.user ADD COLUMN IF NOT EXISTS totp_enabled_at timestamptz;
+-- The time step of the last accepted code, so that a code can't be used twice
+ALTER TABLE auth.user ADD COLUMN IF NOT EXISTS totp_last_step bigint;
+
+CREATE TABLE IF NOT EXISTS auth.recovery_code (
+    id serial PRIMARY KEY NOT NULL,
+    user_id uuid NOT NULL,
+    hash bytea NOT NULL,
+    created_at timestamptz DEFAULT now() NOT NULL,
+
+    CONSTRAINT recovery_code_user_id_fk
+        FOREIGN KEY (user_id)
+        REFERENCES auth.user(id)
+);
@@ -0,0 +1,31 @@
+-- Passkeys and security keys registered by a user. The serialized credential contains the public
+-- key and the signature counter.
+CREATE TABLE IF NOT EXISTS auth.webauthn_credential (
+    id serial PRIMARY KEY NOT NULL,
+    user_id uuid NOT NULL,
+    name text NOT NULL,
+    credential_id bytea NOT NULL UNIQUE,
+    passkey jsonb NOT NULL,
+    last_used_at timestamptz,
+    created_at timestamptz DEFAULT now() NOT NULL,
+    updated_at timestamptz DEFAULT now() NOT NULL,
+
+    CONSTRAINT webauthn_credential_user_id_fk
+        FOREIGN KEY (user_id)
+        REFERENCES auth.user(id)
+);
+
+-- The server side state of a started registration or authentication ceremony, which is needed to
+-- verify the response of the authenticator
+CREATE TABLE IF NOT EXISTS auth.webauthn_ceremony (
+    id uuid PRIMARY KEY NOT NULL,
+    user_id uuid NOT NULL,
+    kind text NOT NULL,
+    state jsonb NOT NULL,
+    expiration timestamptz NOT NULL,
+    created_at timestamptz DEFAULT now() NOT NULL,
+
+    CONSTRAINT webauthn_ceremony_user_id_fk
+        FOREIGN KEY (user_id)
+        REFERENCES auth.user(id)
+);
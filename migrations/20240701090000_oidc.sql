@@ -0,0 +1,27 @@
+-- Links a user to the subject of an OpenID Connect provider
+CREATE TABLE IF NOT EXISTS auth.user_identity (
+    id serial PRIMARY KEY NOT NULL,
+    user_id uuid NOT NULL,
+    provider text NOT NULL,
+    subject text NOT NULL,
+    email text,
+    created_at timestamptz DEFAULT now() NOT NULL,
+    updated_at timestamptz DEFAULT now() NOT NULL,
+
+    CONSTRAINT user_identity_provider_subject_unique
+        UNIQUE (provider, subject),
+    CONSTRAINT user_identity_user_id_fk
+        FOREIGN KEY (user_id)
+        REFERENCES auth.user(id)
+);
+
+-- The state, nonce and PKCE verifier of a started authorization code flow
+CREATE TABLE IF NOT EXISTS auth.oidc_login (
+    state text PRIMARY KEY NOT NULL,
+    provider text NOT NULL,
+    nonce text NOT NULL,
+    pkce_verifier text NOT NULL,
+    redirect_to text,
+    expiration timestamptz NOT NULL,
+    created_at timestamptz DEFAULT now() NOT NULL
+);
@@ -0,0 +1,17 @@
+-- Failed login attempts are counted per account and per source IP within a time window
+CREATE TABLE IF NOT EXISTS auth.failed_login (
+    id serial PRIMARY KEY NOT NULL,
+    user_id uuid,
+    email text NOT NULL,
+    ip_address inet,
+    attempted_at timestamptz DEFAULT now() NOT NULL,
+
+    CONSTRAINT failed_login_user_id_fk
+        FOREIGN KEY (user_id)
+        REFERENCES auth.user(id)
+        ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS failed_login_user_id_idx ON auth.failed_login (user_id, attempted_at);
+CREATE INDEX IF NOT EXISTS failed_login_ip_address_idx ON auth.failed_login (ip_address, attempted_at);
+
+ALTER TABLE auth.user ADD COLUMN IF NOT EXISTS locked_until timestamptz;
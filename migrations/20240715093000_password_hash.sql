@@ -0,0 +1,13 @@
+-- Passwords are stored as PHC strings, which contain the algorithm and its parameters. The PBKDF2
+-- hashes are converted to this format and replaced with Argon2id on the next login of the user.
+ALTER TABLE auth.user ADD COLUMN IF NOT EXISTS password_hash text;
+
+UPDATE auth.user SET password_hash = format(
+    '$pbkdf2-sha512$i=600000,l=64$%s$%s',
+    rtrim(replace(encode(salt, 'base64'), E'\n', ''), '='),
+    rtrim(replace(encode(hash, 'base64'), E'\n', ''), '=')
+) WHERE password_hash IS NULL;
+
+ALTER TABLE auth.user ALTER COLUMN password_hash SET NOT NULL;
+ALTER TABLE auth.user DROP COLUMN IF EXISTS salt;
+ALTER TABLE auth.user DROP COLUMN IF EXISTS hash;
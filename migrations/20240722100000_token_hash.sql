@@ -0,0 +1,17 @@
+-- Tokens are stored as keyed hash (HMAC-SHA256 with TOKEN_HASH_KEY) together with a short prefix
+-- to recognize them. The key isn't known here, so existing tokens get a plain SHA-256 hash, which
+-- is replaced with the keyed hash the next time the token is used.
+ALTER TABLE auth.token ADD COLUMN IF NOT EXISTS token_hash bytea;
+ALTER TABLE auth.token ADD COLUMN IF NOT EXISTS token_prefix text;
+ALTER TABLE auth.token ADD COLUMN IF NOT EXISTS legacy_hash boolean NOT NULL DEFAULT false;
+
+UPDATE auth.token SET
+    token_hash = sha256(convert_to(token, 'UTF8')),
+    token_prefix = left(token, 8),
+    legacy_hash = true
+WHERE token_hash IS NULL;
+
+ALTER TABLE auth.token ALTER COLUMN token_hash SET NOT NULL;
+ALTER TABLE auth.token ALTER COLUMN token_prefix SET NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS token_token_hash_idx ON auth.token (token_hash);
+ALTER TABLE auth.token DROP COLUMN IF EXISTS token;
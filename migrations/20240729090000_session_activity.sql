@@ -0,0 +1,5 @@
+-- The last request of a session, its expiration is extended on activity up to a maximum lifetime
+ALTER TABLE auth.session ADD COLUMN IF NOT EXISTS last_used_at timestamptz DEFAULT now() NOT NULL;
+ALTER TABLE auth.session ADD COLUMN IF NOT EXISTS last_used_ip inet;
+
+UPDATE auth.session SET last_used_at = created_at, last_used_ip = ip_address;
@@ -0,0 +1,10 @@
+-- Users confirm their email with a link. A changed email stays pending until the new address is
+-- confirmed.
+ALTER TABLE auth.user ADD COLUMN IF NOT EXISTS email_verified_at timestamptz;
+ALTER TABLE auth.user ADD COLUMN IF NOT EXISTS pending_email text;
+
+-- Existing users were trusted so far
+UPDATE auth.user SET email_verified_at = created_at WHERE email_verified_at IS NULL;
+
+-- Whether users can log in before they verified their email
+ALTER TABLE settings ADD COLUMN IF NOT EXISTS allow_unverified_login boolean DEFAULT true NOT NULL;
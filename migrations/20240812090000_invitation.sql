@@ -0,0 +1,21 @@
+-- Admins invite users by email, the invitee sets their own password when accepting. Like tokens,
+-- the invitation token is only stored as keyed hash.
+CREATE TABLE IF NOT EXISTS auth.invitation (
+    id serial PRIMARY KEY NOT NULL,
+    email text NOT NULL,
+    role text NOT NULL,
+    tag_ids integer[] DEFAULT '{}' NOT NULL,
+    token_hash bytea NOT NULL,
+    token_prefix text NOT NULL,
+    expiration timestamptz NOT NULL,
+    invited_by uuid NOT NULL,
+    created_at timestamptz DEFAULT now() NOT NULL,
+    updated_at timestamptz DEFAULT now() NOT NULL,
+
+    CONSTRAINT invitation_email_unique UNIQUE (email),
+    CONSTRAINT invitation_token_hash_unique UNIQUE (token_hash),
+    CONSTRAINT invitation_invited_by_fk
+        FOREIGN KEY (invited_by)
+        REFERENCES auth.user(id)
+        ON DELETE CASCADE
+);
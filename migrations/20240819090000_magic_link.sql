@@ -0,0 +1,2 @@
+-- Whether users can log in with a link sent to their email instead of their password
+ALTER TABLE settings ADD COLUMN IF NOT EXISTS allow_magic_link_login boolean DEFAULT false NOT NULL;
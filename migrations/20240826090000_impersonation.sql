@@ -0,0 +1,14 @@
+-- Admins can open a session as another user. The session keeps a link to the admin, and activity
+-- written with it records the admin as the real actor.
+ALTER TABLE auth.session ADD COLUMN IF NOT EXISTS impersonator_id uuid;
+ALTER TABLE auth.session DROP CONSTRAINT IF EXISTS session_impersonator_id_fk;
+ALTER TABLE auth.session ADD CONSTRAINT session_impersonator_id_fk
+    FOREIGN KEY (impersonator_id)
+    REFERENCES auth.user(id)
+    ON DELETE CASCADE;
+
+ALTER TABLE activity ADD COLUMN IF NOT EXISTS impersonator_id uuid;
+ALTER TABLE activity DROP CONSTRAINT IF EXISTS activity_impersonator_id_fk;
+ALTER TABLE activity ADD CONSTRAINT activity_impersonator_id_fk
+    FOREIGN KEY (impersonator_id)
+    REFERENCES auth.user(id);
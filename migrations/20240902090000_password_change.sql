@@ -0,0 +1,10 @@
+-- Admins can require users to change their password on their next login, and a maximum password
+-- age can require it periodically. Sessions started while a change is pending are restricted to
+-- changing the password.
+ALTER TABLE auth.user ADD COLUMN IF NOT EXISTS password_change_required boolean DEFAULT false NOT NULL;
+ALTER TABLE auth.user ADD COLUMN IF NOT EXISTS password_changed_at timestamptz DEFAULT now() NOT NULL;
+
+ALTER TABLE auth.session ADD COLUMN IF NOT EXISTS password_change_required boolean DEFAULT false NOT NULL;
+
+-- The maximum password age in days, no maximum if null
+ALTER TABLE settings ADD COLUMN IF NOT EXISTS password_max_age_days integer;
@@ -0,0 +1,12 @@
+-- Service accounts are users for integrations. They can't log in and only authenticate with
+-- access tokens, and an admin is responsible for each of them.
+ALTER TABLE auth.user ADD COLUMN IF NOT EXISTS kind text DEFAULT 'human' NOT NULL;
+ALTER TABLE auth.user ADD COLUMN IF NOT EXISTS owner_id uuid;
+ALTER TABLE auth.user DROP CONSTRAINT IF EXISTS user_owner_id_fk;
+ALTER TABLE auth.user ADD CONSTRAINT user_owner_id_fk
+    FOREIGN KEY (owner_id)
+    REFERENCES auth.user(id);
+
+-- The access token a request was made with. No foreign key, so the id is kept after the token is
+-- deleted.
+ALTER TABLE activity ADD COLUMN IF NOT EXISTS access_token_id integer;
@@ -0,0 +1,17 @@
+-- Keys that sign JWT access tokens. The id is the `kid` of the tokens. Retired keys don't sign
+-- anymore, but stay published until the tokens they signed have expired.
+CREATE TABLE IF NOT EXISTS auth.signing_key (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+    algorithm text NOT NULL DEFAULT 'EdDSA',
+    public_key bytea NOT NULL,
+    -- Encrypted with a key derived from TOKEN_HASH_KEY
+    encrypted_private_key bytea NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    retired_at timestamptz
+);
+
+-- Refresh tokens are rotated on every use. All tokens that descend from the same login share a
+-- family, which is revoked as a whole once a used token is presented again.
+ALTER TABLE auth.token ADD COLUMN IF NOT EXISTS family_id uuid;
+ALTER TABLE auth.token ADD COLUMN IF NOT EXISTS used_at timestamptz;
+CREATE INDEX IF NOT EXISTS token_family_id_idx ON auth.token (family_id);
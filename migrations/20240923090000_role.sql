@@ -0,0 +1,37 @@
+-- Roles are named sets of permissions that admins can manage. The roles that were hardcoded before
+-- are seeded as built-in roles, which can't be changed or deleted.
+CREATE TABLE IF NOT EXISTS auth.role (
+    name text PRIMARY KEY NOT NULL,
+    description text,
+    permissions text[] DEFAULT '{}' NOT NULL,
+    built_in boolean DEFAULT false NOT NULL,
+    created_at timestamptz DEFAULT now() NOT NULL,
+    updated_at timestamptz DEFAULT now() NOT NULL
+);
+
+INSERT INTO auth.role (name, description, permissions, built_in) VALUES
+    ('admin', 'Full access', ARRAY['users:read', 'users:write', 'profile:write', 'activity:read',
+        'activity:read_all', 'tags:read', 'tokens:manage', 'sessions:manage', 'users:impersonate'], true),
+    ('editor', 'Reads the activity of every user', ARRAY['users:read', 'profile:write', 'activity:read',
+        'activity:read_all', 'tags:read', 'tokens:manage', 'sessions:manage'], true),
+    ('author', 'Manages access tokens', ARRAY['users:read', 'profile:write', 'activity:read', 'tags:read',
+        'tokens:manage', 'sessions:manage'], true),
+    ('contributor', 'Manages the own profile', ARRAY['users:read', 'profile:write', 'activity:read',
+        'tags:read', 'sessions:manage'], true)
+ON CONFLICT (name) DO NOTHING;
+
+-- Role names were parsed case-insensitively and unknown names were treated as admin, so the
+-- stored names are normalized to keep the permissions users had
+UPDATE auth.user SET role = lower(role);
+UPDATE auth.user SET role = 'admin' WHERE role NOT IN (SELECT name FROM auth.role);
+UPDATE auth.invitation SET role = lower(role);
+UPDATE auth.invitation SET role = 'admin' WHERE role NOT IN (SELECT name FROM auth.role);
+
+ALTER TABLE auth.user DROP CONSTRAINT IF EXISTS user_role_fk;
+ALTER TABLE auth.user ADD CONSTRAINT user_role_fk
+    FOREIGN KEY (role)
+    REFERENCES auth.role(name);
+ALTER TABLE auth.invitation DROP CONSTRAINT IF EXISTS invitation_role_fk;
+ALTER TABLE auth.invitation ADD CONSTRAINT invitation_role_fk
+    FOREIGN KEY (role)
+    REFERENCES auth.role(name);
@@ -0,0 +1,47 @@
+-- Groups organize users, e.g. by department. A group can be nested in a parent group, whose
+-- members include the members of all its subgroups. The role of a group grants its permissions to
+-- all these members in addition to their own role.
+CREATE TABLE IF NOT EXISTS auth.group (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    name text UNIQUE NOT NULL,
+    description text,
+    parent_id uuid,
+    role text,
+    created_at timestamptz DEFAULT now() NOT NULL,
+    created_by uuid NOT NULL,
+    updated_at timestamptz DEFAULT now() NOT NULL,
+    updated_by uuid NOT NULL,
+
+    -- Subgroups have to be moved or deleted before their parent
+    CONSTRAINT group_parent_id_fk
+        FOREIGN KEY (parent_id)
+        REFERENCES auth.group(id),
+
+    CONSTRAINT group_role_fk
+        FOREIGN KEY (role)
+        REFERENCES auth.role(name)
+);
+
+CREATE INDEX IF NOT EXISTS group_parent_id_idx ON auth.group (parent_id);
+
+-- Group admins can add and remove members of the group and its subgroups
+CREATE TABLE IF NOT EXISTS auth.group_member (
+    group_id uuid NOT NULL,
+    user_id uuid NOT NULL,
+    is_admin boolean DEFAULT false NOT NULL,
+    created_at timestamptz DEFAULT now() NOT NULL,
+
+    PRIMARY KEY (group_id, user_id),
+
+    CONSTRAINT group_member_group_id_fk
+        FOREIGN KEY (group_id)
+        REFERENCES auth.group(id)
+        ON DELETE CASCADE,
+
+    CONSTRAINT group_member_user_id_fk
+        FOREIGN KEY (user_id)
+        REFERENCES auth.user(id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS group_member_user_id_idx ON auth.group_member (user_id);
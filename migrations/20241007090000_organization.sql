@@ -0,0 +1,119 @@
+-- Organizations are the tenants of a deployment. Users are global identities that can be members
+-- of several organizations, with a role in each. Tags, groups, invitations and activity belong to
+-- one organization, and sessions and tokens act in one.
+CREATE TABLE IF NOT EXISTS auth.organization (
+    id uuid PRIMARY KEY DEFAULT gen_random_uuid() NOT NULL,
+    name text NOT NULL,
+    created_at timestamptz DEFAULT now() NOT NULL,
+    updated_at timestamptz DEFAULT now() NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS auth.organization_member (
+    organization_id uuid NOT NULL,
+    user_id uuid NOT NULL,
+    role text NOT NULL,
+    created_at timestamptz DEFAULT now() NOT NULL,
+
+    PRIMARY KEY (organization_id, user_id),
+
+    CONSTRAINT organization_member_organization_id_fk
+        FOREIGN KEY (organization_id)
+        REFERENCES auth.organization(id)
+        ON DELETE CASCADE,
+
+    CONSTRAINT organization_member_user_id_fk
+        FOREIGN KEY (user_id)
+        REFERENCES auth.user(id)
+        ON DELETE CASCADE,
+
+    CONSTRAINT organization_member_role_fk
+        FOREIGN KEY (role)
+        REFERENCES auth.role(name)
+);
+
+CREATE INDEX IF NOT EXISTS organization_member_user_id_idx ON auth.organization_member (user_id);
+
+-- The organization created by the setup. Its admins manage what is shared by all organizations,
+-- like roles and the auth policy.
+ALTER TABLE settings ADD COLUMN IF NOT EXISTS default_organization_id uuid;
+ALTER TABLE settings DROP CONSTRAINT IF EXISTS settings_default_organization_id_fk;
+ALTER TABLE settings ADD CONSTRAINT settings_default_organization_id_fk
+    FOREIGN KEY (default_organization_id)
+    REFERENCES auth.organization(id);
+
+-- Existing deployments get a default organization with every user in it, keeping their role
+INSERT INTO auth.organization (name)
+    SELECT 'Default'
+    WHERE EXISTS (SELECT 1 FROM auth.user) AND NOT EXISTS (SELECT 1 FROM auth.organization);
+UPDATE settings
+    SET default_organization_id = (SELECT id FROM auth.organization ORDER BY created_at LIMIT 1)
+    WHERE default_organization_id IS NULL;
+INSERT INTO auth.organization_member (organization_id, user_id, role)
+    SELECT (SELECT id FROM auth.organization ORDER BY created_at LIMIT 1), id, role FROM auth.user
+    WHERE EXISTS (SELECT 1 FROM auth.organization)
+ON CONFLICT DO NOTHING;
+
+ALTER TABLE auth.user DROP COLUMN IF EXISTS role;
+
+ALTER TABLE tag ADD COLUMN IF NOT EXISTS organization_id uuid;
+UPDATE tag SET organization_id = (SELECT id FROM auth.organization ORDER BY created_at LIMIT 1)
+    WHERE organization_id IS NULL;
+ALTER TABLE tag ALTER COLUMN organization_id SET NOT NULL;
+ALTER TABLE tag DROP CONSTRAINT IF EXISTS tag_organization_id_fk;
+ALTER TABLE tag ADD CONSTRAINT tag_organization_id_fk
+    FOREIGN KEY (organization_id)
+    REFERENCES auth.organization(id)
+    ON DELETE CASCADE;
+
+ALTER TABLE auth.group ADD COLUMN IF NOT EXISTS organization_id uuid;
+UPDATE auth.group SET organization_id = (SELECT id FROM auth.organization ORDER BY created_at LIMIT 1)
+    WHERE organization_id IS NULL;
+ALTER TABLE auth.group ALTER COLUMN organization_id SET NOT NULL;
+ALTER TABLE auth.group DROP CONSTRAINT IF EXISTS group_organization_id_fk;
+ALTER TABLE auth.group ADD CONSTRAINT group_organization_id_fk
+    FOREIGN KEY (organization_id)
+    REFERENCES auth.organization(id)
+    ON DELETE CASCADE;
+ALTER TABLE auth.group DROP CONSTRAINT IF EXISTS group_name_key;
+ALTER TABLE auth.group DROP CONSTRAINT IF EXISTS group_organization_id_name_unique;
+ALTER TABLE auth.group ADD CONSTRAINT group_organization_id_name_unique UNIQUE (organization_id, name);
+
+ALTER TABLE auth.invitation ADD COLUMN IF NOT EXISTS organization_id uuid;
+UPDATE auth.invitation SET organization_id = (SELECT id FROM auth.organization ORDER BY created_at LIMIT 1)
+    WHERE organization_id IS NULL;
+ALTER TABLE auth.invitation ALTER COLUMN organization_id SET NOT NULL;
+ALTER TABLE auth.invitation DROP CONSTRAINT IF EXISTS invitation_organization_id_fk;
+ALTER TABLE auth.invitation ADD CONSTRAINT invitation_organization_id_fk
+    FOREIGN KEY (organization_id)
+    REFERENCES auth.organization(id)
+    ON DELETE CASCADE;
+ALTER TABLE auth.invitation DROP CONSTRAINT IF EXISTS invitation_email_unique;
+ALTER TABLE auth.invitation DROP CONSTRAINT IF EXISTS invitation_organization_id_email_unique;
+ALTER TABLE auth.invitation ADD CONSTRAINT invitation_organization_id_email_unique UNIQUE (organization_id, email);
+
+-- Activity without an organization happened outside of one, e.g. a login
+ALTER TABLE activity ADD COLUMN IF NOT EXISTS organization_id uuid;
+UPDATE activity SET organization_id = (SELECT id FROM auth.organization ORDER BY created_at LIMIT 1)
+    WHERE organization_id IS NULL;
+CREATE INDEX IF NOT EXISTS activity_organization_id_idx ON activity (organization_id);
+
+-- The active organization of a session, which users can switch between their organizations
+ALTER TABLE auth.session ADD COLUMN IF NOT EXISTS organization_id uuid;
+UPDATE auth.session SET organization_id = (SELECT id FROM auth.organization ORDER BY created_at LIMIT 1)
+    WHERE organization_id IS NULL;
+ALTER TABLE auth.session ALTER COLUMN organization_id SET NOT NULL;
+ALTER TABLE auth.session DROP CONSTRAINT IF EXISTS session_organization_id_fk;
+ALTER TABLE auth.session ADD CONSTRAINT session_organization_id_fk
+    FOREIGN KEY (organization_id)
+    REFERENCES auth.organization(id)
+    ON DELETE CASCADE;
+
+-- Access and refresh tokens act in the organization they were created in
+ALTER TABLE auth.token ADD COLUMN IF NOT EXISTS organization_id uuid;
+UPDATE auth.token SET organization_id = (SELECT id FROM auth.organization ORDER BY created_at LIMIT 1)
+    WHERE organization_id IS NULL AND token_type IN ('static_access', 'refresh');
+ALTER TABLE auth.token DROP CONSTRAINT IF EXISTS token_organization_id_fk;
+ALTER TABLE auth.token ADD CONSTRAINT token_organization_id_fk
+    FOREIGN KEY (organization_id)
+    REFERENCES auth.organization(id)
+    ON DELETE CASCADE;
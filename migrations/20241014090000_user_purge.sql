@@ -0,0 +1,44 @@
+-- Deleted users can be purged, which removes them with their tokens, tag links and credentials.
+-- Sessions aren't linked to the user, they are deleted with the tokens by the purge.
+ALTER TABLE auth.token DROP CONSTRAINT IF EXISTS token_user_id_fk;
+ALTER TABLE auth.token ADD CONSTRAINT token_user_id_fk
+    FOREIGN KEY (user_id)
+    REFERENCES auth.user(id)
+    ON DELETE CASCADE;
+
+ALTER TABLE auth.user_to_tag DROP CONSTRAINT IF EXISTS user_to_tag_user_id_fk;
+ALTER TABLE auth.user_to_tag ADD CONSTRAINT user_to_tag_user_id_fk
+    FOREIGN KEY (user_id)
+    REFERENCES auth.user(id)
+    ON DELETE CASCADE;
+
+ALTER TABLE auth.recovery_code DROP CONSTRAINT IF EXISTS recovery_code_user_id_fk;
+ALTER TABLE auth.recovery_code ADD CONSTRAINT recovery_code_user_id_fk
+    FOREIGN KEY (user_id)
+    REFERENCES auth.user(id)
+    ON DELETE CASCADE;
+
+ALTER TABLE auth.webauthn_credential DROP CONSTRAINT IF EXISTS webauthn_credential_user_id_fk;
+ALTER TABLE auth.webauthn_credential ADD CONSTRAINT webauthn_credential_user_id_fk
+    FOREIGN KEY (user_id)
+    REFERENCES auth.user(id)
+    ON DELETE CASCADE;
+
+ALTER TABLE auth.webauthn_ceremony DROP CONSTRAINT IF EXISTS webauthn_ceremony_user_id_fk;
+ALTER TABLE auth.webauthn_ceremony ADD CONSTRAINT webauthn_ceremony_user_id_fk
+    FOREIGN KEY (user_id)
+    REFERENCES auth.user(id)
+    ON DELETE CASCADE;
+
+ALTER TABLE auth.user_identity DROP CONSTRAINT IF EXISTS user_identity_user_id_fk;
+ALTER TABLE auth.user_identity ADD CONSTRAINT user_identity_user_id_fk
+    FOREIGN KEY (user_id)
+    REFERENCES auth.user(id)
+    ON DELETE CASCADE;
+
+-- The activity is the audit log, so it outlives the users and keeps the ids of purged ones
+ALTER TABLE activity DROP CONSTRAINT IF EXISTS action_by_id_user;
+ALTER TABLE activity DROP CONSTRAINT IF EXISTS activity_impersonator_id_fk;
+
+UPDATE auth.role SET permissions = array_append(permissions, 'users:purge')
+    WHERE name = 'admin' AND NOT 'users:purge' = ANY(permissions);
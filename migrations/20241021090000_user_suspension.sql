@@ -0,0 +1,6 @@
+-- Admins can suspend an account, optionally until a date. Suspended users can't log in and
+-- their credentials are rejected until the suspension ends or is lifted.
+ALTER TABLE auth.user ADD COLUMN IF NOT EXISTS suspended_at timestamptz;
+ALTER TABLE auth.user ADD COLUMN IF NOT EXISTS suspended_until timestamptz;
+ALTER TABLE auth.user ADD COLUMN IF NOT EXISTS suspended_by uuid;
+ALTER TABLE auth.user ADD COLUMN IF NOT EXISTS suspension_reason text;
@@ -0,0 +1,7 @@
+-- Login link requests are counted per source IP, including those for emails without an account
+CREATE TABLE IF NOT EXISTS auth.magic_link_request (
+    id serial PRIMARY KEY NOT NULL,
+    ip_address inet NOT NULL,
+    requested_at timestamptz DEFAULT now() NOT NULL
+);
+CREATE INDEX IF NOT EXISTS magic_link_request_ip_address_idx ON auth.magic_link_request (ip_address, requested_at);
@@ -0,0 +1,8 @@
+-- Requests to send the verification link again are counted per source IP, including those for
+-- emails without an account
+CREATE TABLE IF NOT EXISTS auth.email_verification_request (
+    id serial PRIMARY KEY NOT NULL,
+    ip_address inet NOT NULL,
+    requested_at timestamptz DEFAULT now() NOT NULL
+);
+CREATE INDEX IF NOT EXISTS email_verification_request_ip_address_idx ON auth.email_verification_request (ip_address, requested_at);
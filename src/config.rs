@@ -1,11 +1,34 @@
-use std::str::FromStr;
+use std::{env, str::FromStr};
 
 use uuid::Uuid;
 
 pub const SESSION_COOKIE: &str = "session";
+/// Keeps the session of an admin while they impersonate another user
+pub const IMPERSONATOR_SESSION_COOKIE: &str = "impersonator_session";
+/// Readable by the frontend, which repeats it in `CSRF_HEADER` for unsafe requests
+pub const CSRF_COOKIE: &str = "csrf_token";
+/// Binds a started OpenID Connect login to the browser that started it
+pub const OIDC_STATE_COOKIE: &str = "oidc_state";
+pub const CSRF_HEADER: &str = "x-csrf-token";
 pub const APP_NAME: &str = "Your app name here";
 const SYSTEM_USER_ID: &str = "00000000-0000-4000-0000-000000000000"; // You shouldn't change this
 
 pub fn system_user_uuid() -> Uuid {
     Uuid::from_str(SYSTEM_USER_ID).expect("Couldn't parse SYSTEM_USER_ID")
 }
+
+/// The url of the frontend
+pub fn base_url() -> String {
+    env::var("BASE_URL")
+        .unwrap_or("http://localhost:3000".to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Reads and parses an env var, falling back to the default if it is not set or invalid
+pub fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
@@ -29,4 +29,6 @@ pub enum Event {
         user_id: Uuid,
         new_status: UserStatus,
     },
+    /// The sessions of these tokens ended, so their WebSockets have to be closed
+    SessionsRevoked { token_ids: Vec<i32> },
 }
@@ -1,7 +1,8 @@
-use std::{env, net::SocketAddr, path::PathBuf};
+use std::{env, net::SocketAddr, path::PathBuf, sync::Arc};
 
 use axum::{routing::get, Router};
 use events::EventChannel;
+use service::oidc::OidcProvider;
 use tokio::net::TcpListener;
 use tower_http::{
     services::ServeDir,
@@ -10,6 +11,7 @@ use tower_http::{
 use tracing::Level;
 
 use sqlx::PgPool;
+use webauthn_rs::Webauthn;
 
 mod config;
 mod events;
@@ -24,6 +26,8 @@ struct AppState {
     db: PgPool,
     event_channel: EventChannel,
     upload_path: PathBuf,
+    webauthn: Arc<Webauthn>,
+    oidc_providers: Arc<Vec<OidcProvider>>,
 }
 
 #[tokio::main]
@@ -34,6 +38,7 @@ async fn main() {
         .init();
 
     let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    utils::auth::check_token_hash_key();
     let pool = PgPool::connect(&db_url).await.unwrap();
     init_db(&pool).await;
 
@@ -41,6 +46,8 @@ async fn main() {
         db: pool.clone(),
         event_channel: EventChannel::new(),
         upload_path: PathBuf::from(env::var("UPLOAD_PATH").unwrap_or("./upload".to_string())),
+        webauthn: Arc::new(service::webauthn::webauthn_from_env()),
+        oidc_providers: Arc::new(service::oidc::oidc_providers_from_env()),
     };
 
     let static_files = ServeDir::new("static");
@@ -92,14 +99,22 @@ async fn init_db(db: &PgPool) {
         .await
         .expect("Failed to run migrations");
 
-    // Create triggers to update updated_at
+    // Create triggers to update updated_at. Tables without this column must not have the trigger,
+    // because it would fail every update.
     let tables: Vec<Table> = sqlx::query_as("SELECT * from pg_catalog.pg_tables where schemaname != 'pg_catalog' and schemaname != 'information_schema' and tablename != '_sqlx_migrations'").fetch_all(db).await.unwrap();
     for table in tables {
-        let mut table_name = table.tablename.unwrap();
-        if let Some(schema_name) = table.schemaname {
-            table_name = format!("{}.{table_name}", schema_name);
-        }
-        sqlx::query(
+        let schema_name = table.schemaname.unwrap_or("public".to_string());
+        let table_name = table.tablename.unwrap();
+        let has_updated_at: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_schema = $1 AND table_name = $2 AND column_name = 'updated_at')",
+        )
+        .bind(&schema_name)
+        .bind(&table_name)
+        .fetch_one(db)
+        .await
+        .unwrap();
+        let table_name = format!("{schema_name}.{table_name}");
+        let statement = if has_updated_at {
             format!(
                 r"
 CREATE OR REPLACE TRIGGER update_updated_at_trigger
@@ -109,10 +124,9 @@ EXECUTE FUNCTION update_updated_at_function();
         ",
                 table_name,
             )
-            .as_str(),
-        )
-        .execute(db)
-        .await
-        .unwrap();
+        } else {
+            format!("DROP TRIGGER IF EXISTS update_updated_at_trigger ON {table_name}")
+        };
+        sqlx::query(statement.as_str()).execute(db).await.unwrap();
     }
 }
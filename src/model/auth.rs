@@ -1,7 +1,11 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{prelude::FromRow, types::ipnetwork::IpNetwork};
+use sqlx::{
+    prelude::FromRow,
+    types::{ipnetwork::IpNetwork, Json},
+};
 use uuid::Uuid;
+use webauthn_rs::prelude::{Passkey, PublicKeyCredential};
 
 #[derive(Deserialize, Serialize, Clone, Debug, Default, Eq, PartialEq)]
 #[serde(rename_all = "snake_case")]
@@ -13,22 +17,80 @@ pub enum UserStatus {
     DoNotDisturb,
 }
 
+/// Whether the user can log in, shown to admins next to the user
+#[derive(Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountStatus {
+    Active,
+    Suspended,
+    Deleted,
+}
+
 #[derive(Serialize)]
+#[allow(dead_code)]
 pub struct UserStatusUpdate {
     pub user_id: String,
     pub new_status: UserStatus,
 }
 
+/// The name of a role in `auth.role`, which defines its permissions
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq, Hash)]
+#[serde(from = "String", into = "String")]
+pub struct Role(pub(crate) String);
+
+/// A named set of permissions. Built-in roles are seeded by the migrations and can't be changed.
+#[derive(FromRow, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RoleDefinition {
+    pub name: String,
+    pub description: Option<String>,
+    pub permissions: Vec<String>,
+    pub built_in: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Humans log in themselves, service accounts only authenticate with access tokens
 #[derive(Deserialize, Serialize, Clone, Debug, Default, Copy, Eq, PartialEq)]
-pub enum Role {
+#[serde(rename_all = "lowercase")]
+pub enum UserKind {
     #[default]
-    Admin,
-    Editor,
-    Author,
-    Contributor,
+    Human,
+    Service,
+}
+
+/// Permissions are granted to users by their role and to access tokens as scopes
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Permission {
+    #[serde(rename = "users:read")]
+    UsersRead,
+    /// Create, update and delete other users
+    #[serde(rename = "users:write")]
+    UsersWrite,
+    /// Update the own profile, password, avatar and preferences
+    #[serde(rename = "profile:write")]
+    ProfileWrite,
+    /// Read the own activity
+    #[serde(rename = "activity:read")]
+    ActivityRead,
+    /// Read the activity of every user
+    #[serde(rename = "activity:read_all")]
+    ActivityReadAll,
+    #[serde(rename = "tags:read")]
+    TagsRead,
+    #[serde(rename = "tokens:manage")]
+    TokensManage,
+    #[serde(rename = "sessions:manage")]
+    SessionsManage,
+    /// Open a session as another user
+    #[serde(rename = "users:impersonate")]
+    UsersImpersonate,
+    /// Permanently delete users who were deleted before
+    #[serde(rename = "users:purge")]
+    UsersPurge,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[derive(Deserialize, Serialize, Clone, Debug, Default, Eq, PartialEq)]
 pub enum Language {
     #[serde(rename = "en")]
     #[default]
@@ -37,7 +99,7 @@ pub enum Language {
     German,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+#[derive(Deserialize, Serialize, Clone, Debug, Default, Eq, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum Theme {
     #[default]
@@ -52,11 +114,19 @@ pub struct Session {
     pub user_agent: String,
     pub ip_address: Option<IpNetwork>,
     pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+    pub last_used_ip: Option<IpNetwork>,
+    /// The admin who opened this session as the user
+    pub impersonator_id: Option<Uuid>,
+    /// The session can only change the password of the user until it was changed
+    pub password_change_required: bool,
+    /// The active organization, which the user can switch
+    pub organization_id: Uuid,
 }
 
 pub struct SessionWithToken {
     pub session: Session,
-    pub token: Token,
+    pub token: CreatedToken,
 }
 
 #[derive(Serialize)]
@@ -74,13 +144,35 @@ pub enum TokenType {
     #[serde(rename = "static_access")]
     StaticAccess,
     Session,
+    /// Issued after the password was verified for a user with two-factor authentication
+    #[serde(rename = "two_factor_challenge")]
+    TwoFactorChallenge,
+    /// Sent by email to confirm the email or the pending email of a user
+    #[serde(rename = "email_verification")]
+    EmailVerification,
+    /// Sent by email to log in without the password, can only be used once
+    #[serde(rename = "magic_link")]
+    MagicLink,
+    /// Exchanged for a new JWT access token and refresh token, can only be used once
+    #[serde(rename = "refresh")]
+    Refresh,
+    /// A JWT access token. These are never stored, only the refresh token they were issued with.
+    #[serde(rename = "jwt")]
+    Jwt,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Token {
     pub id: i32,
     pub name: Option<String>,
-    pub token: String,
+    /// The first characters of the token to recognize it, the token itself is only stored as hash
+    pub token_prefix: String,
+    #[allow(dead_code)]
+    #[serde(skip)]
+    pub token_hash: Vec<u8>,
+    /// Whether `token_hash` is still the unkeyed hash of a token that was stored in plaintext
+    #[serde(skip)]
+    pub legacy_hash: bool,
     #[serde(rename = "type")]
     pub token_type: TokenType,
     pub expiration: Option<DateTime<Utc>>,
@@ -88,6 +180,162 @@ pub struct Token {
     pub session_id: Option<i32>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// The permissions a static access token is restricted to
+    pub scopes: Vec<String>,
+    /// The refresh tokens that were rotated from the same login
+    pub family_id: Option<Uuid>,
+    /// When a refresh token was exchanged
+    pub used_at: Option<DateTime<Utc>>,
+    /// The organization an access or refresh token acts in
+    pub organization_id: Option<Uuid>,
+}
+
+/// A token that was just created. The plaintext is not stored and only available here.
+#[derive(Debug)]
+pub struct CreatedToken {
+    pub token: Token,
+    pub plaintext: String,
+}
+
+/// The second step of the login or a confirmation for security relevant changes
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum SecondFactor {
+    Totp {
+        code: String,
+    },
+    RecoveryCode {
+        #[serde(rename = "recoveryCode")]
+        recovery_code: String,
+    },
+}
+
+/// Confirms a security relevant change with the password and the second factor of the user.
+/// Users with TOTP send a code or recovery code, users with only passkeys send an assertion.
+#[derive(Deserialize, Debug)]
+pub struct Reauthentication {
+    pub password: String,
+    #[serde(flatten)]
+    pub second_factor: Option<SecondFactor>,
+    pub passkey: Option<PasskeyAssertion>,
+}
+
+/// The answer of the authenticator to a ceremony started with `/auth/webauthn/login/start`
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyAssertion {
+    pub ceremony_id: Uuid,
+    pub credential: PublicKeyCredential,
+}
+
+/// The methods a user can finish the login with after the password was verified
+#[derive(Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SecondFactorMethod {
+    Totp,
+    Webauthn,
+}
+
+/// A passkey or security key registered by a user
+#[derive(FromRow, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WebauthnCredential {
+    pub id: i32,
+    pub user_id: Uuid,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub credential_id: Vec<u8>,
+    #[serde(skip_serializing)]
+    pub passkey: Json<Passkey>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WebauthnCeremonyKind {
+    Registration,
+    Authentication,
+}
+
+/// The server side state of a started WebAuthn ceremony
+#[derive(FromRow, Debug)]
+#[allow(dead_code)]
+pub struct WebauthnCeremony {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub kind: WebauthnCeremonyKind,
+    pub state: serde_json::Value,
+    pub expiration: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A user linked to the subject of an OpenID Connect provider
+#[derive(FromRow, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UserIdentity {
+    pub id: i32,
+    pub user_id: Uuid,
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A pending invitation of a new user
+#[derive(FromRow, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Invitation {
+    pub id: i32,
+    /// The organization the user joins when accepting
+    pub organization_id: Uuid,
+    pub email: String,
+    pub role: Role,
+    /// The tags the user gets when accepting
+    pub tag_ids: Vec<i32>,
+    #[allow(dead_code)]
+    #[serde(skip)]
+    pub token_hash: Vec<u8>,
+    pub token_prefix: String,
+    pub expiration: DateTime<Utc>,
+    pub invited_by: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A started OpenID Connect login, which is finished by the callback of the provider
+#[derive(FromRow, Debug)]
+#[allow(dead_code)]
+pub struct OidcLogin {
+    pub state: String,
+    pub provider: String,
+    pub nonce: String,
+    pub pkce_verifier: String,
+    pub redirect_to: Option<String>,
+    pub expiration: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A key that signs JWT access tokens, its id is the `kid` of the tokens
+#[derive(FromRow, Debug, Clone)]
+#[allow(dead_code)]
+pub struct SigningKey {
+    pub id: Uuid,
+    pub algorithm: String,
+    /// The raw Ed25519 public key
+    pub public_key: Vec<u8>,
+    /// The PKCS#8 document of the private key, encrypted with `utils::auth::encrypt_secret`
+    pub encrypted_private_key: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+    /// Retired keys don't sign anymore, but still verify until the tokens they signed expired
+    pub retired_at: Option<DateTime<Utc>>,
+}
+
+/// The failed login attempts of an account or source IP within the current window
+pub struct FailedLoginStats {
+    pub count: i64,
+    pub last_attempt_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Deserialize)]
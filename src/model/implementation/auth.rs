@@ -1,4 +1,9 @@
-use crate::model::auth::{Language, Role, Theme, TokenType, UserStatus};
+use std::str::FromStr;
+
+use crate::model::auth::{
+    Language, Permission, Role, RoleDefinition, SecondFactorMethod, Theme, Token, TokenType,
+    UserKind, UserStatus, WebauthnCeremonyKind,
+};
 
 impl From<TokenType> for String {
     fn from(value: TokenType) -> Self {
@@ -6,10 +11,94 @@ impl From<TokenType> for String {
             TokenType::PasswordReset => "password_reset".to_string(),
             TokenType::StaticAccess => "static_access".to_string(),
             TokenType::Session => "session".to_string(),
+            TokenType::TwoFactorChallenge => "two_factor_challenge".to_string(),
+            TokenType::EmailVerification => "email_verification".to_string(),
+            TokenType::MagicLink => "magic_link".to_string(),
+            TokenType::Refresh => "refresh".to_string(),
+            TokenType::Jwt => "jwt".to_string(),
+        }
+    }
+}
+
+impl From<SecondFactorMethod> for String {
+    fn from(value: SecondFactorMethod) -> Self {
+        match value {
+            SecondFactorMethod::Totp => "totp".to_string(),
+            SecondFactorMethod::Webauthn => "webauthn".to_string(),
+        }
+    }
+}
+
+impl From<Permission> for String {
+    fn from(value: Permission) -> Self {
+        match value {
+            Permission::UsersRead => "users:read".to_string(),
+            Permission::UsersWrite => "users:write".to_string(),
+            Permission::ProfileWrite => "profile:write".to_string(),
+            Permission::ActivityRead => "activity:read".to_string(),
+            Permission::ActivityReadAll => "activity:read_all".to_string(),
+            Permission::TagsRead => "tags:read".to_string(),
+            Permission::TokensManage => "tokens:manage".to_string(),
+            Permission::SessionsManage => "sessions:manage".to_string(),
+            Permission::UsersImpersonate => "users:impersonate".to_string(),
+            Permission::UsersPurge => "users:purge".to_string(),
         }
     }
 }
 
+impl Permission {
+    pub const ALL: [Permission; 10] = [
+        Permission::UsersRead,
+        Permission::UsersWrite,
+        Permission::ProfileWrite,
+        Permission::ActivityRead,
+        Permission::ActivityReadAll,
+        Permission::TagsRead,
+        Permission::TokensManage,
+        Permission::SessionsManage,
+        Permission::UsersImpersonate,
+        Permission::UsersPurge,
+    ];
+}
+
+impl Role {
+    pub const ADMIN: &'static str = "admin";
+    pub const EDITOR: &'static str = "editor";
+    pub const AUTHOR: &'static str = "author";
+    pub const CONTRIBUTOR: &'static str = "contributor";
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl RoleDefinition {
+    /// Stored permissions that no longer exist are skipped
+    pub fn permissions(&self) -> Vec<Permission> {
+        self.permissions
+            .iter()
+            .filter_map(|p| p.parse().ok())
+            .collect()
+    }
+}
+
+impl FromStr for Permission {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Permission::ALL
+            .into_iter()
+            .find(|p| String::from(*p) == value)
+            .ok_or(())
+    }
+}
+
+impl Token {
+    pub fn has_scope(&self, permission: Permission) -> bool {
+        self.scopes.contains(&String::from(permission))
+    }
+}
+
 impl From<String> for UserStatus {
     fn from(value: String) -> Self {
         match value.to_lowercase().as_str() {
@@ -33,32 +122,46 @@ impl From<UserStatus> for String {
     }
 }
 
-impl From<String> for Role {
+impl From<String> for UserKind {
     fn from(value: String) -> Self {
         match value.to_lowercase().as_str() {
-            "admin" => Self::Admin,
-            "editor" => Self::Editor,
-            "author" => Self::Author,
-            "contributor" => Self::Contributor,
-            _ => Default::default(),
+            "service" => Self::Service,
+            _ => Self::Human,
         }
     }
 }
 
-impl From<Role> for String {
-    fn from(value: Role) -> Self {
+impl From<UserKind> for String {
+    fn from(value: UserKind) -> Self {
         match value {
-            Role::Admin => "admin".to_string(),
-            Role::Editor => "editor".to_string(),
-            Role::Author => "author".to_string(),
-            Role::Contributor => "contributor".to_string(),
+            UserKind::Human => "human".to_string(),
+            UserKind::Service => "service".to_string(),
         }
     }
 }
 
+/// Role names are case-insensitive
+impl From<String> for Role {
+    fn from(value: String) -> Self {
+        Self(value.trim().to_lowercase())
+    }
+}
+
+impl From<&str> for Role {
+    fn from(value: &str) -> Self {
+        Self::from(value.to_string())
+    }
+}
+
+impl From<Role> for String {
+    fn from(value: Role) -> Self {
+        value.0
+    }
+}
+
 impl From<&Role> for String {
     fn from(value: &Role) -> Self {
-        String::from(*value)
+        value.0.clone()
     }
 }
 
@@ -106,11 +209,34 @@ impl From<String> for TokenType {
             "password_reset" => Self::PasswordReset,
             "static_access" => Self::StaticAccess,
             "session" => Self::Session,
+            "two_factor_challenge" => Self::TwoFactorChallenge,
+            "email_verification" => Self::EmailVerification,
+            "magic_link" => Self::MagicLink,
+            "refresh" => Self::Refresh,
+            "jwt" => Self::Jwt,
             _ => Self::Session,
         }
     }
 }
 
+impl From<WebauthnCeremonyKind> for String {
+    fn from(value: WebauthnCeremonyKind) -> Self {
+        match value {
+            WebauthnCeremonyKind::Registration => "registration".to_string(),
+            WebauthnCeremonyKind::Authentication => "authentication".to_string(),
+        }
+    }
+}
+
+impl From<String> for WebauthnCeremonyKind {
+    fn from(value: String) -> Self {
+        match value.to_lowercase().as_str() {
+            "registration" => Self::Registration,
+            _ => Self::Authentication,
+        }
+    }
+}
+
 // impl<'r> FromRow<'r, PgRow> for Token {
 //     fn from_row(row: &'r PgRow) -> Result<Self, sqlx::Error> {
 //         let id = row.try_get("id")?;
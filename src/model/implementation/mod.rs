@@ -1,12 +1,17 @@
 use super::Settings;
 
 pub mod auth;
+pub mod user;
 
 impl Default for Settings {
     fn default() -> Self {
         Self {
             id: "settings".to_string(),
             setup_finished: false,
+            allow_unverified_login: true,
+            allow_magic_link_login: false,
+            password_max_age_days: None,
+            default_organization_id: None,
         }
     }
 }
@@ -0,0 +1,42 @@
+use chrono::Utc;
+
+use crate::model::{
+    auth::AccountStatus,
+    user::{User, UserUpdateInput},
+};
+
+impl User {
+    /// Suspensions with an end date are over once it passed, without being lifted
+    pub fn is_suspended(&self) -> bool {
+        self.suspended_at.is_some() && self.suspended_until.is_none_or(|until| until > Utc::now())
+    }
+
+    pub fn account_status(&self) -> AccountStatus {
+        if self.deleted_at.is_some() {
+            AccountStatus::Deleted
+        } else if self.is_suspended() {
+            AccountStatus::Suspended
+        } else {
+            AccountStatus::Active
+        }
+    }
+}
+
+impl UserUpdateInput {
+    /// Whether the input changes the account of the user, which is shared by all their
+    /// organizations. Only the role and tags belong to the organization.
+    pub fn changes_account(&self, user: &User) -> bool {
+        self.email.as_ref().is_some_and(|v| *v != user.email)
+            || changes(&self.first_name, &user.first_name)
+            || changes(&self.last_name, &user.last_name)
+            || changes(&self.title, &user.title)
+            || changes(&self.location, &user.location)
+            || changes(&self.description, &user.description)
+            || self.language.as_ref().is_some_and(|v| *v != user.language)
+            || self.theme.as_ref().is_some_and(|v| *v != user.theme)
+    }
+}
+
+fn changes<T: PartialEq>(new: &Option<T>, current: &Option<T>) -> bool {
+    new.is_some() && new != current
+}
@@ -6,6 +6,8 @@ use serde::{Deserialize, Serialize};
 use sqlx::{prelude::FromRow, types::ipnetwork::IpNetwork};
 use uuid::Uuid;
 
+use self::auth::Role;
+
 pub mod auth;
 pub mod implementation;
 pub mod user;
@@ -15,23 +17,37 @@ pub const USER_TABLE_NAME: &str = "auth.user";
 pub const TAG_TABLE_NAME: &str = "tag";
 #[allow(dead_code)]
 pub const ACTIVITY_TABLE_NAME: &str = "activity";
-#[allow(dead_code)]
 pub const SETTINGS_TABLE_NAME: &str = "settings";
 #[allow(dead_code)]
 pub const SESSION_TABLE_NAME: &str = "auth.session";
 #[allow(dead_code)]
 pub const TOKEN_TABLE_NAME: &str = "auth.token";
+pub const ROLE_TABLE_NAME: &str = "auth.role";
+pub const GROUP_TABLE_NAME: &str = "auth.group";
+pub const ORGANIZATION_TABLE_NAME: &str = "auth.organization";
 
+/// The settings of the deployment, shared by all organizations. They only cover the setup and
+/// the login of accounts, which exist before an organization is active and belong to all of
+/// their organizations, so only the default organization can change them.
 #[derive(Deserialize, Clone, Debug, Serialize, FromRow)]
 pub struct Settings {
     pub id: String,
     pub setup_finished: bool,
+    /// Whether users can log in before they verified their email
+    pub allow_unverified_login: bool,
+    /// Whether users can request a login link by email instead of entering their password
+    pub allow_magic_link_login: bool,
+    /// Days after which users have to change their password, no maximum if `None`
+    pub password_max_age_days: Option<i32>,
+    /// The organization created by the setup, whose admins manage the whole deployment
+    pub default_organization_id: Option<Uuid>,
 }
 
 #[derive(Deserialize, Clone, Debug, Serialize, FromRow)]
 #[serde(rename_all = "camelCase")]
 pub struct Tag {
     pub id: i32,
+    pub organization_id: Uuid,
     pub title: String,
     #[serde(with = "ts_milliseconds")]
     pub created_at: DateTime<Utc>,
@@ -44,6 +60,67 @@ pub struct Tag {
     pub deleted_by: Option<Uuid>,
 }
 
+/// A tenant of the deployment. Users can be members of several organizations.
+#[derive(Clone, Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Organization {
+    pub id: Uuid,
+    pub name: String,
+    #[serde(with = "ts_milliseconds")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "ts_milliseconds")]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// An organization of the current user with their role in it
+#[derive(Serialize)]
+pub struct OrganizationWithRole {
+    #[serde(flatten)]
+    pub organization: Organization,
+    pub role: Role,
+}
+
+#[derive(Clone, Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationMember {
+    pub organization_id: Uuid,
+    pub user_id: Uuid,
+    /// The role of the user in this organization
+    pub role: Role,
+    #[serde(with = "ts_milliseconds")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// Members of a group are also members of all its ancestors
+#[derive(Clone, Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Group {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub parent_id: Option<Uuid>,
+    /// The role whose permissions the members get in addition to their own
+    pub role: Option<String>,
+    #[serde(with = "ts_milliseconds")]
+    pub created_at: DateTime<Utc>,
+    pub created_by: Uuid,
+    #[serde(with = "ts_milliseconds")]
+    pub updated_at: DateTime<Utc>,
+    pub updated_by: Uuid,
+}
+
+#[derive(Clone, Debug, Serialize, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupMember {
+    pub group_id: Uuid,
+    pub user_id: Uuid,
+    /// Group admins manage the members of the group and its subgroups
+    pub is_admin: bool,
+    #[serde(with = "ts_milliseconds")]
+    pub created_at: DateTime<Utc>,
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(untagged)]
 pub enum UpdateTag {
@@ -67,4 +144,10 @@ pub struct Activity {
     pub old_data: Option<String>,
     /// The data after the change if it is an update (without secrets, as json)
     pub new_data: Option<String>,
+    /// The admin who acted as `action_by_id` through an impersonation session
+    pub impersonator_id: Option<Uuid>,
+    /// The access token the action was made with
+    pub access_token_id: Option<i32>,
+    /// `None` if the action happened outside of an organization, e.g. a login
+    pub organization_id: Option<Uuid>,
 }
@@ -7,7 +7,7 @@ use sqlx::prelude::*;
 use uuid::Uuid;
 
 use super::{
-    auth::{Language, Role, Theme, UserStatus},
+    auth::{AccountStatus, Language, Role, Theme, UserKind, UserStatus},
     Tag, UpdateTag,
 };
 
@@ -15,25 +15,55 @@ use super::{
 #[serde(rename_all = "camelCase")]
 pub struct User {
     pub id: Uuid,
+    pub kind: UserKind,
+    /// The admin who is responsible for the service account
+    pub owner_id: Option<Uuid>,
     pub email: String,
+    #[serde(with = "ts_milliseconds_option")]
+    pub email_verified_at: Option<DateTime<Utc>>,
+    /// A new email that is used once it is confirmed from this address
+    pub pending_email: Option<String>,
     pub first_name: Option<String>,
     pub last_name: Option<String>,
+    /// PHC string of the hash, see `utils::password`
     #[serde(skip_serializing)]
-    pub salt: Vec<u8>,
-    #[serde(skip_serializing)]
-    pub hash: Vec<u8>,
+    pub password_hash: String,
     pub description: Option<String>,
     pub title: Option<String>,
     pub location: Option<String>,
 
     pub language: Language,
-    pub role: Role,
     pub theme: Theme,
     pub avatar: Option<String>,
 
     pub online_status: UserStatus,
     pub last_active_at: Option<DateTime<Utc>>,
 
+    /// Encrypted with `utils::auth::encrypt_secret`
+    #[serde(skip_serializing)]
+    pub totp_secret: Option<Vec<u8>>,
+    /// The time step of the last accepted code, later codes have to be of a later step
+    #[allow(dead_code)]
+    #[serde(skip_serializing)]
+    pub totp_last_step: Option<i64>,
+    #[serde(with = "ts_milliseconds_option")]
+    pub totp_enabled_at: Option<DateTime<Utc>>,
+    /// Set after too many failed login attempts
+    #[serde(with = "ts_milliseconds_option")]
+    pub locked_until: Option<DateTime<Utc>>,
+    /// Set by admins to make the user change the password on the next login
+    pub password_change_required: bool,
+    #[serde(with = "ts_milliseconds")]
+    pub password_changed_at: DateTime<Utc>,
+    /// Set by admins to keep the user from logging in, see `User::is_suspended`
+    #[serde(with = "ts_milliseconds_option")]
+    pub suspended_at: Option<DateTime<Utc>>,
+    /// The suspension ends by itself at this time, otherwise it has to be lifted
+    #[serde(with = "ts_milliseconds_option")]
+    pub suspended_until: Option<DateTime<Utc>>,
+    pub suspended_by: Option<Uuid>,
+    pub suspension_reason: Option<String>,
+
     #[serde(with = "ts_milliseconds")]
     pub updated_at: DateTime<Utc>,
     pub updated_by: Uuid,
@@ -45,10 +75,23 @@ pub struct User {
     pub deleted_by: Option<Uuid>,
 }
 
+/// A member of an organization with the role they have in it
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserWithRole {
+    #[serde(flatten)]
+    pub user: User,
+    pub role: Role,
+    pub account_status: AccountStatus,
+}
+
 #[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct UserWithTags {
     #[serde(flatten)]
     pub user: User,
+    pub role: Role,
+    pub account_status: AccountStatus,
     pub tags: Vec<Tag>,
 }
 
@@ -69,8 +112,7 @@ pub struct UserUpdateInput {
 #[derive(Default)]
 pub struct UserCreateInput<'a> {
     pub email: String,
-    pub salt: &'a [u8],
-    pub hash: &'a [u8],
+    pub password_hash: &'a str,
     pub first_name: Option<String>,
     pub last_name: Option<String>,
     pub role: Option<Role>,
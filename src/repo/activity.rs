@@ -1,7 +1,11 @@
+use chrono::{DateTime, Utc};
 use sqlx::{types::ipnetwork::IpNetwork, PgConnection};
 use uuid::Uuid;
 
-use crate::model::Activity;
+use crate::{
+    model::Activity,
+    utils::auth::{current_access_token_id, current_impersonator_id, current_organization_id},
+};
 
 use super::DatabasePagination;
 
@@ -51,6 +55,206 @@ pub enum ActivityEntry {
         /// The id of the user logging out
         action_by_id: Uuid,
     },
+    TwoFactorEnable {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        action_by_id: Uuid,
+    },
+    TwoFactorDisable {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        action_by_id: Uuid,
+    },
+    RecoveryCodeUse {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        /// The id of the user logging in with the recovery code
+        action_by_id: Uuid,
+    },
+    WebauthnCredentialAdd {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        action_by_id: Uuid,
+        /// The id of the registered credential
+        item_id: String,
+    },
+    WebauthnCredentialRemove {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        action_by_id: Uuid,
+        /// The id of the removed credential
+        item_id: String,
+    },
+    LoginFailed {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        /// The id of the user whose login failed
+        action_by_id: Uuid,
+    },
+    AccountLock {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        /// The id of the locked user
+        action_by_id: Uuid,
+    },
+    AccountUnlock {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        action_by_id: Uuid,
+        /// The id of the unlocked user
+        item_id: Uuid,
+    },
+    AccountSuspend {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        action_by_id: Uuid,
+        /// The id of the suspended user
+        item_id: Uuid,
+        /// The suspension of the user in json format
+        new_data: String,
+    },
+    AccountUnsuspend {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        action_by_id: Uuid,
+        /// The id of the user whose suspension was lifted
+        item_id: Uuid,
+        /// The lifted suspension in json format
+        old_data: String,
+    },
+    PasswordChangeRequire {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        action_by_id: Uuid,
+        /// The id of the user who has to change the password
+        item_id: Uuid,
+    },
+    InvitationCreate {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        action_by_id: Uuid,
+        /// The id of the invitation
+        item_id: i32,
+    },
+    InvitationResend {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        action_by_id: Uuid,
+        /// The id of the invitation
+        item_id: i32,
+    },
+    InvitationRevoke {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        action_by_id: Uuid,
+        /// The id of the invitation
+        item_id: i32,
+    },
+    InvitationAccept {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        /// The id of the user who accepted
+        action_by_id: Uuid,
+        /// The id of the invitation
+        item_id: i32,
+    },
+    MagicLinkRequest {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        /// The id of the user the link was sent to
+        action_by_id: Uuid,
+    },
+    EmailVerificationRequest {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        /// The id of the user the verification link was sent to
+        action_by_id: Uuid,
+    },
+    MagicLinkLogin {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        /// The id of the user logging in
+        action_by_id: Uuid,
+    },
+    ImpersonationStart {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        /// The id of the admin
+        action_by_id: Uuid,
+        /// The id of the impersonated user
+        item_id: Uuid,
+    },
+    ImpersonationStop {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        /// The id of the admin
+        action_by_id: Uuid,
+        /// The id of the impersonated user
+        item_id: Uuid,
+    },
+    ServiceAccountCreate {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        /// The id of the owner
+        action_by_id: Uuid,
+        /// The id of the service account
+        item_id: Uuid,
+    },
+    ServiceAccountTokenCreate {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        action_by_id: Uuid,
+        /// The id of the access token
+        item_id: i32,
+    },
+    ServiceAccountTokenDelete {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        action_by_id: Uuid,
+        /// The id of the access token
+        item_id: i32,
+    },
+    RefreshTokenReuse {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        /// The id of the user the refresh token belongs to
+        action_by_id: Uuid,
+        /// The family of the refresh token, which was revoked
+        item_id: Uuid,
+    },
+    SigningKeyRotate {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        action_by_id: Uuid,
+        /// The id of the new signing key
+        item_id: Uuid,
+    },
+    /// Also logged when an existing member is made admin or no longer admin
+    GroupMemberAdd {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        action_by_id: Uuid,
+        /// The id of the group
+        item_id: Uuid,
+        /// The previous membership if the user already was a member
+        old_data: Option<String>,
+        new_data: String,
+    },
+    GroupMemberRemove {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        action_by_id: Uuid,
+        /// The id of the group
+        item_id: Uuid,
+        old_data: String,
+    },
+    OrganizationSwitch {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        action_by_id: Uuid,
+        /// The id of the organization that became active
+        item_id: Uuid,
+    },
     Delete {
         ip_address: Option<IpNetwork>,
         user_agent: Option<String>,
@@ -65,6 +269,14 @@ pub enum ActivityEntry {
         table_name: String,
         item_id: String,
     },
+    /// Undoes a `Delete`
+    Restore {
+        ip_address: Option<IpNetwork>,
+        user_agent: Option<String>,
+        action_by_id: Uuid,
+        table_name: String,
+        item_id: String,
+    },
     Create {
         ip_address: Option<IpNetwork>,
         user_agent: Option<String>,
@@ -78,7 +290,13 @@ pub enum ActivityEntry {
 }
 
 impl ActivityRepo {
+    /// Writes the entry. During an impersonation the admin behind it is recorded as well, and so
+    /// is the access token of requests made with one. Entries written during an authenticated
+    /// request belong to its organization.
     pub async fn create_one(data: ActivityEntry, db: &mut PgConnection) -> sqlx::Result<Activity> {
+        let impersonator_id = current_impersonator_id();
+        let access_token_id = current_access_token_id();
+        let organization_id = current_organization_id();
         match data {
             ActivityEntry::Update {
                 table_name,
@@ -91,7 +309,7 @@ impl ActivityRepo {
             } => {
                 sqlx::query_as!(
                     Activity,
-                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, old_data, new_data) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING *"#,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, old_data, new_data, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) RETURNING *"#,
                     "update".to_string(),
                     action_by_id,
                     ip_address,
@@ -100,6 +318,9 @@ impl ActivityRepo {
                     item_id,
                     old_data,
                     new_data,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
                 )
                 .fetch_one(db)
                 .await
@@ -111,11 +332,14 @@ impl ActivityRepo {
             } => {
                 sqlx::query_as!(
                     Activity,
-                    r#"INSERT INTO activity (action, ip_address, user_agent, item_id) VALUES ($1, $2, $3, $4) RETURNING *"#,
+                    r#"INSERT INTO activity (action, ip_address, user_agent, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *"#,
                     "password_reset_request".to_string(),
                     ip_address,
                     user_agent,
                     item_id.to_string(),
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
                 )
                 .fetch_one(db)
                 .await
@@ -127,11 +351,14 @@ impl ActivityRepo {
             } => {
                 sqlx::query_as!(
                     Activity,
-                    r#"INSERT INTO activity (action, ip_address, user_agent, item_id) VALUES ($1, $2, $3, $4) RETURNING *"#,
+                    r#"INSERT INTO activity (action, ip_address, user_agent, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *"#,
                     "password_reset".to_string(),
                     ip_address,
                     user_agent,
                     item_id.to_string(),
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
                 )
                 .fetch_one(db)
                 .await
@@ -144,12 +371,15 @@ impl ActivityRepo {
             } => {
                 sqlx::query_as!(
                     Activity,
-                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, item_id) VALUES ($1, $2, $3, $4, $5) RETURNING *"#,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING *"#,
                     "password_change".to_string(),
                     action_by_id,
                     ip_address,
                     user_agent,
                     item_id.to_string(),
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
                 )
                 .fetch_one(db)
                 .await
@@ -161,11 +391,14 @@ impl ActivityRepo {
             } => {
                 sqlx::query_as!(
                     Activity,
-                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent) VALUES ($1, $2, $3, $4) RETURNING *"#,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *"#,
                     "login".to_string(),
                     action_by_id,
                     ip_address,
                     user_agent,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
                 )
                 .fetch_one(db)
                 .await
@@ -177,104 +410,830 @@ impl ActivityRepo {
             } => {
                 sqlx::query_as!(
                     Activity,
-                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent) VALUES ($1, $2, $3, $4) RETURNING *"#,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *"#,
                     "logout".to_string(),
                     action_by_id,
                     ip_address,
                     user_agent,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
                 )
                 .fetch_one(db)
                 .await
             },
-            ActivityEntry::Delete {
+            ActivityEntry::TwoFactorEnable {
+                ip_address,
+                user_agent,
+                action_by_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *"#,
+                    "two_factor_enable".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::TwoFactorDisable {
+                ip_address,
+                user_agent,
+                action_by_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *"#,
+                    "two_factor_disable".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::RecoveryCodeUse {
+                ip_address,
+                user_agent,
+                action_by_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *"#,
+                    "recovery_code_use".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::WebauthnCredentialAdd {
                 ip_address,
                 user_agent,
                 action_by_id,
-                table_name,
                 item_id,
             } => {
                 sqlx::query_as!(
                     Activity,
-                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *"#,
-                    "delete".to_string(),
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *"#,
+                    "webauthn_credential_add".to_string(),
                     action_by_id,
                     ip_address,
                     user_agent,
-                    table_name,
+                    "auth.webauthn_credential".to_string(),
                     item_id,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
                 )
                 .fetch_one(db)
                 .await
             },
-            ActivityEntry::HardDelete {
+            ActivityEntry::WebauthnCredentialRemove {
                 ip_address,
                 user_agent,
                 action_by_id,
-                table_name,
                 item_id,
             } => {
                 sqlx::query_as!(
                     Activity,
-                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *"#,
-                    "hard_delete".to_string(),
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *"#,
+                    "webauthn_credential_remove".to_string(),
                     action_by_id,
                     ip_address,
                     user_agent,
-                    table_name,
+                    "auth.webauthn_credential".to_string(),
                     item_id,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
                 )
                 .fetch_one(db)
                 .await
             },
-            ActivityEntry::Create {
+            ActivityEntry::LoginFailed {
+                ip_address,
+                user_agent,
+                action_by_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *"#,
+                    "login_failed".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::AccountLock {
+                ip_address,
+                user_agent,
+                action_by_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *"#,
+                    "account_lock".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::AccountUnlock {
+                ip_address,
+                user_agent,
+                action_by_id,
+                item_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *"#,
+                    "account_unlock".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    "auth.user".to_string(),
+                    item_id.to_string(),
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::AccountSuspend {
                 ip_address,
                 user_agent,
                 action_by_id,
-                table_name,
                 item_id,
                 new_data,
             } => {
                 sqlx::query_as!(
                     Activity,
-                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, new_data) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *"#,
-                    "update".to_string(),
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, new_data, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING *"#,
+                    "account_suspend".to_string(),
                     action_by_id,
                     ip_address,
                     user_agent,
-                    table_name,
-                    item_id,
+                    "auth.user".to_string(),
+                    item_id.to_string(),
                     new_data,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
                 )
                 .fetch_one(db)
                 .await
             },
-            ActivityEntry::Comment => todo!(),
-        }
-    }
-
-    pub async fn list_all_for_user_id(
-        user_id: Uuid,
-        options: DatabasePagination,
-        db: &mut PgConnection,
-    ) -> sqlx::Result<Vec<Activity>> {
-        sqlx::query_as!(
-            Activity,
-            r#"SELECT * from activity WHERE action_by_id = $1 ORDER BY action_at DESC LIMIT $2 OFFSET $3"#,
-            user_id,
-            options.limit,
-            options.offset,
-        )
-        .fetch_all(db)
-        .await
-    }
-
-    pub async fn count_all_for_user_id(user_id: Uuid, db: &mut PgConnection) -> sqlx::Result<i64> {
-        let result = sqlx::query!(
-            r#"SELECT COUNT(*) FROM activity WHERE action_by_id = $1"#,
-            user_id
-        )
-        .fetch_one(db)
-        .await;
-        result.map(|r| r.count.unwrap_or(0))
+            ActivityEntry::AccountUnsuspend {
+                ip_address,
+                user_agent,
+                action_by_id,
+                item_id,
+                old_data,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, old_data, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING *"#,
+                    "account_unsuspend".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    "auth.user".to_string(),
+                    item_id.to_string(),
+                    old_data,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::PasswordChangeRequire {
+                ip_address,
+                user_agent,
+                action_by_id,
+                item_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *"#,
+                    "password_change_require".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    "auth.user".to_string(),
+                    item_id.to_string(),
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::InvitationCreate {
+                ip_address,
+                user_agent,
+                action_by_id,
+                item_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *"#,
+                    "invitation_create".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    "auth.invitation".to_string(),
+                    item_id.to_string(),
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::InvitationResend {
+                ip_address,
+                user_agent,
+                action_by_id,
+                item_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *"#,
+                    "invitation_resend".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    "auth.invitation".to_string(),
+                    item_id.to_string(),
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::InvitationRevoke {
+                ip_address,
+                user_agent,
+                action_by_id,
+                item_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *"#,
+                    "invitation_revoke".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    "auth.invitation".to_string(),
+                    item_id.to_string(),
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::InvitationAccept {
+                ip_address,
+                user_agent,
+                action_by_id,
+                item_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *"#,
+                    "invitation_accept".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    "auth.invitation".to_string(),
+                    item_id.to_string(),
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::MagicLinkRequest {
+                ip_address,
+                user_agent,
+                action_by_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *"#,
+                    "magic_link_request".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::EmailVerificationRequest {
+                ip_address,
+                user_agent,
+                action_by_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *"#,
+                    "email_verification_request".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::MagicLinkLogin {
+                ip_address,
+                user_agent,
+                action_by_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *"#,
+                    "magic_link_login".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::ImpersonationStart {
+                ip_address,
+                user_agent,
+                action_by_id,
+                item_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *"#,
+                    "impersonation_start".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    "auth.user".to_string(),
+                    item_id.to_string(),
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::ImpersonationStop {
+                ip_address,
+                user_agent,
+                action_by_id,
+                item_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *"#,
+                    "impersonation_stop".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    "auth.user".to_string(),
+                    item_id.to_string(),
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::ServiceAccountCreate {
+                ip_address,
+                user_agent,
+                action_by_id,
+                item_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *"#,
+                    "service_account_create".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    "auth.user".to_string(),
+                    item_id.to_string(),
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::ServiceAccountTokenCreate {
+                ip_address,
+                user_agent,
+                action_by_id,
+                item_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *"#,
+                    "service_account_token_create".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    "auth.token".to_string(),
+                    item_id.to_string(),
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::ServiceAccountTokenDelete {
+                ip_address,
+                user_agent,
+                action_by_id,
+                item_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *"#,
+                    "service_account_token_delete".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    "auth.token".to_string(),
+                    item_id.to_string(),
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::RefreshTokenReuse {
+                ip_address,
+                user_agent,
+                action_by_id,
+                item_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *"#,
+                    "refresh_token_reuse".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    "auth.token".to_string(),
+                    item_id.to_string(),
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::SigningKeyRotate {
+                ip_address,
+                user_agent,
+                action_by_id,
+                item_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *"#,
+                    "signing_key_rotate".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    "auth.signing_key".to_string(),
+                    item_id.to_string(),
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::GroupMemberAdd {
+                ip_address,
+                user_agent,
+                action_by_id,
+                item_id,
+                old_data,
+                new_data,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, old_data, new_data, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) RETURNING *"#,
+                    "group_member_add".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    "auth.group".to_string(),
+                    item_id.to_string(),
+                    old_data,
+                    new_data,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::GroupMemberRemove {
+                ip_address,
+                user_agent,
+                action_by_id,
+                item_id,
+                old_data,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, old_data, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING *"#,
+                    "group_member_remove".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    "auth.group".to_string(),
+                    item_id.to_string(),
+                    old_data,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::OrganizationSwitch {
+                ip_address,
+                user_agent,
+                action_by_id,
+                item_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *"#,
+                    "organization_switch".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    "auth.organization".to_string(),
+                    item_id.to_string(),
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::Delete {
+                ip_address,
+                user_agent,
+                action_by_id,
+                table_name,
+                item_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *"#,
+                    "delete".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    table_name,
+                    item_id,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::HardDelete {
+                ip_address,
+                user_agent,
+                action_by_id,
+                table_name,
+                item_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *"#,
+                    "hard_delete".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    table_name,
+                    item_id,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::Restore {
+                ip_address,
+                user_agent,
+                action_by_id,
+                table_name,
+                item_id,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *"#,
+                    "restore".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    table_name,
+                    item_id,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::Create {
+                ip_address,
+                user_agent,
+                action_by_id,
+                table_name,
+                item_id,
+                new_data,
+            } => {
+                sqlx::query_as!(
+                    Activity,
+                    r#"INSERT INTO activity (action, action_by_id, ip_address, user_agent, table_name, item_id, new_data, impersonator_id, access_token_id, organization_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING *"#,
+                    "update".to_string(),
+                    action_by_id,
+                    ip_address,
+                    user_agent,
+                    table_name,
+                    item_id,
+                    new_data,
+                    impersonator_id,
+                    access_token_id,
+                    organization_id,
+                )
+                .fetch_one(db)
+                .await
+            },
+            ActivityEntry::Comment => todo!(),
+        }
+    }
+
+    /// The activity of the user in the organization. `include_unscoped` adds what they did outside
+    /// of any organization, like logging in, which only they themselves should see.
+    pub async fn list_all_for_user_id(
+        user_id: Uuid,
+        organization_id: Uuid,
+        include_unscoped: bool,
+        options: DatabasePagination,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Vec<Activity>> {
+        sqlx::query_as!(
+            Activity,
+            r#"SELECT * from activity
+            WHERE action_by_id = $1 AND (organization_id = $2 OR ($3 AND organization_id IS NULL))
+            ORDER BY action_at DESC LIMIT $4 OFFSET $5"#,
+            user_id,
+            organization_id,
+            include_unscoped,
+            options.limit,
+            options.offset,
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// Counts the actions of a user or from an IP since the given time and returns when the
+    /// earliest of them happened
+    pub async fn count_since(
+        action: &str,
+        action_by_id: Option<Uuid>,
+        ip_address: Option<IpNetwork>,
+        since: DateTime<Utc>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<(i64, Option<DateTime<Utc>>)> {
+        let result = sqlx::query!(
+            r#"SELECT COUNT(*) as "count!", MIN(action_at) as first_action_at FROM activity
+            WHERE action = $1
+                AND ($2::uuid IS NULL OR action_by_id = $2)
+                AND ($3::inet IS NULL OR ip_address = $3)
+                AND action_at > $4"#,
+            action,
+            action_by_id,
+            ip_address,
+            since,
+        )
+        .fetch_one(db)
+        .await?;
+        Ok((result.count, result.first_action_at))
+    }
+
+    pub async fn count_all_for_user_id(
+        user_id: Uuid,
+        organization_id: Uuid,
+        include_unscoped: bool,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<i64> {
+        let result = sqlx::query!(
+            r#"SELECT COUNT(*) FROM activity
+            WHERE action_by_id = $1 AND (organization_id = $2 OR ($3 AND organization_id IS NULL))"#,
+            user_id,
+            organization_id,
+            include_unscoped,
+        )
+        .fetch_one(db)
+        .await;
+        result.map(|r| r.count.unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        model::auth::Role,
+        utils::{
+            auth::{ACCESS_TOKEN_ID, IMPERSONATOR_ID, ORGANIZATION_ID},
+            testing,
+        },
+    };
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn records_the_context_of_the_request(pool: PgPool) {
+        let conn = &mut pool.acquire().await.unwrap();
+        let organization = testing::setup(conn).await;
+        let admin =
+            testing::create_user("admin@example.com", Role::ADMIN, organization.id, conn).await;
+        let user =
+            testing::create_user("user@example.com", Role::AUTHOR, organization.id, conn).await;
+        let entry = || ActivityEntry::Logout {
+            ip_address: None,
+            user_agent: None,
+            action_by_id: user.id,
+        };
+
+        let activity = ORGANIZATION_ID
+            .scope(
+                Some(organization.id),
+                IMPERSONATOR_ID.scope(
+                    Some(admin.id),
+                    ACCESS_TOKEN_ID.scope(Some(7), ActivityRepo::create_one(entry(), conn)),
+                ),
+            )
+            .await
+            .unwrap();
+        assert_eq!(activity.impersonator_id, Some(admin.id));
+        assert_eq!(activity.access_token_id, Some(7));
+        assert_eq!(activity.organization_id, Some(organization.id));
+
+        // Outside of a request, e.g. in background jobs
+        let activity = ActivityRepo::create_one(entry(), conn).await.unwrap();
+        assert_eq!(activity.impersonator_id, None);
+        assert_eq!(activity.access_token_id, None);
+        assert_eq!(activity.organization_id, None);
     }
 }
@@ -0,0 +1,47 @@
+use chrono::{DateTime, Utc};
+use sqlx::{types::ipnetwork::IpNetwork, PgConnection};
+
+#[derive(Clone)]
+pub struct EmailVerificationRequestRepo {}
+
+impl EmailVerificationRequestRepo {
+    pub async fn create_one(ip: IpNetwork, db: &mut PgConnection) -> sqlx::Result<()> {
+        sqlx::query!(
+            r#"INSERT INTO auth.email_verification_request (ip_address) VALUES ($1)"#,
+            ip,
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    /// The number of requests from the IP since `since` and when the first of them was made
+    pub async fn count_for_ip(
+        ip: IpNetwork,
+        since: DateTime<Utc>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<(i64, Option<DateTime<Utc>>)> {
+        let result = sqlx::query!(
+            r#"SELECT COUNT(*) as "count!", MIN(requested_at) as first_requested_at
+            FROM auth.email_verification_request WHERE ip_address = $1 AND requested_at > $2"#,
+            ip,
+            since,
+        )
+        .fetch_one(db)
+        .await?;
+        Ok((result.count, result.first_requested_at))
+    }
+
+    pub async fn delete_older_than(
+        since: DateTime<Utc>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<()> {
+        sqlx::query!(
+            r#"DELETE FROM auth.email_verification_request WHERE requested_at <= $1"#,
+            since
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use chrono::{DateTime, Utc};
+use sqlx::{types::ipnetwork::IpNetwork, PgConnection};
+use uuid::Uuid;
+
+use crate::model::auth::FailedLoginStats;
+
+#[derive(Clone)]
+pub struct FailedLoginRepo {}
+
+impl FailedLoginRepo {
+    pub async fn create_one(
+        user_id: Option<Uuid>,
+        email: &str,
+        ip: Option<IpNetwork>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<()> {
+        sqlx::query!(
+            r#"INSERT INTO auth.failed_login (user_id, email, ip_address) VALUES ($1, $2, $3)"#,
+            user_id,
+            email,
+            ip,
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn stats_for_user(
+        user_id: Uuid,
+        since: DateTime<Utc>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<FailedLoginStats> {
+        sqlx::query_as!(
+            FailedLoginStats,
+            r#"SELECT COUNT(*) as "count!", MAX(attempted_at) as last_attempt_at
+            FROM auth.failed_login WHERE user_id = $1 AND attempted_at > $2"#,
+            user_id,
+            since,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn stats_for_ip(
+        ip: IpNetwork,
+        since: DateTime<Utc>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<FailedLoginStats> {
+        sqlx::query_as!(
+            FailedLoginStats,
+            r#"SELECT COUNT(*) as "count!", MAX(attempted_at) as last_attempt_at
+            FROM auth.failed_login WHERE ip_address = $1 AND attempted_at > $2"#,
+            ip,
+            since,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn delete_for_user(user_id: Uuid, db: &mut PgConnection) -> sqlx::Result<()> {
+        sqlx::query!(
+            r#"DELETE FROM auth.failed_login WHERE user_id = $1"#,
+            user_id
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_older_than(
+        since: DateTime<Utc>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<()> {
+        sqlx::query!(
+            r#"DELETE FROM auth.failed_login WHERE attempted_at <= $1"#,
+            since
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::model::{auth::Role, Group, GroupMember};
+
+#[derive(Clone)]
+pub struct GroupRepo {}
+
+impl GroupRepo {
+    pub async fn list(organization_id: Uuid, db: &mut PgConnection) -> sqlx::Result<Vec<Group>> {
+        sqlx::query_as!(
+            Group,
+            r#"SELECT * FROM auth.group WHERE organization_id = $1 ORDER BY name ASC"#,
+            organization_id
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    pub async fn get_by_id(
+        id: Uuid,
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Group> {
+        sqlx::query_as!(
+            Group,
+            r#"SELECT * FROM auth.group WHERE id = $1 AND organization_id = $2"#,
+            id,
+            organization_id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn create_one(
+        organization_id: Uuid,
+        name: &str,
+        description: Option<String>,
+        parent_id: Option<Uuid>,
+        role: Option<&Role>,
+        current_user_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Group> {
+        sqlx::query_as!(
+            Group,
+            r#"INSERT INTO auth.group (organization_id, name, description, parent_id, role, created_by, updated_by)
+            VALUES ($1, $2, $3, $4, $5, $6, $6)
+            RETURNING *"#,
+            organization_id,
+            name,
+            description,
+            parent_id,
+            role.map(Role::as_str),
+            current_user_id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn update_one(
+        id: Uuid,
+        organization_id: Uuid,
+        name: &str,
+        description: Option<String>,
+        parent_id: Option<Uuid>,
+        role: Option<&Role>,
+        current_user_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Group> {
+        sqlx::query_as!(
+            Group,
+            r#"UPDATE auth.group
+            SET name = $3, description = $4, parent_id = $5, role = $6, updated_at = now(), updated_by = $7
+            WHERE id = $1 AND organization_id = $2
+            RETURNING *"#,
+            id,
+            organization_id,
+            name,
+            description,
+            parent_id,
+            role.map(Role::as_str),
+            current_user_id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Fails while the group has subgroups. The memberships are deleted with the group.
+    pub async fn delete_one(
+        id: Uuid,
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Group> {
+        sqlx::query_as!(
+            Group,
+            r#"DELETE FROM auth.group WHERE id = $1 AND organization_id = $2 RETURNING *"#,
+            id,
+            organization_id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// The id of the group and of all groups nested in it
+    pub async fn list_subtree_ids(id: Uuid, db: &mut PgConnection) -> sqlx::Result<Vec<Uuid>> {
+        let result = sqlx::query!(
+            r#"WITH RECURSIVE subtree AS (
+                SELECT id FROM auth.group WHERE id = $1
+                UNION
+                SELECT child.id FROM auth.group child JOIN subtree ON child.parent_id = subtree.id
+            )
+            SELECT id AS "id!" FROM subtree"#,
+            id
+        )
+        .fetch_all(db)
+        .await?;
+        Ok(result.into_iter().map(|r| r.id).collect())
+    }
+
+    /// Whether the user is an admin of the group or of one of its ancestors
+    pub async fn is_admin(
+        group_id: Uuid,
+        user_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<bool> {
+        let result = sqlx::query!(
+            r#"WITH RECURSIVE ancestor AS (
+                SELECT id, parent_id FROM auth.group WHERE id = $1
+                UNION
+                SELECT parent.id, parent.parent_id FROM auth.group parent JOIN ancestor ON parent.id = ancestor.parent_id
+            )
+            SELECT EXISTS (
+                SELECT 1 FROM auth.group_member
+                WHERE user_id = $2 AND is_admin AND group_id IN (SELECT id FROM ancestor)
+            ) AS "is_admin!""#,
+            group_id,
+            user_id,
+        )
+        .fetch_one(db)
+        .await?;
+        Ok(result.is_admin)
+    }
+
+    /// The direct members, without those of subgroups
+    pub async fn list_members(
+        group_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Vec<GroupMember>> {
+        sqlx::query_as!(
+            GroupMember,
+            r#"SELECT * FROM auth.group_member WHERE group_id = $1 ORDER BY created_at ASC"#,
+            group_id
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    pub async fn get_member(
+        group_id: Uuid,
+        user_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Option<GroupMember>> {
+        sqlx::query_as!(
+            GroupMember,
+            r#"SELECT * FROM auth.group_member WHERE group_id = $1 AND user_id = $2"#,
+            group_id,
+            user_id,
+        )
+        .fetch_optional(db)
+        .await
+    }
+
+    /// Adds the user to the group or updates the membership if they already are a member
+    pub async fn upsert_member(
+        group_id: Uuid,
+        user_id: Uuid,
+        is_admin: bool,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<GroupMember> {
+        sqlx::query_as!(
+            GroupMember,
+            r#"INSERT INTO auth.group_member (group_id, user_id, is_admin) VALUES ($1, $2, $3)
+            ON CONFLICT (group_id, user_id) DO UPDATE SET is_admin = EXCLUDED.is_admin
+            RETURNING *"#,
+            group_id,
+            user_id,
+            is_admin,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn delete_member(
+        group_id: Uuid,
+        user_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<GroupMember> {
+        sqlx::query_as!(
+            GroupMember,
+            r#"DELETE FROM auth.group_member WHERE group_id = $1 AND user_id = $2 RETURNING *"#,
+            group_id,
+            user_id,
+        )
+        .fetch_one(db)
+        .await
+    }
+}
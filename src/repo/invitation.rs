@@ -0,0 +1,123 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::{
+    model::auth::{Invitation, Role},
+    utils::auth::{hash_token, token_prefix},
+};
+
+#[derive(Clone)]
+pub struct InvitationRepo {}
+
+impl InvitationRepo {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create_one(
+        organization_id: Uuid,
+        email: &str,
+        role: Role,
+        tag_ids: &[i32],
+        token: &str,
+        expiration: DateTime<Utc>,
+        invited_by: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Invitation> {
+        sqlx::query_as!(
+            Invitation,
+            r#"INSERT INTO auth.invitation
+                (organization_id, email, role, tag_ids, token_hash, token_prefix, expiration, invited_by)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING *"#,
+            organization_id,
+            email,
+            String::from(role),
+            tag_ids,
+            hash_token(token),
+            token_prefix(token),
+            expiration,
+            invited_by,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn list(
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Vec<Invitation>> {
+        sqlx::query_as!(
+            Invitation,
+            r#"SELECT * FROM auth.invitation WHERE organization_id = $1 ORDER BY created_at DESC"#,
+            organization_id
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    pub async fn get_by_id(
+        id: i32,
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Invitation> {
+        sqlx::query_as!(
+            Invitation,
+            r#"SELECT * FROM auth.invitation WHERE id = $1 AND organization_id = $2"#,
+            id,
+            organization_id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Returns the invitation if the token is valid and not expired. The token alone identifies
+    /// the invitation, whatever organization it is for.
+    pub async fn get_valid_by_token(
+        token: &str,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Invitation> {
+        sqlx::query_as!(
+            Invitation,
+            r#"SELECT * FROM auth.invitation WHERE token_hash = $1 AND expiration > now()"#,
+            hash_token(token),
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Replaces the token, so earlier invitation emails stop working
+    pub async fn update_token(
+        id: i32,
+        token: &str,
+        expiration: DateTime<Utc>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Invitation> {
+        sqlx::query_as!(
+            Invitation,
+            r#"UPDATE auth.invitation
+            SET token_hash = $1, token_prefix = $2, expiration = $3
+            WHERE id = $4
+            RETURNING *"#,
+            hash_token(token),
+            token_prefix(token),
+            expiration,
+            id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn delete_by_id(
+        id: i32,
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Invitation> {
+        sqlx::query_as!(
+            Invitation,
+            r#"DELETE FROM auth.invitation WHERE id = $1 AND organization_id = $2 RETURNING *"#,
+            id,
+            organization_id,
+        )
+        .fetch_one(db)
+        .await
+    }
+}
@@ -3,11 +3,24 @@ use std::fmt::Display;
 use serde::Deserialize;
 
 pub mod activity;
+pub mod email_verification_request;
+pub mod failed_login;
+pub mod group;
+pub mod invitation;
+pub mod magic_link_request;
+pub mod oidc_login;
+pub mod organization;
+pub mod recovery_code;
+pub mod role;
 pub mod session;
 pub mod settings;
+pub mod signing_key;
 pub mod tag;
 pub mod token;
 pub mod user;
+pub mod user_identity;
+pub mod webauthn_ceremony;
+pub mod webauthn_credential;
 
 #[derive(Deserialize, Default)]
 #[serde(rename_all = "lowercase")]
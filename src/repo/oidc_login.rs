@@ -0,0 +1,61 @@
+use chrono::Utc;
+use sqlx::PgConnection;
+
+use crate::model::auth::OidcLogin;
+
+#[derive(Clone)]
+pub struct OidcLoginRepo {}
+
+impl OidcLoginRepo {
+    /// Stores a started login, which has to be finished within 10 minutes
+    pub async fn create_one(
+        state: &str,
+        provider: &str,
+        nonce: &str,
+        pkce_verifier: &str,
+        redirect_to: Option<&str>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<OidcLogin> {
+        sqlx::query_as!(
+            OidcLogin,
+            r#"INSERT INTO auth.oidc_login (state, provider, nonce, pkce_verifier, redirect_to, expiration)
+            VALUES ($1, $2, $3, $4, $5, $6) RETURNING *"#,
+            state,
+            provider,
+            nonce,
+            pkce_verifier,
+            redirect_to,
+            Utc::now() + chrono::Duration::minutes(10),
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Deletes and returns the login if it was started for the provider and is not expired, so
+    /// every state can only be used once
+    pub async fn take_valid(
+        state: &str,
+        provider: &str,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<OidcLogin> {
+        sqlx::query_as!(
+            OidcLogin,
+            r#"DELETE FROM auth.oidc_login
+            WHERE state = $1
+                AND provider = $2
+                AND expiration > now()
+            RETURNING *"#,
+            state,
+            provider,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn delete_expired(db: &mut PgConnection) -> sqlx::Result<()> {
+        sqlx::query!(r#"DELETE FROM auth.oidc_login WHERE expiration <= now()"#)
+            .execute(db)
+            .await?;
+        Ok(())
+    }
+}
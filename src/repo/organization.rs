@@ -0,0 +1,152 @@
+use sqlx::{Acquire, PgConnection};
+use uuid::Uuid;
+
+use crate::model::{auth::Role, Organization, OrganizationMember};
+
+#[derive(Clone)]
+pub struct OrganizationRepo {}
+
+impl OrganizationRepo {
+    pub async fn create_one(name: &str, db: &mut PgConnection) -> sqlx::Result<Organization> {
+        sqlx::query_as!(
+            Organization,
+            r#"INSERT INTO auth.organization (name) VALUES ($1) RETURNING *"#,
+            name
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn get_by_id(id: Uuid, db: &mut PgConnection) -> sqlx::Result<Organization> {
+        sqlx::query_as!(
+            Organization,
+            r#"SELECT * FROM auth.organization WHERE id = $1"#,
+            id
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn update_name(
+        id: Uuid,
+        name: &str,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Organization> {
+        sqlx::query_as!(
+            Organization,
+            r#"UPDATE auth.organization SET name = $2, updated_at = now() WHERE id = $1 RETURNING *"#,
+            id,
+            name,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// The organizations the user is a member of, in the order they joined them
+    pub async fn list_for_user(
+        user_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Vec<Organization>> {
+        sqlx::query_as!(
+            Organization,
+            r#"SELECT auth.organization.* FROM auth.organization
+            JOIN auth.organization_member ON organization_member.organization_id = organization.id
+            WHERE organization_member.user_id = $1
+            ORDER BY organization_member.created_at ASC"#,
+            user_id
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    pub async fn list_memberships_for_user(
+        user_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Vec<OrganizationMember>> {
+        sqlx::query_as!(
+            OrganizationMember,
+            r#"SELECT * FROM auth.organization_member WHERE user_id = $1 ORDER BY created_at ASC"#,
+            user_id
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    pub async fn get_member(
+        organization_id: Uuid,
+        user_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<OrganizationMember> {
+        sqlx::query_as!(
+            OrganizationMember,
+            r#"SELECT * FROM auth.organization_member WHERE organization_id = $1 AND user_id = $2"#,
+            organization_id,
+            user_id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// The memberships of the given users, users who aren't members are left out
+    pub async fn list_members(
+        organization_id: Uuid,
+        user_ids: &[Uuid],
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Vec<OrganizationMember>> {
+        sqlx::query_as!(
+            OrganizationMember,
+            r#"SELECT * FROM auth.organization_member WHERE organization_id = $1 AND user_id = ANY($2)"#,
+            organization_id,
+            user_ids,
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// Adds the user with the role or changes the role if they already are a member
+    pub async fn upsert_member(
+        organization_id: Uuid,
+        user_id: Uuid,
+        role: &Role,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<OrganizationMember> {
+        sqlx::query_as!(
+            OrganizationMember,
+            r#"INSERT INTO auth.organization_member (organization_id, user_id, role) VALUES ($1, $2, $3)
+            ON CONFLICT (organization_id, user_id) DO UPDATE SET role = EXCLUDED.role
+            RETURNING *"#,
+            organization_id,
+            user_id,
+            role.as_str(),
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Also removes the user from the groups of the organization
+    pub async fn delete_member(
+        organization_id: Uuid,
+        user_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<OrganizationMember> {
+        let mut tx = db.begin().await?;
+        sqlx::query!(
+            r#"DELETE FROM auth.group_member
+            WHERE user_id = $2 AND group_id IN (SELECT id FROM auth.group WHERE organization_id = $1)"#,
+            organization_id,
+            user_id,
+        )
+        .execute(&mut *tx)
+        .await?;
+        let deleted = sqlx::query_as!(
+            OrganizationMember,
+            r#"DELETE FROM auth.organization_member WHERE organization_id = $1 AND user_id = $2 RETURNING *"#,
+            organization_id,
+            user_id,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(deleted)
+    }
+}
@@ -0,0 +1,50 @@
+use sqlx::{Acquire, PgConnection};
+use uuid::Uuid;
+
+#[derive(Clone)]
+pub struct RecoveryCodeRepo {}
+
+impl RecoveryCodeRepo {
+    /// Replaces all recovery codes of the user with the given hashes
+    pub async fn replace_for_user(
+        user_id: Uuid,
+        hashes: &[Vec<u8>],
+        db: &mut PgConnection,
+    ) -> sqlx::Result<()> {
+        let mut tx = db.begin().await?;
+        RecoveryCodeRepo::delete_for_user(user_id, &mut tx).await?;
+        for hash in hashes {
+            sqlx::query!(
+                r#"INSERT INTO auth.recovery_code (user_id, hash) VALUES ($1, $2)"#,
+                user_id,
+                hash,
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await
+    }
+
+    /// Deletes the matching code, so every code can only be used once. Returns whether a code
+    /// matched.
+    pub async fn use_one(user_id: Uuid, hash: &[u8], db: &mut PgConnection) -> sqlx::Result<bool> {
+        let deleted = sqlx::query!(
+            r#"DELETE FROM auth.recovery_code WHERE user_id = $1 AND hash = $2 RETURNING id"#,
+            user_id,
+            hash,
+        )
+        .fetch_optional(db)
+        .await?;
+        Ok(deleted.is_some())
+    }
+
+    pub async fn delete_for_user(user_id: Uuid, db: &mut PgConnection) -> sqlx::Result<()> {
+        sqlx::query!(
+            r#"DELETE FROM auth.recovery_code WHERE user_id = $1"#,
+            user_id
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::model::auth::{Role, RoleDefinition};
+
+#[derive(Clone)]
+pub struct RoleRepo {}
+
+impl RoleRepo {
+    pub async fn list(db: &mut PgConnection) -> sqlx::Result<Vec<RoleDefinition>> {
+        sqlx::query_as!(
+            RoleDefinition,
+            r#"SELECT * FROM auth.role ORDER BY built_in DESC, name ASC"#
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    pub async fn get_by_name(role: &Role, db: &mut PgConnection) -> sqlx::Result<RoleDefinition> {
+        sqlx::query_as!(
+            RoleDefinition,
+            r#"SELECT * FROM auth.role WHERE name = $1"#,
+            role.as_str(),
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// The roles of the groups of the organization the user is a member of, including the
+    /// ancestors of these groups
+    pub async fn list_for_user_groups(
+        user_id: Uuid,
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Vec<RoleDefinition>> {
+        sqlx::query_as!(
+            RoleDefinition,
+            r#"WITH RECURSIVE member_group AS (
+                SELECT id, parent_id, role FROM auth.group
+                WHERE organization_id = $2
+                    AND id IN (SELECT group_id FROM auth.group_member WHERE user_id = $1)
+                UNION
+                SELECT parent.id, parent.parent_id, parent.role FROM auth.group parent
+                JOIN member_group ON parent.id = member_group.parent_id
+            )
+            SELECT * FROM auth.role WHERE name IN (SELECT role FROM member_group)"#,
+            user_id,
+            organization_id,
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// The roles of the group and its ancestors
+    pub async fn list_for_group(
+        group_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Vec<RoleDefinition>> {
+        sqlx::query_as!(
+            RoleDefinition,
+            r#"WITH RECURSIVE ancestor AS (
+                SELECT id, parent_id, role FROM auth.group WHERE id = $1
+                UNION
+                SELECT parent.id, parent.parent_id, parent.role FROM auth.group parent
+                JOIN ancestor ON parent.id = ancestor.parent_id
+            )
+            SELECT * FROM auth.role WHERE name IN (SELECT role FROM ancestor)"#,
+            group_id
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    pub async fn create_one(
+        role: &Role,
+        description: Option<String>,
+        permissions: &[String],
+        db: &mut PgConnection,
+    ) -> sqlx::Result<RoleDefinition> {
+        sqlx::query_as!(
+            RoleDefinition,
+            r#"INSERT INTO auth.role (name, description, permissions) VALUES ($1, $2, $3) RETURNING *"#,
+            role.as_str(),
+            description,
+            permissions,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Built-in roles are never updated
+    pub async fn update_one(
+        role: &Role,
+        description: Option<String>,
+        permissions: &[String],
+        db: &mut PgConnection,
+    ) -> sqlx::Result<RoleDefinition> {
+        sqlx::query_as!(
+            RoleDefinition,
+            r#"UPDATE auth.role SET description = $2, permissions = $3
+            WHERE name = $1 AND NOT built_in
+            RETURNING *"#,
+            role.as_str(),
+            description,
+            permissions,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Built-in roles are never deleted. Fails while users, invitations or groups have the role.
+    pub async fn delete_one(role: &Role, db: &mut PgConnection) -> sqlx::Result<RoleDefinition> {
+        sqlx::query_as!(
+            RoleDefinition,
+            r#"DELETE FROM auth.role WHERE name = $1 AND NOT built_in RETURNING *"#,
+            role.as_str(),
+        )
+        .fetch_one(db)
+        .await
+    }
+}
@@ -1,8 +1,9 @@
+use chrono::{DateTime, Utc};
 use sqlx::{types::ipnetwork::IpNetwork, Acquire, PgConnection};
 use uuid::Uuid;
 
 use crate::model::{
-    auth::{Session, SessionWithToken, TokenWithSession},
+    auth::{Session, SessionWithToken, TokenType, TokenWithSession},
     user::User,
 };
 
@@ -12,20 +13,28 @@ use super::token::TokenRepo;
 pub struct SessionRepo {}
 
 impl SessionRepo {
+    #[allow(clippy::too_many_arguments)]
     pub async fn create_one_with_token(
         user: &User,
+        organization_id: Uuid,
         ip: Option<IpNetwork>,
         user_agent: String,
+        expiration: DateTime<Utc>,
+        impersonator_id: Option<Uuid>,
+        password_change_required: bool,
         db: &mut PgConnection,
     ) -> sqlx::Result<SessionWithToken> {
         let mut tx = db.begin().await?;
-        let token = TokenRepo::create_one_session_token(user.id, &mut tx).await?;
+        let token = TokenRepo::create_one_session_token(user.id, expiration, &mut tx).await?;
         let session = sqlx::query_as!(
             Session,
-            "INSERT INTO auth.session (token_id, ip_address, user_agent) VALUES ($1, $2, $3) RETURNING *",
-            &token.id,
+            "INSERT INTO auth.session (token_id, ip_address, user_agent, last_used_ip, impersonator_id, password_change_required, organization_id) VALUES ($1, $2, $3, $2, $4, $5, $6) RETURNING *",
+            &token.token.id,
             ip,
             user_agent,
+            impersonator_id,
+            password_change_required,
+            organization_id,
         )
         .fetch_one(&mut *tx)
         .await?;
@@ -34,11 +43,70 @@ impl SessionRepo {
         Ok(SessionWithToken { session, token })
     }
 
+    pub async fn get_by_token_id(token_id: i32, db: &mut PgConnection) -> sqlx::Result<Session> {
+        sqlx::query_as!(
+            Session,
+            "SELECT * FROM auth.session WHERE token_id = $1",
+            token_id
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Switches the active organization of the session
+    pub async fn update_organization(
+        token_id: i32,
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Session> {
+        sqlx::query_as!(
+            Session,
+            "UPDATE auth.session SET organization_id = $2 WHERE token_id = $1 RETURNING *",
+            token_id,
+            organization_id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Lifts the restriction of the session after the password was changed
+    pub async fn clear_password_change_required(
+        token_id: i32,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<()> {
+        sqlx::query!(
+            "UPDATE auth.session SET password_change_required = false WHERE token_id = $1",
+            token_id
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    /// Records a request of the session if the last one was before `touched_before`. Returns the
+    /// session if it was updated.
+    pub async fn touch(
+        token_id: i32,
+        ip: Option<IpNetwork>,
+        touched_before: DateTime<Utc>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Option<Session>> {
+        sqlx::query_as!(
+            Session,
+            r#"UPDATE auth.session SET last_used_at = now(), last_used_ip = $2
+            WHERE token_id = $1 AND last_used_at < $3
+            RETURNING *"#,
+            token_id,
+            ip,
+            touched_before,
+        )
+        .fetch_optional(db)
+        .await
+    }
+
     pub async fn delete_with_token(token: String, db: &mut PgConnection) -> sqlx::Result<()> {
         let mut tx = db.begin().await?;
-        let deleted_token = TokenRepo::delete_one_by_token(token, &mut tx)
-            .await
-            .unwrap();
+        let deleted_token = TokenRepo::delete_one_by_token(&token, &mut tx).await?;
 
         sqlx::query!(
             "DELETE FROM auth.session WHERE token_id = $1",
@@ -50,11 +118,12 @@ impl SessionRepo {
         tx.commit().await
     }
 
+    /// Returns the id of the token of the deleted session
     pub async fn delete_by_id_for_user(
         id: i32,
         user_id: Uuid,
         db: &mut PgConnection,
-    ) -> sqlx::Result<()> {
+    ) -> sqlx::Result<i32> {
         let mut tx = db.begin().await?;
         let deleted = sqlx::query!(
             "DELETE FROM auth.session WHERE id = $1 RETURNING token_id",
@@ -62,9 +131,37 @@ impl SessionRepo {
         )
         .fetch_one(&mut *tx)
         .await?;
-        let _ = TokenRepo::delete_by_id(deleted.token_id, user_id, &mut tx).await?;
+        let token_id = TokenRepo::delete_by_id(deleted.token_id, user_id, &mut tx).await?;
         tx.commit().await?;
-        Ok(())
+        Ok(token_id)
+    }
+
+    /// Deletes every session of the user except the one of `except_token_id` and returns the ids
+    /// of their tokens
+    pub async fn delete_all_for_user(
+        user_id: Uuid,
+        except_token_id: Option<i32>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Vec<i32>> {
+        let mut tx = db.begin().await?;
+        let token_ids: Vec<i32> = sqlx::query_scalar!(
+            r#"DELETE FROM auth.token
+            WHERE user_id = $1 AND token_type = $2 AND id IS DISTINCT FROM $3
+            RETURNING id"#,
+            user_id,
+            String::from(TokenType::Session),
+            except_token_id,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM auth.session WHERE token_id = ANY($1)",
+            &token_ids
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(token_ids)
     }
 
     pub async fn get_sessions_for_user(
@@ -77,12 +174,18 @@ impl SessionRepo {
                 auth.session.user_agent as session_user_agent,
                 auth.session.ip_address as session_ip_address,
                 auth.session.created_at as session_created_at,
+                auth.session.last_used_at as session_last_used_at,
+                auth.session.last_used_ip as session_last_used_ip,
+                auth.session.impersonator_id as session_impersonator_id,
+                auth.session.password_change_required as session_password_change_required,
+                auth.session.organization_id as session_organization_id,
                 auth.token.id as token_id,
                 auth.token.created_at as token_created_at,
                 auth.token.expiration as token_expiration
             from auth.session 
                 LEFT JOIN auth.token ON auth.session.token_id = auth.token.id 
-                WHERE auth.token.user_id = $1"#,
+                WHERE auth.token.user_id = $1
+                    AND (auth.token.expiration IS NULL OR auth.token.expiration > now())"#,
             user_id
         )
         .fetch_all(db)
@@ -99,6 +202,11 @@ impl SessionRepo {
                     user_agent: s.session_user_agent,
                     ip_address: Some(s.session_ip_address),
                     created_at: s.session_created_at,
+                    last_used_at: s.session_last_used_at,
+                    last_used_ip: s.session_last_used_ip,
+                    impersonator_id: s.session_impersonator_id,
+                    password_change_required: s.session_password_change_required,
+                    organization_id: s.session_organization_id,
                 },
             })
             .collect())
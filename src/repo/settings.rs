@@ -9,13 +9,32 @@ impl SettingsRepo {
     pub async fn upsert(settings: Settings, db: &mut PgConnection) -> sqlx::Result<Settings> {
         sqlx::query_as!(
             Settings,
-            r#"INSERT INTO settings (setup_finished) VALUES ($1) ON CONFLICT (id) DO UPDATE SET setup_finished = $1 RETURNING *"#,
-            settings.setup_finished
+            r#"INSERT INTO settings (setup_finished, default_organization_id) VALUES ($1, $2) ON CONFLICT (id) DO UPDATE SET setup_finished = $1, default_organization_id = $2 RETURNING *"#,
+            settings.setup_finished,
+            settings.default_organization_id,
         )
         .fetch_one(db)
         .await
     }
 
+    pub async fn update_auth_policy(
+        allow_unverified_login: bool,
+        allow_magic_link_login: bool,
+        password_max_age_days: Option<i32>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Settings> {
+        sqlx::query_as!(
+            Settings,
+            r#"INSERT INTO settings (allow_unverified_login, allow_magic_link_login, password_max_age_days) VALUES ($1, $2, $3) ON CONFLICT (id) DO UPDATE SET allow_unverified_login = $1, allow_magic_link_login = $2, password_max_age_days = $3 RETURNING *"#,
+            allow_unverified_login,
+            allow_magic_link_login,
+            password_max_age_days,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// The single settings row of the deployment, deliberately not scoped to an organization
     pub async fn get(db: &mut PgConnection) -> sqlx::Result<Settings> {
         sqlx::query_as!(Settings, "SELECT * FROM settings where id = 'settings'")
             .fetch_one(db)
@@ -0,0 +1,87 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::model::auth::SigningKey;
+
+#[derive(Clone)]
+pub struct SigningKeyRepo {}
+
+impl SigningKeyRepo {
+    pub async fn create_one(
+        public_key: &[u8],
+        encrypted_private_key: &[u8],
+        db: &mut PgConnection,
+    ) -> sqlx::Result<SigningKey> {
+        sqlx::query_as!(
+            SigningKey,
+            r#"INSERT INTO auth.signing_key (public_key, encrypted_private_key)
+            VALUES ($1, $2) RETURNING *"#,
+            public_key,
+            encrypted_private_key,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// The newest key that is not retired signs new tokens
+    pub async fn get_active(db: &mut PgConnection) -> sqlx::Result<SigningKey> {
+        sqlx::query_as!(
+            SigningKey,
+            r#"SELECT * FROM auth.signing_key
+            WHERE retired_at IS NULL
+            ORDER BY created_at DESC
+            LIMIT 1"#,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// The keys that may have signed tokens which are still valid
+    pub async fn list_published(
+        retired_after: DateTime<Utc>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Vec<SigningKey>> {
+        sqlx::query_as!(
+            SigningKey,
+            r#"SELECT * FROM auth.signing_key
+            WHERE retired_at IS NULL OR retired_at > $1
+            ORDER BY created_at DESC"#,
+            retired_after,
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    pub async fn retire_all_except(id: Uuid, db: &mut PgConnection) -> sqlx::Result<()> {
+        sqlx::query!(
+            r#"UPDATE auth.signing_key SET retired_at = now()
+            WHERE id != $1 AND retired_at IS NULL"#,
+            id,
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    /// Tokens signed by the deleted keys are rejected right away
+    pub async fn delete_all_except(id: Uuid, db: &mut PgConnection) -> sqlx::Result<()> {
+        sqlx::query!(r#"DELETE FROM auth.signing_key WHERE id != $1"#, id)
+            .execute(db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_retired_before(
+        retired_before: DateTime<Utc>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<()> {
+        sqlx::query!(
+            r#"DELETE FROM auth.signing_key WHERE retired_at <= $1"#,
+            retired_before,
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+}
@@ -7,24 +7,36 @@ use crate::model::{Tag, UpdateTag};
 pub struct TagRepo {}
 
 impl TagRepo {
-    pub async fn list_by_user_id(user_id: Uuid, db: &mut PgConnection) -> sqlx::Result<Vec<Tag>> {
+    /// The tags the user has in the organization
+    pub async fn list_by_user_id(
+        user_id: Uuid,
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Vec<Tag>> {
         sqlx::query_as!(
             Tag,
-            r#"SELECT tag.* FROM tag RIGHT JOIN auth.user_to_tag ON tag.id = auth.user_to_tag.tag_id WHERE auth.user_to_tag.user_id = $1 and deleted_at is null"#,
-            user_id
+            r#"SELECT tag.* FROM tag RIGHT JOIN auth.user_to_tag ON tag.id = auth.user_to_tag.tag_id WHERE auth.user_to_tag.user_id = $1 and deleted_at is null and organization_id = $2"#,
+            user_id,
+            organization_id,
         )
         .fetch_all(db)
         .await
     }
 
-    pub async fn list_all(db: &mut PgConnection) -> sqlx::Result<Vec<Tag>> {
-        sqlx::query_as!(Tag, r#"SELECT * from tag WHERE deleted_at is null"#)
-            .fetch_all(db)
-            .await
+    pub async fn list_all(organization_id: Uuid, db: &mut PgConnection) -> sqlx::Result<Vec<Tag>> {
+        sqlx::query_as!(
+            Tag,
+            r#"SELECT * from tag WHERE deleted_at is null and organization_id = $1"#,
+            organization_id
+        )
+        .fetch_all(db)
+        .await
     }
 
+    /// Existing tags have to belong to the organization, new ones are created in it
     pub async fn create_missing(
         tags: Vec<UpdateTag>,
+        organization_id: Uuid,
         current_user_id: Uuid,
         db: &mut PgConnection,
     ) -> sqlx::Result<Vec<Tag>> {
@@ -33,18 +45,24 @@ impl TagRepo {
         for tag in tags {
             match tag {
                 UpdateTag::Existing { id } => {
-                    let existing = sqlx::query_as!(Tag, r#"SELECT * FROM tag WHERE id = $1"#, id)
-                        .fetch_one(&mut *tx)
-                        .await?;
+                    let existing = sqlx::query_as!(
+                        Tag,
+                        r#"SELECT * FROM tag WHERE id = $1 AND organization_id = $2"#,
+                        id,
+                        organization_id,
+                    )
+                    .fetch_one(&mut *tx)
+                    .await?;
                     all_tags.push(existing);
                 }
                 UpdateTag::New { label } => {
                     let created = sqlx::query_as!(
                         Tag,
-                        r#"INSERT INTO tag (title, created_by, updated_by) VALUES ($1, $2, $3) RETURNING *"#,
+                        r#"INSERT INTO tag (title, created_by, updated_by, organization_id) VALUES ($1, $2, $3, $4) RETURNING *"#,
                         label,
                         current_user_id,
-                        current_user_id
+                        current_user_id,
+                        organization_id,
                     )
                     .fetch_one(&mut *tx)
                     .await?;
@@ -1,31 +1,81 @@
-use crate::utils;
-use chrono::Utc;
+use crate::utils::{
+    self,
+    auth::{hash_token, legacy_hash_token, token_prefix},
+};
+use chrono::{DateTime, Utc};
 use sqlx::PgConnection;
 use uuid::Uuid;
 
-use crate::model::auth::{Token, TokenType};
+use crate::model::auth::{CreatedToken, Permission, Token, TokenType};
 
 #[derive(Clone)]
 pub struct TokenRepo {}
 
 impl TokenRepo {
-    pub async fn delete_one_by_token<'c>(
-        token: String,
-        db: &mut PgConnection,
-    ) -> sqlx::Result<Token> {
+    pub async fn delete_one_by_token(token: &str, db: &mut PgConnection) -> sqlx::Result<Token> {
         sqlx::query_as!(
             Token,
-            r#"DELETE FROM auth.token WHERE token = $1 RETURNING *"#,
-            token,
+            r#"DELETE FROM auth.token
+            WHERE (token_hash = $1 AND NOT legacy_hash) OR (token_hash = $2 AND legacy_hash)
+            RETURNING *"#,
+            hash_token(token),
+            legacy_hash_token(token),
         )
         .fetch_one(db)
         .await
     }
 
     pub async fn get_by_token(token: &str, db: &mut PgConnection) -> sqlx::Result<Token> {
-        sqlx::query_as!(Token, r#"SELECT * FROM auth.token WHERE token = $1"#, token)
-            .fetch_one(db)
-            .await
+        let found = sqlx::query_as!(
+            Token,
+            r#"SELECT * FROM auth.token
+            WHERE (token_hash = $1 AND NOT legacy_hash) OR (token_hash = $2 AND legacy_hash)"#,
+            hash_token(token),
+            legacy_hash_token(token),
+        )
+        .fetch_one(&mut *db)
+        .await?;
+        TokenRepo::upgrade_legacy_hash(found, token, db).await
+    }
+
+    /// Returns the token if it is of the given type and not expired
+    pub async fn get_valid_by_token(
+        token: &str,
+        token_type: TokenType,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Token> {
+        let found = sqlx::query_as!(
+            Token,
+            r#"SELECT * FROM auth.token
+            WHERE ((token_hash = $1 AND NOT legacy_hash) OR (token_hash = $2 AND legacy_hash))
+                AND token_type = $3
+                AND (expiration IS NULL OR expiration > now())"#,
+            hash_token(token),
+            legacy_hash_token(token),
+            String::from(token_type),
+        )
+        .fetch_one(&mut *db)
+        .await?;
+        TokenRepo::upgrade_legacy_hash(found, token, db).await
+    }
+
+    /// Replaces the unkeyed hash of a token from before the token migration with the keyed hash
+    async fn upgrade_legacy_hash(
+        token: Token,
+        plaintext: &str,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Token> {
+        if !token.legacy_hash {
+            return Ok(token);
+        }
+        sqlx::query_as!(
+            Token,
+            r#"UPDATE auth.token SET token_hash = $1, legacy_hash = false WHERE id = $2 RETURNING *"#,
+            hash_token(plaintext),
+            token.id,
+        )
+        .fetch_one(db)
+        .await
     }
 
     pub async fn list_for_user(user_id: Uuid, db: &mut PgConnection) -> sqlx::Result<Vec<Token>> {
@@ -49,61 +99,218 @@ impl TokenRepo {
         .id)
     }
 
+    pub async fn delete_all_of_type_for_user(
+        user_id: Uuid,
+        token_type: TokenType,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<()> {
+        sqlx::query!(
+            r#"DELETE FROM auth.token WHERE user_id = $1 AND token_type = $2"#,
+            user_id,
+            String::from(token_type),
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn update_expiration(
+        id: i32,
+        expiration: DateTime<Utc>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<()> {
+        sqlx::query!(
+            r#"UPDATE auth.token SET expiration = $1 WHERE id = $2"#,
+            expiration,
+            id,
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
     pub async fn create_one_session_token(
         user_id: Uuid,
+        expiration: DateTime<Utc>,
         db: &mut PgConnection,
-    ) -> sqlx::Result<Token> {
-        sqlx::query_as!(
-            Token,
-            r#"INSERT INTO auth.token 
-                (user_id, token_type, expiration, token) 
-            VALUES ($1, $2, $3, $4) 
-            RETURNING *"#,
+    ) -> sqlx::Result<CreatedToken> {
+        TokenRepo::create_one(
             user_id,
-            String::from(TokenType::Session),
-            Utc::now() + chrono::Duration::days(30),
-            utils::auth::generate_session_token(),
+            TokenType::Session,
+            Some(expiration),
+            None,
+            &[],
+            None,
+            None,
+            db,
         )
-        .fetch_one(db)
         .await
     }
 
     pub async fn create_one_password_reset_token(
         user_id: Uuid,
         db: &mut PgConnection,
-    ) -> sqlx::Result<Token> {
-        sqlx::query_as!(
-            Token,
-            r#"INSERT INTO auth.token 
-                (user_id, token_type, expiration, token) 
-            VALUES ($1, $2, $3, $4) 
-            RETURNING *"#,
+    ) -> sqlx::Result<CreatedToken> {
+        TokenRepo::create_one(
             user_id,
-            String::from(TokenType::PasswordReset),
-            Utc::now() + chrono::Duration::minutes(30),
-            utils::auth::generate_session_token()
+            TokenType::PasswordReset,
+            Some(Utc::now() + chrono::Duration::minutes(30)),
+            None,
+            &[],
+            None,
+            None,
+            db,
+        )
+        .await
+    }
+
+    pub async fn create_one_two_factor_challenge(
+        user_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<CreatedToken> {
+        TokenRepo::create_one(
+            user_id,
+            TokenType::TwoFactorChallenge,
+            Some(Utc::now() + chrono::Duration::minutes(5)),
+            None,
+            &[],
+            None,
+            None,
+            db,
+        )
+        .await
+    }
+
+    pub async fn create_one_email_verification_token(
+        user_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<CreatedToken> {
+        TokenRepo::create_one(
+            user_id,
+            TokenType::EmailVerification,
+            Some(Utc::now() + chrono::Duration::days(1)),
+            None,
+            &[],
+            None,
+            None,
+            db,
         )
-        .fetch_one(db)
         .await
     }
 
+    pub async fn create_one_magic_link_token(
+        user_id: Uuid,
+        expiration: DateTime<Utc>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<CreatedToken> {
+        TokenRepo::create_one(
+            user_id,
+            TokenType::MagicLink,
+            Some(expiration),
+            None,
+            &[],
+            None,
+            None,
+            db,
+        )
+        .await
+    }
+
+    /// The token acts in the organization it is created in
     pub async fn create_one_access_token(
         user_id: Uuid,
+        organization_id: Uuid,
         name: String,
+        scopes: &[Permission],
         db: &mut PgConnection,
-    ) -> sqlx::Result<Token> {
+    ) -> sqlx::Result<CreatedToken> {
+        TokenRepo::create_one(
+            user_id,
+            TokenType::StaticAccess,
+            None,
+            Some(name),
+            scopes,
+            None,
+            Some(organization_id),
+            db,
+        )
+        .await
+    }
+
+    /// The access tokens issued with it act in the organization of the login
+    pub async fn create_one_refresh_token(
+        user_id: Uuid,
+        organization_id: Uuid,
+        family_id: Uuid,
+        expiration: DateTime<Utc>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<CreatedToken> {
+        TokenRepo::create_one(
+            user_id,
+            TokenType::Refresh,
+            Some(expiration),
+            None,
+            &[],
+            Some(family_id),
+            Some(organization_id),
+            db,
+        )
+        .await
+    }
+
+    /// Marks the refresh token as used, unless another request used it first
+    pub async fn mark_used(id: i32, db: &mut PgConnection) -> sqlx::Result<Token> {
         sqlx::query_as!(
             Token,
-            r#"INSERT INTO auth.token 
-                (user_id, token_type, token, name)
-            VALUES ($1, $2, $3, $4)
+            r#"UPDATE auth.token SET used_at = now()
+            WHERE id = $1 AND used_at IS NULL
+            RETURNING *"#,
+            id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Revokes every refresh token that was rotated from the same login
+    pub async fn delete_family(family_id: Uuid, db: &mut PgConnection) -> sqlx::Result<()> {
+        sqlx::query!(r#"DELETE FROM auth.token WHERE family_id = $1"#, family_id)
+            .execute(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Generates a token and only stores its hash and prefix
+    #[allow(clippy::too_many_arguments)]
+    async fn create_one(
+        user_id: Uuid,
+        token_type: TokenType,
+        expiration: Option<DateTime<Utc>>,
+        name: Option<String>,
+        scopes: &[Permission],
+        family_id: Option<Uuid>,
+        organization_id: Option<Uuid>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<CreatedToken> {
+        let plaintext = utils::auth::generate_session_token();
+        let scopes: Vec<String> = scopes.iter().copied().map(String::from).collect();
+        let token = sqlx::query_as!(
+            Token,
+            r#"INSERT INTO auth.token
+                (user_id, token_type, expiration, token_hash, token_prefix, name, scopes, family_id, organization_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
             RETURNING *"#,
             user_id,
-            String::from(TokenType::StaticAccess),
-            utils::auth::generate_session_token(),
+            String::from(token_type),
+            expiration,
+            hash_token(&plaintext),
+            token_prefix(&plaintext),
             name,
+            &scopes,
+            family_id,
+            organization_id,
         )
         .fetch_one(db)
-        .await
+        .await?;
+        Ok(CreatedToken { token, plaintext })
     }
 }
@@ -5,60 +5,189 @@ use sqlx::{Acquire, PgConnection};
 use uuid::Uuid;
 
 use crate::model::{
-    auth::{PreferencesInput, Role, UserStatus},
+    auth::{PreferencesInput, Role, UserKind, UserStatus},
     user::{User, UserCreateInput, UserUpdateInput},
+    USER_TABLE_NAME,
 };
 
-use super::{tag::TagRepo, DatabaseListOptions, SortDirection};
+use super::{organization::OrganizationRepo, tag::TagRepo, DatabaseListOptions, SortDirection};
 
 #[derive(Clone)]
 pub struct UserRepo {}
 
 impl UserRepo {
+    /// Creates the user as member of the organization, with the role of `new_user` or
+    /// `Role::AUTHOR`
     pub async fn create_one(
         new_user: UserCreateInput<'_>,
+        organization_id: Uuid,
         current_user_id: Uuid,
         db: &mut PgConnection,
     ) -> sqlx::Result<User> {
-        sqlx::query_as!(
+        let mut tx = db.begin().await?;
+        let created = sqlx::query_as!(
             User,
-            r#"INSERT INTO auth.user (email, first_name, last_name, salt, hash, created_by, updated_by, role) VALUES ($1, $2, $3, $4, $5, $6, $6, $7) returning *"#,
+            r#"INSERT INTO auth.user (email, first_name, last_name, password_hash, created_by, updated_by) VALUES ($1, $2, $3, $4, $5, $5) returning *"#,
             new_user.email,
             new_user.first_name,
             new_user.last_name,
-            new_user.salt,
-            new_user.hash,
+            new_user.password_hash,
             current_user_id,
-            String::from(new_user.role.unwrap_or(Role::Author)),
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        OrganizationRepo::upsert_member(
+            organization_id,
+            created.id,
+            &new_user.role.unwrap_or(Role::from(Role::AUTHOR)),
+            &mut tx,
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(created)
+    }
+
+    /// Creates a user of the kind `UserKind::Service`, which has no usable email or password, as
+    /// member of the organization
+    pub async fn create_service_account(
+        new_account: UserCreateInput<'_>,
+        organization_id: Uuid,
+        owner_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<User> {
+        let mut tx = db.begin().await?;
+        let created = sqlx::query_as!(
+            User,
+            r#"INSERT INTO auth.user (kind, owner_id, email, first_name, description, password_hash, created_by, updated_by)
+            VALUES ($1, $2, $3, $4, $5, $6, $2, $2)
+            RETURNING *"#,
+            String::from(UserKind::Service),
+            owner_id,
+            new_account.email,
+            new_account.first_name,
+            new_account.description,
+            new_account.password_hash,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        OrganizationRepo::upsert_member(
+            organization_id,
+            created.id,
+            &new_account.role.unwrap_or(Role::from(Role::CONTRIBUTOR)),
+            &mut tx,
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(created)
+    }
+
+    /// Deleted users aren't found, like with every other lookup
+    pub async fn get_by_email(email: String, db: &mut PgConnection) -> sqlx::Result<User> {
+        sqlx::query_as!(
+            User,
+            r#"SELECT * FROM auth.user WHERE email = $1 AND deleted_at IS NULL"#,
+            email,
         )
         .fetch_one(db)
         .await
     }
 
-    pub async fn get_by_email(email: String, db: &mut PgConnection) -> sqlx::Result<User> {
-        sqlx::query_as!(User, r#"SELECT * FROM auth.user WHERE email = $1"#, email,)
-            .fetch_one(db)
-            .await
+    /// Whether any user has the email, including deleted ones, which keep it until they are
+    /// purged
+    pub async fn is_email_taken(email: &str, db: &mut PgConnection) -> sqlx::Result<bool> {
+        let result = sqlx::query!(
+            r#"SELECT EXISTS (SELECT 1 FROM auth.user WHERE email = $1) AS "taken!""#,
+            email,
+        )
+        .fetch_one(db)
+        .await?;
+        Ok(result.taken)
     }
 
+    /// Loads any user, for their own requests. Users in the requests of others have to be
+    /// loaded with `get_by_id_in_organization`.
     pub async fn get_by_id(id: Uuid, db: &mut PgConnection) -> sqlx::Result<User> {
-        sqlx::query_as!(User, r#"SELECT * FROM auth.user WHERE id = $1"#, id,)
-            .fetch_one(db)
-            .await
+        sqlx::query_as!(
+            User,
+            r#"SELECT * FROM auth.user WHERE id = $1 AND deleted_at IS NULL"#,
+            id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Only finds members of the organization
+    pub async fn get_by_id_in_organization(
+        id: Uuid,
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<User> {
+        sqlx::query_as!(
+            User,
+            r#"SELECT * FROM auth.user
+            WHERE id = $1 AND deleted_at IS NULL
+                AND id IN (SELECT user_id FROM auth.organization_member WHERE organization_id = $2)"#,
+            id,
+            organization_id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Only finds deleted members of the organization, which can be restored or purged
+    pub async fn get_deleted_in_organization(
+        id: Uuid,
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<User> {
+        sqlx::query_as!(
+            User,
+            r#"SELECT * FROM auth.user
+            WHERE id = $1 AND deleted_at IS NOT NULL
+                AND id IN (SELECT user_id FROM auth.organization_member WHERE organization_id = $2)"#,
+            id,
+            organization_id,
+        )
+        .fetch_one(db)
+        .await
     }
 
+    /// Lists the members of the organization, of every kind if `kind` is `None`. With a group,
+    /// only its members and those of its subgroups are listed. Deleted users are only listed with
+    /// `include_deleted`.
     pub async fn list(
+        organization_id: Uuid,
+        kind: Option<UserKind>,
+        group_id: Option<Uuid>,
+        include_deleted: bool,
         options: DatabaseListOptions,
         db: &mut PgConnection,
     ) -> sqlx::Result<Vec<User>> {
+        let kind = kind.map(String::from);
         match options.sort_direction {
             SortDirection::Asc => {
                 sqlx::query_as!(
                     User,
-                    r#"SELECT * FROM auth.user ORDER BY $1 ASC LIMIT $2 OFFSET $3"#,
+                    r#"SELECT * FROM auth.user
+                    WHERE id IN (SELECT user_id FROM auth.organization_member WHERE organization_id = $6)
+                        AND ($7 OR deleted_at IS NULL)
+                        AND ($4::text IS NULL OR kind = $4)
+                        AND ($5::uuid IS NULL OR id IN (
+                            WITH RECURSIVE subtree AS (
+                                SELECT id FROM auth.group WHERE id = $5 AND organization_id = $6
+                                UNION
+                                SELECT child.id FROM auth.group child JOIN subtree ON child.parent_id = subtree.id
+                            )
+                            SELECT user_id FROM auth.group_member WHERE group_id IN (SELECT id FROM subtree)
+                        ))
+                    ORDER BY $1 ASC LIMIT $2 OFFSET $3"#,
                     options.sort_by,
                     options.limit,
-                    options.offset
+                    options.offset,
+                    kind,
+                    group_id,
+                    organization_id,
+                    include_deleted,
                 )
                 .fetch_all(db)
                 .await
@@ -66,10 +195,26 @@ impl UserRepo {
             SortDirection::Desc => {
                 sqlx::query_as!(
                     User,
-                    r#"SELECT * FROM auth.user ORDER BY $1 DESC LIMIT $2 OFFSET $3"#,
+                    r#"SELECT * FROM auth.user
+                    WHERE id IN (SELECT user_id FROM auth.organization_member WHERE organization_id = $6)
+                        AND ($7 OR deleted_at IS NULL)
+                        AND ($4::text IS NULL OR kind = $4)
+                        AND ($5::uuid IS NULL OR id IN (
+                            WITH RECURSIVE subtree AS (
+                                SELECT id FROM auth.group WHERE id = $5 AND organization_id = $6
+                                UNION
+                                SELECT child.id FROM auth.group child JOIN subtree ON child.parent_id = subtree.id
+                            )
+                            SELECT user_id FROM auth.group_member WHERE group_id IN (SELECT id FROM subtree)
+                        ))
+                    ORDER BY $1 DESC LIMIT $2 OFFSET $3"#,
                     options.sort_by,
                     options.limit,
-                    options.offset
+                    options.offset,
+                    kind,
+                    group_id,
+                    organization_id,
+                    include_deleted,
                 )
                 .fetch_all(db)
                 .await
@@ -77,35 +222,73 @@ impl UserRepo {
         }
     }
 
-    pub async fn count_all(db: &mut PgConnection) -> sqlx::Result<i64> {
-        let result = sqlx::query!(r#"SELECT COUNT(*) FROM auth.user"#)
-            .fetch_one(db)
-            .await;
+    pub async fn count_all(
+        organization_id: Uuid,
+        kind: Option<UserKind>,
+        group_id: Option<Uuid>,
+        include_deleted: bool,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<i64> {
+        let result = sqlx::query!(
+            r#"SELECT COUNT(*) FROM auth.user
+            WHERE id IN (SELECT user_id FROM auth.organization_member WHERE organization_id = $3)
+                AND ($4 OR deleted_at IS NULL)
+                AND ($1::text IS NULL OR kind = $1)
+                AND ($2::uuid IS NULL OR id IN (
+                    WITH RECURSIVE subtree AS (
+                        SELECT id FROM auth.group WHERE id = $2 AND organization_id = $3
+                        UNION
+                        SELECT child.id FROM auth.group child JOIN subtree ON child.parent_id = subtree.id
+                    )
+                    SELECT user_id FROM auth.group_member WHERE group_id IN (SELECT id FROM subtree)
+                ))"#,
+            kind.map(String::from),
+            group_id,
+            organization_id,
+            include_deleted,
+        )
+        .fetch_one(db)
+        .await;
         result.map(|r| r.count.unwrap_or(0))
     }
 
+    /// Lists the members that have one of the roles in the organization
     pub async fn list_for_roles(
+        organization_id: Uuid,
         roles: &[Role],
+        kind: Option<UserKind>,
+        group_id: Option<Uuid>,
+        include_deleted: bool,
         options: DatabaseListOptions,
         db: &mut PgConnection,
     ) -> sqlx::Result<Vec<User>> {
-        let roles = format!(
-            "'{}'",
-            roles
-                .iter()
-                .map(String::from)
-                .collect::<Vec<_>>()
-                .join("','")
-        );
+        let kind = kind.map(String::from);
+        let roles: Vec<String> = roles.iter().map(String::from).collect();
         match options.sort_direction {
             SortDirection::Asc => {
                 sqlx::query_as!(
                     User,
-                    r#"SELECT * FROM auth.user WHERE auth.user.role IN ($1) ORDER BY $2 ASC LIMIT $3 OFFSET $4"#,
-                    roles,
+                    r#"SELECT * FROM auth.user
+                    WHERE id IN (SELECT user_id FROM auth.organization_member WHERE organization_id = $7 AND role = ANY($1))
+                        AND ($8 OR deleted_at IS NULL)
+                        AND ($5::text IS NULL OR kind = $5)
+                        AND ($6::uuid IS NULL OR id IN (
+                            WITH RECURSIVE subtree AS (
+                                SELECT id FROM auth.group WHERE id = $6 AND organization_id = $7
+                                UNION
+                                SELECT child.id FROM auth.group child JOIN subtree ON child.parent_id = subtree.id
+                            )
+                            SELECT user_id FROM auth.group_member WHERE group_id IN (SELECT id FROM subtree)
+                        ))
+                    ORDER BY $2 ASC LIMIT $3 OFFSET $4"#,
+                    &roles,
                     options.sort_by,
                     options.limit,
-                    options.offset
+                    options.offset,
+                    kind,
+                    group_id,
+                    organization_id,
+                    include_deleted,
                 )
                 .fetch_all(db)
                 .await
@@ -113,11 +296,27 @@ impl UserRepo {
             SortDirection::Desc => {
                 sqlx::query_as!(
                     User,
-                    r#"SELECT * FROM auth.user WHERE auth.user.role IN ($1) ORDER BY $2 DESC LIMIT $3 OFFSET $4"#,
-                    roles,
+                    r#"SELECT * FROM auth.user
+                    WHERE id IN (SELECT user_id FROM auth.organization_member WHERE organization_id = $7 AND role = ANY($1))
+                        AND ($8 OR deleted_at IS NULL)
+                        AND ($5::text IS NULL OR kind = $5)
+                        AND ($6::uuid IS NULL OR id IN (
+                            WITH RECURSIVE subtree AS (
+                                SELECT id FROM auth.group WHERE id = $6 AND organization_id = $7
+                                UNION
+                                SELECT child.id FROM auth.group child JOIN subtree ON child.parent_id = subtree.id
+                            )
+                            SELECT user_id FROM auth.group_member WHERE group_id IN (SELECT id FROM subtree)
+                        ))
+                    ORDER BY $2 DESC LIMIT $3 OFFSET $4"#,
+                    &roles,
                     options.sort_by,
                     options.limit,
-                    options.offset
+                    options.offset,
+                    kind,
+                    group_id,
+                    organization_id,
+                    include_deleted,
                 )
                 .fetch_all(db)
                 .await
@@ -125,37 +324,39 @@ impl UserRepo {
         }
     }
 
-    pub async fn count_for_roles(roles: &[Role], db: &mut PgConnection) -> sqlx::Result<i64> {
-        let roles = format!(
-            "'{}'",
-            roles
-                .iter()
-                .map(String::from)
-                .collect::<Vec<_>>()
-                .join("','")
-        );
+    pub async fn count_for_roles(
+        organization_id: Uuid,
+        roles: &[Role],
+        kind: Option<UserKind>,
+        group_id: Option<Uuid>,
+        include_deleted: bool,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<i64> {
+        let roles: Vec<String> = roles.iter().map(String::from).collect();
         let result = sqlx::query!(
-            r#"SELECT COUNT(*) FROM auth.user WHERE auth.user.role in ($1)"#,
-            roles
+            r#"SELECT COUNT(*) FROM auth.user
+            WHERE id IN (SELECT user_id FROM auth.organization_member WHERE organization_id = $4 AND role = ANY($1))
+                AND ($5 OR deleted_at IS NULL)
+                AND ($2::text IS NULL OR kind = $2)
+                AND ($3::uuid IS NULL OR id IN (
+                    WITH RECURSIVE subtree AS (
+                        SELECT id FROM auth.group WHERE id = $3 AND organization_id = $4
+                        UNION
+                        SELECT child.id FROM auth.group child JOIN subtree ON child.parent_id = subtree.id
+                    )
+                    SELECT user_id FROM auth.group_member WHERE group_id IN (SELECT id FROM subtree)
+                ))"#,
+            &roles,
+            kind.map(String::from),
+            group_id,
+            organization_id,
+            include_deleted,
         )
         .fetch_one(db)
         .await;
         result.map(|r| r.count.unwrap_or(0))
     }
 
-    pub async fn get_from_session_token(token: &str, db: &mut PgConnection) -> sqlx::Result<User> {
-        sqlx::query_as!(
-            User,
-            r#"SELECT auth.user.*
-            FROM auth.user 
-            LEFT JOIN auth.token ON auth.user.id = auth.token.user_id 
-                WHERE token = $1"#,
-            token
-        )
-        .fetch_one(db)
-        .await
-    }
-
     pub async fn update_status(
         id: Uuid,
         online_status: UserStatus,
@@ -214,33 +415,255 @@ impl UserRepo {
         .await
     }
 
-    pub async fn update_hash_salt(
+    /// Sets a new password, which fulfills a pending password change
+    pub async fn update_password_hash(
+        id: Uuid,
+        password_hash: &str,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<User> {
+        sqlx::query_as!(
+            User,
+            r#"UPDATE auth.user
+            SET password_hash = $1, password_changed_at = now(), password_change_required = false
+            WHERE id = $2
+            RETURNING *"#,
+            password_hash,
+            id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Stores a new hash of the unchanged password
+    pub async fn replace_password_hash(
+        id: Uuid,
+        password_hash: &str,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<User> {
+        sqlx::query_as!(
+            User,
+            r#"UPDATE auth.user SET password_hash = $1 WHERE id = $2 RETURNING *"#,
+            password_hash,
+            id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn update_owner(
+        id: Uuid,
+        owner_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<User> {
+        sqlx::query_as!(
+            User,
+            r#"UPDATE auth.user SET owner_id = $1 WHERE id = $2 RETURNING *"#,
+            owner_id,
+            id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn update_password_change_required(
+        id: Uuid,
+        password_change_required: bool,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<User> {
+        sqlx::query_as!(
+            User,
+            r#"UPDATE auth.user SET password_change_required = $1 WHERE id = $2 RETURNING *"#,
+            password_change_required,
+            id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn update_pending_email(
         id: Uuid,
-        hash: &[u8],
-        salt: &[u8],
+        pending_email: Option<&str>,
         db: &mut PgConnection,
     ) -> sqlx::Result<User> {
         sqlx::query_as!(
             User,
-            r#"UPDATE auth.user SET hash = $1, salt = $2 WHERE id = $3 RETURNING *"#,
-            hash,
-            salt,
+            r#"UPDATE auth.user SET pending_email = $1 WHERE id = $2 RETURNING *"#,
+            pending_email,
             id,
         )
         .fetch_one(db)
         .await
     }
 
+    /// Marks the current email as verified and keeps a pending email
+    pub async fn mark_email_verified(id: Uuid, db: &mut PgConnection) -> sqlx::Result<User> {
+        sqlx::query_as!(
+            User,
+            r#"UPDATE auth.user SET email_verified_at = now() WHERE id = $1 RETURNING *"#,
+            id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Marks the email as verified. A pending email replaces the current one.
+    pub async fn verify_email(id: Uuid, db: &mut PgConnection) -> sqlx::Result<User> {
+        sqlx::query_as!(
+            User,
+            r#"UPDATE auth.user
+            SET email = coalesce(pending_email, email), pending_email = NULL, email_verified_at = now()
+            WHERE id = $1 RETURNING *"#,
+            id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Sets the TOTP secret of a pending enrolment, enables it with `enabled_at` or disables it
+    /// with `None` for both
+    pub async fn update_totp(
+        id: Uuid,
+        secret: Option<&[u8]>,
+        enabled_at: Option<DateTime<Utc>>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<User> {
+        sqlx::query_as!(
+            User,
+            r#"UPDATE auth.user SET totp_secret = $1, totp_enabled_at = $2 WHERE id = $3 RETURNING *"#,
+            secret,
+            enabled_at,
+            id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Records the time step of an accepted code. Returns `false` if a code of the same or a
+    /// later step was accepted before, so that every code can only be used once.
+    pub async fn use_totp_step(id: Uuid, step: i64, db: &mut PgConnection) -> sqlx::Result<bool> {
+        let result = sqlx::query!(
+            r#"UPDATE auth.user SET totp_last_step = $2
+            WHERE id = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)"#,
+            id,
+            step,
+        )
+        .execute(db)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Locks the row of the user until the transaction ends, so login attempts of the account are
+    /// checked and recorded one after another. Returns the current `locked_until`.
+    pub async fn lock_for_login(
+        id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Option<DateTime<Utc>>> {
+        sqlx::query_scalar!(
+            r#"SELECT locked_until FROM auth.user WHERE id = $1 FOR UPDATE"#,
+            id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn update_locked_until(
+        id: Uuid,
+        locked_until: Option<DateTime<Utc>>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<User> {
+        sqlx::query_as!(
+            User,
+            r#"UPDATE auth.user SET locked_until = $1 WHERE id = $2 RETURNING *"#,
+            locked_until,
+            id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn suspend(
+        id: Uuid,
+        suspended_until: Option<DateTime<Utc>>,
+        reason: Option<&str>,
+        current_user_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<User> {
+        sqlx::query_as!(
+            User,
+            r#"UPDATE auth.user
+            SET suspended_at = now(), suspended_until = $2, suspension_reason = $3, suspended_by = $4
+            WHERE id = $1
+            RETURNING *"#,
+            id,
+            suspended_until,
+            reason,
+            current_user_id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// The ids of the users who are suspended now with the end of their suspension
+    pub async fn list_suspended(
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Vec<(Uuid, Option<DateTime<Utc>>)>> {
+        let rows = sqlx::query!(
+            r#"SELECT id, suspended_until FROM auth.user
+            WHERE suspended_at IS NOT NULL AND (suspended_until IS NULL OR suspended_until > now())"#
+        )
+        .fetch_all(db)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.id, row.suspended_until))
+            .collect())
+    }
+
+    /// The users who are deleted and those who were purged after `purged_after`. The purge keeps
+    /// their id in the activity only.
+    pub async fn list_deleted(
+        purged_after: DateTime<Utc>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Vec<Uuid>> {
+        let rows = sqlx::query!(
+            r#"SELECT id AS "id!" FROM auth.user WHERE deleted_at IS NOT NULL
+            UNION
+            SELECT item_id::uuid FROM activity
+            WHERE action = 'hard_delete' AND table_name = $1 AND action_at > $2"#,
+            USER_TABLE_NAME,
+            purged_after,
+        )
+        .fetch_all(db)
+        .await?;
+        Ok(rows.into_iter().map(|row| row.id).collect())
+    }
+
+    pub async fn unsuspend(id: Uuid, db: &mut PgConnection) -> sqlx::Result<User> {
+        sqlx::query_as!(
+            User,
+            r#"UPDATE auth.user
+            SET suspended_at = NULL, suspended_until = NULL, suspension_reason = NULL, suspended_by = NULL
+            WHERE id = $1
+            RETURNING *"#,
+            id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// The role and tags are changed in the organization, the rest of the user everywhere
     pub async fn update_one(
         id: Uuid,
         data: UserUpdateInput,
+        organization_id: Uuid,
         db: &mut PgConnection,
     ) -> sqlx::Result<User> {
         let mut tx = db.begin().await?;
         let selected = sqlx::query_as!(User, r#"SELECT * from auth.user WHERE id = $1"#, id)
             .fetch_one(&mut *tx)
             .await?;
-        let tags = TagRepo::create_missing(data.tags, selected.id, &mut tx).await?;
+        let tags =
+            TagRepo::create_missing(data.tags, organization_id, selected.id, &mut tx).await?;
         let result = sqlx::query_as!(
             User,
             r#"UPDATE auth.user 
@@ -252,9 +675,8 @@ impl UserRepo {
                 location = $5,
                 description = $6,
                 language = $7,
-                role = $8,
-                theme = $9
-            WHERE id = $10 RETURNING *"#,
+                theme = $8
+            WHERE id = $9 RETURNING *"#,
             data.email.unwrap_or(selected.email),
             data.first_name.or(selected.first_name),
             data.last_name.or(selected.last_name),
@@ -262,15 +684,18 @@ impl UserRepo {
             data.location.or(selected.location),
             data.description.or(selected.description),
             String::from(data.language.unwrap_or(selected.language)),
-            String::from(data.role.unwrap_or(selected.role)),
             String::from(data.theme.unwrap_or(selected.theme)),
             id,
         )
         .fetch_one(&mut *tx)
         .await?;
+        if let Some(role) = &data.role {
+            OrganizationRepo::upsert_member(organization_id, id, role, &mut tx).await?;
+        }
         UserRepo::update_tags(
             selected.id,
             tags.into_iter().map(|t| t.id).collect(),
+            organization_id,
             &mut tx,
         )
         .await?;
@@ -278,15 +703,22 @@ impl UserRepo {
         Ok(result)
     }
 
+    /// Replaces the tags of the user in the organization, those of other organizations are kept
     pub async fn update_tags(
         id: Uuid,
         tag_ids: Vec<i32>,
+        organization_id: Uuid,
         db: &mut PgConnection,
     ) -> sqlx::Result<()> {
         let mut tx = db.begin().await.unwrap();
-        sqlx::query!(r#"DELETE FROM auth.user_to_tag WHERE user_id = $1"#, id)
-            .execute(&mut *tx)
-            .await?;
+        sqlx::query!(
+            r#"DELETE FROM auth.user_to_tag
+            WHERE user_id = $1 AND tag_id IN (SELECT id FROM tag WHERE organization_id = $2)"#,
+            id,
+            organization_id,
+        )
+        .execute(&mut *tx)
+        .await?;
         if !tag_ids.is_empty() {
             for tag_id in tag_ids {
                 sqlx::query!(
@@ -302,13 +734,14 @@ impl UserRepo {
         tx.commit().await
     }
 
+    /// Marks the user as deleted, which keeps the data until the user is restored or purged
     pub async fn delete_one(
         id: Uuid,
         current_user_id: Uuid,
         db: &mut PgConnection,
     ) -> sqlx::Result<sqlx::postgres::PgQueryResult> {
         sqlx::query!(
-            r#"UPDATE auth.user SET deleted_at = $1, deleted_by = $2 WHERE id = $3"#,
+            r#"UPDATE auth.user SET deleted_at = $1, deleted_by = $2 WHERE id = $3 AND deleted_at IS NULL"#,
             Utc::now(),
             current_user_id,
             id
@@ -317,11 +750,64 @@ impl UserRepo {
         .await
     }
 
-    pub async fn search(term: String, db: &mut PgConnection) -> sqlx::Result<Vec<User>> {
+    pub async fn restore_one(id: Uuid, db: &mut PgConnection) -> sqlx::Result<User> {
+        sqlx::query_as!(
+            User,
+            r#"UPDATE auth.user SET deleted_at = NULL, deleted_by = NULL
+            WHERE id = $1 AND deleted_at IS NOT NULL
+            RETURNING *"#,
+            id,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Permanently deletes a deleted user. Their sessions are deleted here, their tokens, tag
+    /// links, credentials and memberships by the database.
+    pub async fn purge_one(id: Uuid, db: &mut PgConnection) -> sqlx::Result<User> {
+        let mut tx = db.begin().await?;
+        sqlx::query!(
+            r#"DELETE FROM auth.session WHERE token_id IN (SELECT id FROM auth.token WHERE user_id = $1)"#,
+            id,
+        )
+        .execute(&mut *tx)
+        .await?;
+        let purged = sqlx::query_as!(
+            User,
+            r#"DELETE FROM auth.user WHERE id = $1 AND deleted_at IS NOT NULL RETURNING *"#,
+            id,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(purged)
+    }
+
+    /// Whether the user owns service accounts, which have to be deleted or handed over before
+    /// the user can be purged
+    pub async fn owns_service_accounts(id: Uuid, db: &mut PgConnection) -> sqlx::Result<bool> {
+        let result = sqlx::query!(
+            r#"SELECT EXISTS (SELECT 1 FROM auth.user WHERE owner_id = $1) AS "owns!""#,
+            id,
+        )
+        .fetch_one(db)
+        .await?;
+        Ok(result.owns)
+    }
+
+    pub async fn search(
+        term: String,
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Vec<User>> {
         sqlx::query_as!(
             User,
-            r#"SELECT * FROM auth.user WHERE email ilike $1 OR first_name ilike $1 OR last_name ilike $1"#,
-            format!("%{term}%")
+            r#"SELECT * FROM auth.user
+            WHERE (email ilike $1 OR first_name ilike $1 OR last_name ilike $1)
+                AND deleted_at IS NULL
+                AND id IN (SELECT user_id FROM auth.organization_member WHERE organization_id = $2)"#,
+            format!("%{term}%"),
+            organization_id,
         ).fetch_all(db).await
     }
 }
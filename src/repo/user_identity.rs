@@ -0,0 +1,43 @@
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::model::auth::UserIdentity;
+
+#[derive(Clone)]
+pub struct UserIdentityRepo {}
+
+impl UserIdentityRepo {
+    pub async fn get_by_subject(
+        provider: &str,
+        subject: &str,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<UserIdentity> {
+        sqlx::query_as!(
+            UserIdentity,
+            r#"SELECT * FROM auth.user_identity WHERE provider = $1 AND subject = $2"#,
+            provider,
+            subject,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn create_one(
+        user_id: Uuid,
+        provider: &str,
+        subject: &str,
+        email: Option<&str>,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<UserIdentity> {
+        sqlx::query_as!(
+            UserIdentity,
+            r#"INSERT INTO auth.user_identity (user_id, provider, subject, email) VALUES ($1, $2, $3, $4) RETURNING *"#,
+            user_id,
+            provider,
+            subject,
+            email,
+        )
+        .fetch_one(db)
+        .await
+    }
+}
@@ -0,0 +1,78 @@
+use chrono::Utc;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::model::auth::{WebauthnCeremony, WebauthnCeremonyKind};
+
+#[derive(Clone)]
+pub struct WebauthnCeremonyRepo {}
+
+impl WebauthnCeremonyRepo {
+    /// Stores the state of a ceremony, which has to be finished within 5 minutes
+    pub async fn create_one(
+        user_id: Uuid,
+        kind: WebauthnCeremonyKind,
+        state: serde_json::Value,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<WebauthnCeremony> {
+        sqlx::query_as!(
+            WebauthnCeremony,
+            r#"INSERT INTO auth.webauthn_ceremony (id, user_id, kind, state, expiration)
+            VALUES ($1, $2, $3, $4, $5) RETURNING *"#,
+            Uuid::new_v4(),
+            user_id,
+            String::from(kind),
+            state,
+            Utc::now() + chrono::Duration::minutes(5),
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Like `take_valid`, but the ceremony can still be finished afterwards
+    pub async fn get_valid(
+        id: Uuid,
+        kind: WebauthnCeremonyKind,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<WebauthnCeremony> {
+        sqlx::query_as!(
+            WebauthnCeremony,
+            r#"SELECT * FROM auth.webauthn_ceremony
+            WHERE id = $1
+                AND kind = $2
+                AND expiration > now()"#,
+            id,
+            String::from(kind),
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Deletes and returns the ceremony if it is of the given kind and not expired, so every
+    /// ceremony can only be finished once
+    pub async fn take_valid(
+        id: Uuid,
+        kind: WebauthnCeremonyKind,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<WebauthnCeremony> {
+        sqlx::query_as!(
+            WebauthnCeremony,
+            r#"DELETE FROM auth.webauthn_ceremony
+            WHERE id = $1
+                AND kind = $2
+                AND expiration > now()
+            RETURNING *"#,
+            id,
+            String::from(kind),
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn delete_expired(db: &mut PgConnection) -> sqlx::Result<()> {
+        sqlx::query!(r#"DELETE FROM auth.webauthn_ceremony WHERE expiration <= now()"#)
+            .execute(db)
+            .await?;
+        Ok(())
+    }
+}
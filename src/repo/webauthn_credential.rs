@@ -0,0 +1,85 @@
+use sqlx::{types::Json, PgConnection};
+use uuid::Uuid;
+use webauthn_rs::prelude::Passkey;
+
+use crate::model::auth::WebauthnCredential;
+
+#[derive(Clone)]
+pub struct WebauthnCredentialRepo {}
+
+impl WebauthnCredentialRepo {
+    pub async fn create_one(
+        user_id: Uuid,
+        name: &str,
+        passkey: &Passkey,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<WebauthnCredential> {
+        sqlx::query_as!(
+            WebauthnCredential,
+            r#"INSERT INTO auth.webauthn_credential (user_id, name, credential_id, passkey)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, name, credential_id, passkey as "passkey: Json<Passkey>", last_used_at, created_at, updated_at"#,
+            user_id,
+            name,
+            passkey.cred_id().as_slice(),
+            Json(passkey) as _,
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn list_for_user(
+        user_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<Vec<WebauthnCredential>> {
+        sqlx::query_as!(
+            WebauthnCredential,
+            r#"SELECT id, user_id, name, credential_id, passkey as "passkey: Json<Passkey>", last_used_at, created_at, updated_at
+            FROM auth.webauthn_credential WHERE user_id = $1 ORDER BY created_at"#,
+            user_id,
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    pub async fn count_for_user(user_id: Uuid, db: &mut PgConnection) -> sqlx::Result<i64> {
+        let result = sqlx::query!(
+            r#"SELECT COUNT(*) FROM auth.webauthn_credential WHERE user_id = $1"#,
+            user_id
+        )
+        .fetch_one(db)
+        .await;
+        result.map(|r| r.count.unwrap_or(0))
+    }
+
+    /// Stores the updated counter of the passkey after it was used for an authentication
+    pub async fn update_after_use(
+        credential_id: &[u8],
+        passkey: &Passkey,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<()> {
+        sqlx::query!(
+            r#"UPDATE auth.webauthn_credential SET passkey = $2, last_used_at = now() WHERE credential_id = $1"#,
+            credential_id,
+            Json(passkey) as _,
+        )
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_by_id_for_user(
+        id: i32,
+        user_id: Uuid,
+        db: &mut PgConnection,
+    ) -> sqlx::Result<i32> {
+        Ok(sqlx::query!(
+            r#"DELETE FROM auth.webauthn_credential WHERE id = $1 AND user_id = $2 RETURNING id"#,
+            id,
+            user_id,
+        )
+        .fetch_one(db)
+        .await?
+        .id)
+    }
+}
@@ -5,7 +5,11 @@ use axum::{
 };
 
 use crate::{
-    utils::middlewares::{auth_middleware, SetupFinishedLayer},
+    model::auth::Permission,
+    utils::middlewares::{
+        auth_middleware, csrf_middleware, default_organization_only, human_users_only,
+        no_impersonation, PermissionLayer, SetupFinishedLayer,
+    },
     AppState,
 };
 
@@ -13,30 +17,328 @@ pub mod api;
 pub mod files;
 
 pub fn create_router(state: AppState) -> Router<AppState> {
+    // Roles, the auth policy and the signing keys are shared by all organizations
+    let default_organization_only =
+        middleware::from_fn_with_state(state.clone(), default_organization_only);
+    // Admins who impersonate a user must not take over their credentials
+    let no_impersonation = middleware::from_fn(no_impersonation);
     let authenticated_router = Router::new()
-        .route("/users", get(api::users::get).post(api::users::post))
+        .route(
+            "/users",
+            get(api::users::get).route_layer(PermissionLayer::new(Permission::UsersRead)),
+        )
+        .route(
+            "/users",
+            post(api::users::post).route_layer(PermissionLayer::new(Permission::UsersWrite)),
+        )
         .route(
             "/users/:id",
-            get(api::users::get_by_id)
-                .put(api::users::put)
-                .delete(api::users::delete),
+            get(api::users::get_by_id).route_layer(PermissionLayer::new(Permission::UsersRead)),
+        )
+        .route(
+            "/users/:id",
+            put(api::users::put).route_layer(PermissionLayer::new(Permission::ProfileWrite)),
+        )
+        .route(
+            "/users/:id",
+            delete(api::users::delete).route_layer(PermissionLayer::new(Permission::UsersWrite)),
+        )
+        .route(
+            "/users/:id/restore",
+            post(api::users::restore).route_layer(PermissionLayer::new(Permission::UsersWrite)),
+        )
+        .route(
+            "/users/:id/purge",
+            post(api::users::purge).route_layer(PermissionLayer::new(Permission::UsersPurge)),
+        )
+        // Accounts are shared by all organizations, so only the default one can unlock, suspend
+        // them or require a password change
+        .route(
+            "/users/:id/unlock",
+            post(api::users::unlock)
+                .route_layer(PermissionLayer::new(Permission::UsersWrite))
+                .route_layer(default_organization_only.clone()),
+        )
+        .route(
+            "/users/:id/suspend",
+            post(api::users::suspend)
+                .route_layer(PermissionLayer::new(Permission::UsersWrite))
+                .route_layer(default_organization_only.clone()),
+        )
+        .route(
+            "/users/:id/unsuspend",
+            post(api::users::unsuspend)
+                .route_layer(PermissionLayer::new(Permission::UsersWrite))
+                .route_layer(default_organization_only.clone()),
+        )
+        .route(
+            "/users/:id/require_password_change",
+            post(api::users::require_password_change)
+                .route_layer(PermissionLayer::new(Permission::UsersWrite))
+                .route_layer(default_organization_only.clone()),
+        )
+        .route(
+            "/users/:id/impersonate",
+            post(api::impersonation::start)
+                .route_layer(PermissionLayer::new(Permission::UsersImpersonate)),
+        )
+        .route(
+            "/users/:id/password",
+            put(api::users::update_password)
+                .route_layer(
+                    PermissionLayer::new(Permission::ProfileWrite).allow_password_change_required(),
+                )
+                .route_layer(no_impersonation.clone()),
         )
-        .route("/users/:id/password", put(api::users::update_password))
         .route(
             "/users/:id/avatar",
-            post(api::users::update_avatar).delete(api::users::delete_avatar),
+            post(api::users::update_avatar)
+                .delete(api::users::delete_avatar)
+                .route_layer(PermissionLayer::new(Permission::ProfileWrite)),
+        )
+        .route(
+            "/users/search",
+            get(api::users::search).route_layer(PermissionLayer::new(Permission::UsersRead)),
+        )
+        .route(
+            "/activity",
+            get(api::activity::get).route_layer(PermissionLayer::new(Permission::ActivityRead)),
         )
-        .route("/users/search", get(api::users::search))
-        .route("/activity", get(api::activity::get))
         .route(
             "/settings/preferences",
-            post(api::settings::post_preferences),
+            post(api::settings::post_preferences)
+                .route_layer(PermissionLayer::new(Permission::ProfileWrite)),
+        )
+        // The auth policy applies to the login of accounts that all organizations share
+        .route(
+            "/settings/auth_policy",
+            get(api::settings::get_auth_policy)
+                .route_layer(PermissionLayer::new(Permission::UsersWrite)),
+        )
+        .route(
+            "/settings/auth_policy",
+            put(api::settings::put_auth_policy)
+                .route_layer(PermissionLayer::new(Permission::UsersWrite))
+                .route_layer(default_organization_only.clone()),
+        )
+        .route(
+            "/tags",
+            get(api::tags::get).route_layer(PermissionLayer::new(Permission::TagsRead)),
+        )
+        .route(
+            "/roles",
+            get(api::roles::list).route_layer(PermissionLayer::new(Permission::UsersRead)),
+        )
+        .route(
+            "/roles",
+            post(api::roles::post)
+                .route_layer(PermissionLayer::new(Permission::UsersWrite))
+                .route_layer(default_organization_only.clone()),
+        )
+        .route(
+            "/roles/:name",
+            get(api::roles::get_by_name).route_layer(PermissionLayer::new(Permission::UsersRead)),
+        )
+        .route(
+            "/roles/:name",
+            put(api::roles::put)
+                .delete(api::roles::delete)
+                .route_layer(PermissionLayer::new(Permission::UsersWrite))
+                .route_layer(default_organization_only.clone()),
+        )
+        // Every user can see and switch between the organizations they are a member of
+        .route(
+            "/organizations",
+            get(api::organizations::list).route_layer(PermissionLayer::authenticated()),
+        )
+        .route(
+            "/organizations",
+            post(api::organizations::post)
+                .route_layer(PermissionLayer::new(Permission::UsersWrite))
+                .route_layer(default_organization_only.clone()),
+        )
+        .route(
+            "/organizations/:id",
+            put(api::organizations::put).route_layer(PermissionLayer::new(Permission::UsersWrite)),
+        )
+        .route(
+            "/organizations/:id/activate",
+            post(api::organizations::activate).route_layer(PermissionLayer::authenticated()),
+        )
+        .route(
+            "/groups",
+            get(api::groups::list).route_layer(PermissionLayer::new(Permission::UsersRead)),
+        )
+        .route(
+            "/groups",
+            post(api::groups::post).route_layer(PermissionLayer::new(Permission::UsersWrite)),
+        )
+        .route(
+            "/groups/:id",
+            get(api::groups::get_by_id).route_layer(PermissionLayer::new(Permission::UsersRead)),
+        )
+        .route(
+            "/groups/:id",
+            put(api::groups::put)
+                .delete(api::groups::delete)
+                .route_layer(PermissionLayer::new(Permission::UsersWrite)),
+        )
+        .route(
+            "/groups/:id/members",
+            get(api::groups::list_members).route_layer(PermissionLayer::new(Permission::UsersRead)),
+        )
+        // Group admins can manage members without users:write, which the service checks
+        .route(
+            "/groups/:id/members/:user_id",
+            put(api::groups::put_member)
+                .delete(api::groups::delete_member)
+                .route_layer(PermissionLayer::new(Permission::UsersRead)),
+        )
+        // The frontend needs the permissions to show what a user can do, even before a pending
+        // password change
+        .route(
+            "/auth/permissions",
+            get(api::roles::effective_permissions)
+                .route_layer(PermissionLayer::authenticated().allow_password_change_required()),
+        )
+        .route(
+            "/auth/totp/enroll",
+            post(api::two_factor::enroll_totp)
+                .route_layer(PermissionLayer::new(Permission::ProfileWrite))
+                .route_layer(no_impersonation.clone()),
+        )
+        .route(
+            "/auth/totp/confirm",
+            post(api::two_factor::confirm_totp)
+                .route_layer(PermissionLayer::new(Permission::ProfileWrite))
+                .route_layer(no_impersonation.clone()),
+        )
+        .route(
+            "/auth/totp/disable",
+            post(api::two_factor::disable_totp)
+                .route_layer(PermissionLayer::new(Permission::ProfileWrite))
+                .route_layer(no_impersonation.clone()),
+        )
+        .route(
+            "/auth/totp/recovery_codes",
+            post(api::two_factor::regenerate_recovery_codes)
+                .route_layer(PermissionLayer::new(Permission::ProfileWrite))
+                .route_layer(no_impersonation.clone()),
+        )
+        .route(
+            "/auth/webauthn/register/start",
+            post(api::webauthn::start_registration)
+                .route_layer(PermissionLayer::new(Permission::ProfileWrite))
+                .route_layer(no_impersonation.clone()),
+        )
+        .route(
+            "/auth/webauthn/register/finish",
+            post(api::webauthn::finish_registration)
+                .route_layer(PermissionLayer::new(Permission::ProfileWrite))
+                .route_layer(no_impersonation.clone()),
+        )
+        .route(
+            "/auth/webauthn/credentials",
+            get(api::webauthn::list_credentials)
+                .route_layer(PermissionLayer::new(Permission::ProfileWrite)),
+        )
+        .route(
+            "/auth/webauthn/credentials/:credential_id",
+            delete(api::webauthn::delete_credential)
+                .route_layer(PermissionLayer::new(Permission::ProfileWrite))
+                .route_layer(no_impersonation.clone()),
+        )
+        .route(
+            "/tokens",
+            post(api::tokens::post)
+                .get(api::tokens::get)
+                .route_layer(PermissionLayer::new(Permission::TokensManage))
+                .route_layer(no_impersonation.clone()),
+        )
+        .route(
+            "/tokens/:token_id",
+            delete(api::tokens::delete_by_id)
+                .route_layer(PermissionLayer::new(Permission::TokensManage))
+                .route_layer(no_impersonation.clone()),
+        )
+        .route(
+            "/sessions",
+            get(api::sessions::list).route_layer(PermissionLayer::new(Permission::SessionsManage)),
+        )
+        .route(
+            "/sessions/:session_id",
+            delete(api::sessions::delete_by_id)
+                .route_layer(PermissionLayer::new(Permission::SessionsManage)),
+        )
+        .route(
+            "/sessions/revoke_others",
+            post(api::sessions::revoke_others)
+                .route_layer(PermissionLayer::new(Permission::SessionsManage))
+                .route_layer(no_impersonation),
+        )
+        // The admin behind an impersonation has to be able to end it in any case
+        .route(
+            "/auth/impersonation/stop",
+            post(api::impersonation::stop)
+                .route_layer(PermissionLayer::authenticated().allow_password_change_required()),
+        )
+        .route(
+            "/invitations",
+            get(api::invitations::list)
+                .post(api::invitations::post)
+                .route_layer(PermissionLayer::new(Permission::UsersWrite)),
+        )
+        .route(
+            "/invitations/:id",
+            delete(api::invitations::delete_by_id)
+                .route_layer(PermissionLayer::new(Permission::UsersWrite)),
+        )
+        .route(
+            "/invitations/:id/resend",
+            post(api::invitations::resend)
+                .route_layer(PermissionLayer::new(Permission::UsersWrite)),
+        )
+        .route(
+            "/service_accounts",
+            post(api::service_accounts::post)
+                .route_layer(PermissionLayer::new(Permission::UsersWrite)),
+        )
+        .route(
+            "/service_accounts/:id/owner",
+            put(api::service_accounts::put_owner)
+                .route_layer(PermissionLayer::new(Permission::UsersWrite)),
+        )
+        .route(
+            "/service_accounts/:id/tokens",
+            get(api::service_accounts::list_tokens)
+                .post(api::service_accounts::post_token)
+                .route_layer(PermissionLayer::new(Permission::UsersWrite)),
+        )
+        .route(
+            "/service_accounts/:id/tokens/:token_id",
+            delete(api::service_accounts::delete_token)
+                .route_layer(PermissionLayer::new(Permission::UsersWrite)),
+        )
+        .route(
+            "/auth/jwt/rotate_key",
+            post(api::jwt::rotate_key)
+                .route_layer(PermissionLayer::new(Permission::UsersWrite))
+                .route_layer(default_organization_only.clone()),
+        )
+        // Sessions belong to the account, which all organizations share
+        .route(
+            "/users/:id/sessions",
+            get(api::sessions::list_for_user)
+                .delete(api::sessions::revoke_all_for_user)
+                .route_layer(PermissionLayer::new(Permission::UsersWrite))
+                .route_layer(default_organization_only.clone()),
+        )
+        .route(
+            "/users/:id/sessions/:session_id",
+            delete(api::sessions::delete_by_id_for_user)
+                .route_layer(PermissionLayer::new(Permission::UsersWrite))
+                .route_layer(default_organization_only),
         )
-        .route("/tags", get(api::tags::get))
-        .route("/tokens", post(api::tokens::post).get(api::tokens::get))
-        .route("/tokens/:token_id", delete(api::tokens::delete_by_id))
-        .route("/sessions", get(api::sessions::list))
-        .route("/sessions/:session_id", delete(api::sessions::delete_by_id))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             auth_middleware,
@@ -46,6 +348,37 @@ pub fn create_router(state: AppState) -> Router<AppState> {
         .route("/auth/check", get(api::auth::check))
         .route("/auth/logout", post(api::auth::logout))
         .route("/auth/login", post(api::auth::login))
+        .route(
+            "/auth/login/second_factor",
+            post(api::auth::login_second_factor),
+        )
+        .route(
+            "/auth/magic_link/request",
+            post(api::auth::request_magic_link),
+        )
+        .route("/auth/magic_link/login", post(api::auth::login_magic_link))
+        .route("/auth/token", post(api::jwt::token))
+        .route("/auth/token/revoke", post(api::jwt::revoke))
+        .route("/auth/jwks.json", get(api::jwt::jwks))
+        .route("/auth/oidc/providers", get(api::oidc::list_providers))
+        .route("/auth/oidc/:provider/login", get(api::oidc::login))
+        .route("/auth/oidc/:provider/callback", get(api::oidc::callback))
+        .route(
+            "/auth/webauthn/login/start",
+            post(api::webauthn::start_login),
+        )
+        .route(
+            "/auth/webauthn/login/finish",
+            post(api::webauthn::finish_login),
+        )
+        .route(
+            "/auth/webauthn/second_factor/start",
+            post(api::webauthn::start_second_factor),
+        )
+        .route(
+            "/auth/webauthn/second_factor/finish",
+            post(api::webauthn::finish_second_factor),
+        )
         .route(
             "/password_reset/request",
             post(api::password_reset::request),
@@ -55,6 +388,19 @@ pub fn create_router(state: AppState) -> Router<AppState> {
             get(api::password_reset::token_check),
         )
         .route("/password_reset/reset", post(api::password_reset::reset))
+        .route(
+            "/email_verification/request",
+            post(api::email_verification::request),
+        )
+        .route(
+            "/email_verification/confirm",
+            post(api::email_verification::confirm),
+        )
+        .route(
+            "/invitations/token_check",
+            get(api::invitations::token_check),
+        )
+        .route("/invitations/accept", post(api::invitations::accept))
         .layer(SetupFinishedLayer::with_state(state.clone()).finished(true));
     Router::new()
         .nest(
@@ -66,13 +412,23 @@ pub fn create_router(state: AppState) -> Router<AppState> {
                         .route("/is_setup_finished", get(api::setup::is_setup_finished))
                         .route("/create_admin_user", post(api::setup::create_admin_user)),
                 )
-                .nest("", setup_finished_router),
+                .nest("", setup_finished_router)
+                .layer(middleware::from_fn(csrf_middleware)),
         )
         .nest(
             "/api/ws",
             Router::new()
-                .route("/user/status", get(api::ws::user_status))
-                .route("/user/me/status", get(api::ws::user_me_status))
+                .route(
+                    "/user/status",
+                    get(api::ws::user_status)
+                        .route_layer(PermissionLayer::new(Permission::UsersRead)),
+                )
+                .route(
+                    "/user/me/status",
+                    get(api::ws::user_me_status)
+                        .route_layer(PermissionLayer::new(Permission::ProfileWrite)),
+                )
+                .layer(middleware::from_fn(human_users_only))
                 .layer(SetupFinishedLayer::with_state(state.clone()).finished(true))
                 .layer(middleware::from_fn_with_state(
                     state.clone(),
@@ -82,7 +438,10 @@ pub fn create_router(state: AppState) -> Router<AppState> {
         .nest(
             "/files",
             Router::new()
-                .route("/avatar/:user_id", get(files::avatar))
+                .route(
+                    "/avatar/:user_id",
+                    get(files::avatar).route_layer(PermissionLayer::new(Permission::UsersRead)),
+                )
                 .layer(SetupFinishedLayer::with_state(state.clone()).finished(true))
                 .layer(middleware::from_fn_with_state(
                     state.clone(),
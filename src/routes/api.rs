@@ -1,10 +1,21 @@
 pub mod activity;
 pub mod auth;
+pub mod email_verification;
+pub mod groups;
+pub mod impersonation;
+pub mod invitations;
+pub mod jwt;
+pub mod oidc;
+pub mod organizations;
 pub mod password_reset;
+pub mod roles;
+pub mod service_accounts;
 pub mod sessions;
 pub mod settings;
 pub mod setup;
 pub mod tags;
 pub mod tokens;
+pub mod two_factor;
 pub mod users;
+pub mod webauthn;
 pub mod ws;
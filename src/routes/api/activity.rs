@@ -2,16 +2,20 @@ use axum::{
     extract::{Query, State},
     http::StatusCode,
     response::{IntoResponse, Response},
-    Json,
+    Extension, Json,
 };
 use macros::JsonErrorResponse;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
-    model::Activity,
+    model::{auth::Permission, Activity},
     repo::{activity::ActivityRepo, DatabasePagination},
-    utils::{error::ErrorResponse, response::Metadata},
+    utils::{
+        auth::{AuthContext, PermissionError},
+        error::ErrorResponse,
+        response::Metadata,
+    },
     AppState,
 };
 
@@ -30,10 +34,19 @@ pub struct GetActivityResponse {
 pub async fn get(
     Query(query): Query<GetActivityQuery>,
     State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
 ) -> Result<Response, Response> {
+    if query.user_id != auth.user.id {
+        auth.require_permission(Permission::ActivityReadAll)
+            .map_err(|e| ActivityError::from(e).into_response())?;
+    }
+    // Activity outside of the organization, like logins, is only shown to the user themselves
+    let include_unscoped = query.user_id == auth.user.id;
     let mut conn = state.db.acquire().await.unwrap();
     let activity = ActivityRepo::list_all_for_user_id(
         query.user_id,
+        auth.user.organization_id,
+        include_unscoped,
         DatabasePagination {
             limit: query.limit,
             offset: (query.page - 1) * query.limit,
@@ -42,9 +55,14 @@ pub async fn get(
     )
     .await
     .map_err(|_| ActivityError::DatabaseError.into_response())?;
-    let count = ActivityRepo::count_all_for_user_id(query.user_id, &mut conn)
-        .await
-        .map_err(|_| ActivityError::DatabaseError.into_response())?;
+    let count = ActivityRepo::count_all_for_user_id(
+        query.user_id,
+        auth.user.organization_id,
+        include_unscoped,
+        &mut conn,
+    )
+    .await
+    .map_err(|_| ActivityError::DatabaseError.into_response())?;
 
     Ok(Json(GetActivityResponse {
         activity,
@@ -61,4 +79,8 @@ pub enum ActivityError {
     #[error("Database Error")]
     #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
     DatabaseError,
+
+    #[error(transparent)]
+    #[status_code(StatusCode::FORBIDDEN)]
+    Permission(#[from] PermissionError),
 }
@@ -2,33 +2,56 @@ use std::net::SocketAddr;
 
 use axum::{
     extract::{ConnectInfo, State},
-    http::StatusCode,
-    response::IntoResponse,
+    http::{header::RETRY_AFTER, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
-use axum_extra::{
-    extract::{cookie::Cookie, CookieJar},
-    headers::UserAgent,
-    TypedHeader,
-};
+use axum_extra::{extract::CookieJar, headers::UserAgent, TypedHeader};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use time::Duration;
+use sqlx::PgConnection;
 
 use crate::{
-    config::SESSION_COOKIE,
-    model::user::User,
+    config::IMPERSONATOR_SESSION_COOKIE,
+    model::{
+        auth::{SecondFactor, SessionWithToken, TokenType},
+        user::User,
+    },
     repo::activity::{ActivityEntry, ActivityRepo},
-    service::auth::AuthService,
-    utils::{extractors::Session, response::Metadata},
+    service::{
+        auth::{AuthError, AuthService, LoginOutcome},
+        magic_link::MagicLinkService,
+    },
+    utils::{
+        auth::{AuthContext, IMPERSONATOR_ID},
+        cookie::{add_csrf_cookie, add_session_cookies, remove_session_cookies, CookieConfig},
+        extractors::Session,
+        response::Metadata,
+    },
     AppState,
 };
 
-pub async fn check(Session(user): Session<User>) -> impl IntoResponse {
+/// Also renews the CSRF cookie of the session, e.g. for sessions that started before it existed
+pub async fn check(
+    Session(auth): Session<AuthContext>,
+    Session(cookie): Session<String>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    let impersonator_id = auth.as_ref().and_then(|auth| auth.impersonator_id);
+    let jar = match (&auth, cookie) {
+        (Some(auth), Some(cookie)) if matches!(auth.token.token_type, TokenType::Session) => {
+            add_csrf_cookie(jar, &cookie)
+        }
+        _ => jar,
+    };
     (
         StatusCode::OK,
+        jar,
         Json(json!({
-            "authenticated": user.is_some(),
+            "authenticated": auth.is_some(),
+            "impersonating": impersonator_id.is_some(),
+            "impersonatorId": impersonator_id,
+            "passwordChangeRequired": auth.as_ref().is_some_and(|auth| auth.password_change_required),
             "_metadata": Metadata::default(),
         })),
     )
@@ -61,33 +84,169 @@ pub async fn login(
         conn,
     )
     .await
+    {
+        Ok(LoginOutcome::Session(user, session_with_token)) => {
+            session_response(jar, user, session_with_token, addr, user_agent, conn).await
+        }
+        Ok(LoginOutcome::SecondFactorRequired(challenge, methods)) => Json(json!({
+            "success": false,
+            "secondFactorRequired": true,
+            "challenge": challenge.plaintext,
+            "methods": methods,
+            "_metadata": Metadata::default(),
+        }))
+        .into_response(),
+        Err(e) => auth_error_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SecondFactorLoginPayload {
+    /// The challenge returned by the login endpoint
+    pub challenge: String,
+    #[serde(flatten)]
+    pub second_factor: SecondFactor,
+}
+pub async fn login_second_factor(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(payload): Json<SecondFactorLoginPayload>,
+) -> impl IntoResponse {
+    let conn = &mut state.db.acquire().await.unwrap();
+    match AuthService::login_second_factor(
+        &payload.challenge,
+        &payload.second_factor,
+        Some(addr.ip().into()),
+        user_agent.to_string(),
+        conn,
+    )
+    .await
     {
         Ok((user, session_with_token)) => {
-            let cookie = Cookie::build((SESSION_COOKIE, session_with_token.token.token))
-                .path("/")
-                .http_only(true)
-                .max_age(Duration::days(30));
-            let cookies = jar.add(cookie);
-            let _ = ActivityRepo::create_one(
-                ActivityEntry::Login {
-                    ip_address: Some(addr.ip().into()),
-                    user_agent: Some(user_agent.to_string()),
-                    action_by_id: user.id,
-                },
-                conn,
-            )
-            .await;
-            (
-                cookies,
-                Json(AuthResponse {
-                    success: true,
-                    _metadata: Default::default(),
-                }),
-            )
-                .into_response()
+            if let SecondFactor::RecoveryCode { .. } = payload.second_factor {
+                let _ = ActivityRepo::create_one(
+                    ActivityEntry::RecoveryCodeUse {
+                        ip_address: Some(addr.ip().into()),
+                        user_agent: Some(user_agent.to_string()),
+                        action_by_id: user.id,
+                    },
+                    conn,
+                )
+                .await;
+            }
+            session_response(jar, user, session_with_token, addr, user_agent, conn).await
+        }
+        Err(e) => auth_error_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MagicLinkRequestPayload {
+    pub email: String,
+}
+pub async fn request_magic_link(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(payload): Json<MagicLinkRequestPayload>,
+) -> impl IntoResponse {
+    let conn = &mut state.db.acquire().await.unwrap();
+    match MagicLinkService::request(
+        payload.email,
+        Some(addr.ip().into()),
+        user_agent.as_str(),
+        conn,
+    )
+    .await
+    {
+        Ok(()) => Json(AuthResponse {
+            success: true,
+            _metadata: Default::default(),
+        })
+        .into_response(),
+        Err(e) => auth_error_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MagicLinkLoginPayload {
+    /// The token of the link sent by email
+    pub token: String,
+}
+pub async fn login_magic_link(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(payload): Json<MagicLinkLoginPayload>,
+) -> impl IntoResponse {
+    let conn = &mut state.db.acquire().await.unwrap();
+    match AuthService::login_magic_link(
+        &payload.token,
+        Some(addr.ip().into()),
+        user_agent.to_string(),
+        conn,
+    )
+    .await
+    {
+        Ok(LoginOutcome::Session(user, session_with_token)) => {
+            session_response(jar, user, session_with_token, addr, user_agent, conn).await
         }
-        Err(e) => e.into_response(),
+        Ok(LoginOutcome::SecondFactorRequired(challenge, methods)) => Json(json!({
+            "success": false,
+            "secondFactorRequired": true,
+            "challenge": challenge.plaintext,
+            "methods": methods,
+            "_metadata": Metadata::default(),
+        }))
+        .into_response(),
+        Err(e) => auth_error_response(e),
+    }
+}
+
+/// Tells throttled clients when they can try again
+pub(super) fn auth_error_response(error: AuthError) -> Response {
+    let retry_after = error.retry_after();
+    let mut response = error.into_response();
+    if let Some(seconds) = retry_after {
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(seconds));
     }
+    response
+}
+
+/// Sets the session cookie and records the login
+pub(super) async fn session_response(
+    jar: CookieJar,
+    user: User,
+    session_with_token: SessionWithToken,
+    addr: SocketAddr,
+    user_agent: UserAgent,
+    conn: &mut PgConnection,
+) -> Response {
+    let password_change_required = session_with_token.session.password_change_required;
+    let cookies = add_session_cookies(jar, session_with_token.token.plaintext);
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::Login {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: user.id,
+        },
+        conn,
+    )
+    .await;
+    (
+        cookies,
+        Json(json!({
+            "success": true,
+            "passwordChangeRequired": password_change_required,
+            "_metadata": Metadata::default(),
+        })),
+    )
+        .into_response()
 }
 
 pub async fn logout(
@@ -95,23 +254,32 @@ pub async fn logout(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     TypedHeader(user_agent): TypedHeader<UserAgent>,
     Session(cookie): Session<String>,
-    Session(user): Session<User>,
+    Session(auth): Session<AuthContext>,
     jar: CookieJar,
 ) -> impl IntoResponse {
     let conn = &mut state.db.acquire().await.unwrap();
-    let jar = jar.remove(Cookie::build(SESSION_COOKIE));
+    // Logging out during an impersonation ends the session of the admin as well
+    if let Some(impersonator_cookie) = jar.get(IMPERSONATOR_SESSION_COOKIE) {
+        let _ = AuthService::logout(impersonator_cookie.value().to_string(), &state.db).await;
+    }
+    let jar = remove_session_cookies(jar)
+        .remove(CookieConfig::from_env().removal(IMPERSONATOR_SESSION_COOKIE));
     if let Some(cookie) = cookie {
         let _ = AuthService::logout(cookie, &state.db).await;
-        if let Some(user) = user {
-            let _ = ActivityRepo::create_one(
-                ActivityEntry::Logout {
-                    ip_address: Some(addr.ip().into()),
-                    user_agent: Some(user_agent.to_string()),
-                    action_by_id: user.id,
-                },
-                conn,
-            )
-            .await;
+        if let Some(auth) = auth {
+            let _ = IMPERSONATOR_ID
+                .scope(
+                    auth.impersonator_id,
+                    ActivityRepo::create_one(
+                        ActivityEntry::Logout {
+                            ip_address: Some(addr.ip().into()),
+                            user_agent: Some(user_agent.to_string()),
+                            action_by_id: auth.user.id,
+                        },
+                        conn,
+                    ),
+                )
+                .await;
         }
     }
     (
@@ -0,0 +1,78 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header::RETRY_AFTER, HeaderValue},
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::{headers::UserAgent, TypedHeader};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    service::email_verification::{EmailVerificationError, EmailVerificationService},
+    utils::response::Metadata,
+    AppState,
+};
+
+#[derive(Deserialize)]
+pub struct EmailVerificationRequestBody {
+    email: String,
+}
+/// Sends the verification link again. Succeeds for unknown emails as well, so it can't be used to
+/// find out which emails have an account.
+pub async fn request(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(body): Json<EmailVerificationRequestBody>,
+) -> Response {
+    let conn = &mut state.db.acquire().await.unwrap();
+    match EmailVerificationService::request(
+        body.email,
+        Some(addr.ip().into()),
+        user_agent.as_str(),
+        conn,
+    )
+    .await
+    {
+        Ok(()) => Json(json!({
+            "success": true,
+            "_metadata": Metadata::default(),
+        }))
+        .into_response(),
+        // Tells throttled clients when they can try again
+        Err(e) => {
+            let retry_after = match e {
+                EmailVerificationError::TooManyRequests(seconds) => Some(seconds),
+                _ => None,
+            };
+            let mut response = e.into_response();
+            if let Some(seconds) = retry_after {
+                response
+                    .headers_mut()
+                    .insert(RETRY_AFTER, HeaderValue::from(seconds));
+            }
+            response
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct EmailVerificationConfirmBody {
+    token: String,
+}
+pub async fn confirm(
+    State(state): State<AppState>,
+    Json(body): Json<EmailVerificationConfirmBody>,
+) -> Result<Response, EmailVerificationError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let user = EmailVerificationService::confirm(&body.token, conn).await?;
+    Ok(Json(json!({
+        "success": true,
+        "email": user.email,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
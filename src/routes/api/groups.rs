@@ -0,0 +1,236 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use axum_extra::{headers::UserAgent, TypedHeader};
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    model::{auth::Role, GROUP_TABLE_NAME},
+    repo::{
+        activity::{ActivityEntry, ActivityRepo},
+        group::GroupRepo,
+    },
+    service::group::{GroupError, GroupInput, GroupService},
+    utils::{auth::AuthContext, response::Metadata},
+    AppState,
+};
+
+pub async fn list(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Result<Response, GroupError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let groups = GroupRepo::list(auth.user.organization_id, conn)
+        .await
+        .map_err(|_| GroupError::DatabaseError)?;
+    Ok(Json(json!({
+        "groups": groups,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+pub async fn get_by_id(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Result<Response, GroupError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let group = GroupService::get(id, auth.user.organization_id, conn).await?;
+    Ok(Json(json!({
+        "group": group,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupBody {
+    name: String,
+    description: Option<String>,
+    /// Nests the group in another one
+    parent_id: Option<Uuid>,
+    /// Grants the permissions of the role to all members of the group and its subgroups
+    role: Option<Role>,
+}
+impl From<GroupBody> for GroupInput {
+    fn from(value: GroupBody) -> Self {
+        GroupInput {
+            name: value.name,
+            description: value.description,
+            parent_id: value.parent_id,
+            role: value.role,
+        }
+    }
+}
+
+pub async fn post(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(body): Json<GroupBody>,
+) -> Result<Response, GroupError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let created = GroupService::create(body.into(), &auth, conn).await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::Create {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: auth.user.id,
+            table_name: GROUP_TABLE_NAME.to_string(),
+            item_id: created.id.to_string(),
+            new_data: serde_json::to_string(&created).unwrap(),
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "created": created,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+pub async fn put(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(body): Json<GroupBody>,
+) -> Result<Response, GroupError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let before_update = GroupService::get(id, auth.user.organization_id, conn).await?;
+    let updated = GroupService::update(id, body.into(), &auth, conn).await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::Update {
+            table_name: GROUP_TABLE_NAME.to_string(),
+            item_id: updated.id.to_string(),
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            old_data: serde_json::to_string(&before_update).unwrap(),
+            new_data: serde_json::to_string(&updated).unwrap(),
+            action_by_id: auth.user.id,
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "updated": updated,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+pub async fn delete(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+) -> Result<Response, GroupError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let deleted = GroupService::delete(id, auth.user.organization_id, conn).await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::Delete {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: auth.user.id,
+            table_name: GROUP_TABLE_NAME.to_string(),
+            item_id: deleted.id.to_string(),
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "deleted": deleted,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+/// The direct members of the group. `GET /users?group=` lists those of the subgroups as well.
+pub async fn list_members(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Result<Response, GroupError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let members = GroupService::list_members(id, auth.user.organization_id, conn).await?;
+    Ok(Json(json!({
+        "members": members,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberPutBody {
+    #[serde(default)]
+    is_admin: bool,
+}
+pub async fn put_member(
+    Path((id, user_id)): Path<(Uuid, Uuid)>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(body): Json<MemberPutBody>,
+) -> Result<Response, GroupError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let (previous, member) =
+        GroupService::set_member(id, user_id, body.is_admin, &auth, conn).await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::GroupMemberAdd {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: auth.user.id,
+            item_id: id,
+            old_data: previous.map(|p| serde_json::to_string(&p).unwrap()),
+            new_data: serde_json::to_string(&member).unwrap(),
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "member": member,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+pub async fn delete_member(
+    Path((id, user_id)): Path<(Uuid, Uuid)>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+) -> Result<Response, GroupError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let removed = GroupService::remove_member(id, user_id, &auth, conn).await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::GroupMemberRemove {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: auth.user.id,
+            item_id: id,
+            old_data: serde_json::to_string(&removed).unwrap(),
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "removed": removed,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
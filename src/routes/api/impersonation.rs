@@ -0,0 +1,119 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use axum_extra::{extract::CookieJar, headers::UserAgent, TypedHeader};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    config::{IMPERSONATOR_SESSION_COOKIE, SESSION_COOKIE},
+    repo::activity::{ActivityEntry, ActivityRepo},
+    service::impersonation::{ImpersonationError, ImpersonationService},
+    utils::{
+        auth::AuthContext,
+        cookie::{add_session_cookies, remove_session_cookies, CookieConfig},
+        response::Metadata,
+    },
+    AppState,
+};
+
+/// Switches the session cookie to a session of the user and keeps the session of the admin in
+/// a second cookie to return to it
+pub async fn start(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    jar: CookieJar,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+) -> Result<Response, ImpersonationError> {
+    let admin_session = jar
+        .get(SESSION_COOKIE)
+        .map(|cookie| cookie.value().to_string())
+        .ok_or(ImpersonationError::SessionRequired)?;
+    let conn = &mut state.db.acquire().await.unwrap();
+    let (user, session_with_token) = ImpersonationService::start(
+        &auth,
+        id,
+        Some(addr.ip().into()),
+        user_agent.to_string(),
+        conn,
+    )
+    .await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::ImpersonationStart {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: auth.user.id,
+            item_id: user.id,
+        },
+        conn,
+    )
+    .await;
+    let jar = add_session_cookies(
+        jar.add(CookieConfig::from_env().session_cookie(
+            IMPERSONATOR_SESSION_COOKIE,
+            admin_session,
+            true,
+        )),
+        session_with_token.token.plaintext,
+    );
+    Ok((
+        jar,
+        Json(json!({
+            "success": true,
+            "user": user,
+            "_metadata": Metadata::default(),
+        })),
+    )
+        .into_response())
+}
+
+/// Ends the impersonation and switches back to the session of the admin if it is still valid
+pub async fn stop(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    jar: CookieJar,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+) -> Result<Response, ImpersonationError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let impersonator_id = ImpersonationService::stop(&auth, &state.event_channel, conn).await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::ImpersonationStop {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: impersonator_id,
+            item_id: auth.user.id,
+        },
+        conn,
+    )
+    .await;
+    let admin_session = ImpersonationService::impersonator_session(
+        impersonator_id,
+        jar.get(IMPERSONATOR_SESSION_COOKIE)
+            .map(|cookie| cookie.value().to_string()),
+        Some(addr.ip().into()),
+        conn,
+    )
+    .await;
+    let restored = admin_session.is_some();
+    let jar = jar.remove(CookieConfig::from_env().removal(IMPERSONATOR_SESSION_COOKIE));
+    let jar = match admin_session {
+        Some(token) => add_session_cookies(jar, token),
+        None => remove_session_cookies(jar),
+    };
+    Ok((
+        jar,
+        Json(json!({
+            "success": true,
+            "restored": restored,
+            "_metadata": Metadata::default(),
+        })),
+    )
+        .into_response())
+}
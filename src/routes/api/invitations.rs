@@ -0,0 +1,212 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use axum_extra::{headers::UserAgent, TypedHeader};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    model::{auth::Role, UpdateTag},
+    repo::{
+        activity::{ActivityEntry, ActivityRepo},
+        invitation::InvitationRepo,
+    },
+    service::invitation::{InvitationError, InvitationService},
+    utils::{
+        auth::{AuthContext, ORGANIZATION_ID},
+        response::Metadata,
+    },
+    AppState,
+};
+
+pub async fn list(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Result<Response, InvitationError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let invitations = InvitationRepo::list(auth.user.organization_id, conn)
+        .await
+        .map_err(|_| InvitationError::DatabaseError)?;
+    Ok(Json(json!({
+        "invitations": invitations,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+#[derive(Deserialize)]
+pub struct InvitationPostBody {
+    email: String,
+    role: Option<String>,
+    #[serde(default)]
+    tags: Vec<UpdateTag>,
+}
+pub async fn post(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(body): Json<InvitationPostBody>,
+) -> Result<Response, InvitationError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let invitation = InvitationService::invite(
+        &body.email,
+        body.role
+            .map(Role::from)
+            .unwrap_or(Role::from(Role::AUTHOR)),
+        body.tags,
+        &auth,
+        conn,
+    )
+    .await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::InvitationCreate {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: auth.user.id,
+            item_id: invitation.id,
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "created": invitation,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+pub async fn resend(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+) -> Result<Response, InvitationError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let invitation = InvitationService::resend(id, auth.user.organization_id, conn).await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::InvitationResend {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: auth.user.id,
+            item_id: invitation.id,
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "updated": invitation,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+pub async fn delete_by_id(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+) -> Result<Response, InvitationError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let invitation = InvitationService::revoke(id, auth.user.organization_id, conn).await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::InvitationRevoke {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: auth.user.id,
+            item_id: invitation.id,
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "deleted": {
+            "id": invitation.id,
+        },
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+#[derive(Deserialize)]
+pub struct InvitationCheckTokenQuery {
+    token: String,
+}
+/// Tells the accept page whether the link is still valid, which email it is for and whether it
+/// only has to ask for the password of an existing account
+pub async fn token_check(
+    State(state): State<AppState>,
+    Query(query): Query<InvitationCheckTokenQuery>,
+) -> impl IntoResponse {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let invitation = InvitationService::get_by_token(&query.token, conn)
+        .await
+        .ok();
+    let existing_user = match &invitation {
+        Some(invitation) => InvitationService::is_for_existing_user(invitation, conn)
+            .await
+            .unwrap_or(false),
+        None => false,
+    };
+    Json(json!({
+        "isValid": invitation.is_some(),
+        "existingUser": existing_user,
+        "email": invitation.map(|i| i.email),
+        "_metadata": Metadata::default(),
+    }))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvitationAcceptBody {
+    token: String,
+    password: String,
+    confirm_password: String,
+    first_name: Option<String>,
+    last_name: Option<String>,
+}
+pub async fn accept(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(body): Json<InvitationAcceptBody>,
+) -> Result<Response, InvitationError> {
+    if body.password != body.confirm_password {
+        return Err(InvitationError::PasswordsDontMatch);
+    }
+    let conn = &mut state.db.acquire().await.unwrap();
+    let (invitation, user) = InvitationService::accept(
+        &body.token,
+        body.password,
+        body.first_name,
+        body.last_name,
+        conn,
+    )
+    .await?;
+    // The request isn't authenticated, so the organization of the invitation is set explicitly
+    let _ = ORGANIZATION_ID
+        .scope(
+            Some(invitation.organization_id),
+            ActivityRepo::create_one(
+                ActivityEntry::InvitationAccept {
+                    ip_address: Some(addr.ip().into()),
+                    user_agent: Some(user_agent.to_string()),
+                    action_by_id: user.id,
+                    item_id: invitation.id,
+                },
+                conn,
+            ),
+        )
+        .await;
+    Ok(Json(json!({
+        "user": user,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
@@ -0,0 +1,175 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, State},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use axum_extra::{headers::UserAgent, TypedHeader};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    model::auth::TokenType,
+    repo::{
+        activity::{ActivityEntry, ActivityRepo},
+        user::UserRepo,
+    },
+    routes::api::auth::auth_error_response,
+    service::{
+        auth::AuthService,
+        jwt::{IssuedTokens, JwtError, JwtService},
+    },
+    utils::{auth::AuthContext, extractors::Session, response::Metadata},
+    AppState,
+};
+
+/// What a client exchanges for an access token
+#[derive(Deserialize)]
+#[serde(tag = "grantType", rename_all = "snake_case")]
+pub enum TokenGrant {
+    /// The session of the request, e.g. for a frontend that calls other services
+    Session,
+    Password {
+        email: String,
+        password: String,
+    },
+    RefreshToken {
+        #[serde(rename = "refreshToken")]
+        refresh_token: String,
+    },
+}
+pub async fn token(
+    State(state): State<AppState>,
+    Session(auth): Session<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(grant): Json<TokenGrant>,
+) -> Response {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let issued = match grant {
+        TokenGrant::Session => {
+            let auth = match auth {
+                Some(auth) if matches!(auth.token.token_type, TokenType::Session) => auth,
+                _ => return JwtError::SessionRequired.into_response(),
+            };
+            if auth.impersonator_id.is_some() {
+                return JwtError::ImpersonationNotAllowed.into_response();
+            }
+            if auth.password_change_required {
+                return JwtError::PasswordChangeRequired.into_response();
+            }
+            let user = match UserRepo::get_by_id(auth.user.id, conn).await {
+                Ok(user) => user,
+                Err(_) => return JwtError::SessionRequired.into_response(),
+            };
+            JwtService::issue(&user, auth.user.organization_id, conn).await
+        }
+        TokenGrant::Password { email, password } => {
+            let (user, organization_id) = match AuthService::login_for_token(
+                email,
+                password,
+                Some(addr.ip().into()),
+                user_agent.to_string(),
+                conn,
+            )
+            .await
+            {
+                Ok(result) => result,
+                Err(e) => return auth_error_response(e),
+            };
+            let _ = ActivityRepo::create_one(
+                ActivityEntry::Login {
+                    ip_address: Some(addr.ip().into()),
+                    user_agent: Some(user_agent.to_string()),
+                    action_by_id: user.id,
+                },
+                conn,
+            )
+            .await;
+            JwtService::issue(&user, organization_id, conn).await
+        }
+        TokenGrant::RefreshToken { refresh_token } => JwtService::refresh(
+            &refresh_token,
+            Some(addr.ip().into()),
+            user_agent.as_str(),
+            conn,
+        )
+        .await
+        .map(|(_, issued)| issued),
+    };
+    match issued {
+        Ok(IssuedTokens {
+            access_token,
+            expires_in,
+            refresh_token,
+        }) => Json(json!({
+            "accessToken": access_token,
+            "tokenType": "Bearer",
+            "expiresIn": expires_in,
+            "refreshToken": refresh_token,
+            "_metadata": Metadata::default(),
+        }))
+        .into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevokeBody {
+    refresh_token: String,
+}
+/// Logs a client out by revoking its refresh token and every token rotated from the same login
+pub async fn revoke(
+    State(state): State<AppState>,
+    Json(body): Json<RevokeBody>,
+) -> Result<Response, JwtError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    JwtService::revoke(&body.refresh_token, conn).await?;
+    Ok(Json(json!({
+        "success": true,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+/// The public keys to verify access tokens with, as JSON Web Key Set
+pub async fn jwks(State(state): State<AppState>) -> Result<Response, JwtError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    Ok(Json(JwtService::jwks(conn).await?).into_response())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RotateKeyBody {
+    /// Rejects the tokens signed by the previous keys right away instead of when they expire
+    #[serde(default)]
+    revoke_previous: bool,
+}
+pub async fn rotate_key(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(body): Json<RotateKeyBody>,
+) -> Result<Response, JwtError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let key = JwtService::rotate_keys(body.revoke_previous, conn).await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::SigningKeyRotate {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: auth.user.id,
+            item_id: key.id,
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "success": true,
+        "keyId": key.id,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
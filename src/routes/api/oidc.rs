@@ -0,0 +1,118 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    response::{IntoResponse, Redirect, Response},
+    Json,
+};
+use axum_extra::{extract::CookieJar, headers::UserAgent, TypedHeader};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::auth::auth_error_response;
+use crate::{
+    config::{self, OIDC_STATE_COOKIE},
+    repo::activity::{ActivityEntry, ActivityRepo},
+    service::{
+        auth::{AuthService, LoginOutcome},
+        oidc::{OidcError, OidcProvider, OidcService},
+    },
+    utils::{
+        cookie::{add_oidc_state_cookie, add_session_cookies, remove_oidc_state_cookie},
+        response::Metadata,
+    },
+    AppState,
+};
+
+pub async fn list_providers(State(state): State<AppState>) -> impl IntoResponse {
+    Json(json!({
+        "providers": *state.oidc_providers,
+        "_metadata": Metadata::default(),
+    }))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginQuery {
+    /// The path of the frontend to return to after the login
+    redirect_to: Option<String>,
+}
+pub async fn login(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(provider): Path<String>,
+    Query(query): Query<LoginQuery>,
+) -> OidcRouteResult {
+    let provider = find_provider(&state, &provider)?;
+    let conn = &mut state.db.acquire().await.unwrap();
+    let (url, oidc_state) =
+        OidcService::start_login(provider, query.redirect_to.as_deref(), conn).await?;
+    let jar = add_oidc_state_cookie(jar, oidc_state);
+    Ok((jar, Redirect::to(&url)).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct CallbackQuery {
+    code: String,
+    state: String,
+}
+pub async fn callback(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Path(provider): Path<String>,
+    Query(query): Query<CallbackQuery>,
+) -> OidcRouteResult {
+    // Otherwise an attacker could send a victim the callback of their own login
+    if jar.get(OIDC_STATE_COOKIE).map(|c| c.value()) != Some(query.state.as_str()) {
+        return Err(OidcError::InvalidState);
+    }
+    let jar = remove_oidc_state_cookie(jar);
+    let provider = find_provider(&state, &provider)?;
+    let conn = &mut state.db.acquire().await.unwrap();
+    let (user, redirect_to) =
+        OidcService::finish_login(provider, query.code, &query.state, conn).await?;
+    let redirect_to = redirect_to.unwrap_or("/".to_string());
+    match AuthService::login_oidc(user, Some(addr.ip().into()), user_agent.to_string(), conn).await
+    {
+        Ok(LoginOutcome::Session(user, session_with_token)) => {
+            let _ = ActivityRepo::create_one(
+                ActivityEntry::Login {
+                    ip_address: Some(addr.ip().into()),
+                    user_agent: Some(user_agent.to_string()),
+                    action_by_id: user.id,
+                },
+                conn,
+            )
+            .await;
+            let jar = add_session_cookies(jar, session_with_token.token.plaintext);
+            let url = format!("{}{}", config::base_url(), redirect_to);
+            Ok((jar, Redirect::to(&url)).into_response())
+        }
+        // The fragment isn't sent to any server, the frontend finishes the login with it
+        Ok(LoginOutcome::SecondFactorRequired(challenge, methods)) => {
+            let methods: Vec<String> = methods.into_iter().map(String::from).collect();
+            let path = redirect_to.split('#').next().unwrap_or("/");
+            let url = format!(
+                "{}{}#secondFactorChallenge={}&methods={}",
+                config::base_url(),
+                path,
+                challenge.plaintext,
+                methods.join(",")
+            );
+            Ok((jar, Redirect::to(&url)).into_response())
+        }
+        Err(e) => Ok((jar, auth_error_response(e)).into_response()),
+    }
+}
+
+fn find_provider<'a>(state: &'a AppState, name: &str) -> Result<&'a OidcProvider, OidcError> {
+    state
+        .oidc_providers
+        .iter()
+        .find(|p| p.name == name)
+        .ok_or(OidcError::ProviderNotFound)
+}
+
+type OidcRouteResult = Result<Response, OidcError>;
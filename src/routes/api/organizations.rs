@@ -0,0 +1,124 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use axum_extra::{headers::UserAgent, TypedHeader};
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    model::ORGANIZATION_TABLE_NAME,
+    repo::activity::{ActivityEntry, ActivityRepo},
+    service::organization::{OrganizationError, OrganizationService},
+    utils::{auth::AuthContext, response::Metadata},
+    AppState,
+};
+
+/// The organizations of the current user, with the one the request acts in
+pub async fn list(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Result<Response, OrganizationError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let organizations = OrganizationService::list_for_user(auth.user.id, conn).await?;
+    Ok(Json(json!({
+        "organizations": organizations,
+        "activeOrganizationId": auth.user.organization_id,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+#[derive(Deserialize)]
+pub struct OrganizationBody {
+    name: String,
+}
+
+pub async fn post(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(body): Json<OrganizationBody>,
+) -> Result<Response, OrganizationError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let created = OrganizationService::create(&body.name, &auth, conn).await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::Create {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: auth.user.id,
+            table_name: ORGANIZATION_TABLE_NAME.to_string(),
+            item_id: created.id.to_string(),
+            new_data: serde_json::to_string(&created).unwrap(),
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "created": created,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+pub async fn put(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(body): Json<OrganizationBody>,
+) -> Result<Response, OrganizationError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let (before_update, updated) = OrganizationService::rename(id, &body.name, &auth, conn).await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::Update {
+            table_name: ORGANIZATION_TABLE_NAME.to_string(),
+            item_id: updated.id.to_string(),
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            old_data: serde_json::to_string(&before_update).unwrap(),
+            new_data: serde_json::to_string(&updated).unwrap(),
+            action_by_id: auth.user.id,
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "updated": updated,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+/// Makes the organization the active one of the session. The following requests act in it.
+pub async fn activate(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+) -> Result<Response, OrganizationError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let organization = OrganizationService::activate(id, &auth, conn).await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::OrganizationSwitch {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: auth.user.id,
+            item_id: organization.id,
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "organization": organization,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
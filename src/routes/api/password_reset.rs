@@ -8,7 +8,10 @@ use serde_json::json;
 
 use crate::{
     repo::{token::TokenRepo, user::UserRepo},
-    service::{auth::AuthService, email::EmailService},
+    service::{
+        auth::{AuthError, AuthService},
+        email::EmailService,
+    },
     utils::response::Metadata,
     AppState,
 };
@@ -33,7 +36,7 @@ pub async fn request(
                 }))
                 .into_response()
             })?;
-        EmailService::send_password_reset_email(user.email, token.token)
+        EmailService::send_password_reset_email(user.email, token.plaintext)
             .await
             .map_err(|_| {
                 Json(json!({
@@ -82,13 +85,20 @@ pub async fn reset(
         return Json(json!({
             "success": false,
             "_metadata": Metadata::default(),
-        }));
+        }))
+        .into_response();
     }
-    let success = AuthService::reset_password(&body.token, body.password, conn)
-        .await
-        .unwrap_or(false);
+    let success =
+        match AuthService::reset_password(&body.token, body.password, &state.event_channel, conn)
+            .await
+        {
+            Ok(success) => success,
+            Err(e @ AuthError::PasswordPolicy(_)) => return e.into_response(),
+            Err(_) => false,
+        };
     Json(json!({
         "success": success,
         "_metadata": Metadata::default(),
     }))
+    .into_response()
 }
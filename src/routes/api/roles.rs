@@ -0,0 +1,173 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use axum_extra::{headers::UserAgent, TypedHeader};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    model::{
+        auth::{Permission, Role},
+        ROLE_TABLE_NAME,
+    },
+    repo::{
+        activity::{ActivityEntry, ActivityRepo},
+        role::RoleRepo,
+    },
+    service::role::{RoleError, RoleService},
+    utils::{auth::AuthContext, response::Metadata},
+    AppState,
+};
+
+pub async fn list(State(state): State<AppState>) -> Result<Response, RoleError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let roles = RoleRepo::list(conn)
+        .await
+        .map_err(|_| RoleError::DatabaseError)?;
+    Ok(Json(json!({
+        "roles": roles,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+pub async fn get_by_name(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Response, RoleError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let role = RoleService::get(&Role::from(name), conn).await?;
+    Ok(Json(json!({
+        "role": role,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+#[derive(Deserialize)]
+pub struct RolePostBody {
+    name: String,
+    description: Option<String>,
+    permissions: Vec<Permission>,
+}
+pub async fn post(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(body): Json<RolePostBody>,
+) -> Result<Response, RoleError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let created = RoleService::create(
+        &Role::from(body.name),
+        body.description,
+        &body.permissions,
+        &auth,
+        conn,
+    )
+    .await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::Create {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: auth.user.id,
+            table_name: ROLE_TABLE_NAME.to_string(),
+            item_id: created.name.clone(),
+            new_data: serde_json::to_string(&created).unwrap(),
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "created": created,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+#[derive(Deserialize)]
+pub struct RolePutBody {
+    description: Option<String>,
+    permissions: Vec<Permission>,
+}
+/// Users with the role get the new permissions with their next request, JWTs with their next
+/// refresh
+pub async fn put(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(body): Json<RolePutBody>,
+) -> Result<Response, RoleError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let role = Role::from(name);
+    let before_update = RoleService::get(&role, conn).await?;
+    let updated =
+        RoleService::update(&role, body.description, &body.permissions, &auth, conn).await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::Update {
+            table_name: ROLE_TABLE_NAME.to_string(),
+            item_id: updated.name.clone(),
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            old_data: serde_json::to_string(&before_update).unwrap(),
+            new_data: serde_json::to_string(&updated).unwrap(),
+            action_by_id: auth.user.id,
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "updated": updated,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+pub async fn delete(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+) -> Result<Response, RoleError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let deleted = RoleService::delete(&Role::from(name), conn).await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::Delete {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: auth.user.id,
+            table_name: ROLE_TABLE_NAME.to_string(),
+            item_id: deleted.name.clone(),
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "deleted": deleted,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+/// The permissions the current request may use, i.e. those of the role in the active
+/// organization, restricted to the scopes of a static access token
+pub async fn effective_permissions(Extension(auth): Extension<AuthContext>) -> Response {
+    let permissions: Vec<Permission> = Permission::ALL
+        .into_iter()
+        .filter(|p| auth.has_permission(*p))
+        .collect();
+    Json(json!({
+        "role": auth.user.role,
+        "organizationId": auth.user.organization_id,
+        "permissions": permissions,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response()
+}
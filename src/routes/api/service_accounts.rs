@@ -0,0 +1,193 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use axum_extra::{headers::UserAgent, TypedHeader};
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    model::{
+        auth::{Permission, Role},
+        USER_TABLE_NAME,
+    },
+    repo::activity::{ActivityEntry, ActivityRepo},
+    service::service_account::{ServiceAccountError, ServiceAccountService},
+    utils::{auth::AuthContext, response::Metadata},
+    AppState,
+};
+
+#[derive(Deserialize)]
+pub struct ServiceAccountPostBody {
+    name: String,
+    description: Option<String>,
+    role: Option<String>,
+}
+/// Service accounts are listed with the users, filtered by `kind=service`
+pub async fn post(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(body): Json<ServiceAccountPostBody>,
+) -> Result<Response, ServiceAccountError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let account = ServiceAccountService::create(
+        body.name,
+        body.description,
+        body.role
+            .map(Role::from)
+            .unwrap_or(Role::from(Role::CONTRIBUTOR)),
+        &auth,
+        conn,
+    )
+    .await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::ServiceAccountCreate {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: auth.user.id,
+            item_id: account.id,
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "created": account,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OwnerPutBody {
+    owner_id: Uuid,
+}
+pub async fn put_owner(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(body): Json<OwnerPutBody>,
+) -> Result<Response, ServiceAccountError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let before_update = ServiceAccountService::get(id, auth.user.organization_id, conn).await?;
+    let updated =
+        ServiceAccountService::update_owner(id, body.owner_id, auth.user.organization_id, conn)
+            .await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::Update {
+            table_name: USER_TABLE_NAME.to_string(),
+            item_id: id.to_string(),
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            old_data: serde_json::to_string(&before_update).unwrap(),
+            new_data: serde_json::to_string(&updated).unwrap(),
+            action_by_id: auth.user.id,
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "updated": updated,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+pub async fn list_tokens(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> Result<Response, ServiceAccountError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let tokens = ServiceAccountService::list_tokens(id, auth.user.organization_id, conn).await?;
+    Ok(Json(json!({
+        "tokens": tokens.into_iter().map(|t| json!({
+            "id": t.id,
+            "name": t.name,
+            "prefix": t.token_prefix,
+            "createdAt": t.created_at,
+            "expiration": t.expiration,
+            "scopes": t.scopes,
+        })).collect::<Vec<_>>(),
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+#[derive(Deserialize)]
+pub struct TokenPostBody {
+    name: String,
+    scopes: Vec<Permission>,
+}
+pub async fn post_token(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(body): Json<TokenPostBody>,
+) -> Result<Response, ServiceAccountError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let created =
+        ServiceAccountService::create_token(id, body.name, &body.scopes, &auth, conn).await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::ServiceAccountTokenCreate {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: auth.user.id,
+            item_id: created.token.id,
+        },
+        conn,
+    )
+    .await;
+    // The plaintext token is only returned once, afterwards only its prefix is known
+    Ok(Json(json!({
+        "created": {
+            "id": created.token.id,
+            "name": created.token.name,
+            "token": created.plaintext,
+            "prefix": created.token.token_prefix,
+            "userId": created.token.user_id,
+            "scopes": created.token.scopes,
+            "createdAt": created.token.created_at,
+            "updatedAt": created.token.updated_at,
+        },
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+pub async fn delete_token(
+    Path((id, token_id)): Path<(Uuid, i32)>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+) -> Result<Response, ServiceAccountError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let deleted_id =
+        ServiceAccountService::delete_token(id, token_id, auth.user.organization_id, conn).await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::ServiceAccountTokenDelete {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: auth.user.id,
+            item_id: deleted_id,
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "deleted": true,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
@@ -2,16 +2,19 @@ use axum::{
     extract::{Path, State},
     http::StatusCode,
     response::{IntoResponse, Response},
-    Json,
+    Extension, Json,
 };
 use macros::JsonErrorResponse;
 use serde_json::json;
+use sqlx::PgConnection;
+use uuid::Uuid;
 
 use crate::{
     model::user::User,
-    repo::session::SessionRepo,
+    repo::{organization::OrganizationRepo, session::SessionRepo},
+    service::{auth::AuthError, session::SessionService},
     utils::extractors::Session,
-    utils::{error::ErrorResponse, response::Metadata},
+    utils::{auth::AuthContext, error::ErrorResponse, response::Metadata},
     AppState,
 };
 
@@ -41,12 +44,7 @@ pub async fn delete_by_id(
 ) -> SessionResult {
     if let Some(user) = user {
         let conn = &mut state.db.acquire().await.unwrap();
-        SessionRepo::delete_by_id_for_user(id, user.id, conn)
-            .await
-            .map_err(|e| match e {
-                sqlx::Error::RowNotFound => SessionError::NotFound,
-                _ => SessionError::DatabaseError,
-            })?;
+        SessionService::revoke(id, user.id, &state.event_channel, conn).await?;
         Ok(Json(json!({
             "deleted": true,
             "_metadata": Metadata::default(),
@@ -57,6 +55,87 @@ pub async fn delete_by_id(
     }
 }
 
+/// Logs the user out everywhere except in the session of this request
+pub async fn revoke_others(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> SessionResult {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let revoked = SessionService::revoke_all(
+        auth.user.id,
+        Some(auth.token.id),
+        &state.event_channel,
+        conn,
+    )
+    .await?;
+    Ok(Json(json!({
+        "revoked": revoked,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+/// Admins only manage the sessions of members of their organization, which has to be the default
+/// one
+async fn check_member(
+    user_id: Uuid,
+    auth: &AuthContext,
+    db: &mut PgConnection,
+) -> Result<(), SessionError> {
+    match OrganizationRepo::get_member(auth.user.organization_id, user_id, db).await {
+        Ok(_) => Ok(()),
+        Err(sqlx::Error::RowNotFound) => Err(SessionError::UserNotFound),
+        Err(_) => Err(SessionError::DatabaseError),
+    }
+}
+
+pub async fn list_for_user(
+    Path(user_id): Path<Uuid>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> SessionResult {
+    let conn = &mut state.db.acquire().await.unwrap();
+    check_member(user_id, &auth, conn).await?;
+    let sessions = SessionRepo::get_sessions_for_user(user_id, conn)
+        .await
+        .map_err(|_| SessionError::DatabaseError)?;
+    Ok(Json(json!({
+        "sessions": sessions,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+pub async fn revoke_all_for_user(
+    Path(user_id): Path<Uuid>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> SessionResult {
+    let conn = &mut state.db.acquire().await.unwrap();
+    check_member(user_id, &auth, conn).await?;
+    let revoked = SessionService::revoke_all(user_id, None, &state.event_channel, conn).await?;
+    Ok(Json(json!({
+        "revoked": revoked,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+pub async fn delete_by_id_for_user(
+    Path((user_id, id)): Path<(Uuid, i32)>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> SessionResult {
+    let conn = &mut state.db.acquire().await.unwrap();
+    check_member(user_id, &auth, conn).await?;
+    SessionService::revoke(id, user_id, &state.event_channel, conn).await?;
+    Ok(Json(json!({
+        "deleted": true,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
 #[derive(thiserror::Error, Debug, JsonErrorResponse)]
 pub enum SessionError {
     #[error("Unauthorized")]
@@ -70,5 +149,18 @@ pub enum SessionError {
     #[error("Session not found")]
     #[status_code(StatusCode::NOT_FOUND)]
     NotFound,
+
+    #[error("User not found")]
+    #[status_code(StatusCode::NOT_FOUND)]
+    UserNotFound,
 }
 pub type SessionResult = Result<Response, SessionError>;
+
+impl From<AuthError> for SessionError {
+    fn from(value: AuthError) -> Self {
+        match value {
+            AuthError::SessionNotFound => SessionError::NotFound,
+            _ => SessionError::DatabaseError,
+        }
+    }
+}
@@ -3,10 +3,11 @@ use std::net::SocketAddr;
 use axum::{
     extract::{ConnectInfo, State},
     http::StatusCode,
-    response::IntoResponse,
-    Json,
+    response::{IntoResponse, Response},
+    Extension, Json,
 };
 use axum_extra::{headers::UserAgent, TypedHeader};
+use macros::JsonErrorResponse;
 use serde::Deserialize;
 use serde_json::json;
 
@@ -14,13 +15,14 @@ use crate::{
     model::{
         auth::{Language, PreferencesInput, Theme},
         user::User,
-        USER_TABLE_NAME,
+        Settings, SETTINGS_TABLE_NAME, USER_TABLE_NAME,
     },
     repo::{
         activity::{ActivityEntry, ActivityRepo},
+        settings::SettingsRepo,
         user::UserRepo,
     },
-    utils::extractors::Session,
+    utils::{auth::AuthContext, error::ErrorResponse, extractors::Session, response::Metadata},
     AppState,
 };
 
@@ -77,3 +79,81 @@ pub async fn post_preferences(
             .into_response()
     }
 }
+
+/// Authentication rules admins can change at runtime
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthPolicyBody {
+    allow_unverified_login: Option<bool>,
+    allow_magic_link_login: Option<bool>,
+    /// 0 removes the maximum
+    password_max_age_days: Option<i32>,
+}
+pub async fn get_auth_policy(State(state): State<AppState>) -> SettingsResult {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let settings = match SettingsRepo::get(conn).await {
+        Ok(settings) => settings,
+        Err(sqlx::Error::RowNotFound) => Default::default(),
+        Err(_) => return Err(SettingsError::DatabaseError),
+    };
+    Ok(auth_policy_response(settings))
+}
+
+pub async fn put_auth_policy(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(body): Json<AuthPolicyBody>,
+) -> SettingsResult {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let before_update = SettingsRepo::get(conn).await.unwrap_or_default();
+    let updated = SettingsRepo::update_auth_policy(
+        body.allow_unverified_login
+            .unwrap_or(before_update.allow_unverified_login),
+        body.allow_magic_link_login
+            .unwrap_or(before_update.allow_magic_link_login),
+        match body.password_max_age_days {
+            Some(days) => Some(days).filter(|days| *days > 0),
+            None => before_update.password_max_age_days,
+        },
+        conn,
+    )
+    .await
+    .map_err(|_| SettingsError::DatabaseError)?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::Update {
+            table_name: SETTINGS_TABLE_NAME.to_string(),
+            item_id: updated.id.clone(),
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            old_data: serde_json::to_string(&before_update).unwrap(),
+            new_data: serde_json::to_string(&updated).unwrap(),
+            action_by_id: auth.user.id,
+        },
+        conn,
+    )
+    .await;
+    Ok(auth_policy_response(updated))
+}
+
+fn auth_policy_response(settings: Settings) -> Response {
+    Json(json!({
+        "policy": {
+            "allowUnverifiedLogin": settings.allow_unverified_login,
+            "allowMagicLinkLogin": settings.allow_magic_link_login,
+            "passwordMaxAgeDays": settings.password_max_age_days,
+        },
+        "_metadata": Metadata::default(),
+    }))
+    .into_response()
+}
+
+type SettingsResult = Result<Response, SettingsError>;
+
+#[derive(thiserror::Error, Debug, JsonErrorResponse)]
+pub enum SettingsError {
+    #[error("Database error")]
+    #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
+    DatabaseError,
+}
@@ -15,8 +15,13 @@ use crate::{
     config::{self, system_user_uuid},
     model::{auth::Role, user::UserCreateInput, USER_TABLE_NAME},
     repo::activity::{ActivityEntry, ActivityRepo},
-    service::{auth::AuthService, setup::SetupService},
-    utils::{error::ErrorResponse, response::Metadata},
+    service::{
+        auth::AuthService,
+        email_verification::EmailVerificationService,
+        password_policy::{PasswordPolicy, PasswordPolicyError, PasswordUserInfo},
+        setup::SetupService,
+    },
+    utils::{auth::ORGANIZATION_ID, error::ErrorResponse, response::Metadata},
     AppState,
 };
 
@@ -43,6 +48,9 @@ pub struct CreateAdminUserPayload {
     password: String,
     #[serde(rename = "confirmPassword")]
     confirm_password: String,
+    /// The name of the first organization, which becomes the default one
+    #[serde(rename = "organizationName")]
+    organization_name: Option<String>,
 }
 pub async fn create_admin_user(
     State(state): State<AppState>,
@@ -57,35 +65,64 @@ pub async fn create_admin_user(
     if payload.password != payload.confirm_password {
         return Err(SetupError::PasswordsDontMatch.into_response());
     }
+    PasswordPolicy::from_env()
+        .check(
+            "password",
+            &payload.password,
+            &PasswordUserInfo {
+                email: &payload.email,
+                first_name: payload.first_name.as_deref(),
+                last_name: payload.last_name.as_deref(),
+            },
+        )
+        .await
+        .map_err(|e| SetupError::from(e).into_response())?;
 
+    let organization_name = payload
+        .organization_name
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or("Default");
+    let organization = SetupService::create_organization(organization_name, conn)
+        .await
+        .map_err(|_| SetupError::FailedToFinishSetup.into_response())?;
     let created = AuthService::create_user(
         UserCreateInput {
             email: payload.email,
             first_name: payload.first_name,
             last_name: payload.last_name,
-            role: Some(Role::Admin),
+            role: Some(Role::from(Role::ADMIN)),
             ..Default::default()
         },
         vec![],
         payload.password,
+        organization.id,
         config::system_user_uuid(),
         conn,
     )
     .await
     .map_err(|e| SetupError::FailedToCreateUser(e.to_string()).into_response())?;
-    let _ = ActivityRepo::create_one(
-        ActivityEntry::Create {
-            ip_address: Some(addr.ip().into()),
-            user_agent: Some(user_agent.to_string()),
-            action_by_id: system_user_uuid(),
-            table_name: USER_TABLE_NAME.to_string(),
-            item_id: created.id.to_string(),
-            new_data: serde_json::to_string(&created).unwrap(),
-        },
-        conn,
-    )
-    .await;
-    SetupService::finish_setup(conn)
+    if let Err(e) = EmailVerificationService::send_verification(&created, conn).await {
+        tracing::error!("Sending the email verification failed: {e}");
+    }
+    let _ = ORGANIZATION_ID
+        .scope(
+            Some(organization.id),
+            ActivityRepo::create_one(
+                ActivityEntry::Create {
+                    ip_address: Some(addr.ip().into()),
+                    user_agent: Some(user_agent.to_string()),
+                    action_by_id: system_user_uuid(),
+                    table_name: USER_TABLE_NAME.to_string(),
+                    item_id: created.id.to_string(),
+                    new_data: serde_json::to_string(&created).unwrap(),
+                },
+                conn,
+            ),
+        )
+        .await;
+    SetupService::finish_setup(organization.id, conn)
         .await
         .map_err(|_| SetupError::FailedToFinishSetup.into_response())?;
 
@@ -113,4 +150,9 @@ pub enum SetupError {
     #[error("Passwords don't match")]
     #[status_code(StatusCode::BAD_REQUEST)]
     PasswordsDontMatch,
+
+    #[error(transparent)]
+    #[status_code(StatusCode::UNPROCESSABLE_ENTITY)]
+    #[field_errors]
+    PasswordPolicy(#[from] PasswordPolicyError),
 }
@@ -1,11 +1,20 @@
-use axum::{extract::State, response::IntoResponse, Json};
+use axum::{extract::State, response::IntoResponse, Extension, Json};
 use serde_json::json;
 
-use crate::{repo::tag::TagRepo, utils::response::Metadata, AppState};
+use crate::{
+    repo::tag::TagRepo,
+    utils::{auth::AuthContext, response::Metadata},
+    AppState,
+};
 
-pub async fn get(State(state): State<AppState>) -> impl IntoResponse {
+pub async fn get(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> impl IntoResponse {
     let conn = &mut state.db.acquire().await.unwrap();
-    let tags = TagRepo::list_all(conn).await.unwrap();
+    let tags = TagRepo::list_all(auth.user.organization_id, conn)
+        .await
+        .unwrap();
     Json(json!({
         "tags": tags,
         "_metadata": Metadata {
@@ -2,16 +2,16 @@ use axum::{
     extract::{Path, State},
     http::StatusCode,
     response::{IntoResponse, Response},
-    Json,
+    Extension, Json,
 };
 use macros::JsonErrorResponse;
 use serde::Deserialize;
 use serde_json::json;
 
 use crate::{
-    model::user::User,
+    model::{auth::Permission, user::User},
     repo::token::TokenRepo,
-    utils::{error::ErrorResponse, extractors::Session, response::Metadata},
+    utils::{auth::AuthContext, error::ErrorResponse, extractors::Session, response::Metadata},
     AppState,
 };
 
@@ -25,8 +25,10 @@ pub async fn get(State(state): State<AppState>, Session(user): Session<User>) ->
             "tokens": tokens.into_iter().map(|t| json!({
                 "id": t.id,
                 "name": t.name,
+                "prefix": t.token_prefix,
                 "createdAt": t.created_at,
                 "expiration": t.expiration,
+                "scopes": t.scopes,
             })).collect::<Vec<_>>(),
             "_metadata": Metadata::default(),
         }))
@@ -39,32 +41,46 @@ pub async fn get(State(state): State<AppState>, Session(user): Session<User>) ->
 #[derive(Deserialize)]
 pub struct TokenPostBody {
     name: String,
+    scopes: Vec<Permission>,
 }
 pub async fn post(
     State(state): State<AppState>,
-    Session(user): Session<User>,
+    Extension(auth): Extension<AuthContext>,
     Json(body): Json<TokenPostBody>,
 ) -> TokenResult {
-    if let Some(user) = user {
-        let conn = &mut state.db.acquire().await.unwrap();
-        let created = TokenRepo::create_one_access_token(user.id, body.name, conn)
-            .await
-            .map_err(|_| TokenError::DatabaseError)?;
-        Ok(Json(json!({
-            "created": {
-                "id": created.id,
-                "name": created.name,
-                "token": created.token,
-                "userId": created.user_id,
-                "createdAt": created.created_at,
-                "updatedAt": created.updated_at,
-            },
-            "_metadata": Metadata::default(),
-        }))
-        .into_response())
-    } else {
-        Err(TokenError::Unauthorized)
+    if body.scopes.is_empty() {
+        return Err(TokenError::MissingScopes);
     }
+    // A token can't get more power than the user and token creating it
+    if let Some(scope) = body.scopes.iter().find(|s| !auth.has_permission(**s)) {
+        return Err(TokenError::ScopeNotGranted(String::from(*scope)));
+    }
+    let conn = &mut state.db.acquire().await.unwrap();
+    // The token acts in the organization it is created in
+    let created = TokenRepo::create_one_access_token(
+        auth.user.id,
+        auth.user.organization_id,
+        body.name,
+        &body.scopes,
+        conn,
+    )
+    .await
+    .map_err(|_| TokenError::DatabaseError)?;
+    // The plaintext token is only returned once, afterwards only its prefix is known
+    Ok(Json(json!({
+        "created": {
+            "id": created.token.id,
+            "name": created.token.name,
+            "token": created.plaintext,
+            "prefix": created.token.token_prefix,
+            "userId": created.token.user_id,
+            "scopes": created.token.scopes,
+            "createdAt": created.token.created_at,
+            "updatedAt": created.token.updated_at,
+        },
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
 }
 
 pub async fn delete_by_id(
@@ -98,4 +114,12 @@ pub enum TokenError {
     #[error("Unauthorized")]
     #[status_code(StatusCode::UNAUTHORIZED)]
     Unauthorized,
+
+    #[error("At least one scope is required")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    MissingScopes,
+
+    #[error("The scope {0} can't be granted by this token")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    ScopeNotGranted(String),
 }
@@ -0,0 +1,152 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::{headers::UserAgent, TypedHeader};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgConnection;
+
+use crate::{
+    model::{auth::SecondFactor, user::User},
+    repo::activity::{ActivityEntry, ActivityRepo},
+    service::two_factor::{TwoFactorError, TwoFactorService},
+    utils::{extractors::Session, response::Metadata},
+    AppState,
+};
+
+pub async fn enroll_totp(
+    State(state): State<AppState>,
+    Session(user): Session<User>,
+) -> TwoFactorRouteResult {
+    if let Some(user) = user {
+        let conn = &mut state.db.acquire().await.unwrap();
+        let enrolment = TwoFactorService::start_totp_enrolment(&user, conn).await?;
+        Ok(Json(json!({
+            "secret": enrolment.secret,
+            "provisioningUri": enrolment.provisioning_uri,
+            "_metadata": Metadata::default(),
+        }))
+        .into_response())
+    } else {
+        Err(TwoFactorError::Unauthorized)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmTotpPayload {
+    code: String,
+}
+pub async fn confirm_totp(
+    State(state): State<AppState>,
+    Session(user): Session<User>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(payload): Json<ConfirmTotpPayload>,
+) -> TwoFactorRouteResult {
+    if let Some(user) = user {
+        let conn = &mut state.db.acquire().await.unwrap();
+        let recovery_codes =
+            TwoFactorService::confirm_totp_enrolment(&user, &payload.code, conn).await?;
+        let _ = ActivityRepo::create_one(
+            ActivityEntry::TwoFactorEnable {
+                ip_address: Some(addr.ip().into()),
+                user_agent: Some(user_agent.to_string()),
+                action_by_id: user.id,
+            },
+            conn,
+        )
+        .await;
+        Ok(Json(json!({
+            "recoveryCodes": recovery_codes,
+            "_metadata": Metadata::default(),
+        }))
+        .into_response())
+    } else {
+        Err(TwoFactorError::Unauthorized)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DisableTotpPayload {
+    password: String,
+    #[serde(flatten)]
+    second_factor: SecondFactor,
+}
+pub async fn disable_totp(
+    State(state): State<AppState>,
+    Session(user): Session<User>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(payload): Json<DisableTotpPayload>,
+) -> TwoFactorRouteResult {
+    if let Some(user) = user {
+        let conn = &mut state.db.acquire().await.unwrap();
+        TwoFactorService::disable(&user, &payload.password, &payload.second_factor, conn).await?;
+        record_recovery_code_use(&user, &payload.second_factor, addr, &user_agent, conn).await;
+        let _ = ActivityRepo::create_one(
+            ActivityEntry::TwoFactorDisable {
+                ip_address: Some(addr.ip().into()),
+                user_agent: Some(user_agent.to_string()),
+                action_by_id: user.id,
+            },
+            conn,
+        )
+        .await;
+        Ok(Json(json!({
+            "success": true,
+            "_metadata": Metadata::default(),
+        }))
+        .into_response())
+    } else {
+        Err(TwoFactorError::Unauthorized)
+    }
+}
+
+pub async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    Session(user): Session<User>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(second_factor): Json<SecondFactor>,
+) -> TwoFactorRouteResult {
+    if let Some(user) = user {
+        let conn = &mut state.db.acquire().await.unwrap();
+        let recovery_codes =
+            TwoFactorService::regenerate_recovery_codes(&user, &second_factor, conn).await?;
+        record_recovery_code_use(&user, &second_factor, addr, &user_agent, conn).await;
+        Ok(Json(json!({
+            "recoveryCodes": recovery_codes,
+            "_metadata": Metadata::default(),
+        }))
+        .into_response())
+    } else {
+        Err(TwoFactorError::Unauthorized)
+    }
+}
+
+/// Recovery codes are logged like in the login, so that the user notices a stolen one
+async fn record_recovery_code_use(
+    user: &User,
+    second_factor: &SecondFactor,
+    addr: SocketAddr,
+    user_agent: &UserAgent,
+    conn: &mut PgConnection,
+) {
+    if let SecondFactor::RecoveryCode { .. } = second_factor {
+        let _ = ActivityRepo::create_one(
+            ActivityEntry::RecoveryCodeUse {
+                ip_address: Some(addr.ip().into()),
+                user_agent: Some(user_agent.to_string()),
+                action_by_id: user.id,
+            },
+            conn,
+        )
+        .await;
+    }
+}
+
+type TwoFactorRouteResult = Result<Response, TwoFactorError>;
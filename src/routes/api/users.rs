@@ -4,9 +4,10 @@ use axum::{
     extract::{ConnectInfo, Multipart, Path, Query, State},
     http::StatusCode,
     response::{IntoResponse, Response},
-    Json,
+    Extension, Json,
 };
 use axum_extra::{headers::UserAgent, TypedHeader};
+use chrono::{serde::ts_milliseconds_option, DateTime, Utc};
 use futures::TryStreamExt;
 use macros::JsonErrorResponse;
 use serde::{Deserialize, Serialize};
@@ -18,18 +19,35 @@ use uuid::Uuid;
 
 use crate::{
     model::{
-        auth::Role,
-        user::{User, UserCreateInput, UserUpdateInput, UserWithTags},
+        auth::{Permission, Role, TokenType, UserKind},
+        user::{User, UserCreateInput, UserUpdateInput, UserWithRole, UserWithTags},
         UpdateTag, USER_TABLE_NAME,
     },
     repo::{
         activity::{ActivityEntry, ActivityRepo},
+        organization::OrganizationRepo,
         tag::TagRepo,
+        token::TokenRepo,
         user::UserRepo,
         DatabaseListOptions, SortDirection,
     },
-    service::auth::AuthService,
-    utils::{error::ErrorResponse, extractors::Session, response::Metadata},
+    service::{
+        auth::AuthService,
+        email_verification::{EmailVerificationError, EmailVerificationService},
+        jwt::JwtService,
+        login_throttle::LoginThrottleService,
+        password_policy::{PasswordPolicy, PasswordPolicyError, PasswordUserInfo},
+        role::{RoleError, RoleService},
+        session::SessionService,
+        suspension::{SuspensionError, SuspensionService},
+    },
+    utils::{
+        auth::{AuthContext, PermissionError},
+        error::ErrorResponse,
+        extractors::Session,
+        middlewares::require_default_organization,
+        response::Metadata,
+    },
     AppState,
 };
 
@@ -37,26 +55,9 @@ fn default_sort_by() -> String {
     "email".to_string()
 }
 
-fn validate_user_id(
-    id: String,
-    user: Option<&User>,
-    allowed_roles: Option<&[Role]>,
-) -> Result<Uuid, UserError> {
+fn validate_user_id(id: String, current_user_id: Option<Uuid>) -> Result<Uuid, UserError> {
     let user_id = match id.as_str() {
-        "me" => {
-            if let Some(user) = user {
-                if allowed_roles
-                    .map(|rs| rs.contains(&user.role))
-                    .unwrap_or(true)
-                {
-                    user.id
-                } else {
-                    return Err(UserError::Forbidden);
-                }
-            } else {
-                return Err(UserError::Unauthorized);
-            }
-        }
+        "me" => current_user_id.ok_or(UserError::Unauthorized)?,
         v => v
             .parse::<Uuid>()
             .map_err(|_| UserError::InvalidId(v.to_string()))?,
@@ -76,11 +77,29 @@ pub struct GetUsersQuery {
     #[serde(default)]
     sort_direction: SortDirection,
     /// a csv of roles to filter by (filter with 'or', not 'and')
-    roles: Option<Vec<String>>,
+    roles: Option<String>,
+    /// Only lists humans or service accounts
+    kind: Option<UserKind>,
+    /// Only lists the members of the group, including those of its subgroups
+    group: Option<Uuid>,
+    /// Lists deleted users as well, which requires `users:write`
+    #[serde(default)]
+    include_deleted: bool,
 }
-pub async fn get(Query(query): Query<GetUsersQuery>, State(state): State<AppState>) -> UserResult {
+/// Lists the members of the active organization with their role in it
+pub async fn get(
+    Query(query): Query<GetUsersQuery>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+) -> UserResult {
+    if query.include_deleted {
+        auth.require_permission(Permission::UsersWrite)?;
+    }
+    let organization_id = auth.user.organization_id;
     let query_roles = query.roles.map(|rs| {
-        rs.iter()
+        rs.split(',')
+            .map(str::trim)
+            .filter(|r| !r.is_empty())
             .map(|r| Role::from(r.to_string()))
             .collect::<Vec<Role>>()
     });
@@ -92,23 +111,65 @@ pub async fn get(Query(query): Query<GetUsersQuery>, State(state): State<AppStat
         sort_direction: query.sort_direction,
     };
     let (users, count) = if let Some(roles) = query_roles {
-        // TODO: filtering by roles does not work currently
-        let result = UserRepo::list_for_roles(&roles, db_list_options, &mut conn)
-            .await
-            .map_err(|_| UserError::DatabaseError)?;
-        let count = UserRepo::count_for_roles(&roles, &mut conn)
-            .await
-            .map_err(|_| UserError::DatabaseError)?;
+        let result = UserRepo::list_for_roles(
+            organization_id,
+            &roles,
+            query.kind,
+            query.group,
+            query.include_deleted,
+            db_list_options,
+            &mut conn,
+        )
+        .await
+        .map_err(|_| UserError::DatabaseError)?;
+        let count = UserRepo::count_for_roles(
+            organization_id,
+            &roles,
+            query.kind,
+            query.group,
+            query.include_deleted,
+            &mut conn,
+        )
+        .await
+        .map_err(|_| UserError::DatabaseError)?;
         (result, count)
     } else {
-        let result = UserRepo::list(db_list_options, &mut conn)
-            .await
-            .map_err(|_| UserError::DatabaseError)?;
-        let count = UserRepo::count_all(&mut conn)
-            .await
-            .map_err(|_| UserError::DatabaseError)?;
+        let result = UserRepo::list(
+            organization_id,
+            query.kind,
+            query.group,
+            query.include_deleted,
+            db_list_options,
+            &mut conn,
+        )
+        .await
+        .map_err(|_| UserError::DatabaseError)?;
+        let count = UserRepo::count_all(
+            organization_id,
+            query.kind,
+            query.group,
+            query.include_deleted,
+            &mut conn,
+        )
+        .await
+        .map_err(|_| UserError::DatabaseError)?;
         (result, count)
     };
+    let user_ids: Vec<Uuid> = users.iter().map(|u| u.id).collect();
+    let members = OrganizationRepo::list_members(organization_id, &user_ids, &mut conn)
+        .await
+        .map_err(|_| UserError::DatabaseError)?;
+    let users: Vec<UserWithRole> = users
+        .into_iter()
+        .filter_map(|user| {
+            let member = members.iter().find(|m| m.user_id == user.id)?;
+            Some(UserWithRole {
+                role: member.role.clone(),
+                account_status: user.account_status(),
+                user,
+            })
+        })
+        .collect();
     Ok(Json(json!({
         "users": users,
         "_metadata": Metadata {
@@ -122,18 +183,28 @@ pub async fn get(Query(query): Query<GetUsersQuery>, State(state): State<AppStat
 pub async fn get_by_id(
     Path(id): Path<String>,
     State(state): State<AppState>,
-    Session(user): Session<User>,
+    Session(auth): Session<AuthContext>,
 ) -> UserResult {
-    let user_id = validate_user_id(id, user.as_ref(), None)?;
+    let auth = auth.ok_or(UserError::Unauthorized)?;
+    let organization_id = auth.user.organization_id;
+    let user_id = validate_user_id(id, Some(auth.user.id))?;
     let mut conn = state.db.acquire().await.unwrap();
+    let member = OrganizationRepo::get_member(organization_id, user_id, &mut conn)
+        .await
+        .map_err(|_| UserError::NotFound)?;
     let user = UserRepo::get_by_id(user_id, &mut conn)
         .await
         .map_err(|_| UserError::NotFound)?;
-    let tags = TagRepo::list_by_user_id(user_id, &mut conn)
+    let tags = TagRepo::list_by_user_id(user_id, organization_id, &mut conn)
         .await
         .unwrap_or(vec![]);
     Ok(Json(json!({
-        "user": UserWithTags { user, tags },
+        "user": UserWithTags {
+            account_status: user.account_status(),
+            user,
+            role: member.role,
+            tags,
+        },
         "_metadata": Metadata::default(),
     }))
     .into_response())
@@ -142,22 +213,52 @@ pub async fn get_by_id(
 pub async fn put(
     Path(id): Path<String>,
     State(state): State<AppState>,
-    Session(user): Session<User>,
+    Extension(auth): Extension<AuthContext>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     TypedHeader(user_agent): TypedHeader<UserAgent>,
-    Json(payload): Json<UserUpdateInput>,
+    Json(mut payload): Json<UserUpdateInput>,
 ) -> UserResult {
-    if user.is_none() {
-        return Err(UserError::Unauthorized);
+    let user_id = validate_user_id(id, Some(auth.user.id))?;
+    // Everyone may update their own profile, but not their own role
+    if user_id != auth.user.id || payload.role.as_ref().is_some_and(|r| *r != auth.user.role) {
+        auth.require_permission(Permission::UsersWrite)?;
     }
+    let user = &auth.user;
     let conn = &mut state.db.acquire().await.unwrap();
-    let user_id = validate_user_id(id, user.as_ref(), Some(&[Role::Admin]))?;
-    let user = user.unwrap();
+    if let Some(role) = payload
+        .role
+        .as_ref()
+        .filter(|r| user_id != user.id || **r != user.role)
+    {
+        RoleService::check_assignable(role, &auth, conn).await?;
+    }
     let mut tx = conn.begin().await.unwrap();
-    let before_update = UserRepo::get_by_id(user_id, &mut tx)
+    let before_update = UserRepo::get_by_id_in_organization(user_id, user.organization_id, &mut tx)
         .await
         .map_err(|_| UserError::NotFound)?;
-    let updated = UserRepo::update_one(user_id, payload, &mut tx)
+    // The account is shared by all organizations of the user. Otherwise the admin of one
+    // organization could e.g. change the email and take over the account in the others.
+    if user_id != user.id && payload.changes_account(&before_update) {
+        require_default_organization(&auth, &mut tx).await?;
+    }
+    // A new email is only used once it is confirmed from the new address
+    if let Some(new_email) = payload
+        .email
+        .take()
+        .filter(|email| *email != before_update.email)
+    {
+        // The admin could otherwise receive the confirmation and take over the account
+        if auth.impersonator_id.is_some() {
+            return Err(PermissionError::ImpersonationNotAllowed.into());
+        }
+        EmailVerificationService::request_email_change(&before_update, &new_email, &mut tx)
+            .await
+            .map_err(|e| match e {
+                EmailVerificationError::EmailTaken => UserError::EmailTaken,
+                e => UserError::InternalServerError(e.to_string()),
+            })?;
+    }
+    let updated = UserRepo::update_one(user_id, payload, user.organization_id, &mut tx)
         .await
         .map_err(|_| UserError::DatabaseError)?;
     let _ = ActivityRepo::create_one(
@@ -181,18 +282,46 @@ pub async fn put(
     .into_response())
 }
 
+/// Removes the user from the active organization. Users who aren't a member of any other
+/// organization are deleted instead and stay a member, so that the organization can restore or
+/// purge them. Their sessions and refresh tokens are revoked.
 pub async fn delete(
     Path(id): Path<Uuid>,
     State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     TypedHeader(user_agent): TypedHeader<UserAgent>,
     Session(current_user): Session<User>,
 ) -> UserResult {
     if let Some(current_user) = current_user {
         let conn = &mut state.db.acquire().await.unwrap();
-        UserRepo::delete_one(id, current_user.id, conn)
+        let mut tx = conn.begin().await.unwrap();
+        UserRepo::get_by_id_in_organization(id, auth.user.organization_id, &mut tx)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => UserError::NotFound,
+                _ => UserError::DatabaseError,
+            })?;
+        let memberships = OrganizationRepo::list_memberships_for_user(id, &mut tx)
             .await
             .map_err(|_| UserError::DatabaseError)?;
+        if memberships.len() > 1 {
+            OrganizationRepo::delete_member(auth.user.organization_id, id, &mut tx)
+                .await
+                .map_err(|_| UserError::DatabaseError)?;
+        } else {
+            UserRepo::delete_one(id, current_user.id, &mut tx)
+                .await
+                .map_err(|_| UserError::DatabaseError)?;
+            SessionService::revoke_all(id, None, &state.event_channel, &mut tx)
+                .await
+                .map_err(|_| UserError::DatabaseError)?;
+            TokenRepo::delete_all_of_type_for_user(id, TokenType::Refresh, &mut tx)
+                .await
+                .map_err(|_| UserError::DatabaseError)?;
+        }
+        tx.commit().await.unwrap();
+        JwtService::reload_revocations();
         let _ = ActivityRepo::create_one(
             ActivityEntry::Delete {
                 ip_address: Some(addr.ip().into()),
@@ -215,6 +344,251 @@ pub async fn delete(
     Err(UserError::Unauthorized)
 }
 
+/// Undoes the deletion of a user of the active organization
+pub async fn restore(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+) -> UserResult {
+    let conn = &mut state.db.acquire().await.unwrap();
+    UserRepo::get_deleted_in_organization(id, auth.user.organization_id, conn)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => UserError::NotFound,
+            _ => UserError::DatabaseError,
+        })?;
+    let restored = UserRepo::restore_one(id, conn)
+        .await
+        .map_err(|_| UserError::DatabaseError)?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::Restore {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: auth.user.id,
+            table_name: USER_TABLE_NAME.to_string(),
+            item_id: id.to_string(),
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "restored": restored,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+/// Permanently deletes a deleted user of the active organization with their tokens, sessions
+/// and tag links. The activity of the user is kept.
+pub async fn purge(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+) -> UserResult {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let mut tx = conn.begin().await.unwrap();
+    UserRepo::get_deleted_in_organization(id, auth.user.organization_id, &mut tx)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => UserError::NotFound,
+            _ => UserError::DatabaseError,
+        })?;
+    if UserRepo::owns_service_accounts(id, &mut tx)
+        .await
+        .map_err(|_| UserError::DatabaseError)?
+    {
+        return Err(UserError::OwnsServiceAccounts);
+    }
+    let purged = UserRepo::purge_one(id, &mut tx)
+        .await
+        .map_err(|_| UserError::DatabaseError)?;
+    // The access tokens of the user are rejected by this entry once the user is gone
+    ActivityRepo::create_one(
+        ActivityEntry::HardDelete {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: auth.user.id,
+            table_name: USER_TABLE_NAME.to_string(),
+            item_id: id.to_string(),
+        },
+        &mut tx,
+    )
+    .await
+    .map_err(|_| UserError::DatabaseError)?;
+    tx.commit().await.unwrap();
+    let avatar_path = state.upload_path.join("user-avatar").join(id.to_string());
+    let _ = tokio::fs::remove_file(avatar_path).await;
+    Ok(Json(json!({
+        "purged": {
+            "id": purged.id,
+        },
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+/// Lifts the lockout after too many failed login attempts
+pub async fn unlock(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Session(current_user): Session<User>,
+) -> UserResult {
+    if let Some(current_user) = current_user {
+        let conn = &mut state.db.acquire().await.unwrap();
+        UserRepo::get_by_id_in_organization(id, auth.user.organization_id, conn)
+            .await
+            .map_err(|_| UserError::NotFound)?;
+        let user = LoginThrottleService::unlock(id, conn)
+            .await
+            .map_err(|_| UserError::DatabaseError)?;
+        let _ = ActivityRepo::create_one(
+            ActivityEntry::AccountUnlock {
+                ip_address: Some(addr.ip().into()),
+                user_agent: Some(user_agent.to_string()),
+                action_by_id: current_user.id,
+                item_id: id,
+            },
+            conn,
+        )
+        .await;
+        return Ok(Json(json!({
+            "user": user,
+            "_metadata": Metadata::default()
+        }))
+        .into_response());
+    }
+    Err(UserError::Unauthorized)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SuspendBody {
+    /// The suspension lasts until it is lifted without an end
+    #[serde(default, with = "ts_milliseconds_option")]
+    suspended_until: Option<DateTime<Utc>>,
+    /// Shown to the user in the email about the suspension
+    reason: Option<String>,
+}
+pub async fn suspend(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(body): Json<SuspendBody>,
+) -> Result<Response, SuspensionError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let suspended = SuspensionService::suspend(
+        id,
+        body.suspended_until,
+        body.reason,
+        &auth,
+        &state.event_channel,
+        conn,
+    )
+    .await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::AccountSuspend {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: auth.user.id,
+            item_id: id,
+            new_data: suspension_data(&suspended),
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "user": suspended,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+pub async fn unsuspend(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+) -> Result<Response, SuspensionError> {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let (before, unsuspended) = SuspensionService::unsuspend(id, &auth, conn).await?;
+    let _ = ActivityRepo::create_one(
+        ActivityEntry::AccountUnsuspend {
+            ip_address: Some(addr.ip().into()),
+            user_agent: Some(user_agent.to_string()),
+            action_by_id: auth.user.id,
+            item_id: id,
+            old_data: suspension_data(&before),
+        },
+        conn,
+    )
+    .await;
+    Ok(Json(json!({
+        "user": unsuspended,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+/// The suspension of the user for the activity
+fn suspension_data(user: &User) -> String {
+    json!({
+        "suspendedAt": user.suspended_at,
+        "suspendedUntil": user.suspended_until,
+        "suspendedBy": user.suspended_by,
+        "suspensionReason": user.suspension_reason,
+    })
+    .to_string()
+}
+
+/// Makes the user change the password on the next login
+pub async fn require_password_change(
+    Path(id): Path<Uuid>,
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Session(current_user): Session<User>,
+) -> UserResult {
+    if let Some(current_user) = current_user {
+        let conn = &mut state.db.acquire().await.unwrap();
+        UserRepo::get_by_id_in_organization(id, auth.user.organization_id, conn)
+            .await
+            .map_err(|_| UserError::NotFound)?;
+        let user = UserRepo::update_password_change_required(id, true, conn)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => UserError::NotFound,
+                _ => UserError::DatabaseError,
+            })?;
+        let _ = ActivityRepo::create_one(
+            ActivityEntry::PasswordChangeRequire {
+                ip_address: Some(addr.ip().into()),
+                user_agent: Some(user_agent.to_string()),
+                action_by_id: current_user.id,
+                item_id: id,
+            },
+            conn,
+        )
+        .await;
+        return Ok(Json(json!({
+            "user": user,
+            "_metadata": Metadata::default()
+        }))
+        .into_response());
+    }
+    Err(UserError::Unauthorized)
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct UserPostBody {
@@ -227,25 +601,45 @@ pub struct UserPostBody {
     password: String,
     tags: Vec<UpdateTag>,
     role: Option<String>,
+    /// Makes the user replace the password set by the admin on the first login
+    require_password_change: Option<bool>,
 }
 #[derive(Serialize)]
 pub struct UserPostResponse {
     created: User,
     _metadata: Metadata,
 }
+/// Creates the user as a member of the active organization
 pub async fn post(
     State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
     Session(session_user): Session<User>,
     Json(body): Json<UserPostBody>,
 ) -> UserResult {
     if let Some(current_user) = session_user {
+        PasswordPolicy::from_env()
+            .check(
+                "password",
+                &body.password,
+                &PasswordUserInfo {
+                    email: &body.email,
+                    first_name: body.first_name.as_deref(),
+                    last_name: body.last_name.as_deref(),
+                },
+            )
+            .await?;
         let conn = &mut state.db.acquire().await.unwrap();
+        let role = body
+            .role
+            .map(Role::from)
+            .unwrap_or(Role::from(Role::AUTHOR));
+        RoleService::check_assignable(&role, &auth, conn).await?;
         let created = AuthService::create_user(
             UserCreateInput {
                 email: body.email,
                 first_name: body.first_name,
                 last_name: body.last_name,
-                role: body.role.map(|r| r.into()),
+                role: Some(role),
                 location: body.location,
                 description: body.description,
                 title: body.title,
@@ -253,11 +647,22 @@ pub async fn post(
             },
             body.tags,
             body.password,
+            auth.user.organization_id,
             current_user.id,
             conn,
         )
         .await
         .map_err(|_| UserError::DatabaseError)?;
+        let created = if body.require_password_change.unwrap_or(false) {
+            UserRepo::update_password_change_required(created.id, true, conn)
+                .await
+                .map_err(|_| UserError::DatabaseError)?
+        } else {
+            created
+        };
+        if let Err(e) = EmailVerificationService::send_verification(&created, conn).await {
+            tracing::error!("Sending the email verification failed: {e}");
+        }
         Ok(Json(UserPostResponse {
             created,
             _metadata: Metadata::default(),
@@ -279,10 +684,11 @@ pub struct UserSearchResponse {
 }
 pub async fn search(
     State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
     Query(query): Query<UserSearchQuery>,
 ) -> UserResult {
     let conn = &mut state.db.acquire().await.unwrap();
-    let result = UserRepo::search(query.q, conn)
+    let result = UserRepo::search(query.q, auth.user.organization_id, conn)
         .await
         .map_err(|_| UserError::DatabaseError)?;
     Ok(Json(UserSearchResponse {
@@ -300,6 +706,7 @@ pub struct UpdatePasswordPayload {
 }
 pub async fn update_password(
     Session(user): Session<User>,
+    Extension(auth): Extension<AuthContext>,
     State(state): State<AppState>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     TypedHeader(user_agent): TypedHeader<UserAgent>,
@@ -314,11 +721,14 @@ pub async fn update_password(
             user.id,
             payload.current_password,
             payload.new_password,
+            Some(auth.token.id),
+            &state.event_channel,
             conn,
         )
         .await
         .map_err(|e| match e {
             crate::service::auth::AuthError::InvalidCredentials => UserError::InvalidCredentials,
+            crate::service::auth::AuthError::PasswordPolicy(e) => UserError::PasswordPolicy(e),
             e => UserError::InternalServerError(e.to_string()),
         })?;
         let _ = ActivityRepo::create_one(
@@ -361,7 +771,7 @@ pub async fn update_avatar(
             if !mime.starts_with("image") {
                 return Err(UserError::WrongAvatarFileType);
             }
-            let field_with_io_err = field.map_err(|err| io::Error::new(io::ErrorKind::Other, err));
+            let field_with_io_err = field.map_err(io::Error::other);
             let mut stream = StreamReader::new(field_with_io_err);
             dbg!(&upload_destination);
             let mut file = File::create(&upload_destination)
@@ -424,9 +834,21 @@ pub enum UserError {
     #[status_code(StatusCode::UNAUTHORIZED)]
     Unauthorized,
 
-    #[error("Forbidden")]
+    #[error(transparent)]
     #[status_code(StatusCode::FORBIDDEN)]
-    Forbidden,
+    Permission(#[from] PermissionError),
+
+    #[error("The email is already used by another account")]
+    #[status_code(StatusCode::CONFLICT)]
+    EmailTaken,
+
+    #[error("Unknown role {0}")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    UnknownRole(String),
+
+    #[error("The permission {0} can't be granted")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    PermissionNotGranted(String),
 
     #[error("Invalid id {0}")]
     #[status_code(StatusCode::BAD_REQUEST)]
@@ -452,9 +874,30 @@ pub enum UserError {
     #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
     AvatarFileWriteError,
 
+    #[error("The user still owns service accounts")]
+    #[status_code(StatusCode::CONFLICT)]
+    OwnsServiceAccounts,
+
     #[error("Missing avatar field in multipart")]
     #[status_code(StatusCode::BAD_REQUEST)]
     MissingAvatarField,
+
+    #[error(transparent)]
+    #[status_code(StatusCode::UNPROCESSABLE_ENTITY)]
+    #[field_errors]
+    PasswordPolicy(#[from] PasswordPolicyError),
 }
 
 pub type UserResult = Result<Response, UserError>;
+
+impl From<RoleError> for UserError {
+    fn from(value: RoleError) -> Self {
+        match value {
+            RoleError::UnknownRole(role) => UserError::UnknownRole(role),
+            RoleError::PermissionNotGranted(permission) => {
+                UserError::PermissionNotGranted(permission)
+            }
+            _ => UserError::DatabaseError,
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use axum_extra::{extract::CookieJar, headers::UserAgent, TypedHeader};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::PgConnection;
+use uuid::Uuid;
+use webauthn_rs::prelude::{PublicKeyCredential, RegisterPublicKeyCredential};
+
+use crate::{
+    model::{
+        auth::{Reauthentication, SecondFactor, TokenType},
+        user::User,
+    },
+    repo::{
+        activity::{ActivityEntry, ActivityRepo},
+        token::TokenRepo,
+        user::UserRepo,
+        webauthn_credential::WebauthnCredentialRepo,
+    },
+    service::{
+        auth::AuthService,
+        webauthn::{WebauthnError, WebauthnService},
+    },
+    utils::{auth::AuthContext, extractors::Session, response::Metadata},
+    AppState,
+};
+
+use super::auth::{auth_error_response, session_response};
+
+/// Adding a passkey requires a session and the password and second factor of the user, so that
+/// a stolen token or an unattended browser can't add a login. The ceremony that is started
+/// expires after a few minutes.
+pub async fn start_registration(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Session(user): Session<User>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(payload): Json<Reauthentication>,
+) -> WebauthnRouteResult {
+    if let Some(user) = user {
+        require_session(&auth)?;
+        let conn = &mut state.db.acquire().await.unwrap();
+        if let Err(e) = reauthenticate(&state, &user, &payload, addr, &user_agent, conn).await {
+            return Ok(e);
+        }
+        let (ceremony_id, options) =
+            WebauthnService::start_registration(&state.webauthn, &user, conn).await?;
+        Ok(Json(json!({
+            "ceremonyId": ceremony_id,
+            "options": options,
+            "_metadata": Metadata::default(),
+        }))
+        .into_response())
+    } else {
+        Err(WebauthnError::Unauthorized)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FinishRegistrationPayload {
+    ceremony_id: Uuid,
+    /// A name to recognize the passkey in the list of credentials
+    name: String,
+    credential: RegisterPublicKeyCredential,
+}
+pub async fn finish_registration(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Session(user): Session<User>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(payload): Json<FinishRegistrationPayload>,
+) -> WebauthnRouteResult {
+    if let Some(user) = user {
+        require_session(&auth)?;
+        let conn = &mut state.db.acquire().await.unwrap();
+        let credential = WebauthnService::finish_registration(
+            &state.webauthn,
+            &user,
+            payload.ceremony_id,
+            &payload.name,
+            &payload.credential,
+            conn,
+        )
+        .await?;
+        let _ = ActivityRepo::create_one(
+            ActivityEntry::WebauthnCredentialAdd {
+                ip_address: Some(addr.ip().into()),
+                user_agent: Some(user_agent.to_string()),
+                action_by_id: user.id,
+                item_id: credential.id.to_string(),
+            },
+            conn,
+        )
+        .await;
+        Ok(Json(json!({
+            "credential": credential,
+            "_metadata": Metadata::default(),
+        }))
+        .into_response())
+    } else {
+        Err(WebauthnError::Unauthorized)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct StartLoginPayload {
+    email: String,
+}
+pub async fn start_login(
+    State(state): State<AppState>,
+    Json(payload): Json<StartLoginPayload>,
+) -> WebauthnRouteResult {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let user = UserRepo::get_by_email(payload.email, conn)
+        .await
+        .map_err(|_| WebauthnError::InvalidCredentials)?;
+    let (ceremony_id, options) =
+        WebauthnService::start_authentication(&state.webauthn, &user, conn)
+            .await
+            .map_err(|e| match e {
+                WebauthnError::NoCredentials => WebauthnError::InvalidCredentials,
+                e => e,
+            })?;
+    Ok(Json(json!({
+        "ceremonyId": ceremony_id,
+        "options": options,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FinishLoginPayload {
+    ceremony_id: Uuid,
+    credential: PublicKeyCredential,
+}
+pub async fn finish_login(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(payload): Json<FinishLoginPayload>,
+) -> Response {
+    let conn = &mut state.db.acquire().await.unwrap();
+    match AuthService::login_webauthn(
+        &state.webauthn,
+        payload.ceremony_id,
+        &payload.credential,
+        Some(addr.ip().into()),
+        user_agent.to_string(),
+        conn,
+    )
+    .await
+    {
+        Ok((user, session_with_token)) => {
+            session_response(jar, user, session_with_token, addr, user_agent, conn).await
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct StartSecondFactorPayload {
+    /// The challenge returned by the login endpoint
+    challenge: String,
+}
+pub async fn start_second_factor(
+    State(state): State<AppState>,
+    Json(payload): Json<StartSecondFactorPayload>,
+) -> WebauthnRouteResult {
+    let conn = &mut state.db.acquire().await.unwrap();
+    let challenge =
+        TokenRepo::get_valid_by_token(&payload.challenge, TokenType::TwoFactorChallenge, conn)
+            .await
+            .map_err(|_| WebauthnError::InvalidCredentials)?;
+    let user = UserRepo::get_by_id(challenge.user_id, conn)
+        .await
+        .map_err(|_| WebauthnError::InvalidCredentials)?;
+    let (ceremony_id, options) =
+        WebauthnService::start_authentication(&state.webauthn, &user, conn).await?;
+    Ok(Json(json!({
+        "ceremonyId": ceremony_id,
+        "options": options,
+        "_metadata": Metadata::default(),
+    }))
+    .into_response())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FinishSecondFactorPayload {
+    challenge: String,
+    ceremony_id: Uuid,
+    credential: PublicKeyCredential,
+}
+pub async fn finish_second_factor(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Json(payload): Json<FinishSecondFactorPayload>,
+) -> Response {
+    let conn = &mut state.db.acquire().await.unwrap();
+    match AuthService::login_second_factor_webauthn(
+        &state.webauthn,
+        &payload.challenge,
+        payload.ceremony_id,
+        &payload.credential,
+        Some(addr.ip().into()),
+        user_agent.to_string(),
+        conn,
+    )
+    .await
+    {
+        Ok((user, session_with_token)) => {
+            session_response(jar, user, session_with_token, addr, user_agent, conn).await
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+pub async fn list_credentials(
+    State(state): State<AppState>,
+    Session(user): Session<User>,
+) -> WebauthnRouteResult {
+    if let Some(user) = user {
+        let conn = &mut state.db.acquire().await.unwrap();
+        let credentials = WebauthnCredentialRepo::list_for_user(user.id, conn)
+            .await
+            .map_err(|_| WebauthnError::DatabaseError)?;
+        Ok(Json(json!({
+            "credentials": credentials,
+            "_metadata": Metadata::default(),
+        }))
+        .into_response())
+    } else {
+        Err(WebauthnError::Unauthorized)
+    }
+}
+
+/// Like adding a passkey, removing one requires a session and the password and second factor
+pub async fn delete_credential(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    Session(user): Session<User>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    Path(id): Path<i32>,
+    Json(payload): Json<Reauthentication>,
+) -> WebauthnRouteResult {
+    if let Some(user) = user {
+        require_session(&auth)?;
+        let conn = &mut state.db.acquire().await.unwrap();
+        if let Err(e) = reauthenticate(&state, &user, &payload, addr, &user_agent, conn).await {
+            return Ok(e);
+        }
+        WebauthnCredentialRepo::delete_by_id_for_user(id, user.id, conn)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => WebauthnError::NotFound,
+                _ => WebauthnError::DatabaseError,
+            })?;
+        let _ = ActivityRepo::create_one(
+            ActivityEntry::WebauthnCredentialRemove {
+                ip_address: Some(addr.ip().into()),
+                user_agent: Some(user_agent.to_string()),
+                action_by_id: user.id,
+                item_id: id.to_string(),
+            },
+            conn,
+        )
+        .await;
+        Ok(Json(json!({
+            "deleted": true,
+            "_metadata": Metadata::default(),
+        }))
+        .into_response())
+    } else {
+        Err(WebauthnError::Unauthorized)
+    }
+}
+
+/// Passkeys are managed by the user in the browser, not with access tokens
+fn require_session(auth: &AuthContext) -> Result<(), WebauthnError> {
+    match auth.token.token_type {
+        TokenType::Session => Ok(()),
+        _ => Err(WebauthnError::SessionRequired),
+    }
+}
+
+/// Returns the error response of a failed re-authentication, which includes when to retry
+async fn reauthenticate(
+    state: &AppState,
+    user: &User,
+    reauthentication: &Reauthentication,
+    addr: SocketAddr,
+    user_agent: &UserAgent,
+    conn: &mut PgConnection,
+) -> Result<(), Response> {
+    AuthService::reauthenticate(
+        &state.webauthn,
+        user,
+        reauthentication,
+        Some(addr.ip().into()),
+        user_agent.as_str(),
+        conn,
+    )
+    .await
+    .map_err(auth_error_response)?;
+    if let Some(SecondFactor::RecoveryCode { .. }) = reauthentication.second_factor {
+        let _ = ActivityRepo::create_one(
+            ActivityEntry::RecoveryCodeUse {
+                ip_address: Some(addr.ip().into()),
+                user_agent: Some(user_agent.to_string()),
+                action_by_id: user.id,
+            },
+            conn,
+        )
+        .await;
+    }
+    Ok(())
+}
+
+type WebauthnRouteResult = Result<Response, WebauthnError>;
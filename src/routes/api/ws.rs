@@ -2,25 +2,46 @@ use std::net::SocketAddr;
 
 use axum::{
     extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
         ConnectInfo, Query, State,
     },
     http::StatusCode,
     response::{IntoResponse, Response},
+    Extension,
 };
 use chrono::Utc;
 use futures::{sink::SinkExt, stream::StreamExt};
 use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
 use uuid::Uuid;
 
 use crate::{
     events::Event,
     model::{auth::UserStatus, user::User},
     repo::user::UserRepo,
-    utils::extractors::Session,
+    utils::{auth::AuthContext, extractors::Session},
     AppState,
 };
 
+/// Sent before a socket is closed, because the session it was opened with ended
+fn session_revoked_message() -> Message {
+    Message::Close(Some(CloseFrame {
+        code: close_code::POLICY,
+        reason: "Session revoked".into(),
+    }))
+}
+
+/// Resolves once the session of the token is revoked
+async fn session_revoked(mut event_receiver: broadcast::Receiver<Event>, token_id: i32) {
+    loop {
+        match event_receiver.recv().await {
+            Ok(Event::SessionsRevoked { token_ids }) if token_ids.contains(&token_id) => return,
+            Err(RecvError::Closed) => return std::future::pending().await,
+            _ => {}
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct UserStatusQuery {
     id: Uuid,
@@ -39,13 +60,16 @@ pub async fn user_status(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Query(query): Query<UserStatusQuery>,
     State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
 ) -> Result<Response, Response> {
     let mut conn = state.db.acquire().await.unwrap();
-    let user = UserRepo::get_by_id(query.id, &mut conn)
+    let user = UserRepo::get_by_id_in_organization(query.id, auth.user.organization_id, &mut conn)
         .await
         .map_err(|_| (StatusCode::NOT_FOUND, "User not found").into_response())?;
     Ok(ws
-        .on_upgrade(move |socket| handle_user_status_socket(socket, addr, state, user))
+        .on_upgrade(move |socket| {
+            handle_user_status_socket(socket, addr, state, user, auth.token.id)
+        })
         .into_response())
 }
 async fn handle_user_status_socket(
@@ -53,6 +77,7 @@ async fn handle_user_status_socket(
     addr: SocketAddr,
     state: AppState,
     user: User,
+    token_id: i32,
 ) {
     let (mut socket_tx, mut socket_rx) = socket.split();
     let chan = state.event_channel;
@@ -91,6 +116,12 @@ async fn handle_user_status_socket(
                         }
                     }
                 }
+                Event::SessionsRevoked { token_ids } => {
+                    if token_ids.contains(&token_id) {
+                        let _ = socket_tx.send(session_revoked_message()).await;
+                        break;
+                    }
+                }
             }
         }
     });
@@ -128,6 +159,7 @@ pub async fn user_me_status(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     State(state): State<AppState>,
     Session(user): Session<User>,
+    Extension(auth): Extension<AuthContext>,
 ) -> impl IntoResponse {
     if let Some(user) = user {
         // Ignore the result, because this errors if there are no receivers in the channel
@@ -136,7 +168,9 @@ pub async fn user_me_status(
             new_status: UserStatus::Online,
         });
         // TODO: update in database
-        ws.on_upgrade(move |socket| handle_user_me_status_socket(socket, addr, state, user))
+        ws.on_upgrade(move |socket| {
+            handle_user_me_status_socket(socket, addr, state, user, auth.token.id)
+        })
     } else {
         (StatusCode::UNAUTHORIZED, "Unauthorized").into_response()
     }
@@ -153,9 +187,11 @@ async fn handle_user_me_status_socket(
     addr: SocketAddr,
     state: AppState,
     user: User,
+    token_id: i32,
 ) {
     let (mut socket_tx, mut socket_rx) = socket.split();
     let chan = state.event_channel;
+    let revoked = session_revoked(chan.subscribe(), token_id);
     let mut conn = state.db.acquire().await.unwrap();
 
     socket_tx
@@ -164,7 +200,19 @@ async fn handle_user_me_status_socket(
         .unwrap();
 
     let socket_rx_task = tokio::spawn(async move {
-        while let Some(Ok(msg)) = socket_rx.next().await {
+        tokio::pin!(revoked);
+        loop {
+            let msg = tokio::select! {
+                msg = socket_rx.next() => msg,
+                _ = &mut revoked => {
+                    let _ = socket_tx.send(session_revoked_message()).await;
+                    tracing::info!("Session revoked, disconnected from me-user-status: {addr}");
+                    break;
+                }
+            };
+            let Some(Ok(msg)) = msg else {
+                break;
+            };
             match msg {
                 Message::Text(text) => {
                     let event: UserMeStatusMessage = match serde_json::from_str(&text) {
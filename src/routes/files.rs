@@ -1,18 +1,30 @@
 use axum::{
     body::Body,
     extract::{Path, State},
+    http::StatusCode,
     response::{IntoResponse, Response},
+    Extension,
 };
 use tokio::fs::File;
 use tokio_util::io::ReaderStream;
+use uuid::Uuid;
 
-use crate::AppState;
+use crate::{repo::organization::OrganizationRepo, utils::auth::AuthContext, AppState};
 
+/// Only the avatars of members of the active organization are served
 pub async fn avatar(
     State(state): State<AppState>,
-    Path(user_id): Path<String>,
+    Extension(auth): Extension<AuthContext>,
+    Path(user_id): Path<Uuid>,
 ) -> Result<Response, Response> {
-    let avatar_path = state.upload_path.join("user-avatar").join(user_id);
+    let conn = &mut state.db.acquire().await.unwrap();
+    OrganizationRepo::get_member(auth.user.organization_id, user_id, conn)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND.into_response())?;
+    let avatar_path = state
+        .upload_path
+        .join("user-avatar")
+        .join(user_id.to_string());
     let file = File::open(avatar_path)
         .await
         .map_err(|e| Response::new(Body::from(e.to_string())))?;
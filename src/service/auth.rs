@@ -1,41 +1,72 @@
-use std::{num::NonZeroU32, str::FromStr};
+use std::str::FromStr;
 
 use axum::{http::StatusCode, response::IntoResponse, Json};
-use chrono::Utc;
+use chrono::{Duration, Utc};
 use macros::JsonErrorResponse;
-use rand::Rng;
-use ring::{digest, pbkdf2};
 use sqlx::{types::ipnetwork::IpNetwork, Acquire, PgConnection, PgPool};
 use uuid::Uuid;
+use webauthn_rs::{prelude::PublicKeyCredential, Webauthn};
 
 use crate::{
+    events::EventChannel,
     model::{
-        auth::{SessionWithToken, TokenType},
+        auth::{
+            CreatedToken, Reauthentication, SecondFactor, SecondFactorMethod, SessionWithToken,
+            TokenType, UserKind,
+        },
         user::{User, UserCreateInput},
         UpdateTag,
     },
-    repo::{session::SessionRepo, tag::TagRepo, token::TokenRepo, user::UserRepo},
-    utils::error::ErrorResponse,
+    repo::{
+        organization::OrganizationRepo, session::SessionRepo, settings::SettingsRepo, tag::TagRepo,
+        token::TokenRepo, user::UserRepo,
+    },
+    service::{
+        email_verification::EmailVerificationService,
+        jwt::JwtService,
+        login_throttle::LoginThrottleService,
+        magic_link::MagicLinkService,
+        password_policy::{PasswordPolicy, PasswordPolicyError, PasswordUserInfo},
+        role::RoleService,
+        session::{SessionConfig, SessionService},
+        two_factor::{TwoFactorError, TwoFactorService},
+        webauthn::{WebauthnError, WebauthnService},
+    },
+    utils::{
+        auth::{AuthContext, AuthCredential, AuthUser},
+        error::ErrorResponse,
+        password::{hash_password, needs_rehash, verify_dummy_password},
+    },
 };
 
 #[derive(Clone)]
 pub struct AuthService {}
 
+#[allow(clippy::large_enum_variant)]
+pub enum LoginOutcome {
+    /// The credentials were valid and a session was created
+    Session(User, SessionWithToken),
+    /// The password was valid, but the user has to finish the login with one of the second
+    /// factor methods and this challenge token
+    SecondFactorRequired(CreatedToken, Vec<SecondFactorMethod>),
+}
+
 impl AuthService {
+    /// Creates the user as a member of the organization
     pub async fn create_user(
         data: UserCreateInput<'_>,
         tags: Vec<UpdateTag>,
         password: String,
+        organization_id: Uuid,
         current_user_id: uuid::Uuid,
         db: &mut PgConnection,
     ) -> AuthResult<User> {
-        let (salt, hash) = hash_password(password, None);
+        let password_hash = hash_password(&password);
         let mut tx = db.begin().await.unwrap();
         let created = UserRepo::create_one(
             UserCreateInput {
                 email: data.email,
-                salt: &salt,
-                hash: &hash,
+                password_hash: &password_hash,
                 first_name: data.first_name,
                 last_name: data.last_name,
                 role: data.role,
@@ -43,17 +74,19 @@ impl AuthService {
                 description: data.description,
                 title: data.title,
             },
+            organization_id,
             current_user_id,
             &mut tx,
         )
         .await
         .map_err(|_| AuthError::DatabaseError)?;
-        let tags = TagRepo::create_missing(tags, created.id, &mut tx)
+        let tags = TagRepo::create_missing(tags, organization_id, created.id, &mut tx)
             .await
             .map_err(|_| AuthError::DatabaseError)?;
         UserRepo::update_tags(
             created.id,
             tags.into_iter().map(|t| t.id).collect(),
+            organization_id,
             &mut tx,
         )
         .await
@@ -62,29 +95,50 @@ impl AuthService {
         Ok(created)
     }
 
+    /// Changes the password and ends every other session of the user than the one of
+    /// `keep_token_id`
     pub async fn update_password(
         user_id: Uuid,
         current_password: String,
         new_password: String,
+        keep_token_id: Option<i32>,
+        events: &EventChannel,
         db: &mut PgConnection,
     ) -> AuthResult<User> {
         let from_db = UserRepo::get_by_id(user_id, db)
             .await
             .map_err(|_| AuthError::InvalidCredentials)?;
-        let (_, hash) = hash_password(current_password, Some(from_db.salt.clone()));
-        if hash != *from_db.hash {
+        if !verify_password(&from_db, &current_password) {
             return Err(AuthError::InvalidCredentials);
         }
-        let (salt, hash) = hash_password(new_password, Some(from_db.salt));
-        let updated = UserRepo::update_hash_salt(user_id, &hash, &salt, db)
+        PasswordPolicy::from_env()
+            .check(
+                "new_password",
+                &new_password,
+                &PasswordUserInfo {
+                    email: &from_db.email,
+                    first_name: from_db.first_name.as_deref(),
+                    last_name: from_db.last_name.as_deref(),
+                },
+            )
+            .await?;
+        let updated = UserRepo::update_password_hash(user_id, &hash_password(&new_password), db)
             .await
             .map_err(|e| AuthError::InternalServerError(e.to_string()))?;
+        if let Some(token_id) = keep_token_id {
+            SessionRepo::clear_password_change_required(token_id, db)
+                .await
+                .map_err(|_| AuthError::DatabaseError)?;
+        }
+        AuthService::revoke_after_password_change(user_id, keep_token_id, events, db).await?;
         Ok(updated)
     }
 
+    /// Sets the new password and ends every session of the user
     pub async fn reset_password(
         token: &str,
         new_password: String,
+        events: &EventChannel,
         db: &mut PgConnection,
     ) -> AuthResult<bool> {
         let token = TokenRepo::get_by_token(token, db)
@@ -93,44 +147,521 @@ impl AuthService {
         let user = UserRepo::get_by_id(token.user_id, db)
             .await
             .map_err(|_| AuthError::DatabaseError)?;
-        let (salt, hash) = hash_password(new_password, Some(user.salt));
-        let _ = UserRepo::update_hash_salt(user.id, &hash, &salt, db)
+        PasswordPolicy::from_env()
+            .check(
+                "password",
+                &new_password,
+                &PasswordUserInfo {
+                    email: &user.email,
+                    first_name: user.first_name.as_deref(),
+                    last_name: user.last_name.as_deref(),
+                },
+            )
+            .await?;
+        let _ = UserRepo::update_password_hash(user.id, &hash_password(&new_password), db)
             .await
             .map_err(|e| AuthError::InternalServerError(e.to_string()))?;
-        let _ = TokenRepo::delete_by_id(token.id, token.user_id, db).await;
+        AuthService::revoke_after_password_change(user.id, None, events, db).await?;
         Ok(true)
     }
 
+    /// Whoever knew the old password or requested a reset or login link must not stay logged in
+    async fn revoke_after_password_change(
+        user_id: Uuid,
+        keep_token_id: Option<i32>,
+        events: &EventChannel,
+        db: &mut PgConnection,
+    ) -> AuthResult<()> {
+        SessionService::revoke_all(user_id, keep_token_id, events, db).await?;
+        for token_type in [
+            TokenType::PasswordReset,
+            TokenType::MagicLink,
+            TokenType::Refresh,
+        ] {
+            TokenRepo::delete_all_of_type_for_user(user_id, token_type, db)
+                .await
+                .map_err(|_| AuthError::DatabaseError)?;
+        }
+        Ok(())
+    }
+
     pub async fn login(
         email: String,
         password: String,
         ip: Option<IpNetwork>,
         user_agent: String,
         db: &mut PgConnection,
+    ) -> AuthResult<LoginOutcome> {
+        let user =
+            AuthService::verify_password_login(email, &password, ip, &user_agent, db).await?;
+        AuthService::start_session_or_second_factor(user, ip, user_agent, db).await
+    }
+
+    /// Logs in with the password for a JWT instead of a session. Without a session there is no
+    /// second step, so users with a second factor or a pending password change have to log in
+    /// with a session and exchange it. Returns the organization the tokens act in as well.
+    pub async fn login_for_token(
+        email: String,
+        password: String,
+        ip: Option<IpNetwork>,
+        user_agent: String,
+        db: &mut PgConnection,
+    ) -> AuthResult<(User, Uuid)> {
+        let user =
+            AuthService::verify_password_login(email, &password, ip, &user_agent, db).await?;
+        AuthService::check_human(&user)?;
+        AuthService::check_not_suspended(&user)?;
+        AuthService::check_email_verified(&user, db).await?;
+        if TwoFactorService::is_enabled(&user)
+            || WebauthnService::has_credentials(&user, db)
+                .await
+                .map_err(|_| AuthError::DatabaseError)?
+        {
+            return Err(AuthError::SecondFactorRequired);
+        }
+        if AuthService::password_change_required(&user, db).await? {
+            return Err(AuthError::PasswordChangeRequired);
+        }
+        let organization_id = AuthService::login_organization(&user, db).await?;
+        LoginThrottleService::record_success(&user, db).await?;
+        Ok((user, organization_id))
+    }
+
+    /// A login starts in the organization the user joined first. They can switch to their other
+    /// organizations afterwards.
+    pub async fn login_organization(user: &User, db: &mut PgConnection) -> AuthResult<Uuid> {
+        let memberships = OrganizationRepo::list_memberships_for_user(user.id, db)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+        memberships
+            .first()
+            .map(|m| m.organization_id)
+            .ok_or(AuthError::NoOrganization)
+    }
+
+    /// Checks the password within the limits of the login throttle
+    async fn verify_password_login(
+        email: String,
+        password: &str,
+        ip: Option<IpNetwork>,
+        user_agent: &str,
+        db: &mut PgConnection,
+    ) -> AuthResult<User> {
+        let user = match UserRepo::get_by_email(email.clone(), db).await {
+            Ok(user) => Some(user),
+            Err(sqlx::Error::RowNotFound) => None,
+            Err(_) => return Err(AuthError::DatabaseError),
+        };
+        let mut tx = db.begin().await.map_err(|_| AuthError::DatabaseError)?;
+        LoginThrottleService::check(user.as_ref(), ip, &mut tx).await?;
+        let valid = match &user {
+            Some(user) => verify_password(user, password),
+            None => {
+                verify_dummy_password(password);
+                false
+            }
+        };
+        let user = match user {
+            Some(user) if valid => user,
+            user => {
+                LoginThrottleService::record_failure(
+                    user.as_ref(),
+                    &email,
+                    ip,
+                    user_agent,
+                    &mut tx,
+                )
+                .await?;
+                tx.commit().await.map_err(|_| AuthError::DatabaseError)?;
+                return Err(AuthError::InvalidCredentials);
+            }
+        };
+        tx.commit().await.map_err(|_| AuthError::DatabaseError)?;
+        // The password is only known during the login, so outdated hashes are replaced here
+        if needs_rehash(&user.password_hash) {
+            return Ok(
+                UserRepo::replace_password_hash(user.id, &hash_password(password), db)
+                    .await
+                    .unwrap_or(user),
+            );
+        }
+        Ok(user)
+    }
+
+    /// Logs in with a link sent by email instead of the password. The second factor is still
+    /// required.
+    pub async fn login_magic_link(
+        token: &str,
+        ip: Option<IpNetwork>,
+        user_agent: String,
+        db: &mut PgConnection,
+    ) -> AuthResult<LoginOutcome> {
+        let user = MagicLinkService::take_valid(token, ip, &user_agent, db).await?;
+        LoginThrottleService::check(Some(&user), ip, db).await?;
+        AuthService::start_session_or_second_factor(user, ip, user_agent, db).await
+    }
+
+    /// Logs in a user an OpenID Connect provider vouched for. The provider replaces the
+    /// password, but not the second factor.
+    pub async fn login_oidc(
+        user: User,
+        ip: Option<IpNetwork>,
+        user_agent: String,
+        db: &mut PgConnection,
+    ) -> AuthResult<LoginOutcome> {
+        LoginThrottleService::check(Some(&user), ip, db).await?;
+        AuthService::start_session_or_second_factor(user, ip, user_agent, db).await
+    }
+
+    /// Continues a login after the first factor was verified
+    async fn start_session_or_second_factor(
+        user: User,
+        ip: Option<IpNetwork>,
+        user_agent: String,
+        db: &mut PgConnection,
+    ) -> AuthResult<LoginOutcome> {
+        AuthService::check_human(&user)?;
+        AuthService::check_not_suspended(&user)?;
+        AuthService::check_email_verified(&user, db).await?;
+        let mut methods = vec![];
+        if TwoFactorService::is_enabled(&user) {
+            methods.push(SecondFactorMethod::Totp);
+        }
+        if WebauthnService::has_credentials(&user, db)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?
+        {
+            methods.push(SecondFactorMethod::Webauthn);
+        }
+        if !methods.is_empty() {
+            let challenge = TokenRepo::create_one_two_factor_challenge(user.id, db)
+                .await
+                .map_err(|_| AuthError::DatabaseError)?;
+            return Ok(LoginOutcome::SecondFactorRequired(challenge, methods));
+        }
+        let session_with_token = AuthService::create_session(&user, ip, user_agent, db).await?;
+        Ok(LoginOutcome::Session(user, session_with_token))
+    }
+
+    /// Finishes a login that returned `LoginOutcome::SecondFactorRequired`
+    pub async fn login_second_factor(
+        challenge: &str,
+        second_factor: &SecondFactor,
+        ip: Option<IpNetwork>,
+        user_agent: String,
+        db: &mut PgConnection,
+    ) -> AuthResult<(User, SessionWithToken)> {
+        let challenge = TokenRepo::get_valid_by_token(challenge, TokenType::TwoFactorChallenge, db)
+            .await
+            .map_err(|_| AuthError::InvalidCredentials)?;
+        let user = UserRepo::get_by_id(challenge.user_id, db)
+            .await
+            .map_err(|_| AuthError::InvalidCredentials)?;
+        let mut tx = db.begin().await.map_err(|_| AuthError::DatabaseError)?;
+        LoginThrottleService::check(Some(&user), ip, &mut tx).await?;
+        match TwoFactorService::verify(&user, second_factor, &mut tx).await {
+            Ok(()) => {}
+            Err(TwoFactorError::InvalidCode | TwoFactorError::NotEnabled) => {
+                LoginThrottleService::record_failure(
+                    Some(&user),
+                    &user.email,
+                    ip,
+                    &user_agent,
+                    &mut tx,
+                )
+                .await?;
+                tx.commit().await.map_err(|_| AuthError::DatabaseError)?;
+                return Err(AuthError::InvalidCredentials);
+            }
+            Err(e) => return Err(AuthError::InternalServerError(e.to_string())),
+        }
+        tx.commit().await.map_err(|_| AuthError::DatabaseError)?;
+        let _ = TokenRepo::delete_by_id(challenge.id, user.id, db).await;
+        let session_with_token = AuthService::create_session(&user, ip, user_agent, db).await?;
+        Ok((user, session_with_token))
+    }
+
+    /// Finishes a login that returned `LoginOutcome::SecondFactorRequired` with a passkey. The
+    /// WebAuthn ceremony has to be started for the user of the challenge.
+    pub async fn login_second_factor_webauthn(
+        webauthn: &Webauthn,
+        challenge: &str,
+        ceremony_id: Uuid,
+        credential: &PublicKeyCredential,
+        ip: Option<IpNetwork>,
+        user_agent: String,
+        db: &mut PgConnection,
     ) -> AuthResult<(User, SessionWithToken)> {
-        let user = UserRepo::get_by_email(email, db)
+        let challenge = TokenRepo::get_valid_by_token(challenge, TokenType::TwoFactorChallenge, db)
+            .await
+            .map_err(|_| AuthError::InvalidCredentials)?;
+        let user = UserRepo::get_by_id(challenge.user_id, db)
             .await
             .map_err(|_| AuthError::InvalidCredentials)?;
-        // compare
-        if pbkdf2::verify(
-            PBKDF2_ALG,
-            NonZeroU32::new(600_000).unwrap(),
-            &user.salt,
-            password.as_bytes(),
-            user.hash.as_slice(),
+        AuthService::verify_passkey_login(
+            webauthn,
+            &user,
+            ceremony_id,
+            credential,
+            ip,
+            &user_agent,
+            db,
+        )
+        .await?;
+        let _ = TokenRepo::delete_by_id(challenge.id, user.id, db).await;
+        let session_with_token = AuthService::create_session(&user, ip, user_agent, db).await?;
+        Ok((user, session_with_token))
+    }
+
+    /// Logs in without a password. A passkey always verifies the user, so no second factor is
+    /// needed.
+    pub async fn login_webauthn(
+        webauthn: &Webauthn,
+        ceremony_id: Uuid,
+        credential: &PublicKeyCredential,
+        ip: Option<IpNetwork>,
+        user_agent: String,
+        db: &mut PgConnection,
+    ) -> AuthResult<(User, SessionWithToken)> {
+        let user = WebauthnService::authenticating_user(ceremony_id, db).await?;
+        AuthService::verify_passkey_login(
+            webauthn,
+            &user,
+            ceremony_id,
+            credential,
+            ip,
+            &user_agent,
+            db,
         )
-        .is_ok()
+        .await?;
+        let session_with_token = AuthService::create_session(&user, ip, user_agent, db).await?;
+        Ok((user, session_with_token))
+    }
+
+    /// Checks the passkey of the user within the limits of the login throttle, like
+    /// `verify_password_login`
+    async fn verify_passkey_login(
+        webauthn: &Webauthn,
+        user: &User,
+        ceremony_id: Uuid,
+        credential: &PublicKeyCredential,
+        ip: Option<IpNetwork>,
+        user_agent: &str,
+        db: &mut PgConnection,
+    ) -> AuthResult<()> {
+        let mut tx = db.begin().await.map_err(|_| AuthError::DatabaseError)?;
+        LoginThrottleService::check(Some(user), ip, &mut tx).await?;
+        match WebauthnService::finish_authentication(webauthn, ceremony_id, credential, &mut tx)
+            .await
         {
-            let session_with_token = SessionRepo::create_one_with_token(&user, ip, user_agent, db)
+            Ok(passkey_user) if passkey_user.id == user.id => {}
+            Err(WebauthnError::DatabaseError) => return Err(AuthError::DatabaseError),
+            Err(WebauthnError::InternalServerError(e)) => {
+                return Err(AuthError::InternalServerError(e))
+            }
+            _ => {
+                LoginThrottleService::record_failure(
+                    Some(user),
+                    &user.email,
+                    ip,
+                    user_agent,
+                    &mut tx,
+                )
+                .await?;
+                tx.commit().await.map_err(|_| AuthError::DatabaseError)?;
+                return Err(AuthError::InvalidCredentials);
+            }
+        }
+        tx.commit().await.map_err(|_| AuthError::DatabaseError)
+    }
+
+    /// Confirms that the logged in user is present before a security relevant change. Failures
+    /// count towards the login throttle like failed logins.
+    pub async fn reauthenticate(
+        webauthn: &Webauthn,
+        user: &User,
+        reauthentication: &Reauthentication,
+        ip: Option<IpNetwork>,
+        user_agent: &str,
+        db: &mut PgConnection,
+    ) -> AuthResult<()> {
+        let mut tx = db.begin().await.map_err(|_| AuthError::DatabaseError)?;
+        LoginThrottleService::check(Some(user), ip, &mut tx).await?;
+        let has_totp = TwoFactorService::is_enabled(user);
+        let has_passkeys = WebauthnService::has_credentials(user, &mut tx)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+        // The password is checked first, so a wrong one doesn't use up a recovery code
+        let valid = verify_password(user, &reauthentication.password)
+            && match (&reauthentication.second_factor, &reauthentication.passkey) {
+                (Some(second_factor), _) if has_totp => {
+                    match TwoFactorService::verify(user, second_factor, &mut tx).await {
+                        Ok(()) => true,
+                        Err(TwoFactorError::InvalidCode) => false,
+                        Err(e) => return Err(AuthError::InternalServerError(e.to_string())),
+                    }
+                }
+                (_, Some(passkey)) if has_passkeys => match WebauthnService::finish_authentication(
+                    webauthn,
+                    passkey.ceremony_id,
+                    &passkey.credential,
+                    &mut tx,
+                )
+                .await
+                {
+                    Ok(passkey_user) => passkey_user.id == user.id,
+                    Err(WebauthnError::DatabaseError) => return Err(AuthError::DatabaseError),
+                    Err(_) => false,
+                },
+                _ => !has_totp && !has_passkeys,
+            };
+        if !valid {
+            LoginThrottleService::record_failure(Some(user), &user.email, ip, user_agent, &mut tx)
+                .await?;
+            tx.commit().await.map_err(|_| AuthError::DatabaseError)?;
+            return Err(AuthError::InvalidCredentials);
+        }
+        tx.commit().await.map_err(|_| AuthError::DatabaseError)
+    }
+
+    /// The session is restricted to changing the password if a change is pending
+    async fn create_session(
+        user: &User,
+        ip: Option<IpNetwork>,
+        user_agent: String,
+        db: &mut PgConnection,
+    ) -> AuthResult<SessionWithToken> {
+        AuthService::check_human(user)?;
+        AuthService::check_not_suspended(user)?;
+        AuthService::check_email_verified(user, db).await?;
+        let organization_id = AuthService::login_organization(user, db).await?;
+        LoginThrottleService::record_success(user, db).await?;
+        let password_change_required = AuthService::password_change_required(user, db).await?;
+        let expiration = SessionConfig::from_env().expiration(Utc::now());
+        SessionRepo::create_one_with_token(
+            user,
+            organization_id,
+            ip,
+            user_agent,
+            expiration,
+            None,
+            password_change_required,
+            db,
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("{:?}", e);
+            AuthError::SessionCreateFailed
+        })
+    }
+
+    /// Whether an admin required a new password or the password is older than the maximum age
+    /// of the settings
+    pub async fn password_change_required(user: &User, db: &mut PgConnection) -> AuthResult<bool> {
+        if user.password_change_required {
+            return Ok(true);
+        }
+        let max_age_days = match SettingsRepo::get(db).await {
+            Ok(settings) => settings.password_max_age_days,
+            Err(sqlx::Error::RowNotFound) => None,
+            Err(_) => return Err(AuthError::DatabaseError),
+        };
+        Ok(max_age_days.is_some_and(|days| {
+            user.password_changed_at + Duration::days(days.into()) < Utc::now()
+        }))
+    }
+
+    /// Service accounts only authenticate with access tokens and never get a session
+    fn check_human(user: &User) -> AuthResult<()> {
+        match user.kind {
+            UserKind::Human => Ok(()),
+            UserKind::Service => Err(AuthError::InvalidCredentials),
+        }
+    }
+
+    pub fn check_not_suspended(user: &User) -> AuthResult<()> {
+        if user.is_suspended() {
+            return Err(AuthError::AccountSuspended);
+        }
+        Ok(())
+    }
+
+    /// Rejects users with an unverified email, unless the settings allow their login
+    pub async fn check_email_verified(user: &User, db: &mut PgConnection) -> AuthResult<()> {
+        match EmailVerificationService::can_login(user, db).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(AuthError::EmailNotVerified),
+            Err(_) => Err(AuthError::DatabaseError),
+        }
+    }
+
+    /// Authenticates a request. JWTs are verified without the database, other credentials are
+    /// resolved by `authenticate`.
+    pub async fn authenticate_request(
+        credential: AuthCredential,
+        ip: Option<IpNetwork>,
+        db: &PgPool,
+    ) -> AuthResult<AuthContext> {
+        if let AuthCredential::Jwt(token) = credential {
+            return JwtService::verify(&token, db)
                 .await
-                .map_err(|e| {
-                    tracing::error!("{:?}", e);
-                    AuthError::SessionCreateFailed
-                })?;
-            Ok((user, session_with_token))
-        } else {
-            Err(AuthError::InvalidCredentials)
+                .map_err(|_| AuthError::InvalidCredentials);
+        }
+        let conn = &mut db.acquire().await.map_err(|_| AuthError::DatabaseError)?;
+        AuthService::authenticate(credential, ip, conn).await
+    }
+
+    /// Resolves a credential to its user. Session cookies only accept session tokens and bearer
+    /// credentials only accept static access tokens, expired tokens are rejected for both.
+    /// Sessions are extended by their idle timeout. JWTs are only accepted by
+    /// `authenticate_request`. The request acts in the active organization of the session or
+    /// in the organization of the access token, which the user has to still be a member of.
+    pub async fn authenticate(
+        credential: AuthCredential,
+        ip: Option<IpNetwork>,
+        db: &mut PgConnection,
+    ) -> AuthResult<AuthContext> {
+        let (token, token_type) = match credential {
+            AuthCredential::SessionCookie(token) => (token, TokenType::Session),
+            AuthCredential::Bearer(token) => (token, TokenType::StaticAccess),
+            AuthCredential::Jwt(_) => return Err(AuthError::InvalidCredentials),
+        };
+        let token = TokenRepo::get_valid_by_token(&token, token_type, db)
+            .await
+            .map_err(|_| AuthError::InvalidCredentials)?;
+        let (impersonator_id, password_change_required, organization_id) =
+            if matches!(token.token_type, TokenType::Session) {
+                let session = SessionService::touch(&token, ip, db).await?;
+                (
+                    session.impersonator_id,
+                    session.password_change_required,
+                    session.organization_id,
+                )
+            } else {
+                let organization_id = token.organization_id.ok_or(AuthError::InvalidCredentials)?;
+                (None, false, organization_id)
+            };
+        let user = UserRepo::get_by_id(token.user_id, db)
+            .await
+            .map_err(|_| AuthError::InvalidCredentials)?;
+        if matches!(token.token_type, TokenType::Session) {
+            AuthService::check_human(&user)?;
         }
+        AuthService::check_not_suspended(&user)?;
+        let member = match OrganizationRepo::get_member(organization_id, user.id, db).await {
+            Ok(member) => member,
+            Err(sqlx::Error::RowNotFound) => return Err(AuthError::InvalidCredentials),
+            Err(_) => return Err(AuthError::DatabaseError),
+        };
+        let permissions = RoleService::member_permissions(&member, db)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+        Ok(AuthContext {
+            user: AuthUser::new(user, member, permissions),
+            token,
+            impersonator_id,
+            password_change_required,
+        })
     }
 
     pub async fn check_password_reset_token(token: &str, db: &mut PgConnection) -> bool {
@@ -149,34 +680,24 @@ impl AuthService {
         false
     }
 
+    /// The token comes from a cookie, so it isn't trusted to be a valid or existing one
     pub async fn logout(token: String, db: &PgPool) -> AuthResult<()> {
-        SessionRepo::delete_with_token(
-            sqlx::types::Uuid::from_str(&token).unwrap().to_string(),
-            &mut db.acquire().await.unwrap(),
-        )
-        .await
-        .map_err(|e| AuthError::InternalServerError(e.to_string()))?;
+        let token = sqlx::types::Uuid::from_str(&token)
+            .map_err(|_| AuthError::InvalidCredentials)?
+            .to_string();
+        let conn = &mut db.acquire().await.map_err(|_| AuthError::DatabaseError)?;
+        SessionRepo::delete_with_token(token, conn)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => AuthError::InvalidCredentials,
+                e => AuthError::InternalServerError(e.to_string()),
+            })?;
         Ok(())
     }
 }
 
-static PBKDF2_ALG: pbkdf2::Algorithm = pbkdf2::PBKDF2_HMAC_SHA512;
-pub const CREDENTIAL_LEN: usize = digest::SHA512_OUTPUT_LEN;
-pub const SALT_LEN: usize = 32;
-pub type Credential = [u8; CREDENTIAL_LEN];
-
-fn hash_password(password: String, salt: Option<Vec<u8>>) -> (Vec<u8>, Credential) {
-    let generated = rand::thread_rng().gen::<[u8; SALT_LEN]>().to_vec();
-    let salt = salt.unwrap_or(generated);
-    let mut hash: Credential = [0u8; CREDENTIAL_LEN];
-    pbkdf2::derive(
-        PBKDF2_ALG,
-        NonZeroU32::new(600_000).unwrap(),
-        &salt,
-        password.as_bytes(),
-        &mut hash,
-    );
-    (salt, hash)
+pub fn verify_password(user: &User, password: &str) -> bool {
+    crate::utils::password::verify_password(&user.password_hash, password)
 }
 
 #[derive(thiserror::Error, Debug, JsonErrorResponse)]
@@ -196,6 +717,124 @@ pub enum AuthError {
     #[error("Database error")]
     #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
     DatabaseError,
+
+    #[error("The email is not verified")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    EmailNotVerified,
+
+    #[error("Session not found")]
+    #[status_code(StatusCode::NOT_FOUND)]
+    SessionNotFound,
+
+    #[error("The account is locked, retry in {0} seconds")]
+    #[status_code(StatusCode::LOCKED)]
+    AccountLocked(i64),
+
+    #[error("The account is suspended")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    AccountSuspended,
+
+    #[error("Too many failed login attempts, retry in {0} seconds")]
+    #[status_code(StatusCode::TOO_MANY_REQUESTS)]
+    TooManyAttempts(i64),
+
+    #[error("Too many requests, retry in {0} seconds")]
+    #[status_code(StatusCode::TOO_MANY_REQUESTS)]
+    TooManyRequests(i64),
+
+    #[error("A second factor is required, log in with a session and exchange it instead")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    SecondFactorRequired,
+
+    #[error("The password has to be changed first")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    PasswordChangeRequired,
+
+    #[error("Login links are disabled")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    MagicLinkDisabled,
+
+    #[error("The user isn't a member of any organization")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    NoOrganization,
+
+    #[error(transparent)]
+    #[status_code(StatusCode::UNPROCESSABLE_ENTITY)]
+    #[field_errors]
+    PasswordPolicy(#[from] PasswordPolicyError),
+}
+
+impl AuthError {
+    /// The seconds the client has to wait before the next login attempt
+    pub fn retry_after(&self) -> Option<i64> {
+        match self {
+            AuthError::AccountLocked(seconds)
+            | AuthError::TooManyAttempts(seconds)
+            | AuthError::TooManyRequests(seconds) => Some(*seconds),
+            _ => None,
+        }
+    }
+}
+
+impl From<WebauthnError> for AuthError {
+    fn from(value: WebauthnError) -> Self {
+        match value {
+            WebauthnError::DatabaseError => AuthError::DatabaseError,
+            WebauthnError::InternalServerError(e) => AuthError::InternalServerError(e),
+            _ => AuthError::InvalidCredentials,
+        }
+    }
 }
 
 pub type AuthResult<T> = Result<T, AuthError>;
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::{model::auth::Role, utils::testing};
+
+    #[sqlx::test]
+    async fn checks_the_policy_only_for_the_right_current_password(pool: PgPool) {
+        let conn = &mut pool.acquire().await.unwrap();
+        let organization = testing::setup(conn).await;
+        let user =
+            testing::create_user("user@example.com", Role::AUTHOR, organization.id, conn).await;
+        let events = EventChannel::new();
+        // Someone without the password learns nothing about the policy
+        let result = AuthService::update_password(
+            user.id,
+            "wrong password".to_string(),
+            "short".to_string(),
+            None,
+            &events,
+            conn,
+        )
+        .await;
+        assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+
+        let result = AuthService::update_password(
+            user.id,
+            testing::PASSWORD.to_string(),
+            "short".to_string(),
+            None,
+            &events,
+            conn,
+        )
+        .await;
+        assert!(matches!(result, Err(AuthError::PasswordPolicy(_))));
+
+        let updated = AuthService::update_password(
+            user.id,
+            testing::PASSWORD.to_string(),
+            "another long horse 7".to_string(),
+            None,
+            &events,
+            conn,
+        )
+        .await
+        .unwrap();
+        assert!(verify_password(&updated, "another long horse 7"));
+    }
+}
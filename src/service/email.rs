@@ -1,5 +1,7 @@
 use std::env;
 
+use chrono::{DateTime, Utc};
+
 use lettre::{
     address::AddressError,
     message::{header::ContentType, Mailbox},
@@ -19,31 +21,133 @@ impl EmailService {
     ) -> Result<(), EmailServiceError> {
         let reset_link = format!(
             "{}/admin/reset-password?token={}",
-            env::var("BASE_URL").expect("BASE_URL env var not defined"),
+            config::base_url(),
+            token
+        );
+        EmailService::send(
+            &receiver_email,
+            "Reset your password",
+            format!(
+                r#"<p>Reset your password by clicking this link: <a href="{reset_link}">{reset_link}</a></p>"#,
+            ),
+        )
+    }
+
+    /// Sent to a new user and to the new address of an email change
+    pub async fn send_email_verification_email(
+        receiver_email: String,
+        token: String,
+    ) -> Result<(), EmailServiceError> {
+        let verify_link = format!("{}/admin/verify-email?token={}", config::base_url(), token);
+        EmailService::send(
+            &receiver_email,
+            "Confirm your email",
+            format!(
+                r#"<p>Confirm your email by clicking this link: <a href="{verify_link}">{verify_link}</a></p>"#,
+            ),
+        )
+    }
+
+    /// Warns the old address about an email change, in case the account was taken over
+    pub async fn send_email_change_notice_email(
+        receiver_email: String,
+        new_email: String,
+    ) -> Result<(), EmailServiceError> {
+        let new_email = escape_html(&new_email);
+        EmailService::send(
+            &receiver_email,
+            "Your email is being changed",
+            format!(
+                r#"<p>The email of your account is being changed to {new_email}. If you didn't request this, reset your password and contact an administrator.</p>"#,
+            ),
+        )
+    }
+
+    pub async fn send_magic_link_email(
+        receiver_email: String,
+        token: String,
+        valid_minutes: i64,
+    ) -> Result<(), EmailServiceError> {
+        let login_link = format!("{}/admin/magic-link?token={}", config::base_url(), token);
+        EmailService::send(
+            &receiver_email,
+            "Your login link",
+            format!(
+                r#"<p>Log in by clicking this link: <a href="{login_link}">{login_link}</a></p><p>The link can be used once and expires in {valid_minutes} minutes. If you didn't request it, you can ignore this email.</p>"#,
+            ),
+        )
+    }
+
+    pub async fn send_invitation_email(
+        receiver_email: String,
+        token: String,
+    ) -> Result<(), EmailServiceError> {
+        let invitation_link = format!(
+            "{}/admin/accept-invitation?token={}",
+            config::base_url(),
             token
         );
+        EmailService::send(
+            &receiver_email,
+            &format!("You are invited to {}", config::APP_NAME),
+            format!(
+                r#"<p>You were invited to {}. Choose your password and accept the invitation by clicking this link: <a href="{invitation_link}">{invitation_link}</a></p>"#,
+                config::APP_NAME,
+            ),
+        )
+    }
+
+    pub async fn send_account_suspended_email(
+        receiver_email: String,
+        suspended_until: Option<DateTime<Utc>>,
+        reason: Option<String>,
+    ) -> Result<(), EmailServiceError> {
+        let until = match suspended_until {
+            Some(until) => format!(" until {}", until.format("%Y-%m-%d %H:%M UTC")),
+            None => String::new(),
+        };
+        let reason = match reason {
+            Some(reason) => format!("<p>Reason: {}</p>", escape_html(&reason)),
+            None => String::new(),
+        };
+        EmailService::send(
+            &receiver_email,
+            "Your account was suspended",
+            format!(
+                r#"<p>Your account at {} was suspended{until}. You can't log in until the suspension ends.</p>{reason}"#,
+                config::APP_NAME,
+            ),
+        )
+    }
+
+    pub async fn send_account_unsuspended_email(
+        receiver_email: String,
+    ) -> Result<(), EmailServiceError> {
+        EmailService::send(
+            &receiver_email,
+            "Your account is active again",
+            format!(
+                r#"<p>The suspension of your account at {} was lifted. You can log in again.</p>"#,
+                config::APP_NAME,
+            ),
+        )
+    }
+
+    fn send(receiver_email: &str, subject: &str, body: String) -> Result<(), EmailServiceError> {
         let email = Message::builder()
             .from(Mailbox::new(
                 Some(config::APP_NAME.into()),
-                env::var("SMTP_FROM")
-                    .expect("No SMTP_FROM env var provided")
-                    .parse()?,
+                smtp_var("SMTP_FROM")?.parse()?,
             ))
             .to(receiver_email.parse()?)
-            .subject("Reset your password")
+            .subject(subject)
             .header(ContentType::TEXT_HTML)
-            .body(format!(
-                r#"<p>Reset your password by clicking this link: <a href="{reset_link}">{reset_link}</a></p>"#,
-            ))?;
+            .body(body)?;
 
-        let credentials = Credentials::new(
-            env::var("SMTP_USER").expect("SMTP_USER env var not defined"),
-            env::var("SMTP_PASS").expect("SMTP_PASS env var not defined"),
-        );
-        let mailer =
-            SmtpTransport::relay(&env::var("SMTP_HOST").expect("SMTP_HOST env var not defined"))?
-                .credentials(credentials)
-                .build();
+        let credentials = Credentials::new(smtp_var("SMTP_USER")?, smtp_var("SMTP_PASS")?);
+        let mailer = SmtpTransport::relay(&smtp_var("SMTP_HOST")?)?
+            .credentials(credentials)
+            .build();
         match mailer.send(&email) {
             Ok(_) => Ok(()),
             Err(e) => Err(e.into()),
@@ -51,6 +155,18 @@ impl EmailService {
     }
 }
 
+/// For text that users entered, like the reason of a suspension or a new email
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn smtp_var(key: &'static str) -> Result<String, EmailServiceError> {
+    env::var(key).map_err(|_| EmailServiceError::NotConfigured(key))
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum EmailServiceError {
     #[error("Invalid address format")]
@@ -61,6 +177,9 @@ pub enum EmailServiceError {
 
     #[error("Transport error when sending email")]
     Transport(lettre::transport::smtp::Error),
+
+    #[error("The env var {0} is not set")]
+    NotConfigured(&'static str),
 }
 
 impl From<AddressError> for EmailServiceError {
@@ -0,0 +1,227 @@
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use chrono::{DateTime, Duration, Utc};
+use macros::JsonErrorResponse;
+use sqlx::{types::ipnetwork::IpNetwork, Acquire, PgConnection};
+
+use crate::{
+    config::env_or,
+    model::{auth::TokenType, user::User},
+    repo::{
+        activity::{ActivityEntry, ActivityRepo},
+        email_verification_request::EmailVerificationRequestRepo,
+        settings::SettingsRepo,
+        token::TokenRepo,
+        user::UserRepo,
+    },
+    service::email::EmailService,
+    utils::error::ErrorResponse,
+};
+
+pub struct EmailVerificationConfig {
+    /// Links that can be requested again for an account within the window
+    pub max_requests: i64,
+    /// Links that can be requested again from an IP within the window
+    pub max_requests_per_ip: i64,
+    pub window: Duration,
+}
+
+impl EmailVerificationConfig {
+    pub fn from_env() -> Self {
+        Self {
+            max_requests: env_or("EMAIL_VERIFICATION_MAX_REQUESTS", 3),
+            max_requests_per_ip: env_or("EMAIL_VERIFICATION_MAX_REQUESTS_PER_IP", 10),
+            window: Duration::minutes(env_or("EMAIL_VERIFICATION_REQUEST_WINDOW_MINUTES", 15)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct EmailVerificationService {}
+
+impl EmailVerificationService {
+    /// Sends the verification link again, limited like `MagicLinkService::request`. Unknown
+    /// emails, verified accounts and accounts that reached their limit are ignored without an
+    /// error, so the endpoint doesn't tell which emails have an account.
+    pub async fn request(
+        email: String,
+        ip: Option<IpNetwork>,
+        user_agent: &str,
+        db: &mut PgConnection,
+    ) -> EmailVerificationResult<()> {
+        let config = EmailVerificationConfig::from_env();
+        let since = Utc::now() - config.window;
+        if let Some(ip) = ip {
+            let (count, first_request_at) =
+                EmailVerificationRequestRepo::count_for_ip(ip, since, db)
+                    .await
+                    .map_err(|_| EmailVerificationError::DatabaseError)?;
+            if count >= config.max_requests_per_ip {
+                return Err(EmailVerificationError::TooManyRequests(seconds_until_free(
+                    first_request_at,
+                    &config,
+                )));
+            }
+            // Recorded before the user is looked up, so unknown emails count as well
+            let _ = EmailVerificationRequestRepo::delete_older_than(since, db).await;
+            EmailVerificationRequestRepo::create_one(ip, db)
+                .await
+                .map_err(|_| EmailVerificationError::DatabaseError)?;
+        }
+        let user = match UserRepo::get_by_email(email, db).await {
+            Ok(user) => user,
+            Err(sqlx::Error::RowNotFound) => return Ok(()),
+            Err(_) => return Err(EmailVerificationError::DatabaseError),
+        };
+        let (count, _) =
+            ActivityRepo::count_since("email_verification_request", Some(user.id), None, since, db)
+                .await
+                .map_err(|_| EmailVerificationError::DatabaseError)?;
+        if count >= config.max_requests {
+            tracing::warn!(
+                "Email verification requests of user {} reached the limit",
+                user.id
+            );
+            return Ok(());
+        }
+        match EmailVerificationService::send_verification(&user, db).await {
+            Ok(()) => {}
+            Err(EmailVerificationError::AlreadyVerified) => return Ok(()),
+            // Not returned, so the response doesn't tell that the account exists
+            Err(EmailVerificationError::EmailError(e)) => {
+                tracing::error!("Sending the email verification failed: {e}");
+            }
+            Err(e) => return Err(e),
+        }
+        ActivityRepo::create_one(
+            ActivityEntry::EmailVerificationRequest {
+                ip_address: ip,
+                user_agent: Some(user_agent.to_string()),
+                action_by_id: user.id,
+            },
+            db,
+        )
+        .await
+        .map_err(|_| EmailVerificationError::DatabaseError)?;
+        Ok(())
+    }
+
+    /// Sends a verification link to the pending email of the user or, if there is none, to the
+    /// unverified email. Earlier links stop working.
+    pub async fn send_verification(
+        user: &User,
+        db: &mut PgConnection,
+    ) -> EmailVerificationResult<()> {
+        let receiver = match (&user.pending_email, user.email_verified_at) {
+            (Some(pending_email), _) => pending_email.clone(),
+            (None, None) => user.email.clone(),
+            (None, Some(_)) => return Err(EmailVerificationError::AlreadyVerified),
+        };
+        TokenRepo::delete_all_of_type_for_user(user.id, TokenType::EmailVerification, db)
+            .await
+            .map_err(|_| EmailVerificationError::DatabaseError)?;
+        let token = TokenRepo::create_one_email_verification_token(user.id, db)
+            .await
+            .map_err(|_| EmailVerificationError::DatabaseError)?;
+        EmailService::send_email_verification_email(receiver, token.plaintext)
+            .await
+            .map_err(|e| EmailVerificationError::EmailError(e.to_string()))
+    }
+
+    /// Keeps the new email pending until it is confirmed from the new address and notifies the
+    /// old address about the change
+    pub async fn request_email_change(
+        user: &User,
+        new_email: &str,
+        db: &mut PgConnection,
+    ) -> EmailVerificationResult<User> {
+        // Deleted users keep their email until they are purged
+        match UserRepo::is_email_taken(new_email, db).await {
+            Ok(true) => return Err(EmailVerificationError::EmailTaken),
+            Ok(false) => {}
+            Err(_) => return Err(EmailVerificationError::DatabaseError),
+        }
+        let updated = UserRepo::update_pending_email(user.id, Some(new_email), db)
+            .await
+            .map_err(|_| EmailVerificationError::DatabaseError)?;
+        EmailVerificationService::send_verification(&updated, db).await?;
+        if let Err(e) =
+            EmailService::send_email_change_notice_email(user.email.clone(), new_email.to_string())
+                .await
+        {
+            tracing::error!("Sending the email change notice failed: {e}");
+        }
+        Ok(updated)
+    }
+
+    /// Verifies the email the token was sent to
+    pub async fn confirm(token: &str, db: &mut PgConnection) -> EmailVerificationResult<User> {
+        let mut tx = db.begin().await.unwrap();
+        let token = TokenRepo::get_valid_by_token(token, TokenType::EmailVerification, &mut tx)
+            .await
+            .map_err(|_| EmailVerificationError::InvalidToken)?;
+        let user = UserRepo::verify_email(token.user_id, &mut tx)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::Database(e) if e.is_unique_violation() => {
+                    EmailVerificationError::EmailTaken
+                }
+                _ => EmailVerificationError::DatabaseError,
+            })?;
+        TokenRepo::delete_all_of_type_for_user(user.id, TokenType::EmailVerification, &mut tx)
+            .await
+            .map_err(|_| EmailVerificationError::DatabaseError)?;
+        tx.commit().await.unwrap();
+        Ok(user)
+    }
+
+    /// Whether the user may log in according to the `allow_unverified_login` setting
+    pub async fn can_login(user: &User, db: &mut PgConnection) -> EmailVerificationResult<bool> {
+        if user.email_verified_at.is_some() {
+            return Ok(true);
+        }
+        let settings = match SettingsRepo::get(db).await {
+            Ok(settings) => settings,
+            Err(sqlx::Error::RowNotFound) => Default::default(),
+            Err(_) => return Err(EmailVerificationError::DatabaseError),
+        };
+        Ok(settings.allow_unverified_login)
+    }
+}
+
+/// The earliest request of the window has to leave it before the next one is allowed
+fn seconds_until_free(
+    first_request_at: Option<DateTime<Utc>>,
+    config: &EmailVerificationConfig,
+) -> i64 {
+    let free_at = first_request_at.unwrap_or_else(Utc::now) + config.window;
+    (free_at - Utc::now()).num_seconds().max(0) + 1
+}
+
+#[derive(thiserror::Error, Debug, JsonErrorResponse)]
+pub enum EmailVerificationError {
+    #[error("The link is invalid or expired")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    InvalidToken,
+
+    #[error("The email is already verified")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    AlreadyVerified,
+
+    #[error("The email is already used by another account")]
+    #[status_code(StatusCode::CONFLICT)]
+    EmailTaken,
+
+    #[error("Too many requests, retry in {0} seconds")]
+    #[status_code(StatusCode::TOO_MANY_REQUESTS)]
+    TooManyRequests(i64),
+
+    #[error("Sending the email failed: {0}")]
+    #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
+    EmailError(String),
+
+    #[error("Database error")]
+    #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
+    DatabaseError,
+}
+
+pub type EmailVerificationResult<T> = Result<T, EmailVerificationError>;
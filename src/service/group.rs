@@ -0,0 +1,283 @@
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use macros::JsonErrorResponse;
+use sqlx::{Acquire, PgConnection};
+use uuid::Uuid;
+
+use crate::{
+    model::{
+        auth::{Permission, Role, RoleDefinition},
+        Group, GroupMember,
+    },
+    repo::{group::GroupRepo, role::RoleRepo, user::UserRepo},
+    service::role::{RoleError, RoleService},
+    utils::{auth::AuthContext, error::ErrorResponse},
+};
+
+const MAX_NAME_LEN: usize = 128;
+
+/// What a group is created or updated with
+pub struct GroupInput {
+    pub name: String,
+    pub description: Option<String>,
+    pub parent_id: Option<Uuid>,
+    pub role: Option<Role>,
+}
+
+#[derive(Clone)]
+pub struct GroupService {}
+
+impl GroupService {
+    /// Groups of other organizations aren't found
+    pub async fn get(id: Uuid, organization_id: Uuid, db: &mut PgConnection) -> GroupResult<Group> {
+        GroupRepo::get_by_id(id, organization_id, db)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => GroupError::NotFound,
+                _ => GroupError::DatabaseError,
+            })
+    }
+
+    /// Like roles, nobody can create a group whose role grants permissions they don't have
+    pub async fn create(
+        input: GroupInput,
+        auth: &AuthContext,
+        db: &mut PgConnection,
+    ) -> GroupResult<Group> {
+        let name = GroupService::check_input(&input, auth, db).await?;
+        GroupRepo::create_one(
+            auth.user.organization_id,
+            &name,
+            input.description,
+            input.parent_id,
+            input.role.as_ref(),
+            auth.user.id,
+            db,
+        )
+        .await
+        .map_err(map_write_error)
+    }
+
+    pub async fn update(
+        id: Uuid,
+        input: GroupInput,
+        auth: &AuthContext,
+        db: &mut PgConnection,
+    ) -> GroupResult<Group> {
+        let mut tx = db.begin().await.unwrap();
+        GroupService::get(id, auth.user.organization_id, &mut tx).await?;
+        let name = GroupService::check_input(&input, auth, &mut tx).await?;
+        if let Some(parent_id) = input.parent_id {
+            let subtree = GroupRepo::list_subtree_ids(id, &mut tx)
+                .await
+                .map_err(|_| GroupError::DatabaseError)?;
+            if subtree.contains(&parent_id) {
+                return Err(GroupError::CyclicParent);
+            }
+        }
+        let updated = GroupRepo::update_one(
+            id,
+            auth.user.organization_id,
+            &name,
+            input.description,
+            input.parent_id,
+            input.role.as_ref(),
+            auth.user.id,
+            &mut tx,
+        )
+        .await
+        .map_err(map_write_error)?;
+        tx.commit().await.unwrap();
+        Ok(updated)
+    }
+
+    pub async fn delete(
+        id: Uuid,
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> GroupResult<Group> {
+        GroupRepo::delete_one(id, organization_id, db)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => GroupError::NotFound,
+                sqlx::Error::Database(e) if e.is_foreign_key_violation() => {
+                    GroupError::HasSubgroups
+                }
+                _ => GroupError::DatabaseError,
+            })
+    }
+
+    pub async fn list_members(
+        id: Uuid,
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> GroupResult<Vec<GroupMember>> {
+        GroupService::get(id, organization_id, db).await?;
+        GroupRepo::list_members(id, db)
+            .await
+            .map_err(|_| GroupError::DatabaseError)
+    }
+
+    /// Adds the user to the group or changes whether they are an admin of it. Returns the previous
+    /// membership as well.
+    pub async fn set_member(
+        id: Uuid,
+        user_id: Uuid,
+        is_admin: bool,
+        auth: &AuthContext,
+        db: &mut PgConnection,
+    ) -> GroupResult<(Option<GroupMember>, GroupMember)> {
+        let mut tx = db.begin().await.unwrap();
+        GroupService::check_can_manage(id, auth, &mut tx).await?;
+        match UserRepo::get_by_id_in_organization(user_id, auth.user.organization_id, &mut tx).await
+        {
+            Ok(_) => {}
+            Err(sqlx::Error::RowNotFound) => return Err(GroupError::UserNotFound),
+            Err(_) => return Err(GroupError::DatabaseError),
+        }
+        let previous = GroupRepo::get_member(id, user_id, &mut tx)
+            .await
+            .map_err(|_| GroupError::DatabaseError)?;
+        let member = GroupRepo::upsert_member(id, user_id, is_admin, &mut tx)
+            .await
+            .map_err(|_| GroupError::DatabaseError)?;
+        tx.commit().await.unwrap();
+        Ok((previous, member))
+    }
+
+    pub async fn remove_member(
+        id: Uuid,
+        user_id: Uuid,
+        auth: &AuthContext,
+        db: &mut PgConnection,
+    ) -> GroupResult<GroupMember> {
+        GroupService::check_can_manage(id, auth, db).await?;
+        GroupRepo::delete_member(id, user_id, db)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => GroupError::NotAMember,
+                _ => GroupError::DatabaseError,
+            })
+    }
+
+    /// Members can be managed by users who can manage users and by the group's admins. Group
+    /// admins can't hand out permissions of the group they don't have themselves, e.g. when they
+    /// use an access token with fewer scopes.
+    async fn check_can_manage(
+        id: Uuid,
+        auth: &AuthContext,
+        db: &mut PgConnection,
+    ) -> GroupResult<()> {
+        GroupService::get(id, auth.user.organization_id, db).await?;
+        if auth.has_permission(Permission::UsersWrite) {
+            return Ok(());
+        }
+        let is_admin = GroupRepo::is_admin(id, auth.user.id, db)
+            .await
+            .map_err(|_| GroupError::DatabaseError)?;
+        if !is_admin {
+            return Err(GroupError::NotGroupAdmin);
+        }
+        let roles = RoleRepo::list_for_group(id, db)
+            .await
+            .map_err(|_| GroupError::DatabaseError)?;
+        let permissions: Vec<Permission> =
+            roles.iter().flat_map(RoleDefinition::permissions).collect();
+        Ok(RoleService::check_grantable(&permissions, auth)?)
+    }
+
+    /// Returns the trimmed name
+    async fn check_input(
+        input: &GroupInput,
+        auth: &AuthContext,
+        db: &mut PgConnection,
+    ) -> GroupResult<String> {
+        let name = input.name.trim();
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(GroupError::InvalidName);
+        }
+        if let Some(parent_id) = input.parent_id {
+            match GroupRepo::get_by_id(parent_id, auth.user.organization_id, db).await {
+                Ok(_) => {}
+                Err(sqlx::Error::RowNotFound) => return Err(GroupError::ParentNotFound),
+                Err(_) => return Err(GroupError::DatabaseError),
+            }
+        }
+        if let Some(role) = &input.role {
+            let permissions = RoleService::permissions(role, db).await?;
+            RoleService::check_grantable(&permissions, auth)?;
+        }
+        Ok(name.to_string())
+    }
+}
+
+fn map_write_error(e: sqlx::Error) -> GroupError {
+    match e {
+        sqlx::Error::Database(e) if e.is_unique_violation() => GroupError::NameTaken,
+        _ => GroupError::DatabaseError,
+    }
+}
+
+#[derive(thiserror::Error, Debug, JsonErrorResponse)]
+pub enum GroupError {
+    #[error("Group not found")]
+    #[status_code(StatusCode::NOT_FOUND)]
+    NotFound,
+
+    #[error("Group names can't be empty or longer than 128 characters")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    InvalidName,
+
+    #[error("A group with this name already exists")]
+    #[status_code(StatusCode::CONFLICT)]
+    NameTaken,
+
+    #[error("The parent group doesn't exist")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    ParentNotFound,
+
+    #[error("A group can't be nested in itself or in one of its subgroups")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    CyclicParent,
+
+    #[error("The group still has subgroups")]
+    #[status_code(StatusCode::CONFLICT)]
+    HasSubgroups,
+
+    #[error("Unknown role {0}")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    UnknownRole(String),
+
+    #[error("The permission {0} can't be granted")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    PermissionNotGranted(String),
+
+    #[error("Only admins of the group can manage its members")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    NotGroupAdmin,
+
+    #[error("User not found")]
+    #[status_code(StatusCode::NOT_FOUND)]
+    UserNotFound,
+
+    #[error("The user isn't a member of the group")]
+    #[status_code(StatusCode::NOT_FOUND)]
+    NotAMember,
+
+    #[error("Database error")]
+    #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
+    DatabaseError,
+}
+
+impl From<RoleError> for GroupError {
+    fn from(value: RoleError) -> Self {
+        match value {
+            RoleError::UnknownRole(role) => GroupError::UnknownRole(role),
+            RoleError::PermissionNotGranted(permission) => {
+                GroupError::PermissionNotGranted(permission)
+            }
+            _ => GroupError::DatabaseError,
+        }
+    }
+}
+
+pub type GroupResult<T> = Result<T, GroupError>;
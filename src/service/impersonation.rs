@@ -0,0 +1,141 @@
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use chrono::Utc;
+use macros::JsonErrorResponse;
+use sqlx::{types::ipnetwork::IpNetwork, PgConnection};
+use uuid::Uuid;
+
+use crate::{
+    events::EventChannel,
+    model::{
+        auth::{Permission, SessionWithToken, TokenType, UserKind},
+        user::User,
+    },
+    repo::{organization::OrganizationRepo, session::SessionRepo, user::UserRepo},
+    service::{
+        auth::AuthService,
+        role::RoleService,
+        session::{SessionConfig, SessionService},
+    },
+    utils::{
+        auth::{AuthContext, AuthCredential},
+        error::ErrorResponse,
+    },
+};
+
+#[derive(Clone)]
+pub struct ImpersonationService {}
+
+impl ImpersonationService {
+    /// Opens a session as the target user that is linked to the admin. It ends after
+    /// `IMPERSONATION_MAX_LIFETIME_MINUTES` at the latest.
+    pub async fn start(
+        auth: &AuthContext,
+        target_id: Uuid,
+        ip: Option<IpNetwork>,
+        user_agent: String,
+        db: &mut PgConnection,
+    ) -> ImpersonationResult<(User, SessionWithToken)> {
+        if auth.impersonator_id.is_some() {
+            return Err(ImpersonationError::AlreadyImpersonating);
+        }
+        if !matches!(auth.token.token_type, TokenType::Session) {
+            return Err(ImpersonationError::SessionRequired);
+        }
+        // Admins can only impersonate members of their organization, in that organization
+        let organization_id = auth.user.organization_id;
+        let target = UserRepo::get_by_id_in_organization(target_id, organization_id, db)
+            .await
+            .map_err(|_| ImpersonationError::UserNotFound)?;
+        let target_member = OrganizationRepo::get_member(organization_id, target.id, db)
+            .await
+            .map_err(|_| ImpersonationError::DatabaseError)?;
+        // Otherwise admins could act in the name of each other, service accounts never get a
+        // session and suspended users can't use one
+        let target_permissions = RoleService::member_permissions(&target_member, db)
+            .await
+            .map_err(|_| ImpersonationError::DatabaseError)?;
+        if target.id == auth.user.id
+            || target_permissions.contains(&Permission::UsersImpersonate)
+            || target.kind == UserKind::Service
+            || target.is_suspended()
+        {
+            return Err(ImpersonationError::NotAllowed);
+        }
+        let expiration = SessionConfig::from_env().impersonation_expiration(Utc::now());
+        let session_with_token = SessionRepo::create_one_with_token(
+            &target,
+            organization_id,
+            ip,
+            user_agent,
+            expiration,
+            Some(auth.user.id),
+            false,
+            db,
+        )
+        .await
+        .map_err(|_| ImpersonationError::DatabaseError)?;
+        Ok((target, session_with_token))
+    }
+
+    /// Ends the impersonation session and returns the id of the admin
+    pub async fn stop(
+        auth: &AuthContext,
+        events: &EventChannel,
+        db: &mut PgConnection,
+    ) -> ImpersonationResult<Uuid> {
+        let impersonator_id = auth
+            .impersonator_id
+            .ok_or(ImpersonationError::NotImpersonating)?;
+        let session = SessionRepo::get_by_token_id(auth.token.id, db)
+            .await
+            .map_err(|_| ImpersonationError::DatabaseError)?;
+        SessionService::revoke(session.id, auth.user.id, events, db)
+            .await
+            .map_err(|_| ImpersonationError::DatabaseError)?;
+        Ok(impersonator_id)
+    }
+
+    /// Returns the session token of the admin if it is still a valid session of them, so the
+    /// admin can continue with it after the impersonation
+    pub async fn impersonator_session(
+        impersonator_id: Uuid,
+        impersonator_cookie: Option<String>,
+        ip: Option<IpNetwork>,
+        db: &mut PgConnection,
+    ) -> Option<String> {
+        let cookie = impersonator_cookie?;
+        let auth = AuthService::authenticate(AuthCredential::SessionCookie(cookie.clone()), ip, db)
+            .await
+            .ok()?;
+        (auth.user.id == impersonator_id && auth.impersonator_id.is_none()).then_some(cookie)
+    }
+}
+
+#[derive(thiserror::Error, Debug, JsonErrorResponse)]
+pub enum ImpersonationError {
+    #[error("User not found")]
+    #[status_code(StatusCode::NOT_FOUND)]
+    UserNotFound,
+
+    #[error("This user can't be impersonated")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    NotAllowed,
+
+    #[error("Impersonation is only possible from a session")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    SessionRequired,
+
+    #[error("Already impersonating a user")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    AlreadyImpersonating,
+
+    #[error("Not impersonating a user")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    NotImpersonating,
+
+    #[error("Database error")]
+    #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
+    DatabaseError,
+}
+
+pub type ImpersonationResult<T> = Result<T, ImpersonationError>;
@@ -0,0 +1,300 @@
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use chrono::{DateTime, Duration, Utc};
+use macros::JsonErrorResponse;
+use sqlx::{Acquire, PgConnection};
+use uuid::Uuid;
+
+use crate::{
+    config::env_or,
+    model::{
+        auth::{Invitation, Role},
+        user::{User, UserCreateInput},
+        UpdateTag,
+    },
+    repo::{
+        invitation::InvitationRepo, organization::OrganizationRepo, tag::TagRepo, user::UserRepo,
+    },
+    service::{
+        auth::{verify_password, AuthService},
+        email::EmailService,
+        password_policy::{PasswordPolicy, PasswordPolicyError, PasswordUserInfo},
+        role::{RoleError, RoleService},
+    },
+    utils::{
+        auth::{generate_session_token, AuthContext},
+        error::ErrorResponse,
+    },
+};
+
+fn expiration() -> DateTime<Utc> {
+    Utc::now() + Duration::days(env_or("INVITATION_EXPIRATION_DAYS", 7))
+}
+
+#[derive(Clone)]
+pub struct InvitationService {}
+
+impl InvitationService {
+    /// Creates the invitation to the organization and sends the link to the email. New tags are
+    /// created right away, so the invitation only keeps their ids. Users who already have an
+    /// account can be invited as well and join with it.
+    pub async fn invite(
+        email: &str,
+        role: Role,
+        tags: Vec<UpdateTag>,
+        auth: &AuthContext,
+        db: &mut PgConnection,
+    ) -> InvitationResult<Invitation> {
+        let organization_id = auth.user.organization_id;
+        let invited_by = auth.user.id;
+        InvitationService::check_not_member(email, organization_id, db).await?;
+        RoleService::check_assignable(&role, auth, db)
+            .await
+            .map_err(|e| match e {
+                RoleError::UnknownRole(role) => InvitationError::UnknownRole(role),
+                RoleError::PermissionNotGranted(permission) => {
+                    InvitationError::PermissionNotGranted(permission)
+                }
+                _ => InvitationError::DatabaseError,
+            })?;
+        let mut tx = db.begin().await.unwrap();
+        let tag_ids: Vec<i32> = TagRepo::create_missing(tags, organization_id, invited_by, &mut tx)
+            .await
+            .map_err(|_| InvitationError::DatabaseError)?
+            .into_iter()
+            .map(|t| t.id)
+            .collect();
+        let token = generate_session_token();
+        let invitation = InvitationRepo::create_one(
+            organization_id,
+            email,
+            role,
+            &tag_ids,
+            &token,
+            expiration(),
+            invited_by,
+            &mut tx,
+        )
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(e) if e.is_unique_violation() => InvitationError::AlreadyInvited,
+            _ => InvitationError::DatabaseError,
+        })?;
+        EmailService::send_invitation_email(invitation.email.clone(), token)
+            .await
+            .map_err(|e| InvitationError::EmailError(e.to_string()))?;
+        tx.commit().await.unwrap();
+        Ok(invitation)
+    }
+
+    /// Sends a new link and extends the invitation. Earlier links stop working.
+    pub async fn resend(
+        id: i32,
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> InvitationResult<Invitation> {
+        let mut tx = db.begin().await.unwrap();
+        InvitationRepo::get_by_id(id, organization_id, &mut tx)
+            .await
+            .map_err(|_| InvitationError::NotFound)?;
+        let token = generate_session_token();
+        let invitation = InvitationRepo::update_token(id, &token, expiration(), &mut tx)
+            .await
+            .map_err(|_| InvitationError::DatabaseError)?;
+        EmailService::send_invitation_email(invitation.email.clone(), token)
+            .await
+            .map_err(|e| InvitationError::EmailError(e.to_string()))?;
+        tx.commit().await.unwrap();
+        Ok(invitation)
+    }
+
+    pub async fn revoke(
+        id: i32,
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> InvitationResult<Invitation> {
+        InvitationRepo::delete_by_id(id, organization_id, db)
+            .await
+            .map_err(|_| InvitationError::NotFound)
+    }
+
+    pub async fn get_by_token(token: &str, db: &mut PgConnection) -> InvitationResult<Invitation> {
+        InvitationRepo::get_valid_by_token(token, db)
+            .await
+            .map_err(|_| InvitationError::InvalidToken)
+    }
+
+    /// Creates the user with the role and tags of the invitation. The email counts as verified,
+    /// since the invitee received the link. Users who already have an account confirm it with
+    /// their password and join the organization with it.
+    pub async fn accept(
+        token: &str,
+        password: String,
+        first_name: Option<String>,
+        last_name: Option<String>,
+        db: &mut PgConnection,
+    ) -> InvitationResult<(Invitation, User)> {
+        let mut tx = db.begin().await.unwrap();
+        let invitation = InvitationService::get_by_token(token, &mut tx).await?;
+        let organization_id = invitation.organization_id;
+        InvitationService::check_not_member(&invitation.email, organization_id, &mut tx).await?;
+        match UserRepo::get_by_email(invitation.email.clone(), &mut tx).await {
+            Ok(existing) => {
+                if !verify_password(&existing, &password) {
+                    return Err(InvitationError::InvalidPassword);
+                }
+                OrganizationRepo::upsert_member(
+                    organization_id,
+                    existing.id,
+                    &invitation.role,
+                    &mut tx,
+                )
+                .await
+                .map_err(|_| InvitationError::DatabaseError)?;
+                UserRepo::update_tags(
+                    existing.id,
+                    invitation.tag_ids.clone(),
+                    organization_id,
+                    &mut tx,
+                )
+                .await
+                .map_err(|_| InvitationError::DatabaseError)?;
+                InvitationRepo::delete_by_id(invitation.id, organization_id, &mut tx)
+                    .await
+                    .map_err(|_| InvitationError::DatabaseError)?;
+                tx.commit().await.unwrap();
+                return Ok((invitation, existing));
+            }
+            Err(sqlx::Error::RowNotFound) => {}
+            Err(_) => return Err(InvitationError::DatabaseError),
+        }
+        PasswordPolicy::from_env()
+            .check(
+                "password",
+                &password,
+                &PasswordUserInfo {
+                    email: &invitation.email,
+                    first_name: first_name.as_deref(),
+                    last_name: last_name.as_deref(),
+                },
+            )
+            .await?;
+        let created = AuthService::create_user(
+            UserCreateInput {
+                email: invitation.email.clone(),
+                first_name,
+                last_name,
+                role: Some(invitation.role.clone()),
+                ..Default::default()
+            },
+            invitation
+                .tag_ids
+                .iter()
+                .map(|id| UpdateTag::Existing { id: *id })
+                .collect(),
+            password,
+            organization_id,
+            invitation.invited_by,
+            &mut tx,
+        )
+        .await
+        .map_err(|_| InvitationError::DatabaseError)?;
+        let user = UserRepo::mark_email_verified(created.id, &mut tx)
+            .await
+            .map_err(|_| InvitationError::DatabaseError)?;
+        InvitationRepo::delete_by_id(invitation.id, organization_id, &mut tx)
+            .await
+            .map_err(|_| InvitationError::DatabaseError)?;
+        tx.commit().await.unwrap();
+        Ok((invitation, user))
+    }
+
+    /// Whether the invitation is for an email that already has an account
+    pub async fn is_for_existing_user(
+        invitation: &Invitation,
+        db: &mut PgConnection,
+    ) -> InvitationResult<bool> {
+        match UserRepo::get_by_email(invitation.email.clone(), db).await {
+            Ok(_) => Ok(true),
+            Err(sqlx::Error::RowNotFound) => Ok(false),
+            Err(_) => Err(InvitationError::DatabaseError),
+        }
+    }
+
+    /// Deleted users can't be invited, their organization has to restore them instead
+    async fn check_not_member(
+        email: &str,
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> InvitationResult<()> {
+        let user = match UserRepo::get_by_email(email.to_string(), db).await {
+            Ok(user) => user,
+            Err(sqlx::Error::RowNotFound) => {
+                return match UserRepo::is_email_taken(email, db).await {
+                    Ok(true) => Err(InvitationError::AccountDeleted),
+                    Ok(false) => Ok(()),
+                    Err(_) => Err(InvitationError::DatabaseError),
+                };
+            }
+            Err(_) => return Err(InvitationError::DatabaseError),
+        };
+        match OrganizationRepo::get_member(organization_id, user.id, db).await {
+            Ok(_) => Err(InvitationError::AlreadyMember),
+            Err(sqlx::Error::RowNotFound) => Ok(()),
+            Err(_) => Err(InvitationError::DatabaseError),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, JsonErrorResponse)]
+pub enum InvitationError {
+    #[error("Invitation not found")]
+    #[status_code(StatusCode::NOT_FOUND)]
+    NotFound,
+
+    #[error("The invitation is invalid or expired")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    InvalidToken,
+
+    #[error("Passwords do not match")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    PasswordsDontMatch,
+
+    #[error("The user is already a member of the organization")]
+    #[status_code(StatusCode::CONFLICT)]
+    AlreadyMember,
+
+    #[error("The account of this email was deleted")]
+    #[status_code(StatusCode::CONFLICT)]
+    AccountDeleted,
+
+    #[error("The password doesn't match the existing account")]
+    #[status_code(StatusCode::UNAUTHORIZED)]
+    InvalidPassword,
+
+    #[error("Unknown role {0}")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    UnknownRole(String),
+
+    #[error("The permission {0} can't be granted")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    PermissionNotGranted(String),
+
+    #[error("The email is already invited")]
+    #[status_code(StatusCode::CONFLICT)]
+    AlreadyInvited,
+
+    #[error("Sending the email failed: {0}")]
+    #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
+    EmailError(String),
+
+    #[error("Database error")]
+    #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
+    DatabaseError,
+
+    #[error(transparent)]
+    #[status_code(StatusCode::UNPROCESSABLE_ENTITY)]
+    #[field_errors]
+    PasswordPolicy(#[from] PasswordPolicyError),
+}
+
+pub type InvitationResult<T> = Result<T, InvitationError>;
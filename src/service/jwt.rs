@@ -0,0 +1,707 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{LazyLock, RwLock},
+    time::{Duration as StdDuration, Instant},
+};
+
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use jsonwebtoken::{
+    decode, decode_header, encode,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use macros::JsonErrorResponse;
+use ring::{
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::ipnetwork::IpNetwork, Acquire, PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    config::{self, env_or},
+    model::{
+        auth::{Permission, Role, SigningKey, Token, TokenType, UserKind},
+        user::User,
+    },
+    repo::{
+        activity::{ActivityEntry, ActivityRepo},
+        organization::OrganizationRepo,
+        signing_key::SigningKeyRepo,
+        token::TokenRepo,
+        user::UserRepo,
+    },
+    service::{auth::AuthService, role::RoleService},
+    utils::{
+        auth::{decrypt_secret, encrypt_secret, AuthContext, AuthUser},
+        error::ErrorResponse,
+    },
+};
+
+/// How long the verification keys and the revoked users are cached. Keys that were revoked and
+/// users who were suspended or deleted on another instance are rejected after at most this long.
+const KEY_CACHE_TTL: StdDuration = StdDuration::from_secs(60);
+/// Tokens with an unknown key id reload the keys at most this often
+const KEY_CACHE_MIN_RELOAD_INTERVAL: StdDuration = StdDuration::from_secs(5);
+
+pub struct JwtConfig {
+    pub access_token_lifetime: Duration,
+    /// Refresh tokens keep the expiration of the first token of their family, so this is the
+    /// longest time a client stays logged in without entering the password
+    pub refresh_token_lifetime: Duration,
+    /// A new signing key is generated when the active key is older than this
+    pub key_rotation_interval: Duration,
+    pub issuer: String,
+}
+
+impl JwtConfig {
+    pub fn from_env() -> Self {
+        Self {
+            access_token_lifetime: Duration::minutes(env_or(
+                "JWT_ACCESS_TOKEN_LIFETIME_MINUTES",
+                15,
+            )),
+            refresh_token_lifetime: Duration::days(env_or("JWT_REFRESH_TOKEN_LIFETIME_DAYS", 30)),
+            key_rotation_interval: Duration::days(env_or("JWT_KEY_ROTATION_DAYS", 30)),
+            issuer: env_or("JWT_ISSUER", config::base_url()),
+        }
+    }
+}
+
+/// The claims of an access token. They contain everything authorization needs, so requests with
+/// the token don't load the user. Changes to the role and its permissions apply once the token is
+/// refreshed.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Claims {
+    pub sub: Uuid,
+    /// The organization the token acts in
+    pub org: Uuid,
+    /// The role in the organization
+    pub role: Role,
+    pub kind: UserKind,
+    /// The permissions of the role when the token was issued
+    pub permissions: Vec<Permission>,
+    pub iat: i64,
+    pub exp: i64,
+    pub iss: String,
+    /// The id of the refresh token the access token was issued with
+    pub tid: i32,
+}
+
+impl Claims {
+    fn into_auth_context(self) -> AuthContext {
+        let timestamp = |seconds| Utc.timestamp_opt(seconds, 0).single().unwrap_or_default();
+        AuthContext {
+            user: AuthUser {
+                id: self.sub,
+                organization_id: self.org,
+                role: self.role,
+                kind: self.kind,
+                permissions: self.permissions,
+                loaded: None,
+            },
+            token: Token {
+                id: self.tid,
+                name: None,
+                token_prefix: String::new(),
+                token_hash: vec![],
+                legacy_hash: false,
+                token_type: TokenType::Jwt,
+                expiration: Some(timestamp(self.exp)),
+                user_id: self.sub,
+                session_id: None,
+                created_at: timestamp(self.iat),
+                updated_at: timestamp(self.iat),
+                scopes: vec![],
+                family_id: None,
+                used_at: None,
+                organization_id: Some(self.org),
+            },
+            impersonator_id: None,
+            password_change_required: false,
+        }
+    }
+}
+
+/// An access token together with the refresh token to get the next one
+pub struct IssuedTokens {
+    pub access_token: String,
+    pub expires_in: i64,
+    pub refresh_token: String,
+}
+
+/// The verification keys by their id
+struct KeyCache {
+    keys: HashMap<Uuid, DecodingKey>,
+    loaded_at: Option<Instant>,
+}
+
+static KEY_CACHE: LazyLock<RwLock<KeyCache>> = LazyLock::new(|| {
+    RwLock::new(KeyCache {
+        keys: HashMap::new(),
+        loaded_at: None,
+    })
+});
+
+/// The users whose access tokens are rejected: the suspended ones with the end of their
+/// suspension, `None` until it is lifted, and the deleted ones
+struct RevocationCache {
+    suspended_until: HashMap<Uuid, Option<DateTime<Utc>>>,
+    deleted: HashSet<Uuid>,
+    loaded_at: Option<Instant>,
+}
+
+impl RevocationCache {
+    async fn load(db: &mut PgConnection) -> JwtResult<Self> {
+        let suspended = UserRepo::list_suspended(db)
+            .await
+            .map_err(|_| JwtError::DatabaseError)?;
+        // Tokens of users purged before were issued before the deletion and expired since
+        let purged_after = Utc::now() - JwtConfig::from_env().access_token_lifetime;
+        let deleted = UserRepo::list_deleted(purged_after, db)
+            .await
+            .map_err(|_| JwtError::DatabaseError)?;
+        Ok(RevocationCache {
+            suspended_until: suspended.into_iter().collect(),
+            deleted: deleted.into_iter().collect(),
+            loaded_at: Some(Instant::now()),
+        })
+    }
+
+    fn is_revoked(&self, user_id: Uuid) -> bool {
+        self.deleted.contains(&user_id)
+            || self
+                .suspended_until
+                .get(&user_id)
+                .is_some_and(|until| until.is_none_or(|until| until > Utc::now()))
+    }
+}
+
+static REVOCATION_CACHE: LazyLock<RwLock<RevocationCache>> = LazyLock::new(|| {
+    RwLock::new(RevocationCache {
+        suspended_until: HashMap::new(),
+        deleted: HashSet::new(),
+        loaded_at: None,
+    })
+});
+
+#[derive(Clone)]
+pub struct JwtService {}
+
+impl JwtService {
+    /// Starts a new family of refresh tokens for the user, which act in the organization
+    pub async fn issue(
+        user: &User,
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> JwtResult<IssuedTokens> {
+        let expiration = Utc::now() + JwtConfig::from_env().refresh_token_lifetime;
+        JwtService::issue_in_family(user, organization_id, Uuid::new_v4(), expiration, db).await
+    }
+
+    async fn issue_in_family(
+        user: &User,
+        organization_id: Uuid,
+        family_id: Uuid,
+        expiration: DateTime<Utc>,
+        db: &mut PgConnection,
+    ) -> JwtResult<IssuedTokens> {
+        let config = JwtConfig::from_env();
+        let mut tx = db.begin().await.unwrap();
+        let member = match OrganizationRepo::get_member(organization_id, user.id, &mut tx).await {
+            Ok(member) => member,
+            Err(sqlx::Error::RowNotFound) => return Err(JwtError::NotAMember),
+            Err(_) => return Err(JwtError::DatabaseError),
+        };
+        let refresh_token = TokenRepo::create_one_refresh_token(
+            user.id,
+            organization_id,
+            family_id,
+            expiration,
+            &mut tx,
+        )
+        .await
+        .map_err(|_| JwtError::DatabaseError)?;
+        let permissions = RoleService::member_permissions(&member, &mut tx)
+            .await
+            .map_err(|_| JwtError::DatabaseError)?;
+        let key = JwtService::active_key(&config, &mut tx).await?;
+        let now = Utc::now();
+        let claims = Claims {
+            sub: user.id,
+            org: organization_id,
+            role: member.role,
+            kind: user.kind,
+            permissions,
+            iat: now.timestamp(),
+            exp: (now + config.access_token_lifetime).timestamp(),
+            iss: config.issuer,
+            tid: refresh_token.token.id,
+        };
+        let access_token = JwtService::sign(&claims, &key)?;
+        tx.commit().await.unwrap();
+        Ok(IssuedTokens {
+            access_token,
+            expires_in: config.access_token_lifetime.num_seconds(),
+            refresh_token: refresh_token.plaintext,
+        })
+    }
+
+    /// Exchanges the refresh token for a new access token and refresh token. Every refresh token
+    /// can only be used once. If a used token comes back, either the client or an attacker
+    /// holds a copy, so the whole family is revoked.
+    pub async fn refresh(
+        refresh_token: &str,
+        ip: Option<IpNetwork>,
+        user_agent: &str,
+        db: &mut PgConnection,
+    ) -> JwtResult<(User, IssuedTokens)> {
+        let token = TokenRepo::get_valid_by_token(refresh_token, TokenType::Refresh, db)
+            .await
+            .map_err(|_| JwtError::InvalidRefreshToken)?;
+        let (Some(family_id), Some(expiration), Some(organization_id)) =
+            (token.family_id, token.expiration, token.organization_id)
+        else {
+            return Err(JwtError::InvalidRefreshToken);
+        };
+        let first_use = match token.used_at {
+            Some(_) => false,
+            None => match TokenRepo::mark_used(token.id, db).await {
+                Ok(_) => true,
+                Err(sqlx::Error::RowNotFound) => false,
+                Err(_) => return Err(JwtError::DatabaseError),
+            },
+        };
+        if !first_use {
+            TokenRepo::delete_family(family_id, db)
+                .await
+                .map_err(|_| JwtError::DatabaseError)?;
+            let _ = ActivityRepo::create_one(
+                ActivityEntry::RefreshTokenReuse {
+                    ip_address: ip,
+                    user_agent: Some(user_agent.to_string()),
+                    action_by_id: token.user_id,
+                    item_id: family_id,
+                },
+                db,
+            )
+            .await;
+            return Err(JwtError::RefreshTokenReused);
+        }
+        let user = UserRepo::get_by_id(token.user_id, db)
+            .await
+            .map_err(|_| JwtError::InvalidRefreshToken)?;
+        if user.kind != UserKind::Human || user.is_suspended() {
+            return Err(JwtError::InvalidRefreshToken);
+        }
+        if AuthService::password_change_required(&user, db)
+            .await
+            .map_err(|_| JwtError::DatabaseError)?
+        {
+            return Err(JwtError::PasswordChangeRequired);
+        }
+        let issued =
+            JwtService::issue_in_family(&user, organization_id, family_id, expiration, db).await?;
+        Ok((user, issued))
+    }
+
+    /// Revokes the family of the refresh token. Access tokens that were already issued stay valid
+    /// until they expire.
+    pub async fn revoke(refresh_token: &str, db: &mut PgConnection) -> JwtResult<()> {
+        let token = TokenRepo::get_valid_by_token(refresh_token, TokenType::Refresh, db)
+            .await
+            .map_err(|_| JwtError::InvalidRefreshToken)?;
+        let family_id = token.family_id.ok_or(JwtError::InvalidRefreshToken)?;
+        TokenRepo::delete_family(family_id, db)
+            .await
+            .map_err(|_| JwtError::DatabaseError)
+    }
+
+    /// Verifies the signature and claims of an access token with the cached keys and rejects
+    /// tokens of suspended, deleted and purged users. The database is only queried to reload the
+    /// caches.
+    pub async fn verify(token: &str, db: &PgPool) -> JwtResult<AuthContext> {
+        let kid = JwtService::key_id(token)?;
+        let (key, reload) = {
+            let cache = KEY_CACHE.read().unwrap();
+            let key = cache.keys.get(&kid).cloned();
+            let reload = match cache.loaded_at.map(|loaded_at| loaded_at.elapsed()) {
+                None => true,
+                Some(age) if key.is_none() => age >= KEY_CACHE_MIN_RELOAD_INTERVAL,
+                Some(age) => age >= KEY_CACHE_TTL,
+            };
+            (key, reload)
+        };
+        let key = if reload {
+            JwtService::reload_keys(db).await?;
+            KEY_CACHE.read().unwrap().keys.get(&kid).cloned()
+        } else {
+            key
+        };
+        let key = key.ok_or(JwtError::InvalidToken)?;
+        let claims = JwtService::decode_claims(token, &key)?;
+        if JwtService::is_revoked(claims.sub, db).await? {
+            return Err(JwtError::InvalidToken);
+        }
+        Ok(claims.into_auth_context())
+    }
+
+    fn sign(claims: &Claims, key: &SigningKey) -> JwtResult<String> {
+        let private_key =
+            decrypt_secret(&key.encrypted_private_key).ok_or(JwtError::SigningKeyError)?;
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(key.id.to_string());
+        encode(&header, claims, &EncodingKey::from_ed_der(&private_key))
+            .map_err(|_| JwtError::SigningKeyError)
+    }
+
+    /// The id of the key that signed the token, read before the signature is checked
+    fn key_id(token: &str) -> JwtResult<Uuid> {
+        let header = decode_header(token).map_err(|_| JwtError::InvalidToken)?;
+        header
+            .kid
+            .and_then(|kid| kid.parse::<Uuid>().ok())
+            .ok_or(JwtError::InvalidToken)
+    }
+
+    /// Checks the signature, the expiration and the issuer of the token
+    fn decode_claims(token: &str, key: &DecodingKey) -> JwtResult<Claims> {
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_issuer(&[JwtConfig::from_env().issuer]);
+        validation.set_required_spec_claims(&["exp", "iss", "sub"]);
+        decode::<Claims>(token, key, &validation)
+            .map(|data| data.claims)
+            .map_err(|_| JwtError::InvalidToken)
+    }
+
+    async fn is_revoked(user_id: Uuid, db: &PgPool) -> JwtResult<bool> {
+        let expired = REVOCATION_CACHE
+            .read()
+            .unwrap()
+            .loaded_at
+            .is_none_or(|loaded_at| loaded_at.elapsed() >= KEY_CACHE_TTL);
+        if expired {
+            let conn = &mut db.acquire().await.map_err(|_| JwtError::DatabaseError)?;
+            let cache = RevocationCache::load(conn).await?;
+            *REVOCATION_CACHE.write().unwrap() = cache;
+        }
+        Ok(REVOCATION_CACHE.read().unwrap().is_revoked(user_id))
+    }
+
+    /// Makes the next verification reload the revoked users, so that a suspension or deletion
+    /// applies to the access tokens of this instance right away
+    pub fn reload_revocations() {
+        REVOCATION_CACHE.write().unwrap().loaded_at = None;
+    }
+
+    async fn reload_keys(db: &PgPool) -> JwtResult<()> {
+        let conn = &mut db.acquire().await.map_err(|_| JwtError::DatabaseError)?;
+        let keys = JwtService::published_keys(conn).await?;
+        let mut cache = KEY_CACHE.write().unwrap();
+        cache.keys = keys
+            .into_iter()
+            .map(|key| (key.id, DecodingKey::from_ed_der(&key.public_key)))
+            .collect();
+        cache.loaded_at = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Retired keys stay published until the last token they signed expired
+    async fn published_keys(db: &mut PgConnection) -> JwtResult<Vec<SigningKey>> {
+        let retired_after = Utc::now() - JwtConfig::from_env().access_token_lifetime;
+        SigningKeyRepo::list_published(retired_after, db)
+            .await
+            .map_err(|_| JwtError::DatabaseError)
+    }
+
+    /// The public keys of the published signing keys, for clients that verify the tokens
+    pub async fn jwks(db: &mut PgConnection) -> JwtResult<JwkSet> {
+        let keys = JwtService::published_keys(db).await?;
+        Ok(JwkSet {
+            keys: keys
+                .into_iter()
+                .map(|key| Jwk {
+                    common: CommonParameters {
+                        public_key_use: Some(PublicKeyUse::Signature),
+                        key_algorithm: Some(KeyAlgorithm::EdDSA),
+                        key_id: Some(key.id.to_string()),
+                        ..Default::default()
+                    },
+                    algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                        key_type: OctetKeyPairType::OctetKeyPair,
+                        curve: EllipticCurve::Ed25519,
+                        x: URL_SAFE_NO_PAD.encode(&key.public_key),
+                    }),
+                })
+                .collect(),
+        })
+    }
+
+    /// Returns the key that signs new tokens and rotates it if it is older than the rotation
+    /// interval
+    async fn active_key(config: &JwtConfig, db: &mut PgConnection) -> JwtResult<SigningKey> {
+        match SigningKeyRepo::get_active(db).await {
+            Ok(key) if key.created_at + config.key_rotation_interval > Utc::now() => Ok(key),
+            Ok(_) | Err(sqlx::Error::RowNotFound) => JwtService::rotate_keys(false, db).await,
+            Err(_) => Err(JwtError::DatabaseError),
+        }
+    }
+
+    /// Generates a new signing key and retires the previous keys. With `revoke_previous`, e.g.
+    /// after a key leaked, the previous keys are deleted and their tokens rejected right away.
+    pub async fn rotate_keys(
+        revoke_previous: bool,
+        db: &mut PgConnection,
+    ) -> JwtResult<SigningKey> {
+        let (public_key, encrypted_private_key) = JwtService::generate_key_pair()?;
+        let mut tx = db.begin().await.unwrap();
+        let key = SigningKeyRepo::create_one(&public_key, &encrypted_private_key, &mut tx)
+            .await
+            .map_err(|_| JwtError::DatabaseError)?;
+        if revoke_previous {
+            SigningKeyRepo::delete_all_except(key.id, &mut tx).await
+        } else {
+            SigningKeyRepo::retire_all_except(key.id, &mut tx).await
+        }
+        .map_err(|_| JwtError::DatabaseError)?;
+        let retired_before = Utc::now() - JwtConfig::from_env().access_token_lifetime;
+        SigningKeyRepo::delete_retired_before(retired_before, &mut tx)
+            .await
+            .map_err(|_| JwtError::DatabaseError)?;
+        tx.commit().await.unwrap();
+        KEY_CACHE.write().unwrap().loaded_at = None;
+        Ok(key)
+    }
+
+    /// Returns the raw public key and the encrypted PKCS#8 document of a new Ed25519 key pair
+    fn generate_key_pair() -> JwtResult<(Vec<u8>, Vec<u8>)> {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|_| JwtError::SigningKeyError)?;
+        let key_pair =
+            Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).map_err(|_| JwtError::SigningKeyError)?;
+        Ok((
+            key_pair.public_key().as_ref().to_vec(),
+            encrypt_secret(pkcs8.as_ref()),
+        ))
+    }
+}
+
+#[derive(thiserror::Error, Debug, JsonErrorResponse)]
+pub enum JwtError {
+    #[error("Invalid or expired access token")]
+    #[status_code(StatusCode::UNAUTHORIZED)]
+    InvalidToken,
+
+    #[error("Invalid or expired refresh token")]
+    #[status_code(StatusCode::UNAUTHORIZED)]
+    InvalidRefreshToken,
+
+    #[error("The refresh token was already used, all tokens of this login are revoked")]
+    #[status_code(StatusCode::UNAUTHORIZED)]
+    RefreshTokenReused,
+
+    #[error("A session is required")]
+    #[status_code(StatusCode::UNAUTHORIZED)]
+    SessionRequired,
+
+    #[error("Impersonation sessions can't be exchanged for tokens")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    ImpersonationNotAllowed,
+
+    #[error("The password has to be changed first")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    PasswordChangeRequired,
+
+    #[error("The user isn't a member of the organization anymore")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    NotAMember,
+
+    #[error("Signing the token failed")]
+    #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
+    SigningKeyError,
+
+    #[error("Database error")]
+    #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
+    DatabaseError,
+}
+
+pub type JwtResult<T> = Result<T, JwtError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        model::USER_TABLE_NAME,
+        utils::{auth::set_test_token_hash_key, testing},
+    };
+
+    fn signing_key() -> SigningKey {
+        set_test_token_hash_key();
+        let (public_key, encrypted_private_key) = JwtService::generate_key_pair().unwrap();
+        SigningKey {
+            id: Uuid::new_v4(),
+            algorithm: "EdDSA".to_string(),
+            public_key,
+            encrypted_private_key,
+            created_at: Utc::now(),
+            retired_at: None,
+        }
+    }
+
+    fn claims(sub: Uuid) -> Claims {
+        let now = Utc::now();
+        Claims {
+            sub,
+            org: Uuid::new_v4(),
+            role: Role::from(Role::EDITOR.to_string()),
+            kind: UserKind::Human,
+            permissions: vec![Permission::UsersRead],
+            iat: now.timestamp(),
+            exp: (now + Duration::minutes(15)).timestamp(),
+            iss: JwtConfig::from_env().issuer,
+            tid: 1,
+        }
+    }
+
+    /// The keys as `reload_keys` caches them
+    fn published(keys: &[&SigningKey]) -> HashMap<Uuid, DecodingKey> {
+        keys.iter()
+            .map(|key| (key.id, DecodingKey::from_ed_der(&key.public_key)))
+            .collect()
+    }
+
+    /// Verifies like `JwtService::verify` with the cached keys, without the suspension check
+    fn verify(token: &str, keys: &HashMap<Uuid, DecodingKey>) -> JwtResult<Claims> {
+        let key = keys
+            .get(&JwtService::key_id(token)?)
+            .ok_or(JwtError::InvalidToken)?;
+        JwtService::decode_claims(token, key)
+    }
+
+    #[test]
+    fn verifies_tokens_of_the_active_and_retired_keys() {
+        let retired = signing_key();
+        let active = signing_key();
+        let keys = published(&[&retired, &active]);
+        let user_id = Uuid::new_v4();
+        let old_token = JwtService::sign(&claims(user_id), &retired).unwrap();
+        let new_token = JwtService::sign(&claims(user_id), &active).unwrap();
+        assert_eq!(JwtService::key_id(&old_token).unwrap(), retired.id);
+        assert_eq!(JwtService::key_id(&new_token).unwrap(), active.id);
+        assert_eq!(verify(&old_token, &keys).unwrap().sub, user_id);
+        assert_eq!(verify(&new_token, &keys).unwrap().sub, user_id);
+    }
+
+    #[test]
+    fn rejects_tokens_of_revoked_keys() {
+        let revoked = signing_key();
+        let active = signing_key();
+        let token = JwtService::sign(&claims(Uuid::new_v4()), &revoked).unwrap();
+        let result = verify(&token, &published(&[&active]));
+        assert!(matches!(result, Err(JwtError::InvalidToken)));
+    }
+
+    #[test]
+    fn rejects_tokens_signed_for_the_id_of_another_key() {
+        let active = signing_key();
+        let other = SigningKey {
+            id: active.id,
+            ..signing_key()
+        };
+        let token = JwtService::sign(&claims(Uuid::new_v4()), &other).unwrap();
+        let result = verify(&token, &published(&[&active]));
+        assert!(matches!(result, Err(JwtError::InvalidToken)));
+    }
+
+    #[test]
+    fn rejects_tampered_tokens() {
+        let key = signing_key();
+        let keys = published(&[&key]);
+        let token = JwtService::sign(&claims(Uuid::new_v4()), &key).unwrap();
+        let other_token = JwtService::sign(&claims(Uuid::new_v4()), &key).unwrap();
+        let parts: Vec<&str> = token.split('.').collect();
+        let other_parts: Vec<&str> = other_token.split('.').collect();
+        let tampered = format!("{}.{}.{}", parts[0], other_parts[1], parts[2]);
+        assert!(matches!(
+            verify(&tampered, &keys),
+            Err(JwtError::InvalidToken)
+        ));
+        assert!(matches!(
+            verify("not a token", &keys),
+            Err(JwtError::InvalidToken)
+        ));
+    }
+
+    #[test]
+    fn rejects_expired_tokens_and_other_issuers() {
+        let key = signing_key();
+        let keys = published(&[&key]);
+        let expired = Claims {
+            exp: (Utc::now() - Duration::minutes(5)).timestamp(),
+            ..claims(Uuid::new_v4())
+        };
+        let token = JwtService::sign(&expired, &key).unwrap();
+        assert!(matches!(verify(&token, &keys), Err(JwtError::InvalidToken)));
+        let other_issuer = Claims {
+            iss: "https://other.example.com".to_string(),
+            ..claims(Uuid::new_v4())
+        };
+        let token = JwtService::sign(&other_issuer, &key).unwrap();
+        assert!(matches!(verify(&token, &keys), Err(JwtError::InvalidToken)));
+    }
+
+    #[test]
+    fn fails_to_sign_with_a_key_that_can_not_be_decrypted() {
+        let mut key = signing_key();
+        let last = key.encrypted_private_key.len() - 1;
+        key.encrypted_private_key[last] ^= 1;
+        let result = JwtService::sign(&claims(Uuid::new_v4()), &key);
+        assert!(matches!(result, Err(JwtError::SigningKeyError)));
+    }
+
+    #[sqlx::test]
+    async fn revokes_deleted_and_purged_users(pool: PgPool) {
+        let conn = &mut pool.acquire().await.unwrap();
+        let organization = testing::setup(conn).await;
+        let mut users = vec![];
+        for email in [
+            "active@example.com",
+            "deleted@example.com",
+            "purged@example.com",
+        ] {
+            users.push(testing::create_user(email, Role::AUTHOR, organization.id, conn).await);
+        }
+        let [active, deleted, purged] = &users[..] else {
+            unreachable!()
+        };
+        for user in [deleted, purged] {
+            UserRepo::delete_one(user.id, active.id, conn)
+                .await
+                .unwrap();
+        }
+        UserRepo::purge_one(purged.id, conn).await.unwrap();
+        ActivityRepo::create_one(
+            ActivityEntry::HardDelete {
+                ip_address: None,
+                user_agent: None,
+                action_by_id: active.id,
+                table_name: USER_TABLE_NAME.to_string(),
+                item_id: purged.id.to_string(),
+            },
+            conn,
+        )
+        .await
+        .unwrap();
+
+        let cache = RevocationCache::load(conn).await.unwrap();
+        assert!(!cache.is_revoked(active.id));
+        assert!(cache.is_revoked(deleted.id));
+        assert!(cache.is_revoked(purged.id));
+    }
+}
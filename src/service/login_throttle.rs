@@ -0,0 +1,224 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{types::ipnetwork::IpNetwork, PgConnection};
+use uuid::Uuid;
+
+use crate::{
+    config::env_or,
+    model::user::User,
+    repo::{
+        activity::{ActivityEntry, ActivityRepo},
+        failed_login::FailedLoginRepo,
+        user::UserRepo,
+    },
+    service::auth::{AuthError, AuthResult},
+};
+
+/// The longest progressive delay between two failed attempts of an account
+const MAX_DELAY_SECONDS: i64 = 60;
+
+pub struct LoginThrottleConfig {
+    /// Failed attempts of an account within the window until it is locked
+    pub max_failed_attempts: i64,
+    /// Failed attempts from an IP within the window until it is blocked
+    pub max_failed_attempts_per_ip: i64,
+    pub window: Duration,
+    pub lockout: Duration,
+    /// The delay after the first failed attempt, which doubles with every further failure
+    pub delay_base_seconds: i64,
+}
+
+impl LoginThrottleConfig {
+    pub fn from_env() -> Self {
+        Self {
+            max_failed_attempts: env_or("LOGIN_MAX_FAILED_ATTEMPTS", 5),
+            max_failed_attempts_per_ip: env_or("LOGIN_MAX_FAILED_ATTEMPTS_PER_IP", 20),
+            window: Duration::minutes(env_or("LOGIN_ATTEMPT_WINDOW_MINUTES", 15)),
+            lockout: Duration::minutes(env_or("LOGIN_LOCKOUT_MINUTES", 15)),
+            delay_base_seconds: env_or("LOGIN_DELAY_BASE_SECONDS", 1),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct LoginThrottleService {}
+
+impl LoginThrottleService {
+    /// Rejects a login attempt if the account is locked, the IP made too many failed attempts or
+    /// the progressive delay since the last failed attempt of the account did not pass yet.
+    ///
+    /// Within a transaction the account stays locked until it ends. The attempt has to be
+    /// verified and recorded with `record_failure` in the same transaction, or concurrent attempts
+    /// could all pass the check before any of them is recorded.
+    pub async fn check(
+        user: Option<&User>,
+        ip: Option<IpNetwork>,
+        db: &mut PgConnection,
+    ) -> AuthResult<()> {
+        let config = LoginThrottleConfig::from_env();
+        let locked_until = match user {
+            Some(user) => UserRepo::lock_for_login(user.id, db)
+                .await
+                .map_err(|_| AuthError::DatabaseError)?,
+            None => None,
+        };
+        let now = Utc::now();
+        if let Some(locked_until) = locked_until.filter(|until| *until > now) {
+            return Err(AuthError::AccountLocked(seconds_until(locked_until)));
+        }
+        if let Some(ip) = ip {
+            let stats = FailedLoginRepo::stats_for_ip(ip, now - config.window, db)
+                .await
+                .map_err(|_| AuthError::DatabaseError)?;
+            let blocked_until = stats
+                .last_attempt_at
+                .map(|last| last + config.lockout)
+                .filter(|_| stats.count >= config.max_failed_attempts_per_ip);
+            if let Some(blocked_until) = blocked_until.filter(|until| *until > now) {
+                return Err(AuthError::TooManyAttempts(seconds_until(blocked_until)));
+            }
+        }
+        if let Some(user) = user {
+            let stats =
+                FailedLoginRepo::stats_for_user(user.id, window_start(locked_until, &config), db)
+                    .await
+                    .map_err(|_| AuthError::DatabaseError)?;
+            if let Some(last) = stats.last_attempt_at {
+                let exponent = (stats.count - 1).clamp(0, 16) as u32;
+                let delay =
+                    (config.delay_base_seconds * 2_i64.pow(exponent)).min(MAX_DELAY_SECONDS);
+                let retry_at = last + Duration::seconds(delay);
+                if retry_at > now {
+                    return Err(AuthError::TooManyAttempts(seconds_until(retry_at)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Records a failed password or second factor and locks the account once it reached the
+    /// threshold. Attempts for unknown emails only count for the IP.
+    pub async fn record_failure(
+        user: Option<&User>,
+        email: &str,
+        ip: Option<IpNetwork>,
+        user_agent: &str,
+        db: &mut PgConnection,
+    ) -> AuthResult<()> {
+        let config = LoginThrottleConfig::from_env();
+        let _ = FailedLoginRepo::delete_older_than(Utc::now() - config.window, db).await;
+        FailedLoginRepo::create_one(user.map(|u| u.id), email, ip, db)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+        let Some(user) = user else {
+            return Ok(());
+        };
+        let _ = ActivityRepo::create_one(
+            ActivityEntry::LoginFailed {
+                ip_address: ip,
+                user_agent: Some(user_agent.to_string()),
+                action_by_id: user.id,
+            },
+            db,
+        )
+        .await;
+        let stats =
+            FailedLoginRepo::stats_for_user(user.id, window_start(user.locked_until, &config), db)
+                .await
+                .map_err(|_| AuthError::DatabaseError)?;
+        if stats.count >= config.max_failed_attempts {
+            UserRepo::update_locked_until(user.id, Some(Utc::now() + config.lockout), db)
+                .await
+                .map_err(|_| AuthError::DatabaseError)?;
+            let _ = ActivityRepo::create_one(
+                ActivityEntry::AccountLock {
+                    ip_address: ip,
+                    user_agent: Some(user_agent.to_string()),
+                    action_by_id: user.id,
+                },
+                db,
+            )
+            .await;
+        }
+        Ok(())
+    }
+
+    /// Resets the failed attempts of the account after a successful login
+    pub async fn record_success(user: &User, db: &mut PgConnection) -> AuthResult<()> {
+        FailedLoginRepo::delete_for_user(user.id, db)
+            .await
+            .map_err(|_| AuthError::DatabaseError)
+    }
+
+    pub async fn unlock(user_id: Uuid, db: &mut PgConnection) -> AuthResult<User> {
+        FailedLoginRepo::delete_for_user(user_id, db)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+        UserRepo::update_locked_until(user_id, None, db)
+            .await
+            .map_err(|_| AuthError::DatabaseError)
+    }
+}
+
+/// Attempts before the end of the last lockout don't count again
+fn window_start(
+    locked_until: Option<DateTime<Utc>>,
+    config: &LoginThrottleConfig,
+) -> DateTime<Utc> {
+    let window_start = Utc::now() - config.window;
+    match locked_until {
+        Some(locked_until) if locked_until > window_start => locked_until,
+        _ => window_start,
+    }
+}
+
+fn seconds_until(time: DateTime<Utc>) -> i64 {
+    (time - Utc::now()).num_seconds().max(0) + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+
+    use super::*;
+    use crate::{model::auth::Role, service::auth::AuthService, utils::testing};
+
+    #[sqlx::test]
+    async fn checks_concurrent_attempts_one_after_another(
+        _: PgPoolOptions,
+        connect_options: PgConnectOptions,
+    ) {
+        // Every attempt gets its own connection, so they can all run at the same time. The pool of
+        // the test is limited to a few connections.
+        let pool = PgPoolOptions::new()
+            .max_connections(21)
+            .connect_with(connect_options)
+            .await
+            .unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let organization = testing::setup(&mut conn).await;
+        testing::create_user("user@example.com", Role::AUTHOR, organization.id, &mut conn).await;
+        let attempts = (0..20).map(|_| {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                let mut conn = pool.acquire().await.unwrap();
+                AuthService::login(
+                    "user@example.com".to_string(),
+                    "wrong password".to_string(),
+                    None,
+                    "test".to_string(),
+                    &mut conn,
+                )
+                .await
+            })
+        });
+        let verified = futures::future::join_all(attempts)
+            .await
+            .into_iter()
+            .filter(|result| matches!(result.as_ref().unwrap(), Err(AuthError::InvalidCredentials)))
+            .count() as i64;
+        assert!(verified >= 1);
+        assert!(verified <= LoginThrottleConfig::from_env().max_failed_attempts);
+        drop(conn);
+        pool.close().await;
+    }
+}
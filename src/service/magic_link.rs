@@ -0,0 +1,164 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{types::ipnetwork::IpNetwork, Acquire, PgConnection};
+
+use crate::{
+    config::env_or,
+    model::{auth::TokenType, user::User},
+    repo::{
+        activity::{ActivityEntry, ActivityRepo},
+        magic_link_request::MagicLinkRequestRepo,
+        settings::SettingsRepo,
+        token::TokenRepo,
+        user::UserRepo,
+    },
+    service::{
+        auth::{AuthError, AuthResult},
+        email::EmailService,
+    },
+};
+
+pub struct MagicLinkConfig {
+    pub expiration: Duration,
+    /// Links that can be requested for an account within the window
+    pub max_requests: i64,
+    /// Links that can be requested from an IP within the window
+    pub max_requests_per_ip: i64,
+    pub window: Duration,
+}
+
+impl MagicLinkConfig {
+    pub fn from_env() -> Self {
+        Self {
+            expiration: Duration::minutes(env_or("MAGIC_LINK_EXPIRATION_MINUTES", 15)),
+            max_requests: env_or("MAGIC_LINK_MAX_REQUESTS", 3),
+            max_requests_per_ip: env_or("MAGIC_LINK_MAX_REQUESTS_PER_IP", 10),
+            window: Duration::minutes(env_or("MAGIC_LINK_REQUEST_WINDOW_MINUTES", 15)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MagicLinkService {}
+
+impl MagicLinkService {
+    pub async fn is_enabled(db: &mut PgConnection) -> AuthResult<bool> {
+        match SettingsRepo::get(db).await {
+            Ok(settings) => Ok(settings.allow_magic_link_login),
+            Err(sqlx::Error::RowNotFound) => Ok(false),
+            Err(_) => Err(AuthError::DatabaseError),
+        }
+    }
+
+    /// Sends a login link to the user. Unknown emails and accounts that reached their limit are
+    /// ignored without an error, so the endpoint doesn't tell which emails have an account.
+    pub async fn request(
+        email: String,
+        ip: Option<IpNetwork>,
+        user_agent: &str,
+        db: &mut PgConnection,
+    ) -> AuthResult<()> {
+        if !MagicLinkService::is_enabled(db).await? {
+            return Err(AuthError::MagicLinkDisabled);
+        }
+        let config = MagicLinkConfig::from_env();
+        let since = Utc::now() - config.window;
+        if let Some(ip) = ip {
+            let (count, first_request_at) = MagicLinkRequestRepo::count_for_ip(ip, since, db)
+                .await
+                .map_err(|_| AuthError::DatabaseError)?;
+            if count >= config.max_requests_per_ip {
+                return Err(AuthError::TooManyRequests(seconds_until_free(
+                    first_request_at,
+                    &config,
+                )));
+            }
+            // Recorded before the user is looked up, so unknown emails count as well
+            let _ = MagicLinkRequestRepo::delete_older_than(since, db).await;
+            MagicLinkRequestRepo::create_one(ip, db)
+                .await
+                .map_err(|_| AuthError::DatabaseError)?;
+        }
+        let user = match UserRepo::get_by_email(email, db).await {
+            Ok(user) => user,
+            Err(sqlx::Error::RowNotFound) => return Ok(()),
+            Err(_) => return Err(AuthError::DatabaseError),
+        };
+        let (count, _) =
+            ActivityRepo::count_since("magic_link_request", Some(user.id), None, since, db)
+                .await
+                .map_err(|_| AuthError::DatabaseError)?;
+        if count >= config.max_requests {
+            tracing::warn!("Magic link requests of user {} reached the limit", user.id);
+            return Ok(());
+        }
+        let token =
+            TokenRepo::create_one_magic_link_token(user.id, Utc::now() + config.expiration, db)
+                .await
+                .map_err(|_| AuthError::DatabaseError)?;
+        ActivityRepo::create_one(
+            ActivityEntry::MagicLinkRequest {
+                ip_address: ip,
+                user_agent: Some(user_agent.to_string()),
+                action_by_id: user.id,
+            },
+            db,
+        )
+        .await
+        .map_err(|_| AuthError::DatabaseError)?;
+        if let Err(e) = EmailService::send_magic_link_email(
+            user.email,
+            token.plaintext,
+            config.expiration.num_minutes(),
+        )
+        .await
+        {
+            tracing::error!("Sending the magic link failed: {e}");
+        }
+        Ok(())
+    }
+
+    /// Uses up the login link and returns its user. The email counts as verified, since the
+    /// user received the link.
+    pub async fn take_valid(
+        token: &str,
+        ip: Option<IpNetwork>,
+        user_agent: &str,
+        db: &mut PgConnection,
+    ) -> AuthResult<User> {
+        if !MagicLinkService::is_enabled(db).await? {
+            return Err(AuthError::MagicLinkDisabled);
+        }
+        let mut tx = db.begin().await.unwrap();
+        let token = TokenRepo::get_valid_by_token(token, TokenType::MagicLink, &mut tx)
+            .await
+            .map_err(|_| AuthError::InvalidCredentials)?;
+        TokenRepo::delete_by_id(token.id, token.user_id, &mut tx)
+            .await
+            .map_err(|_| AuthError::InvalidCredentials)?;
+        let mut user = UserRepo::get_by_id(token.user_id, &mut tx)
+            .await
+            .map_err(|_| AuthError::InvalidCredentials)?;
+        if user.email_verified_at.is_none() {
+            user = UserRepo::mark_email_verified(user.id, &mut tx)
+                .await
+                .map_err(|_| AuthError::DatabaseError)?;
+        }
+        let _ = ActivityRepo::create_one(
+            ActivityEntry::MagicLinkLogin {
+                ip_address: ip,
+                user_agent: Some(user_agent.to_string()),
+                action_by_id: user.id,
+            },
+            &mut tx,
+        )
+        .await;
+        tx.commit().await.unwrap();
+        Ok(user)
+    }
+}
+
+/// The earliest request of the window has to leave it before the next one is allowed
+fn seconds_until_free(first_request_at: Option<DateTime<Utc>>, config: &MagicLinkConfig) -> i64 {
+    let free_at = first_request_at.unwrap_or_else(Utc::now) + config.window;
+    (free_at - Utc::now()).num_seconds().max(0) + 1
+}
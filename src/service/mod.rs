@@ -1,3 +1,19 @@
 pub mod auth;
 pub mod email;
+pub mod email_verification;
+pub mod group;
+pub mod impersonation;
+pub mod invitation;
+pub mod jwt;
+pub mod login_throttle;
+pub mod magic_link;
+pub mod oidc;
+pub mod organization;
+pub mod password_policy;
+pub mod role;
+pub mod service_account;
+pub mod session;
 pub mod setup;
+pub mod suspension;
+pub mod two_factor;
+pub mod webauthn;
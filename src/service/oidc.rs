@@ -0,0 +1,339 @@
+use std::env;
+
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use macros::JsonErrorResponse;
+use openidconnect::{
+    core::{CoreAuthenticationFlow, CoreClient, CoreProviderMetadata},
+    reqwest, AuthorizationCode, ClientId, ClientSecret, CsrfToken, EndpointMaybeSet,
+    EndpointNotSet, EndpointSet, IssuerUrl, Nonce, PkceCodeChallenge, PkceCodeVerifier,
+    RedirectUrl, Scope,
+};
+use serde::Serialize;
+use sqlx::{Acquire, PgConnection};
+
+use crate::{
+    config,
+    model::{
+        auth::Role,
+        user::{User, UserCreateInput},
+    },
+    repo::{
+        oidc_login::OidcLoginRepo, settings::SettingsRepo, user::UserRepo,
+        user_identity::UserIdentityRepo,
+    },
+    service::{auth::AuthService, role::RoleService},
+    utils::{auth::generate_session_token, error::ErrorResponse},
+};
+
+type OidcClient = CoreClient<
+    EndpointSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointMaybeSet,
+    EndpointMaybeSet,
+>;
+
+/// An OpenID Connect provider users can sign in with
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OidcProvider {
+    /// Used in the urls of the login and callback endpoints
+    pub name: String,
+    pub display_name: String,
+    #[serde(skip_serializing)]
+    pub issuer_url: String,
+    #[serde(skip_serializing)]
+    pub client_id: String,
+    #[serde(skip_serializing)]
+    pub client_secret: Option<String>,
+    #[serde(skip_serializing)]
+    pub redirect_url: String,
+    #[serde(skip_serializing)]
+    pub scopes: Vec<String>,
+    /// The role of users that are created on their first login, `None` disables the sign up
+    #[serde(skip_serializing)]
+    pub default_role: Option<Role>,
+}
+
+/// Reads the providers listed in `OIDC_PROVIDERS` (comma separated names). Every provider is
+/// configured with `OIDC_<NAME>_ISSUER`, `OIDC_<NAME>_CLIENT_ID` and optionally
+/// `OIDC_<NAME>_CLIENT_SECRET`, `OIDC_<NAME>_DISPLAY_NAME`, `OIDC_<NAME>_SCOPES`,
+/// `OIDC_<NAME>_REDIRECT_URL` and `OIDC_<NAME>_DEFAULT_ROLE` (`none` disables the sign up).
+pub fn oidc_providers_from_env() -> Vec<OidcProvider> {
+    let Ok(names) = env::var("OIDC_PROVIDERS") else {
+        return vec![];
+    };
+    names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            let var = |key: &str| env::var(format!("OIDC_{}_{key}", name.to_uppercase())).ok();
+            let required =
+                |key: &str| var(key).unwrap_or_else(|| panic!("OIDC provider {name} has no {key}"));
+            // Custom roles are only known to the database, so an unknown role fails the sign up
+            let default_role = match var("DEFAULT_ROLE")
+                .unwrap_or(Role::CONTRIBUTOR.to_string())
+                .to_lowercase()
+                .as_str()
+            {
+                "none" => None,
+                role => Some(Role::from(role)),
+            };
+            OidcProvider {
+                name: name.to_string(),
+                display_name: var("DISPLAY_NAME").unwrap_or(name.to_string()),
+                issuer_url: required("ISSUER"),
+                client_id: required("CLIENT_ID"),
+                client_secret: var("CLIENT_SECRET"),
+                redirect_url: var("REDIRECT_URL").unwrap_or(format!(
+                    "{}/api/rest/auth/oidc/{name}/callback",
+                    config::base_url()
+                )),
+                scopes: var("SCOPES")
+                    .unwrap_or("email profile".to_string())
+                    .split_whitespace()
+                    .map(str::to_string)
+                    .collect(),
+                default_role,
+            }
+        })
+        .collect()
+}
+
+#[derive(Clone)]
+pub struct OidcService {}
+
+impl OidcService {
+    /// Returns the authorization url of the provider the user has to be redirected to and the
+    /// state, which the browser has to present again in the callback.
+    /// `redirect_to` is the path of the frontend the user is sent to after the login.
+    pub async fn start_login(
+        provider: &OidcProvider,
+        redirect_to: Option<&str>,
+        db: &mut PgConnection,
+    ) -> OidcResult<(String, String)> {
+        let redirect_to = redirect_to.filter(|path| is_relative_path(path));
+        let client = OidcService::client(provider, &http_client()?).await?;
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let mut request = client
+            .authorize_url(
+                CoreAuthenticationFlow::AuthorizationCode,
+                CsrfToken::new_random,
+                Nonce::new_random,
+            )
+            .set_pkce_challenge(pkce_challenge);
+        for scope in &provider.scopes {
+            request = request.add_scope(Scope::new(scope.clone()));
+        }
+        let (url, state, nonce) = request.url();
+        let _ = OidcLoginRepo::delete_expired(db).await;
+        OidcLoginRepo::create_one(
+            state.secret(),
+            &provider.name,
+            nonce.secret(),
+            pkce_verifier.secret(),
+            redirect_to,
+            db,
+        )
+        .await
+        .map_err(|_| OidcError::DatabaseError)?;
+        Ok((url.to_string(), state.secret().clone()))
+    }
+
+    /// Exchanges the code from the callback, verifies the id token and returns the linked user,
+    /// who still has to pass the checks of every other login. Returns the path of the frontend
+    /// the login was started from as well.
+    pub async fn finish_login(
+        provider: &OidcProvider,
+        code: String,
+        state: &str,
+        db: &mut PgConnection,
+    ) -> OidcResult<(User, Option<String>)> {
+        let login = OidcLoginRepo::take_valid(state, &provider.name, db)
+            .await
+            .map_err(|_| OidcError::InvalidState)?;
+        let http_client = http_client()?;
+        let client = OidcService::client(provider, &http_client).await?;
+        let token_response = client
+            .exchange_code(AuthorizationCode::new(code))
+            .map_err(|e| OidcError::ProviderError(e.to_string()))?
+            .set_pkce_verifier(PkceCodeVerifier::new(login.pkce_verifier))
+            .request_async(&http_client)
+            .await
+            .map_err(|e| OidcError::ProviderError(e.to_string()))?;
+        let id_token = token_response
+            .extra_fields()
+            .id_token()
+            .ok_or(OidcError::InvalidIdToken)?;
+        let claims = id_token
+            .claims(&client.id_token_verifier(), &Nonce::new(login.nonce))
+            .map_err(|_| OidcError::InvalidIdToken)?;
+
+        let subject = claims.subject().as_str();
+        let email = claims.email().map(|e| e.to_string());
+        let email_verified = claims.email_verified().unwrap_or(false);
+        let mut tx = db.begin().await.unwrap();
+        let user = match UserIdentityRepo::get_by_subject(&provider.name, subject, &mut tx).await {
+            // Deleted users aren't found and can't log in until they are restored
+            Ok(identity) => UserRepo::get_by_id(identity.user_id, &mut tx)
+                .await
+                .map_err(|e| match e {
+                    sqlx::Error::RowNotFound => OidcError::SignUpDisabled,
+                    _ => OidcError::DatabaseError,
+                })?,
+            Err(sqlx::Error::RowNotFound) => {
+                let email = email.clone().ok_or(OidcError::MissingEmail)?;
+                let user = match UserRepo::get_by_email(email.clone(), &mut tx).await {
+                    // An existing account is only linked if the provider verified the email,
+                    // otherwise anyone could take it over by using the same email
+                    Ok(_) if !email_verified => return Err(OidcError::EmailNotVerified),
+                    Ok(user) => user,
+                    Err(sqlx::Error::RowNotFound) => {
+                        if UserRepo::is_email_taken(&email, &mut tx)
+                            .await
+                            .map_err(|_| OidcError::DatabaseError)?
+                        {
+                            return Err(OidcError::SignUpDisabled);
+                        }
+                        let role = provider
+                            .default_role
+                            .clone()
+                            .ok_or(OidcError::SignUpDisabled)?;
+                        RoleService::get(&role, &mut tx)
+                            .await
+                            .map_err(|e| OidcError::InternalServerError(e.to_string()))?;
+                        // New users join the default organization, others can invite them later
+                        let organization_id = SettingsRepo::get(&mut tx)
+                            .await
+                            .map_err(|_| OidcError::DatabaseError)?
+                            .default_organization_id
+                            .ok_or(OidcError::SignUpDisabled)?;
+                        AuthService::create_user(
+                            UserCreateInput {
+                                email,
+                                first_name: claims
+                                    .given_name()
+                                    .and_then(|n| n.get(None))
+                                    .map(|n| n.to_string()),
+                                last_name: claims
+                                    .family_name()
+                                    .and_then(|n| n.get(None))
+                                    .map(|n| n.to_string()),
+                                role: Some(role),
+                                ..Default::default()
+                            },
+                            vec![],
+                            // The user can only set a password with a password reset
+                            generate_session_token(),
+                            organization_id,
+                            config::system_user_uuid(),
+                            &mut tx,
+                        )
+                        .await
+                        .map_err(|e| OidcError::InternalServerError(e.to_string()))?
+                    }
+                    Err(_) => return Err(OidcError::DatabaseError),
+                };
+                UserIdentityRepo::create_one(
+                    user.id,
+                    &provider.name,
+                    subject,
+                    Some(&user.email),
+                    &mut tx,
+                )
+                .await
+                .map_err(|_| OidcError::DatabaseError)?;
+                user
+            }
+            Err(_) => return Err(OidcError::DatabaseError),
+        };
+        // The provider vouches for the email, so it doesn't have to be confirmed again
+        let user = if email_verified
+            && user.email_verified_at.is_none()
+            && email.as_deref() == Some(user.email.as_str())
+        {
+            UserRepo::mark_email_verified(user.id, &mut tx)
+                .await
+                .map_err(|_| OidcError::DatabaseError)?
+        } else {
+            user
+        };
+        tx.commit().await.unwrap();
+        Ok((user, login.redirect_to))
+    }
+
+    async fn client(
+        provider: &OidcProvider,
+        http_client: &reqwest::Client,
+    ) -> OidcResult<OidcClient> {
+        let issuer_url = IssuerUrl::new(provider.issuer_url.clone())
+            .map_err(|e| OidcError::InternalServerError(e.to_string()))?;
+        let metadata = CoreProviderMetadata::discover_async(issuer_url, http_client)
+            .await
+            .map_err(|e| OidcError::ProviderError(e.to_string()))?;
+        let redirect_url = RedirectUrl::new(provider.redirect_url.clone())
+            .map_err(|e| OidcError::InternalServerError(e.to_string()))?;
+        Ok(CoreClient::from_provider_metadata(
+            metadata,
+            ClientId::new(provider.client_id.clone()),
+            provider.client_secret.clone().map(ClientSecret::new),
+        )
+        .set_redirect_uri(redirect_url))
+    }
+}
+
+fn http_client() -> OidcResult<reqwest::Client> {
+    // Following redirects would allow SSRF attacks
+    reqwest::ClientBuilder::new()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .map_err(|e| OidcError::InternalServerError(e.to_string()))
+}
+
+/// Prevents redirects to other sites after the login
+fn is_relative_path(path: &str) -> bool {
+    path.starts_with('/') && !path.starts_with("//") && !path.contains('\\')
+}
+
+#[derive(thiserror::Error, Debug, JsonErrorResponse)]
+pub enum OidcError {
+    #[error("Provider not found")]
+    #[status_code(StatusCode::NOT_FOUND)]
+    ProviderNotFound,
+
+    #[error("The login does not exist or expired")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    InvalidState,
+
+    #[error("The provider returned an error: {0}")]
+    #[status_code(StatusCode::BAD_GATEWAY)]
+    ProviderError(String),
+
+    #[error("The id token is invalid")]
+    #[status_code(StatusCode::UNAUTHORIZED)]
+    InvalidIdToken,
+
+    #[error("The provider did not return an email")]
+    #[status_code(StatusCode::UNAUTHORIZED)]
+    MissingEmail,
+
+    #[error("The email is not verified by the provider")]
+    #[status_code(StatusCode::UNAUTHORIZED)]
+    EmailNotVerified,
+
+    #[error("There is no account for this login")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    SignUpDisabled,
+
+    #[error("Internal server error: {0}")]
+    #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
+    InternalServerError(String),
+
+    #[error("Database error")]
+    #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
+    DatabaseError,
+}
+
+pub type OidcResult<T> = Result<T, OidcError>;
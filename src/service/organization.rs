@@ -0,0 +1,145 @@
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use macros::JsonErrorResponse;
+use sqlx::{Acquire, PgConnection};
+use uuid::Uuid;
+
+use crate::{
+    model::{
+        auth::{Role, TokenType},
+        Organization, OrganizationWithRole,
+    },
+    repo::{organization::OrganizationRepo, session::SessionRepo},
+    utils::{auth::AuthContext, error::ErrorResponse},
+};
+
+const MAX_NAME_LEN: usize = 128;
+
+#[derive(Clone)]
+pub struct OrganizationService {}
+
+impl OrganizationService {
+    /// The organizations the user is a member of, in the order they joined them
+    pub async fn list_for_user(
+        user_id: Uuid,
+        db: &mut PgConnection,
+    ) -> OrganizationResult<Vec<OrganizationWithRole>> {
+        let organizations = OrganizationRepo::list_for_user(user_id, db)
+            .await
+            .map_err(|_| OrganizationError::DatabaseError)?;
+        let memberships = OrganizationRepo::list_memberships_for_user(user_id, db)
+            .await
+            .map_err(|_| OrganizationError::DatabaseError)?;
+        Ok(organizations
+            .into_iter()
+            .zip(memberships)
+            .map(|(organization, member)| OrganizationWithRole {
+                organization,
+                role: member.role,
+            })
+            .collect())
+    }
+
+    /// The creator becomes an admin of the new organization
+    pub async fn create(
+        name: &str,
+        auth: &AuthContext,
+        db: &mut PgConnection,
+    ) -> OrganizationResult<Organization> {
+        let name = check_name(name)?;
+        let mut tx = db.begin().await.unwrap();
+        let created = OrganizationRepo::create_one(name, &mut tx)
+            .await
+            .map_err(|_| OrganizationError::DatabaseError)?;
+        OrganizationRepo::upsert_member(
+            created.id,
+            auth.user.id,
+            &Role::from(Role::ADMIN),
+            &mut tx,
+        )
+        .await
+        .map_err(|_| OrganizationError::DatabaseError)?;
+        tx.commit().await.unwrap();
+        Ok(created)
+    }
+
+    /// Only the active organization of the request can be renamed. Returns the organization
+    /// before and after the change.
+    pub async fn rename(
+        id: Uuid,
+        name: &str,
+        auth: &AuthContext,
+        db: &mut PgConnection,
+    ) -> OrganizationResult<(Organization, Organization)> {
+        if id != auth.user.organization_id {
+            return Err(OrganizationError::NotFound);
+        }
+        let name = check_name(name)?;
+        let before_update = OrganizationRepo::get_by_id(id, db)
+            .await
+            .map_err(|_| OrganizationError::NotFound)?;
+        let updated = OrganizationRepo::update_name(id, name, db)
+            .await
+            .map_err(|_| OrganizationError::DatabaseError)?;
+        Ok((before_update, updated))
+    }
+
+    /// Switches the active organization of the session. Access tokens always act in the
+    /// organization they were created in.
+    pub async fn activate(
+        id: Uuid,
+        auth: &AuthContext,
+        db: &mut PgConnection,
+    ) -> OrganizationResult<Organization> {
+        if !matches!(auth.token.token_type, TokenType::Session) {
+            return Err(OrganizationError::SessionRequired);
+        }
+        if auth.impersonator_id.is_some() {
+            return Err(OrganizationError::ImpersonationNotAllowed);
+        }
+        match OrganizationRepo::get_member(id, auth.user.id, db).await {
+            Ok(_) => {}
+            Err(sqlx::Error::RowNotFound) => return Err(OrganizationError::NotFound),
+            Err(_) => return Err(OrganizationError::DatabaseError),
+        }
+        SessionRepo::update_organization(auth.token.id, id, db)
+            .await
+            .map_err(|_| OrganizationError::DatabaseError)?;
+        OrganizationRepo::get_by_id(id, db)
+            .await
+            .map_err(|_| OrganizationError::DatabaseError)
+    }
+}
+
+/// Returns the trimmed name
+fn check_name(name: &str) -> OrganizationResult<&str> {
+    let name = name.trim();
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(OrganizationError::InvalidName);
+    }
+    Ok(name)
+}
+
+#[derive(thiserror::Error, Debug, JsonErrorResponse)]
+pub enum OrganizationError {
+    #[error("Organization not found")]
+    #[status_code(StatusCode::NOT_FOUND)]
+    NotFound,
+
+    #[error("Organization names can't be empty or longer than 128 characters")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    InvalidName,
+
+    #[error("Only sessions can switch the organization")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    SessionRequired,
+
+    #[error("Impersonation sessions can't switch the organization")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    ImpersonationNotAllowed,
+
+    #[error("Database error")]
+    #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
+    DatabaseError,
+}
+
+pub type OrganizationResult<T> = Result<T, OrganizationError>;
@@ -0,0 +1,379 @@
+use std::{env, io::ErrorKind, path::PathBuf};
+
+use ring::digest;
+
+use crate::{
+    config::{self, env_or},
+    utils::error::FieldError,
+};
+
+/// Shorter terms are not banned, because they appear in too many good passwords
+const MIN_BANNED_TERM_LEN: usize = 3;
+
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    /// Caps the work of hashing a password
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    /// Terms that must not appear in a password, in addition to the email and name of the user
+    pub banned_terms: Vec<String>,
+    /// A directory of k-anonymity range files of breached passwords. Each file is named after the
+    /// first 5 characters of the uppercase SHA-1 hex of the passwords, e.g. `5BAA6.txt`, and
+    /// contains lines of the remaining characters and how often the password was seen,
+    /// e.g. `1E4C9B93F3F0682250B6CF8331B7EE68FD8:3861493`. This is the format of
+    /// api.pwnedpasswords.com and of its downloader.
+    pub breached_list_path: Option<PathBuf>,
+}
+
+impl PasswordPolicy {
+    pub fn from_env() -> Self {
+        let banned_terms = env::var("PASSWORD_BANNED_TERMS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|term| term.trim().to_string())
+            .chain([config::APP_NAME.to_string()])
+            .filter(|term| !term.is_empty())
+            .collect();
+        Self {
+            min_length: env_or("PASSWORD_MIN_LENGTH", 8),
+            max_length: env_or("PASSWORD_MAX_LENGTH", 128),
+            require_lowercase: env_or("PASSWORD_REQUIRE_LOWERCASE", false),
+            require_uppercase: env_or("PASSWORD_REQUIRE_UPPERCASE", false),
+            require_digit: env_or("PASSWORD_REQUIRE_DIGIT", false),
+            require_symbol: env_or("PASSWORD_REQUIRE_SYMBOL", false),
+            banned_terms,
+            breached_list_path: env::var("PASSWORD_BREACHED_LIST_PATH")
+                .ok()
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from),
+        }
+    }
+
+    /// Returns every rule the password of the user breaks as error of the given field
+    pub async fn check(
+        &self,
+        field: &str,
+        password: &str,
+        user: &PasswordUserInfo<'_>,
+    ) -> Result<(), PasswordPolicyError> {
+        let mut violations = vec![];
+        let mut violation = |code: &'static str, message: String| {
+            violations.push(FieldError {
+                field: field.to_string(),
+                code,
+                message,
+            })
+        };
+        let length = password.chars().count();
+        if length < self.min_length {
+            violation(
+                "too_short",
+                format!("Must be at least {} characters long", self.min_length),
+            );
+        }
+        if length > self.max_length {
+            violation(
+                "too_long",
+                format!("Must be at most {} characters long", self.max_length),
+            );
+        }
+        if self.require_lowercase && !password.chars().any(char::is_lowercase) {
+            violation(
+                "missing_lowercase",
+                "Must contain a lowercase letter".into(),
+            );
+        }
+        if self.require_uppercase && !password.chars().any(char::is_uppercase) {
+            violation(
+                "missing_uppercase",
+                "Must contain an uppercase letter".into(),
+            );
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            violation("missing_digit", "Must contain a digit".into());
+        }
+        if self.require_symbol && !password.chars().any(|c| !c.is_alphanumeric()) {
+            violation("missing_symbol", "Must contain a symbol".into());
+        }
+        let lowercase = password.to_lowercase();
+        let contains = |term: &str| {
+            term.chars().count() >= MIN_BANNED_TERM_LEN && lowercase.contains(&term.to_lowercase())
+        };
+        if user.terms().any(contains) {
+            violation(
+                "contains_user_info",
+                "Must not contain your email or name".into(),
+            );
+        }
+        if let Some(term) = self.banned_terms.iter().find(|term| contains(term)) {
+            violation(
+                "contains_banned_term",
+                format!("Must not contain \"{term}\""),
+            );
+        }
+        if length <= self.max_length && self.is_breached(password).await {
+            violation(
+                "breached",
+                "This password appeared in a data breach, choose another one".into(),
+            );
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(PasswordPolicyError(violations))
+        }
+    }
+
+    /// Looks the password up in the range file of its hash prefix. Without a configured list or
+    /// if the list can't be read, no password counts as breached.
+    async fn is_breached(&self, password: &str) -> bool {
+        let Some(path) = &self.breached_list_path else {
+            return false;
+        };
+        let hash: String = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes())
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect();
+        let (prefix, suffix) = hash.split_at(5);
+        let range = match tokio::fs::read_to_string(path.join(format!("{prefix}.txt"))).await {
+            Ok(range) => range,
+            Err(e) if e.kind() == ErrorKind::NotFound => return false,
+            Err(e) => {
+                tracing::error!("Reading the breached password list failed: {e}");
+                return false;
+            }
+        };
+        range.lines().any(|line| match line.trim().split_once(':') {
+            // Padding entries of the range api have a count of 0
+            Some((line_suffix, count)) => {
+                line_suffix.eq_ignore_ascii_case(suffix) && count.trim() != "0"
+            }
+            None => false,
+        })
+    }
+}
+
+/// What is known about the user whose password is checked
+#[derive(Default)]
+pub struct PasswordUserInfo<'a> {
+    pub email: &'a str,
+    pub first_name: Option<&'a str>,
+    pub last_name: Option<&'a str>,
+}
+
+impl PasswordUserInfo<'_> {
+    fn terms(&self) -> impl Iterator<Item = &str> {
+        let local_part = self.email.split('@').next();
+        [
+            Some(self.email),
+            local_part,
+            self.first_name,
+            self.last_name,
+        ]
+        .into_iter()
+        .flatten()
+        .map(str::trim)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("The password does not meet the password policy")]
+pub struct PasswordPolicyError(pub Vec<FieldError>);
+
+impl From<PasswordPolicyError> for Vec<FieldError> {
+    fn from(value: PasswordPolicyError) -> Self {
+        value.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 8,
+            max_length: 128,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            banned_terms: vec![],
+            breached_list_path: None,
+        }
+    }
+
+    async fn violations(
+        policy: &PasswordPolicy,
+        password: &str,
+        user: &PasswordUserInfo<'_>,
+    ) -> Vec<&'static str> {
+        match policy.check("password", password, user).await {
+            Ok(()) => vec![],
+            Err(e) => e.0.into_iter().map(|error| error.code).collect(),
+        }
+    }
+
+    /// A directory with the range file of `password`, whose SHA-1 is
+    /// `5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8`
+    fn breached_list(range: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("breached-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("5BAA6.txt"), range).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn accepts_passwords_that_follow_every_rule() {
+        let policy = PasswordPolicy {
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: true,
+            ..policy()
+        };
+        let user = PasswordUserInfo::default();
+        assert!(violations(&policy, "Long horse 9 battery", &user)
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn checks_the_length_in_characters() {
+        let policy = PasswordPolicy {
+            max_length: 10,
+            ..policy()
+        };
+        let user = PasswordUserInfo::default();
+        assert_eq!(violations(&policy, "short", &user).await, ["too_short"]);
+        assert!(violations(&policy, "ääääääää", &user).await.is_empty());
+        assert_eq!(
+            violations(&policy, "much too long", &user).await,
+            ["too_long"]
+        );
+    }
+
+    #[tokio::test]
+    async fn checks_the_required_characters() {
+        let user = PasswordUserInfo::default();
+        let cases = [
+            (
+                PasswordPolicy {
+                    require_lowercase: true,
+                    ..policy()
+                },
+                "LONG HORSE",
+                "missing_lowercase",
+            ),
+            (
+                PasswordPolicy {
+                    require_uppercase: true,
+                    ..policy()
+                },
+                "long horse",
+                "missing_uppercase",
+            ),
+            (
+                PasswordPolicy {
+                    require_digit: true,
+                    ..policy()
+                },
+                "long horse",
+                "missing_digit",
+            ),
+            (
+                PasswordPolicy {
+                    require_symbol: true,
+                    ..policy()
+                },
+                "longhorse9",
+                "missing_symbol",
+            ),
+        ];
+        for (policy, password, code) in cases {
+            assert_eq!(violations(&policy, password, &user).await, [code]);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_the_email_and_name_of_the_user() {
+        let policy = policy();
+        let user = PasswordUserInfo {
+            email: "jane.doe@example.com",
+            first_name: Some("Jane"),
+            last_name: Some("Do"),
+        };
+        assert_eq!(
+            violations(&policy, "x Jane.Doe x", &user).await,
+            ["contains_user_info"]
+        );
+        assert_eq!(
+            violations(&policy, "my name is JANE", &user).await,
+            ["contains_user_info"]
+        );
+        // Terms shorter than MIN_BANNED_TERM_LEN are ignored
+        assert!(violations(&policy, "do or do not", &user).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_banned_terms() {
+        let policy = PasswordPolicy {
+            banned_terms: vec!["Acme".into()],
+            ..policy()
+        };
+        let user = PasswordUserInfo::default();
+        assert_eq!(
+            violations(&policy, "acme acme acme", &user).await,
+            ["contains_banned_term"]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_every_violation() {
+        let policy = PasswordPolicy {
+            require_digit: true,
+            banned_terms: vec!["acme".into()],
+            ..policy()
+        };
+        let user = PasswordUserInfo::default();
+        assert_eq!(
+            violations(&policy, "acme", &user).await,
+            ["too_short", "missing_digit", "contains_banned_term"]
+        );
+    }
+
+    #[tokio::test]
+    async fn looks_up_breached_passwords_in_the_range_file() {
+        let path = breached_list(
+            "0018A45C4D1DEF81644B54AB7F969B88D65:1\r\n1e4c9b93f3f0682250b6cf8331b7ee68fd8:3861493\r\n",
+        );
+        let policy = PasswordPolicy {
+            breached_list_path: Some(path.clone()),
+            ..policy()
+        };
+        let user = PasswordUserInfo::default();
+        assert_eq!(violations(&policy, "password", &user).await, ["breached"]);
+        assert!(violations(&policy, "long horse 9 battery", &user)
+            .await
+            .is_empty());
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn ignores_padding_entries_and_missing_range_files() {
+        let path = breached_list("1E4C9B93F3F0682250B6CF8331B7EE68FD8:0\n");
+        let policy = PasswordPolicy {
+            breached_list_path: Some(path.clone()),
+            ..policy()
+        };
+        assert!(!policy.is_breached("password").await);
+        std::fs::remove_dir_all(&path).unwrap();
+        assert!(!policy.is_breached("password").await);
+    }
+}
@@ -0,0 +1,159 @@
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use macros::JsonErrorResponse;
+use sqlx::PgConnection;
+
+use crate::{
+    model::{
+        auth::{Permission, Role, RoleDefinition},
+        OrganizationMember,
+    },
+    repo::role::RoleRepo,
+    utils::{auth::AuthContext, error::ErrorResponse},
+};
+
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Clone)]
+pub struct RoleService {}
+
+impl RoleService {
+    /// The permissions the role grants
+    pub async fn permissions(role: &Role, db: &mut PgConnection) -> RoleResult<Vec<Permission>> {
+        Ok(RoleService::get(role, db).await?.permissions())
+    }
+
+    /// The permissions of the member's role in the organization and of the roles of their groups
+    /// there
+    pub async fn member_permissions(
+        member: &OrganizationMember,
+        db: &mut PgConnection,
+    ) -> RoleResult<Vec<Permission>> {
+        let mut permissions = RoleService::permissions(&member.role, db).await?;
+        let group_roles =
+            RoleRepo::list_for_user_groups(member.user_id, member.organization_id, db)
+                .await
+                .map_err(|_| RoleError::DatabaseError)?;
+        for permission in group_roles.iter().flat_map(RoleDefinition::permissions) {
+            if !permissions.contains(&permission) {
+                permissions.push(permission);
+            }
+        }
+        Ok(permissions)
+    }
+
+    pub async fn get(role: &Role, db: &mut PgConnection) -> RoleResult<RoleDefinition> {
+        RoleRepo::get_by_name(role, db).await.map_err(|e| match e {
+            sqlx::Error::RowNotFound => RoleError::UnknownRole(role.as_str().to_string()),
+            _ => RoleError::DatabaseError,
+        })
+    }
+
+    /// Nobody can create or edit a role with permissions they don't have themselves
+    pub async fn create(
+        role: &Role,
+        description: Option<String>,
+        permissions: &[Permission],
+        auth: &AuthContext,
+        db: &mut PgConnection,
+    ) -> RoleResult<RoleDefinition> {
+        let name = role.as_str();
+        if name.is_empty()
+            || name.len() > MAX_NAME_LEN
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(RoleError::InvalidName);
+        }
+        RoleService::check_grantable(permissions, auth)?;
+        RoleRepo::create_one(role, description, &permission_names(permissions), db)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::Database(e) if e.is_unique_violation() => RoleError::NameTaken,
+                _ => RoleError::DatabaseError,
+            })
+    }
+
+    pub async fn update(
+        role: &Role,
+        description: Option<String>,
+        permissions: &[Permission],
+        auth: &AuthContext,
+        db: &mut PgConnection,
+    ) -> RoleResult<RoleDefinition> {
+        if RoleService::get(role, db).await?.built_in {
+            return Err(RoleError::BuiltIn);
+        }
+        RoleService::check_grantable(permissions, auth)?;
+        RoleRepo::update_one(role, description, &permission_names(permissions), db)
+            .await
+            .map_err(|_| RoleError::DatabaseError)
+    }
+
+    pub async fn delete(role: &Role, db: &mut PgConnection) -> RoleResult<RoleDefinition> {
+        if RoleService::get(role, db).await?.built_in {
+            return Err(RoleError::BuiltIn);
+        }
+        RoleRepo::delete_one(role, db).await.map_err(|e| match e {
+            sqlx::Error::Database(e) if e.is_foreign_key_violation() => RoleError::InUse,
+            _ => RoleError::DatabaseError,
+        })
+    }
+
+    /// Nobody can give a user a role with permissions they don't have themselves
+    pub async fn check_assignable(
+        role: &Role,
+        auth: &AuthContext,
+        db: &mut PgConnection,
+    ) -> RoleResult<()> {
+        let permissions = RoleService::permissions(role, db).await?;
+        RoleService::check_grantable(&permissions, auth)
+    }
+
+    pub fn check_grantable(permissions: &[Permission], auth: &AuthContext) -> RoleResult<()> {
+        match permissions.iter().find(|p| !auth.has_permission(**p)) {
+            Some(permission) => Err(RoleError::PermissionNotGranted(String::from(*permission))),
+            None => Ok(()),
+        }
+    }
+}
+
+fn permission_names(permissions: &[Permission]) -> Vec<String> {
+    let mut names: Vec<String> = permissions.iter().copied().map(String::from).collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+#[derive(thiserror::Error, Debug, JsonErrorResponse)]
+pub enum RoleError {
+    #[error("Unknown role {0}")]
+    #[status_code(StatusCode::NOT_FOUND)]
+    UnknownRole(String),
+
+    #[error("Role names may only contain letters, digits, '_' and '-'")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    InvalidName,
+
+    #[error("A role with this name already exists")]
+    #[status_code(StatusCode::CONFLICT)]
+    NameTaken,
+
+    #[error("Built-in roles can't be changed")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    BuiltIn,
+
+    #[error("The role is still assigned to users, invitations or groups")]
+    #[status_code(StatusCode::CONFLICT)]
+    InUse,
+
+    #[error("The permission {0} can't be granted")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    PermissionNotGranted(String),
+
+    #[error("Database error")]
+    #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
+    DatabaseError,
+}
+
+pub type RoleResult<T> = Result<T, RoleError>;
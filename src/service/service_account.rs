@@ -0,0 +1,304 @@
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use macros::JsonErrorResponse;
+use sqlx::{Acquire, PgConnection};
+use uuid::Uuid;
+
+use crate::{
+    model::{
+        auth::{CreatedToken, Permission, Role, Token, UserKind},
+        user::{User, UserCreateInput},
+    },
+    repo::{organization::OrganizationRepo, token::TokenRepo, user::UserRepo},
+    service::role::{RoleError, RoleService},
+    utils::{
+        auth::{generate_session_token, AuthContext},
+        error::ErrorResponse,
+        password::hash_password,
+    },
+};
+
+#[derive(Clone)]
+pub struct ServiceAccountService {}
+
+impl ServiceAccountService {
+    /// Creates a service account in the current organization that is owned by the current user.
+    /// Its role can't grant more than the role of the owner.
+    pub async fn create(
+        name: String,
+        description: Option<String>,
+        role: Role,
+        auth: &AuthContext,
+        db: &mut PgConnection,
+    ) -> ServiceAccountResult<User> {
+        if let Some(permission) = RoleService::permissions(&role, db)
+            .await?
+            .iter()
+            .find(|p| !auth.has_permission(**p))
+        {
+            return Err(ServiceAccountError::PermissionNotGranted(String::from(
+                *permission,
+            )));
+        }
+        // Service accounts can't log in, so neither the email nor the password is ever used
+        UserRepo::create_service_account(
+            UserCreateInput {
+                email: format!("{}@service-account.invalid", Uuid::new_v4()),
+                password_hash: &hash_password(&generate_session_token()),
+                first_name: Some(name),
+                description,
+                role: Some(role),
+                ..Default::default()
+            },
+            auth.user.organization_id,
+            auth.user.id,
+            db,
+        )
+        .await
+        .map_err(|_| ServiceAccountError::DatabaseError)
+    }
+
+    pub async fn get(
+        id: Uuid,
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> ServiceAccountResult<User> {
+        match UserRepo::get_by_id_in_organization(id, organization_id, db).await {
+            Ok(user) if user.kind == UserKind::Service => Ok(user),
+            Ok(_) | Err(sqlx::Error::RowNotFound) => Err(ServiceAccountError::NotFound),
+            Err(_) => Err(ServiceAccountError::DatabaseError),
+        }
+    }
+
+    /// Hands the service account over to another user who can manage users in the organization
+    pub async fn update_owner(
+        id: Uuid,
+        owner_id: Uuid,
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> ServiceAccountResult<User> {
+        let mut tx = db.begin().await.unwrap();
+        ServiceAccountService::get(id, organization_id, &mut tx).await?;
+        let owner = UserRepo::get_by_id_in_organization(owner_id, organization_id, &mut tx)
+            .await
+            .map_err(|_| ServiceAccountError::InvalidOwner)?;
+        let owner_member = OrganizationRepo::get_member(organization_id, owner.id, &mut tx)
+            .await
+            .map_err(|_| ServiceAccountError::DatabaseError)?;
+        if owner.kind != UserKind::Human
+            || !RoleService::member_permissions(&owner_member, &mut tx)
+                .await?
+                .contains(&Permission::UsersWrite)
+        {
+            return Err(ServiceAccountError::InvalidOwner);
+        }
+        let updated = UserRepo::update_owner(id, owner_id, &mut tx)
+            .await
+            .map_err(|_| ServiceAccountError::DatabaseError)?;
+        tx.commit().await.unwrap();
+        Ok(updated)
+    }
+
+    pub async fn list_tokens(
+        id: Uuid,
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> ServiceAccountResult<Vec<Token>> {
+        ServiceAccountService::get(id, organization_id, db).await?;
+        TokenRepo::list_for_user(id, db)
+            .await
+            .map_err(|_| ServiceAccountError::DatabaseError)
+    }
+
+    /// The scopes have to be granted to the service account and by the token that
+    /// creates it
+    pub async fn create_token(
+        id: Uuid,
+        name: String,
+        scopes: &[Permission],
+        auth: &AuthContext,
+        db: &mut PgConnection,
+    ) -> ServiceAccountResult<CreatedToken> {
+        if scopes.is_empty() {
+            return Err(ServiceAccountError::MissingScopes);
+        }
+        let organization_id = auth.user.organization_id;
+        let account = ServiceAccountService::get(id, organization_id, db).await?;
+        let member = OrganizationRepo::get_member(organization_id, account.id, db)
+            .await
+            .map_err(|_| ServiceAccountError::DatabaseError)?;
+        let granted = RoleService::member_permissions(&member, db).await?;
+        if let Some(scope) = scopes
+            .iter()
+            .find(|s| !granted.contains(s) || !auth.has_permission(**s))
+        {
+            return Err(ServiceAccountError::PermissionNotGranted(String::from(
+                *scope,
+            )));
+        }
+        TokenRepo::create_one_access_token(id, organization_id, name, scopes, db)
+            .await
+            .map_err(|_| ServiceAccountError::DatabaseError)
+    }
+
+    pub async fn delete_token(
+        id: Uuid,
+        token_id: i32,
+        organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> ServiceAccountResult<i32> {
+        ServiceAccountService::get(id, organization_id, db).await?;
+        TokenRepo::delete_by_id(token_id, id, db)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => ServiceAccountError::TokenNotFound,
+                _ => ServiceAccountError::DatabaseError,
+            })
+    }
+}
+
+#[derive(thiserror::Error, Debug, JsonErrorResponse)]
+pub enum ServiceAccountError {
+    #[error("Service account not found")]
+    #[status_code(StatusCode::NOT_FOUND)]
+    NotFound,
+
+    #[error("Token not found")]
+    #[status_code(StatusCode::NOT_FOUND)]
+    TokenNotFound,
+
+    #[error("The owner has to be a user who can manage users")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    InvalidOwner,
+
+    #[error("At least one scope is required")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    MissingScopes,
+
+    #[error("The permission {0} can't be granted")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    PermissionNotGranted(String),
+
+    #[error("Unknown role {0}")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    UnknownRole(String),
+
+    #[error("Database error")]
+    #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
+    DatabaseError,
+}
+
+impl From<RoleError> for ServiceAccountError {
+    fn from(value: RoleError) -> Self {
+        match value {
+            RoleError::UnknownRole(role) => ServiceAccountError::UnknownRole(role),
+            _ => ServiceAccountError::DatabaseError,
+        }
+    }
+}
+
+pub type ServiceAccountResult<T> = Result<T, ServiceAccountError>;
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::{
+        model::auth::SessionWithToken,
+        service::auth::{AuthError, AuthService},
+        utils::{auth::AuthCredential, testing},
+    };
+
+    async fn auth_of(session: &SessionWithToken, db: &mut PgConnection) -> AuthContext {
+        let credential = AuthCredential::SessionCookie(session.token.plaintext.clone());
+        AuthService::authenticate(credential, None, db)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn creates_only_accounts_whose_role_the_owner_has(pool: PgPool) {
+        let conn = &mut pool.acquire().await.unwrap();
+        let organization = testing::setup(conn).await;
+        let author =
+            testing::create_user("author@example.com", Role::AUTHOR, organization.id, conn).await;
+        let session = testing::create_session(&author, organization.id, conn).await;
+        let auth = auth_of(&session, conn).await;
+
+        let result = ServiceAccountService::create(
+            "Deploy".to_string(),
+            None,
+            Role::from(Role::ADMIN),
+            &auth,
+            conn,
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(ServiceAccountError::PermissionNotGranted(_))
+        ));
+
+        let account = ServiceAccountService::create(
+            "Deploy".to_string(),
+            None,
+            Role::from(Role::AUTHOR),
+            &auth,
+            conn,
+        )
+        .await
+        .unwrap();
+        assert_eq!(account.kind, UserKind::Service);
+    }
+
+    #[sqlx::test]
+    async fn authenticates_accounts_only_with_access_tokens(pool: PgPool) {
+        let conn = &mut pool.acquire().await.unwrap();
+        let organization = testing::setup(conn).await;
+        let admin =
+            testing::create_user("admin@example.com", Role::ADMIN, organization.id, conn).await;
+        let session = testing::create_session(&admin, organization.id, conn).await;
+        let auth = auth_of(&session, conn).await;
+        let account = ServiceAccountService::create(
+            "Deploy".to_string(),
+            None,
+            Role::from(Role::AUTHOR),
+            &auth,
+            conn,
+        )
+        .await
+        .unwrap();
+
+        let token = ServiceAccountService::create_token(
+            account.id,
+            "CI".to_string(),
+            &[Permission::UsersRead],
+            &auth,
+            conn,
+        )
+        .await
+        .unwrap();
+        let credential = AuthCredential::Bearer(token.plaintext);
+        let token_auth = AuthService::authenticate(credential, None, conn)
+            .await
+            .unwrap();
+        assert_eq!(token_auth.user.id, account.id);
+
+        // Even with a known password there is no login and no session
+        UserRepo::update_password_hash(account.id, &hash_password(testing::PASSWORD), conn)
+            .await
+            .unwrap();
+        let result = AuthService::login(
+            account.email.clone(),
+            testing::PASSWORD.to_string(),
+            None,
+            "test".to_string(),
+            conn,
+        )
+        .await;
+        assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+        let session = testing::create_session(&account, organization.id, conn).await;
+        let credential = AuthCredential::SessionCookie(session.token.plaintext.clone());
+        let result = AuthService::authenticate(credential, None, conn).await;
+        assert!(matches!(result, Err(AuthError::InvalidCredentials)));
+    }
+}
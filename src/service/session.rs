@@ -0,0 +1,199 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{types::ipnetwork::IpNetwork, PgConnection};
+use uuid::Uuid;
+
+use crate::{
+    config::env_or,
+    events::{Event, EventChannel},
+    model::auth::{Session, Token},
+    repo::{session::SessionRepo, token::TokenRepo},
+    service::auth::{AuthError, AuthResult},
+};
+
+pub struct SessionConfig {
+    /// Sessions end this long after the login, regardless of their activity
+    pub max_lifetime: Duration,
+    /// Sessions end after this long without a request
+    pub idle_timeout: Duration,
+    /// The activity of a session is written at most once per interval
+    pub touch_interval: Duration,
+    /// Impersonation sessions end this long after they were opened
+    pub impersonation_max_lifetime: Duration,
+}
+
+impl SessionConfig {
+    pub fn from_env() -> Self {
+        Self {
+            max_lifetime: Duration::days(env_or("SESSION_MAX_LIFETIME_DAYS", 30)),
+            idle_timeout: Duration::hours(env_or("SESSION_IDLE_TIMEOUT_HOURS", 168)),
+            touch_interval: Duration::seconds(env_or("SESSION_TOUCH_INTERVAL_SECONDS", 60)),
+            impersonation_max_lifetime: Duration::minutes(env_or(
+                "IMPERSONATION_MAX_LIFETIME_MINUTES",
+                60,
+            )),
+        }
+    }
+
+    /// The expiration of a session that is used now
+    pub fn expiration(&self, created_at: DateTime<Utc>) -> DateTime<Utc> {
+        (Utc::now() + self.idle_timeout).min(created_at + self.max_lifetime)
+    }
+
+    /// The expiration of an impersonation session that is used now
+    pub fn impersonation_expiration(&self, created_at: DateTime<Utc>) -> DateTime<Utc> {
+        self.expiration(created_at)
+            .min(created_at + self.impersonation_max_lifetime)
+    }
+}
+
+#[derive(Clone)]
+pub struct SessionService {}
+
+impl SessionService {
+    /// Records a request of the session and extends its expiration by the idle timeout. Requests
+    /// within the touch interval of the last recorded one are skipped to avoid a write on every
+    /// request. Returns the session of the token.
+    pub async fn touch(
+        token: &Token,
+        ip: Option<IpNetwork>,
+        db: &mut PgConnection,
+    ) -> AuthResult<Session> {
+        let config = SessionConfig::from_env();
+        let touched_before = Utc::now() - config.touch_interval;
+        let session = SessionRepo::get_by_token_id(token.id, db)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => AuthError::InvalidCredentials,
+                _ => AuthError::DatabaseError,
+            })?;
+        if session.last_used_at >= touched_before {
+            return Ok(session);
+        }
+        let touched = SessionRepo::touch(token.id, ip, touched_before, db)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+        let Some(session) = touched else {
+            return Ok(session);
+        };
+        let expiration = match session.impersonator_id {
+            Some(_) => config.impersonation_expiration(session.created_at),
+            None => config.expiration(session.created_at),
+        };
+        TokenRepo::update_expiration(token.id, expiration, db)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+        Ok(session)
+    }
+
+    /// Ends every session of the user except the one of `keep_token_id` and closes their
+    /// WebSockets. Returns the number of ended sessions.
+    pub async fn revoke_all(
+        user_id: Uuid,
+        keep_token_id: Option<i32>,
+        events: &EventChannel,
+        db: &mut PgConnection,
+    ) -> AuthResult<usize> {
+        let token_ids = SessionRepo::delete_all_for_user(user_id, keep_token_id, db)
+            .await
+            .map_err(|_| AuthError::DatabaseError)?;
+        let count = token_ids.len();
+        if count > 0 {
+            // Ignore the result, because this errors if there are no receivers in the channel
+            let _ = events.publish(Event::SessionsRevoked { token_ids });
+        }
+        Ok(count)
+    }
+
+    /// Ends a session of the user and closes its WebSockets
+    pub async fn revoke(
+        session_id: i32,
+        user_id: Uuid,
+        events: &EventChannel,
+        db: &mut PgConnection,
+    ) -> AuthResult<()> {
+        let token_id = SessionRepo::delete_by_id_for_user(session_id, user_id, db)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => AuthError::SessionNotFound,
+                _ => AuthError::DatabaseError,
+            })?;
+        // Ignore the result, because this errors if there are no receivers in the channel
+        let _ = events.publish(Event::SessionsRevoked {
+            token_ids: vec![token_id],
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::{
+        model::auth::{Role, SessionWithToken},
+        service::auth::AuthService,
+        utils::{auth::AuthCredential, testing},
+    };
+
+    async fn is_valid(session: &SessionWithToken, db: &mut PgConnection) -> bool {
+        let credential = AuthCredential::SessionCookie(session.token.plaintext.clone());
+        AuthService::authenticate(credential, None, db)
+            .await
+            .is_ok()
+    }
+
+    #[sqlx::test]
+    async fn revokes_only_sessions_of_the_user(pool: PgPool) {
+        let conn = &mut pool.acquire().await.unwrap();
+        let organization = testing::setup(conn).await;
+        let user =
+            testing::create_user("user@example.com", Role::AUTHOR, organization.id, conn).await;
+        let other =
+            testing::create_user("other@example.com", Role::AUTHOR, organization.id, conn).await;
+        let session = testing::create_session(&user, organization.id, conn).await;
+        let other_session = testing::create_session(&other, organization.id, conn).await;
+        let events = EventChannel::new();
+
+        let result = SessionService::revoke(other_session.session.id, user.id, &events, conn).await;
+        assert!(matches!(result, Err(AuthError::SessionNotFound)));
+        assert!(is_valid(&other_session, conn).await);
+
+        SessionService::revoke(session.session.id, user.id, &events, conn)
+            .await
+            .unwrap();
+        assert!(!is_valid(&session, conn).await);
+        assert!(is_valid(&other_session, conn).await);
+    }
+
+    #[sqlx::test]
+    async fn revokes_all_sessions_but_the_kept_one(pool: PgPool) {
+        let conn = &mut pool.acquire().await.unwrap();
+        let organization = testing::setup(conn).await;
+        let user =
+            testing::create_user("user@example.com", Role::AUTHOR, organization.id, conn).await;
+        let other =
+            testing::create_user("other@example.com", Role::AUTHOR, organization.id, conn).await;
+        let current = testing::create_session(&user, organization.id, conn).await;
+        let first = testing::create_session(&user, organization.id, conn).await;
+        let second = testing::create_session(&user, organization.id, conn).await;
+        let other_session = testing::create_session(&other, organization.id, conn).await;
+        let events = EventChannel::new();
+
+        let revoked =
+            SessionService::revoke_all(user.id, Some(current.token.token.id), &events, conn)
+                .await
+                .unwrap();
+        assert_eq!(revoked, 2);
+        assert!(is_valid(&current, conn).await);
+        assert!(!is_valid(&first, conn).await);
+        assert!(!is_valid(&second, conn).await);
+        assert!(is_valid(&other_session, conn).await);
+
+        let revoked = SessionService::revoke_all(user.id, None, &events, conn)
+            .await
+            .unwrap();
+        assert_eq!(revoked, 1);
+        assert!(!is_valid(&current, conn).await);
+    }
+}
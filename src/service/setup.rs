@@ -1,15 +1,32 @@
 use sqlx::PgConnection;
+use uuid::Uuid;
 
-use crate::{model::Settings, repo::settings::SettingsRepo};
+use crate::{
+    model::{Organization, Settings},
+    repo::{organization::OrganizationRepo, settings::SettingsRepo},
+};
 
 #[derive(Clone)]
 pub struct SetupService {}
 
 impl SetupService {
-    pub async fn finish_setup(db: &mut PgConnection) -> Result<Settings, sqlx::Error> {
+    /// The first organization becomes the default one, whose admins manage what all
+    /// organizations share
+    pub async fn create_organization(
+        name: &str,
+        db: &mut PgConnection,
+    ) -> Result<Organization, sqlx::Error> {
+        OrganizationRepo::create_one(name, db).await
+    }
+
+    pub async fn finish_setup(
+        default_organization_id: Uuid,
+        db: &mut PgConnection,
+    ) -> Result<Settings, sqlx::Error> {
         SettingsRepo::upsert(
             Settings {
                 setup_finished: true,
+                default_organization_id: Some(default_organization_id),
                 ..Default::default()
             },
             db,
@@ -18,10 +35,7 @@ impl SetupService {
     }
 
     pub async fn is_setup_finished(db: &mut PgConnection) -> Result<bool, sqlx::Error> {
-        let settings = match SettingsRepo::get(db).await {
-            Ok(settings) => settings,
-            Err(_) => Settings::default(),
-        };
+        let settings: Settings = SettingsRepo::get(db).await.unwrap_or_default();
         Ok(settings.setup_finished)
     }
 }
@@ -0,0 +1,141 @@
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use chrono::{DateTime, Utc};
+use macros::JsonErrorResponse;
+use sqlx::{Acquire, PgConnection};
+use uuid::Uuid;
+
+use crate::{
+    events::EventChannel,
+    model::{auth::TokenType, user::User},
+    repo::{token::TokenRepo, user::UserRepo},
+    service::{email::EmailService, jwt::JwtService, session::SessionService},
+    utils::{auth::AuthContext, error::ErrorResponse},
+};
+
+const MAX_REASON_LEN: usize = 1024;
+
+#[derive(Clone)]
+pub struct SuspensionService {}
+
+impl SuspensionService {
+    /// Suspends a member of the active organization, until `suspended_until` or until the
+    /// suspension is lifted. The sessions and refresh tokens of the user are revoked and their
+    /// access tokens are rejected while the suspension lasts. JWTs that were already issued are
+    /// rejected by this instance right away and by others once they reload the suspended users.
+    /// Suspending a suspended user replaces the suspension.
+    pub async fn suspend(
+        user_id: Uuid,
+        suspended_until: Option<DateTime<Utc>>,
+        reason: Option<String>,
+        auth: &AuthContext,
+        events: &EventChannel,
+        db: &mut PgConnection,
+    ) -> SuspensionResult<User> {
+        if user_id == auth.user.id {
+            return Err(SuspensionError::OwnAccount);
+        }
+        if suspended_until.is_some_and(|until| until <= Utc::now()) {
+            return Err(SuspensionError::InvalidEnd);
+        }
+        let reason = reason
+            .map(|r| r.trim().to_string())
+            .filter(|r| !r.is_empty());
+        if reason.as_ref().is_some_and(|r| r.len() > MAX_REASON_LEN) {
+            return Err(SuspensionError::InvalidReason);
+        }
+        let mut tx = db.begin().await.unwrap();
+        SuspensionService::get_member(user_id, auth, &mut tx).await?;
+        let suspended = UserRepo::suspend(
+            user_id,
+            suspended_until,
+            reason.as_deref(),
+            auth.user.id,
+            &mut tx,
+        )
+        .await
+        .map_err(|_| SuspensionError::DatabaseError)?;
+        SessionService::revoke_all(user_id, None, events, &mut tx)
+            .await
+            .map_err(|_| SuspensionError::DatabaseError)?;
+        TokenRepo::delete_all_of_type_for_user(user_id, TokenType::Refresh, &mut tx)
+            .await
+            .map_err(|_| SuspensionError::DatabaseError)?;
+        tx.commit().await.unwrap();
+        JwtService::reload_revocations();
+        if let Err(e) = EmailService::send_account_suspended_email(
+            suspended.email.clone(),
+            suspended.suspended_until,
+            suspended.suspension_reason.clone(),
+        )
+        .await
+        {
+            tracing::error!("Sending the suspension email failed: {e}");
+        }
+        Ok(suspended)
+    }
+
+    /// Lifts the suspension of a member of the active organization. Returns the user before and
+    /// after.
+    pub async fn unsuspend(
+        user_id: Uuid,
+        auth: &AuthContext,
+        db: &mut PgConnection,
+    ) -> SuspensionResult<(User, User)> {
+        let before = SuspensionService::get_member(user_id, auth, db).await?;
+        if !before.is_suspended() {
+            return Err(SuspensionError::NotSuspended);
+        }
+        let unsuspended = UserRepo::unsuspend(user_id, db)
+            .await
+            .map_err(|_| SuspensionError::DatabaseError)?;
+        JwtService::reload_revocations();
+        if let Err(e) =
+            EmailService::send_account_unsuspended_email(unsuspended.email.clone()).await
+        {
+            tracing::error!("Sending the unsuspension email failed: {e}");
+        }
+        Ok((before, unsuspended))
+    }
+
+    async fn get_member(
+        user_id: Uuid,
+        auth: &AuthContext,
+        db: &mut PgConnection,
+    ) -> SuspensionResult<User> {
+        UserRepo::get_by_id_in_organization(user_id, auth.user.organization_id, db)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => SuspensionError::UserNotFound,
+                _ => SuspensionError::DatabaseError,
+            })
+    }
+}
+
+#[derive(thiserror::Error, Debug, JsonErrorResponse)]
+pub enum SuspensionError {
+    #[error("User not found")]
+    #[status_code(StatusCode::NOT_FOUND)]
+    UserNotFound,
+
+    #[error("You can't suspend your own account")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    OwnAccount,
+
+    #[error("The end of the suspension has to be in the future")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    InvalidEnd,
+
+    #[error("The reason can't be longer than 1024 characters")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    InvalidReason,
+
+    #[error("The user isn't suspended")]
+    #[status_code(StatusCode::CONFLICT)]
+    NotSuspended,
+
+    #[error("Database error")]
+    #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
+    DatabaseError,
+}
+
+pub type SuspensionResult<T> = Result<T, SuspensionError>;
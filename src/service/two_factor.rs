@@ -0,0 +1,266 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use chrono::Utc;
+use macros::JsonErrorResponse;
+use rand::{distributions::Alphanumeric, Rng};
+use ring::constant_time;
+use sqlx::{Acquire, PgConnection};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::{
+    config,
+    model::{auth::SecondFactor, user::User},
+    repo::{recovery_code::RecoveryCodeRepo, user::UserRepo},
+    service::auth::verify_password,
+    utils::{
+        auth::{decrypt_secret, encrypt_secret, hash_token},
+        error::ErrorResponse,
+    },
+};
+
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_LEN: usize = 10;
+
+pub struct TotpEnrolment {
+    /// The base32 encoded secret for manual entry
+    pub secret: String,
+    /// The `otpauth://` URI to render as QR code
+    pub provisioning_uri: String,
+}
+
+#[derive(Clone)]
+pub struct TwoFactorService {}
+
+impl TwoFactorService {
+    pub fn is_enabled(user: &User) -> bool {
+        user.totp_enabled_at.is_some()
+    }
+
+    /// Stores a new secret for the user, which is only used after it was confirmed with
+    /// `confirm_totp_enrolment`
+    pub async fn start_totp_enrolment(
+        user: &User,
+        db: &mut PgConnection,
+    ) -> TwoFactorResult<TotpEnrolment> {
+        if TwoFactorService::is_enabled(user) {
+            return Err(TwoFactorError::AlreadyEnabled);
+        }
+        let secret = Secret::Raw(rand::thread_rng().gen::<[u8; 20]>().to_vec())
+            .to_encoded()
+            .to_string();
+        let totp = totp_for(&secret, user)?;
+        UserRepo::update_totp(user.id, Some(&encrypt_secret(secret.as_bytes())), None, db)
+            .await
+            .map_err(|_| TwoFactorError::DatabaseError)?;
+        Ok(TotpEnrolment {
+            secret,
+            provisioning_uri: totp.get_url(),
+        })
+    }
+
+    /// Enables TOTP if the code matches the pending enrolment and returns the recovery codes
+    pub async fn confirm_totp_enrolment(
+        user: &User,
+        code: &str,
+        db: &mut PgConnection,
+    ) -> TwoFactorResult<Vec<String>> {
+        if TwoFactorService::is_enabled(user) {
+            return Err(TwoFactorError::AlreadyEnabled);
+        }
+        let secret = user
+            .totp_secret
+            .as_deref()
+            .ok_or(TwoFactorError::NotEnrolled)?;
+        let mut tx = db.begin().await.unwrap();
+        TwoFactorService::check_code(user, code, &mut tx).await?;
+        UserRepo::update_totp(user.id, Some(secret), Some(Utc::now()), &mut tx)
+            .await
+            .map_err(|_| TwoFactorError::DatabaseError)?;
+        let codes = TwoFactorService::replace_recovery_codes(user, &mut tx).await?;
+        tx.commit().await.unwrap();
+        Ok(codes)
+    }
+
+    /// Invalidates the remaining recovery codes and returns new ones
+    pub async fn regenerate_recovery_codes(
+        user: &User,
+        second_factor: &SecondFactor,
+        db: &mut PgConnection,
+    ) -> TwoFactorResult<Vec<String>> {
+        TwoFactorService::verify(user, second_factor, db).await?;
+        TwoFactorService::replace_recovery_codes(user, db).await
+    }
+
+    pub async fn disable(
+        user: &User,
+        password: &str,
+        second_factor: &SecondFactor,
+        db: &mut PgConnection,
+    ) -> TwoFactorResult<()> {
+        if !verify_password(user, password) {
+            return Err(TwoFactorError::InvalidCredentials);
+        }
+        TwoFactorService::verify(user, second_factor, db).await?;
+        let mut tx = db.begin().await.unwrap();
+        UserRepo::update_totp(user.id, None, None, &mut tx)
+            .await
+            .map_err(|_| TwoFactorError::DatabaseError)?;
+        RecoveryCodeRepo::delete_for_user(user.id, &mut tx)
+            .await
+            .map_err(|_| TwoFactorError::DatabaseError)?;
+        tx.commit().await.unwrap();
+        Ok(())
+    }
+
+    /// Checks a TOTP code or uses up a recovery code
+    pub async fn verify(
+        user: &User,
+        second_factor: &SecondFactor,
+        db: &mut PgConnection,
+    ) -> TwoFactorResult<()> {
+        if user.totp_secret.is_none() || !TwoFactorService::is_enabled(user) {
+            return Err(TwoFactorError::NotEnabled);
+        }
+        match second_factor {
+            SecondFactor::Totp { code } => TwoFactorService::check_code(user, code, db).await,
+            SecondFactor::RecoveryCode { recovery_code } => {
+                let valid =
+                    RecoveryCodeRepo::use_one(user.id, &hash_recovery_code(recovery_code), db)
+                        .await
+                        .map_err(|_| TwoFactorError::DatabaseError)?;
+                if valid {
+                    Ok(())
+                } else {
+                    Err(TwoFactorError::InvalidCode)
+                }
+            }
+        }
+    }
+
+    /// Accepts a code of the current or a neighbouring time step, unless a code of the same or a
+    /// later step was accepted before. Otherwise an observed code could be replayed.
+    async fn check_code(user: &User, code: &str, db: &mut PgConnection) -> TwoFactorResult<()> {
+        let secret = user
+            .totp_secret
+            .as_deref()
+            .and_then(decrypt_secret)
+            .and_then(|secret| String::from_utf8(secret).ok())
+            .ok_or(TwoFactorError::InternalServerError(
+                "The TOTP secret can't be decrypted".to_string(),
+            ))?;
+        let step =
+            matching_step(&totp_for(&secret, user)?, code).ok_or(TwoFactorError::InvalidCode)?;
+        let unused = UserRepo::use_totp_step(user.id, step, db)
+            .await
+            .map_err(|_| TwoFactorError::DatabaseError)?;
+        if !unused {
+            return Err(TwoFactorError::InvalidCode);
+        }
+        Ok(())
+    }
+
+    async fn replace_recovery_codes(
+        user: &User,
+        db: &mut PgConnection,
+    ) -> TwoFactorResult<Vec<String>> {
+        let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| generate_recovery_code())
+            .collect();
+        let hashes: Vec<Vec<u8>> = codes.iter().map(|c| hash_recovery_code(c)).collect();
+        RecoveryCodeRepo::replace_for_user(user.id, &hashes, db)
+            .await
+            .map_err(|_| TwoFactorError::DatabaseError)?;
+        Ok(codes)
+    }
+}
+
+fn totp_for(secret: &str, user: &User) -> TwoFactorResult<TOTP> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| TwoFactorError::InternalServerError(e.to_string()))?;
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        secret,
+        Some(config::APP_NAME.to_string()),
+        user.email.clone(),
+    )
+    .map_err(|e| TwoFactorError::InternalServerError(e.to_string()))
+}
+
+/// The time step of the code, which may be one step off to allow for clock drift
+fn matching_step(totp: &TOTP, code: &str) -> Option<i64> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let current = now / totp.step;
+    let skew = u64::from(totp.skew);
+    (current.saturating_sub(skew)..=current + skew)
+        .find(|step| {
+            constant_time::verify_slices_are_equal(
+                totp.generate(step * totp.step).as_bytes(),
+                code.as_bytes(),
+            )
+            .is_ok()
+        })
+        .and_then(|step| i64::try_from(step).ok())
+}
+
+/// Formatted as `xxxxx-xxxxx` for readability
+fn generate_recovery_code() -> String {
+    let code: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(RECOVERY_CODE_LEN)
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .collect();
+    let (first, second) = code.split_at(RECOVERY_CODE_LEN / 2);
+    format!("{first}-{second}")
+}
+
+/// Recovery codes are stored as HMAC like tokens. Dashes, whitespace and case are ignored.
+fn hash_recovery_code(code: &str) -> Vec<u8> {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hash_token(&normalized)
+}
+
+#[derive(thiserror::Error, Debug, JsonErrorResponse)]
+pub enum TwoFactorError {
+    #[error("Unauthorized")]
+    #[status_code(StatusCode::UNAUTHORIZED)]
+    Unauthorized,
+
+    #[error("Two-factor authentication is already enabled")]
+    #[status_code(StatusCode::CONFLICT)]
+    AlreadyEnabled,
+
+    #[error("Two-factor authentication was not enrolled")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    NotEnrolled,
+
+    #[error("Two-factor authentication is not enabled")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    NotEnabled,
+
+    #[error("Invalid two-factor code")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    InvalidCode,
+
+    #[error("Invalid credentials")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    InvalidCredentials,
+
+    #[error("Internal server error: {0}")]
+    #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
+    InternalServerError(String),
+
+    #[error("Database error")]
+    #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
+    DatabaseError,
+}
+
+pub type TwoFactorResult<T> = Result<T, TwoFactorError>;
@@ -0,0 +1,246 @@
+use std::env;
+
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use macros::JsonErrorResponse;
+use sqlx::{Acquire, PgConnection};
+use uuid::Uuid;
+use webauthn_rs::prelude::{
+    CreationChallengeResponse, Passkey, PasskeyAuthentication, PasskeyRegistration,
+    PublicKeyCredential, RegisterPublicKeyCredential, RequestChallengeResponse, Url, Webauthn,
+    WebauthnBuilder,
+};
+
+use crate::{
+    config,
+    model::{
+        auth::{WebauthnCeremonyKind, WebauthnCredential},
+        user::User,
+    },
+    repo::{
+        user::UserRepo, webauthn_ceremony::WebauthnCeremonyRepo,
+        webauthn_credential::WebauthnCredentialRepo,
+    },
+    utils::error::ErrorResponse,
+};
+
+/// Builds the relying party from the `BASE_URL` of the frontend. The relying party id defaults to
+/// its host and can be overwritten with `WEBAUTHN_RP_ID`, e.g. to allow passkeys on subdomains.
+pub fn webauthn_from_env() -> Webauthn {
+    let origin = Url::parse(&config::base_url()).expect("BASE_URL is not a valid url");
+    let rp_id = env::var("WEBAUTHN_RP_ID")
+        .unwrap_or(origin.host_str().expect("BASE_URL has no host").to_string());
+    WebauthnBuilder::new(&rp_id, &origin)
+        .expect("Invalid WebAuthn configuration")
+        .rp_name(config::APP_NAME)
+        .build()
+        .expect("Invalid WebAuthn configuration")
+}
+
+#[derive(Clone)]
+pub struct WebauthnService {}
+
+impl WebauthnService {
+    pub async fn has_credentials(user: &User, db: &mut PgConnection) -> WebauthnResult<bool> {
+        WebauthnCredentialRepo::count_for_user(user.id, db)
+            .await
+            .map(|count| count > 0)
+            .map_err(|_| WebauthnError::DatabaseError)
+    }
+
+    /// Returns the id of the ceremony and the options for `navigator.credentials.create()`
+    pub async fn start_registration(
+        webauthn: &Webauthn,
+        user: &User,
+        db: &mut PgConnection,
+    ) -> WebauthnResult<(Uuid, CreationChallengeResponse)> {
+        let existing = WebauthnCredentialRepo::list_for_user(user.id, db)
+            .await
+            .map_err(|_| WebauthnError::DatabaseError)?
+            .into_iter()
+            .map(|c| c.passkey.cred_id().clone())
+            .collect();
+        let display_name = match (&user.first_name, &user.last_name) {
+            (Some(first_name), Some(last_name)) => format!("{first_name} {last_name}"),
+            _ => user.email.clone(),
+        };
+        let (options, state) = webauthn
+            .start_passkey_registration(user.id, &user.email, &display_name, Some(existing))
+            .map_err(|e| WebauthnError::InternalServerError(e.to_string()))?;
+        let ceremony_id = WebauthnService::store_ceremony(
+            user.id,
+            WebauthnCeremonyKind::Registration,
+            &state,
+            db,
+        )
+        .await?;
+        Ok((ceremony_id, options))
+    }
+
+    pub async fn finish_registration(
+        webauthn: &Webauthn,
+        user: &User,
+        ceremony_id: Uuid,
+        name: &str,
+        credential: &RegisterPublicKeyCredential,
+        db: &mut PgConnection,
+    ) -> WebauthnResult<WebauthnCredential> {
+        let ceremony =
+            WebauthnCeremonyRepo::take_valid(ceremony_id, WebauthnCeremonyKind::Registration, db)
+                .await
+                .map_err(|_| WebauthnError::InvalidCeremony)?;
+        if ceremony.user_id != user.id {
+            return Err(WebauthnError::InvalidCeremony);
+        }
+        let state: PasskeyRegistration =
+            serde_json::from_value(ceremony.state).map_err(|_| WebauthnError::InvalidCeremony)?;
+        let passkey = webauthn
+            .finish_passkey_registration(credential, &state)
+            .map_err(|_| WebauthnError::VerificationFailed)?;
+        WebauthnCredentialRepo::create_one(user.id, name, &passkey, db)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::Database(e) if e.is_unique_violation() => {
+                    WebauthnError::CredentialAlreadyRegistered
+                }
+                _ => WebauthnError::DatabaseError,
+            })
+    }
+
+    /// Returns the id of the ceremony and the options for `navigator.credentials.get()`, which
+    /// only allow the passkeys of the given user
+    pub async fn start_authentication(
+        webauthn: &Webauthn,
+        user: &User,
+        db: &mut PgConnection,
+    ) -> WebauthnResult<(Uuid, RequestChallengeResponse)> {
+        let passkeys: Vec<Passkey> = WebauthnCredentialRepo::list_for_user(user.id, db)
+            .await
+            .map_err(|_| WebauthnError::DatabaseError)?
+            .into_iter()
+            .map(|c| c.passkey.0)
+            .collect();
+        if passkeys.is_empty() {
+            return Err(WebauthnError::NoCredentials);
+        }
+        let (options, state) = webauthn
+            .start_passkey_authentication(&passkeys)
+            .map_err(|e| WebauthnError::InternalServerError(e.to_string()))?;
+        let ceremony_id = WebauthnService::store_ceremony(
+            user.id,
+            WebauthnCeremonyKind::Authentication,
+            &state,
+            db,
+        )
+        .await?;
+        Ok((ceremony_id, options))
+    }
+
+    /// The user an authentication ceremony was started for, so a failed assertion can be counted
+    /// for the account before the ceremony is finished
+    pub async fn authenticating_user(
+        ceremony_id: Uuid,
+        db: &mut PgConnection,
+    ) -> WebauthnResult<User> {
+        let ceremony =
+            WebauthnCeremonyRepo::get_valid(ceremony_id, WebauthnCeremonyKind::Authentication, db)
+                .await
+                .map_err(|_| WebauthnError::InvalidCeremony)?;
+        UserRepo::get_by_id(ceremony.user_id, db)
+            .await
+            .map_err(|_| WebauthnError::InvalidCeremony)
+    }
+
+    /// Verifies the assertion of the authenticator and returns the user the passkey belongs to
+    pub async fn finish_authentication(
+        webauthn: &Webauthn,
+        ceremony_id: Uuid,
+        credential: &PublicKeyCredential,
+        db: &mut PgConnection,
+    ) -> WebauthnResult<User> {
+        let ceremony =
+            WebauthnCeremonyRepo::take_valid(ceremony_id, WebauthnCeremonyKind::Authentication, db)
+                .await
+                .map_err(|_| WebauthnError::InvalidCeremony)?;
+        let state: PasskeyAuthentication =
+            serde_json::from_value(ceremony.state).map_err(|_| WebauthnError::InvalidCeremony)?;
+        let result = webauthn
+            .finish_passkey_authentication(credential, &state)
+            .map_err(|_| WebauthnError::VerificationFailed)?;
+        let mut tx = db.begin().await.unwrap();
+        let mut stored = WebauthnCredentialRepo::list_for_user(ceremony.user_id, &mut tx)
+            .await
+            .map_err(|_| WebauthnError::DatabaseError)?
+            .into_iter()
+            .find(|c| c.credential_id == result.cred_id().as_slice())
+            .ok_or(WebauthnError::VerificationFailed)?;
+        stored.passkey.update_credential(&result);
+        WebauthnCredentialRepo::update_after_use(&stored.credential_id, &stored.passkey, &mut tx)
+            .await
+            .map_err(|_| WebauthnError::DatabaseError)?;
+        let user = UserRepo::get_by_id(ceremony.user_id, &mut tx)
+            .await
+            .map_err(|_| WebauthnError::DatabaseError)?;
+        tx.commit().await.unwrap();
+        Ok(user)
+    }
+
+    async fn store_ceremony<T: serde::Serialize>(
+        user_id: Uuid,
+        kind: WebauthnCeremonyKind,
+        state: &T,
+        db: &mut PgConnection,
+    ) -> WebauthnResult<Uuid> {
+        let state = serde_json::to_value(state)
+            .map_err(|e| WebauthnError::InternalServerError(e.to_string()))?;
+        let _ = WebauthnCeremonyRepo::delete_expired(db).await;
+        WebauthnCeremonyRepo::create_one(user_id, kind, state, db)
+            .await
+            .map(|c| c.id)
+            .map_err(|_| WebauthnError::DatabaseError)
+    }
+}
+
+#[derive(thiserror::Error, Debug, JsonErrorResponse)]
+pub enum WebauthnError {
+    #[error("Unauthorized")]
+    #[status_code(StatusCode::UNAUTHORIZED)]
+    Unauthorized,
+
+    #[error("Invalid credentials")]
+    #[status_code(StatusCode::UNAUTHORIZED)]
+    InvalidCredentials,
+
+    #[error("Passkeys can only be managed with a session")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    SessionRequired,
+
+    #[error("No passkeys registered")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    NoCredentials,
+
+    #[error("The ceremony does not exist or expired")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    InvalidCeremony,
+
+    #[error("Verifying the passkey failed")]
+    #[status_code(StatusCode::BAD_REQUEST)]
+    VerificationFailed,
+
+    #[error("The passkey is already registered")]
+    #[status_code(StatusCode::CONFLICT)]
+    CredentialAlreadyRegistered,
+
+    #[error("Passkey not found")]
+    #[status_code(StatusCode::NOT_FOUND)]
+    NotFound,
+
+    #[error("Internal server error: {0}")]
+    #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
+    InternalServerError(String),
+
+    #[error("Database error")]
+    #[status_code(StatusCode::INTERNAL_SERVER_ERROR)]
+    DatabaseError,
+}
+
+pub type WebauthnResult<T> = Result<T, WebauthnError>;
@@ -1,3 +1,356 @@
+use std::{env, net::SocketAddr, sync::LazyLock};
+
+use axum::{
+    extract::ConnectInfo,
+    http::{Extensions, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::{
+    extract::CookieJar,
+    headers::{authorization::Bearer, Authorization, HeaderMapExt},
+};
+use macros::JsonErrorResponse;
+use ring::{
+    aead, digest, hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+use sqlx::types::ipnetwork::IpNetwork;
+use uuid::Uuid;
+
+use crate::{
+    config::SESSION_COOKIE,
+    model::{
+        auth::{Permission, Role, Token, TokenType, UserKind},
+        user::User,
+        OrganizationMember,
+    },
+    utils::error::ErrorResponse,
+};
+
+/// The number of characters of a token that are stored in plaintext to recognize it
+const TOKEN_PREFIX_LEN: usize = 8;
+
+static TOKEN_HASH_KEY: LazyLock<hmac::Key> = LazyLock::new(|| {
+    let secret = env::var("TOKEN_HASH_KEY").expect("TOKEN_HASH_KEY must be set");
+    assert!(
+        secret.len() >= 32,
+        "TOKEN_HASH_KEY must be at least 32 characters long"
+    );
+    hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes())
+});
+
+/// Panics on startup instead of the first request if `TOKEN_HASH_KEY` is missing or too short
+pub fn check_token_hash_key() {
+    LazyLock::force(&TOKEN_HASH_KEY);
+}
+
+/// Lets tests hash and encrypt without a configured `TOKEN_HASH_KEY`
+#[cfg(test)]
+pub fn set_test_token_hash_key() {
+    static SET: std::sync::Once = std::sync::Once::new();
+    SET.call_once(|| {
+        if env::var("TOKEN_HASH_KEY").is_err() {
+            env::set_var("TOKEN_HASH_KEY", "test-token-hash-key-0123456789abcdef");
+        }
+    });
+}
+
 pub fn generate_session_token() -> String {
     uuid::Uuid::new_v4().to_string()
 }
+
+/// Tokens are stored as HMAC, so a database dump doesn't contain usable tokens
+pub fn hash_token(token: &str) -> Vec<u8> {
+    hmac::sign(&TOKEN_HASH_KEY, token.as_bytes())
+        .as_ref()
+        .to_vec()
+}
+
+/// The hash the token migration stored for tokens that were saved in plaintext before
+pub fn legacy_hash_token(token: &str) -> Vec<u8> {
+    digest::digest(&digest::SHA256, token.as_bytes())
+        .as_ref()
+        .to_vec()
+}
+
+/// The CSRF token of a session is derived from its token, so it can be checked without storing it
+/// and can't be set by another site
+pub fn csrf_token(session_token: &str) -> String {
+    hmac::sign(&TOKEN_HASH_KEY, format!("csrf:{session_token}").as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Compares in constant time, so the token can't be guessed byte by byte
+pub fn verify_csrf_token(session_token: &str, csrf: &str) -> bool {
+    let expected = csrf_token(session_token);
+    expected.len() == csrf.len()
+        && expected
+            .bytes()
+            .zip(csrf.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Encrypts a secret that has to be stored, e.g. a private signing key. The key is derived from
+/// `TOKEN_HASH_KEY` and the random nonce is prepended to the ciphertext.
+pub fn encrypt_secret(plaintext: &[u8]) -> Vec<u8> {
+    let mut nonce = [0; aead::NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .expect("Generating a nonce failed");
+    let mut in_out = plaintext.to_vec();
+    secret_key()
+        .seal_in_place_append_tag(
+            aead::Nonce::assume_unique_for_key(nonce),
+            aead::Aad::empty(),
+            &mut in_out,
+        )
+        .expect("Encrypting the secret failed");
+    [nonce.as_slice(), &in_out].concat()
+}
+
+/// Returns `None` if the secret was encrypted with another `TOKEN_HASH_KEY`
+pub fn decrypt_secret(encrypted: &[u8]) -> Option<Vec<u8>> {
+    if encrypted.len() < aead::NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = encrypted.split_at(aead::NONCE_LEN);
+    let nonce = aead::Nonce::try_assume_unique_for_key(nonce).ok()?;
+    let mut in_out = ciphertext.to_vec();
+    let plaintext = secret_key()
+        .open_in_place(nonce, aead::Aad::empty(), &mut in_out)
+        .ok()?;
+    Some(plaintext.to_vec())
+}
+
+fn secret_key() -> aead::LessSafeKey {
+    let derived = hmac::sign(&TOKEN_HASH_KEY, b"secret-encryption");
+    aead::LessSafeKey::new(
+        aead::UnboundKey::new(&aead::AES_256_GCM, derived.as_ref())
+            .expect("The derived key has the length of AES-256"),
+    )
+}
+
+pub fn token_prefix(token: &str) -> String {
+    token.chars().take(TOKEN_PREFIX_LEN).collect()
+}
+
+/// A credential sent by the client to authenticate a request
+pub enum AuthCredential {
+    /// The value of the session cookie set by the login endpoint
+    SessionCookie(String),
+    /// A static access token sent as `Authorization: Bearer <token>`
+    Bearer(String),
+    /// A JWT access token sent as `Authorization: Bearer <jwt>`
+    Jwt(String),
+}
+
+impl AuthCredential {
+    /// Reads the credential from the request headers. A bearer token takes precedence over the
+    /// session cookie.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        if let Some(Authorization(bearer)) = headers.typed_get::<Authorization<Bearer>>() {
+            let token = bearer.token().to_string();
+            // Static access tokens are UUIDs, which never contain a dot
+            if token.contains('.') {
+                return Some(Self::Jwt(token));
+            }
+            return Some(Self::Bearer(token));
+        }
+        CookieJar::from_headers(headers)
+            .get(SESSION_COOKIE)
+            .map(|cookie| Self::SessionCookie(cookie.value().to_string()))
+    }
+}
+
+/// The address of the client the request came from
+pub fn client_ip(extensions: &Extensions) -> Option<IpNetwork> {
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().into())
+}
+
+tokio::task_local! {
+    /// The admin behind a request that is made with an impersonation session. `auth_middleware`
+    /// sets it for the request, so activity written meanwhile records the real actor.
+    pub static IMPERSONATOR_ID: Option<Uuid>;
+}
+
+pub fn current_impersonator_id() -> Option<Uuid> {
+    IMPERSONATOR_ID.try_with(|id| *id).ok().flatten()
+}
+
+tokio::task_local! {
+    /// The static access token a request is made with, set by `auth_middleware` like
+    /// `IMPERSONATOR_ID`
+    pub static ACCESS_TOKEN_ID: Option<i32>;
+}
+
+pub fn current_access_token_id() -> Option<i32> {
+    ACCESS_TOKEN_ID.try_with(|id| *id).ok().flatten()
+}
+
+tokio::task_local! {
+    /// The active organization of a request, set by `auth_middleware` like `IMPERSONATOR_ID`
+    pub static ORGANIZATION_ID: Option<Uuid>;
+}
+
+pub fn current_organization_id() -> Option<Uuid> {
+    ORGANIZATION_ID.try_with(|id| *id).ok().flatten()
+}
+
+/// The authenticated user of a request together with the token that was used.
+/// `auth_middleware` inserts this into the request extensions.
+#[derive(Clone, Debug)]
+pub struct AuthContext {
+    pub user: AuthUser,
+    pub token: Token,
+    /// The admin who opened the session, if it is an impersonation session
+    pub impersonator_id: Option<Uuid>,
+    /// The session was started while a password change was pending and may only change the
+    /// password, see `PermissionLayer::allow_password_change_required`
+    pub password_change_required: bool,
+}
+
+/// What authorization needs to know about the user of a request. Requests with a JWT are
+/// authenticated from its claims alone, so the whole user is only loaded for other credentials.
+#[derive(Clone, Debug)]
+pub struct AuthUser {
+    pub id: Uuid,
+    /// The organization the request acts in
+    pub organization_id: Uuid,
+    /// The role of the user in the organization
+    pub role: Role,
+    pub kind: UserKind,
+    /// What the role and the groups granted when the request was authenticated
+    pub permissions: Vec<Permission>,
+    /// `None` if the request was authenticated by a JWT
+    pub loaded: Option<User>,
+}
+
+impl AuthUser {
+    pub fn new(user: User, member: OrganizationMember, permissions: Vec<Permission>) -> Self {
+        Self {
+            id: user.id,
+            organization_id: member.organization_id,
+            role: member.role,
+            kind: user.kind,
+            permissions,
+            loaded: Some(user),
+        }
+    }
+}
+
+impl AuthContext {
+    /// The role of the user has to grant the permission. Sessions may then use it, static access
+    /// tokens only if it is in their scopes.
+    pub fn require_permission(&self, permission: Permission) -> Result<(), PermissionError> {
+        if !self.user.permissions.contains(&permission) {
+            return Err(PermissionError::MissingPermission(String::from(permission)));
+        }
+        match self.token.token_type {
+            TokenType::StaticAccess if !self.token.has_scope(permission) => {
+                Err(PermissionError::MissingScope(String::from(permission)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.require_permission(permission).is_ok()
+    }
+}
+
+#[derive(thiserror::Error, Debug, JsonErrorResponse)]
+pub enum PermissionError {
+    #[error("Unauthorized")]
+    #[status_code(StatusCode::UNAUTHORIZED)]
+    Unauthorized,
+
+    #[error("Missing the permission {0}")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    MissingPermission(String),
+
+    #[error("Access token is missing the scope {0}")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    MissingScope(String),
+
+    #[error("The password has to be changed first")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    PasswordChangeRequired,
+
+    #[error("Missing or invalid CSRF token")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    InvalidCsrfToken,
+
+    #[error("Service accounts can't use this")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    ServiceAccountNotAllowed,
+
+    #[error("Only the default organization can manage this")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    DefaultOrganizationOnly,
+
+    #[error("Impersonation sessions can't use this")]
+    #[status_code(StatusCode::FORBIDDEN)]
+    ImpersonationNotAllowed,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_the_csrf_token_of_the_session() {
+        set_test_token_hash_key();
+        let session_token = generate_session_token();
+        let csrf = csrf_token(&session_token);
+        assert_eq!(csrf.len(), 64);
+        assert_eq!(csrf, csrf_token(&session_token));
+        assert!(verify_csrf_token(&session_token, &csrf));
+    }
+
+    #[test]
+    fn rejects_invalid_csrf_tokens() {
+        set_test_token_hash_key();
+        let session_token = generate_session_token();
+        let csrf = csrf_token(&session_token);
+        let mut tampered = csrf.clone().into_bytes();
+        tampered[10] = if tampered[10] == b'0' { b'1' } else { b'0' };
+        let tampered = String::from_utf8(tampered).unwrap();
+        assert!(!verify_csrf_token(&session_token, &tampered));
+        assert!(!verify_csrf_token(&session_token, &csrf[..63]));
+        assert!(!verify_csrf_token(&session_token, &format!("{csrf}0")));
+        assert!(!verify_csrf_token(&session_token, &csrf.to_uppercase()));
+        assert!(!verify_csrf_token(&session_token, ""));
+        assert!(!verify_csrf_token(&generate_session_token(), &csrf));
+    }
+
+    #[test]
+    fn decrypts_encrypted_secrets() {
+        set_test_token_hash_key();
+        let encrypted = encrypt_secret(b"JBSWY3DPEHPK3PXP");
+        assert_eq!(decrypt_secret(&encrypted).unwrap(), b"JBSWY3DPEHPK3PXP");
+        // The random nonce makes every ciphertext unique
+        assert_ne!(encrypted, encrypt_secret(b"JBSWY3DPEHPK3PXP"));
+        assert_eq!(decrypt_secret(&encrypt_secret(b"")).unwrap(), b"");
+    }
+
+    #[test]
+    fn rejects_tampered_secrets() {
+        set_test_token_hash_key();
+        let encrypted = encrypt_secret(b"JBSWY3DPEHPK3PXP");
+        for i in 0..encrypted.len() {
+            let mut tampered = encrypted.clone();
+            tampered[i] ^= 1;
+            assert!(decrypt_secret(&tampered).is_none(), "byte {i} was changed");
+        }
+        assert!(decrypt_secret(&encrypted[..encrypted.len() - 1]).is_none());
+        assert!(decrypt_secret(&encrypted[..aead::NONCE_LEN - 1]).is_none());
+        assert!(decrypt_secret(&[]).is_none());
+    }
+}
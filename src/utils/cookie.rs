@@ -0,0 +1,108 @@
+use std::env;
+
+use axum_extra::extract::{
+    cookie::{Cookie, SameSite},
+    CookieJar,
+};
+use time::Duration;
+
+use crate::{
+    config::{self, env_or, CSRF_COOKIE, OIDC_STATE_COOKIE, SESSION_COOKIE},
+    service::session::SessionConfig,
+    utils::auth::csrf_token,
+};
+
+/// Attributes of the cookies the api sets
+pub struct CookieConfig {
+    pub same_site: SameSite,
+    /// Only send the cookies over https
+    pub secure: bool,
+    /// Share the cookies with subdomains of this domain
+    pub domain: Option<String>,
+}
+
+impl CookieConfig {
+    pub fn from_env() -> Self {
+        let same_site = match env::var("COOKIE_SAME_SITE")
+            .unwrap_or_default()
+            .to_lowercase()
+            .as_str()
+        {
+            "strict" => SameSite::Strict,
+            "none" => SameSite::None,
+            _ => SameSite::Lax,
+        };
+        let secure = env_or("COOKIE_SECURE", config::base_url().starts_with("https://"));
+        Self {
+            same_site,
+            // Browsers drop cookies with `SameSite=None` that aren't secure
+            secure: secure || same_site == SameSite::None,
+            domain: env::var("COOKIE_DOMAIN").ok().filter(|d| !d.is_empty()),
+        }
+    }
+
+    /// A cookie with the configured attributes for the whole api
+    pub fn cookie(&self, name: &'static str, value: String, http_only: bool) -> Cookie<'static> {
+        let mut cookie = Cookie::build((name, value))
+            .path("/")
+            .http_only(http_only)
+            .same_site(self.same_site)
+            .secure(self.secure);
+        if let Some(domain) = &self.domain {
+            cookie = cookie.domain(domain.clone());
+        }
+        cookie.build()
+    }
+
+    /// A cookie that lasts as long as a session can
+    pub fn session_cookie(
+        &self,
+        name: &'static str,
+        value: String,
+        http_only: bool,
+    ) -> Cookie<'static> {
+        let mut cookie = self.cookie(name, value, http_only);
+        cookie.set_max_age(Duration::seconds(
+            SessionConfig::from_env().max_lifetime.num_seconds(),
+        ));
+        cookie
+    }
+
+    /// Removing a cookie only works with the path and domain it was set with
+    pub fn removal(&self, name: &'static str) -> Cookie<'static> {
+        self.cookie(name, String::new(), true)
+    }
+}
+
+/// Sets the session cookie and the CSRF cookie of the session. The CSRF cookie is readable by
+/// the frontend, which has to send it back in the `X-CSRF-Token` header.
+pub fn add_session_cookies(jar: CookieJar, session_token: String) -> CookieJar {
+    add_csrf_cookie(jar, &session_token).add(CookieConfig::from_env().session_cookie(
+        SESSION_COOKIE,
+        session_token,
+        true,
+    ))
+}
+
+pub fn add_csrf_cookie(jar: CookieJar, session_token: &str) -> CookieJar {
+    jar.add(CookieConfig::from_env().session_cookie(CSRF_COOKIE, csrf_token(session_token), false))
+}
+
+pub fn remove_session_cookies(jar: CookieJar) -> CookieJar {
+    let config = CookieConfig::from_env();
+    jar.remove(config.removal(SESSION_COOKIE))
+        .remove(config.removal(CSRF_COOKIE))
+}
+
+/// Holds the state of a started OpenID Connect login as long as the login is valid. The provider
+/// redirects back with a cross-site navigation, which `SameSite=Strict` would send it without.
+pub fn add_oidc_state_cookie(jar: CookieJar, state: String) -> CookieJar {
+    let mut cookie = CookieConfig::from_env().cookie(OIDC_STATE_COOKIE, state, true);
+    cookie.set_same_site(SameSite::Lax);
+    cookie.set_max_age(Duration::minutes(10));
+    jar.add(cookie)
+}
+
+pub fn remove_oidc_state_cookie(jar: CookieJar) -> CookieJar {
+    jar.remove(CookieConfig::from_env().removal(OIDC_STATE_COOKIE))
+}
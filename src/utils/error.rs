@@ -6,5 +6,18 @@ use super::response::Metadata;
 #[serde(rename_all = "camelCase")]
 pub struct ErrorResponse {
     pub error_message: String,
+    /// The invalid fields of the request body, if the error is about them
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub field_errors: Vec<FieldError>,
     pub _metadata: Metadata,
 }
+
+/// Why the value of a field was rejected. The code is meant for the frontend to pick a
+/// translation, the message is a fallback in english.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldError {
+    pub field: String,
+    pub code: &'static str,
+    pub message: String,
+}
@@ -8,7 +8,14 @@ use axum::{
 };
 use axum_extra::extract::CookieJar;
 
-use crate::{config::SESSION_COOKIE, model::user::User, repo::user::UserRepo, AppState};
+use crate::{
+    config::SESSION_COOKIE,
+    model::user::User,
+    repo::user::UserRepo,
+    service::auth::AuthService,
+    utils::auth::{client_ip, AuthContext, AuthCredential, AuthUser},
+    AppState,
+};
 
 pub struct Session<T>(pub Option<T>);
 
@@ -21,19 +28,45 @@ where
     type Rejection = Infallible;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        let state = AppState::from_ref(state);
-        let jar: CookieJar = match parts.extract().await {
-            Ok(jar) => jar,
-            Err(_) => CookieJar::new(),
+        let Session(auth) = Session::<AuthContext>::from_request_parts(parts, state).await?;
+        let user = match auth.map(|auth| auth.user) {
+            Some(AuthUser {
+                loaded: Some(user), ..
+            }) => Some(user),
+            // Requests with a JWT only carry its claims
+            Some(AuthUser { id, .. }) => {
+                let state = AppState::from_ref(state);
+                let conn = &mut state.db.acquire().await.unwrap();
+                UserRepo::get_by_id(id, conn).await.ok()
+            }
+            None => None,
         };
-        let session_cookie = jar.get(SESSION_COOKIE).map(|cookie| cookie.value());
-        let user = if let Some(session) = session_cookie {
-            let conn = &mut state.db.acquire().await.unwrap();
-            UserRepo::get_from_session_token(session, conn).await.ok()
+        Ok(Self(user))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Session<AuthContext>
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        // Already resolved by `auth_middleware`
+        if let Some(auth) = parts.extensions.get::<AuthContext>() {
+            return Ok(Self(Some(auth.clone())));
+        }
+        let state = AppState::from_ref(state);
+        let auth = if let Some(credential) = AuthCredential::from_headers(&parts.headers) {
+            AuthService::authenticate_request(credential, client_ip(&parts.extensions), &state.db)
+                .await
+                .ok()
         } else {
             None
         };
-        Ok(Self(user))
+        Ok(Self(auth))
     }
 }
 
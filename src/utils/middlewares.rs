@@ -1,15 +1,25 @@
-use crate::{config::SESSION_COOKIE, repo::user::UserRepo, service::setup::SetupService, AppState};
+use crate::{
+    config::CSRF_HEADER,
+    model::auth::{Permission, TokenType, UserKind},
+    repo::settings::SettingsRepo,
+    service::{auth::AuthService, setup::SetupService},
+    utils::auth::{
+        client_ip, verify_csrf_token, AuthContext, AuthCredential, PermissionError,
+        ACCESS_TOKEN_ID, IMPERSONATOR_ID, ORGANIZATION_ID,
+    },
+    AppState,
+};
 
 use axum::{
     extract::{Request, State},
     http::StatusCode,
     middleware::Next,
     response::{IntoResponse, Response},
-    Json, RequestExt,
+    Extension, Json,
 };
-use axum_extra::extract::CookieJar;
 use futures::future::BoxFuture;
 use serde_json::json;
+use sqlx::PgConnection;
 use tower::{Layer, Service};
 
 #[derive(Clone)]
@@ -105,15 +115,14 @@ pub async fn auth_middleware(
     mut req: Request,
     next: Next,
 ) -> Result<Response, Response> {
-    let conn = &mut state.db.acquire().await.unwrap();
-    let jar = req.extract_parts::<CookieJar>().await.unwrap();
-    let session_cookie = jar.get(SESSION_COOKIE).map(|cookie| cookie.value());
-    let user = if let Some(session) = session_cookie {
-        UserRepo::get_from_session_token(session, conn).await.ok()
+    let auth = if let Some(credential) = AuthCredential::from_headers(req.headers()) {
+        AuthService::authenticate_request(credential, client_ip(req.extensions()), &state.db)
+            .await
+            .ok()
     } else {
         None
     };
-    if user.is_none() {
+    let Some(auth) = auth else {
         return Err((
             StatusCode::UNAUTHORIZED,
             Json(json!({
@@ -121,6 +130,373 @@ pub async fn auth_middleware(
             })),
         )
             .into_response());
+    };
+    let impersonator_id = auth.impersonator_id;
+    let access_token_id =
+        matches!(auth.token.token_type, TokenType::StaticAccess).then_some(auth.token.id);
+    let organization_id = Some(auth.user.organization_id);
+    req.extensions_mut().insert(auth);
+    Ok(IMPERSONATOR_ID
+        .scope(
+            impersonator_id,
+            ACCESS_TOKEN_ID.scope(
+                access_token_id,
+                ORGANIZATION_ID.scope(organization_id, next.run(req)),
+            ),
+        )
+        .await)
+}
+
+/// Rejects requests from other organizations than the default one, for what all organizations
+/// share like roles and the auth policy. Has to be applied inside of `auth_middleware`.
+pub async fn default_organization_only(
+    State(state): State<AppState>,
+    Extension(auth): Extension<AuthContext>,
+    req: Request,
+    next: Next,
+) -> Result<Response, PermissionError> {
+    let conn = &mut state
+        .db
+        .acquire()
+        .await
+        .map_err(|_| PermissionError::Unauthorized)?;
+    require_default_organization(&auth, conn).await?;
+    Ok(next.run(req).await)
+}
+
+/// Like `default_organization_only`, for handlers that only need it for some requests
+pub async fn require_default_organization(
+    auth: &AuthContext,
+    db: &mut PgConnection,
+) -> Result<(), PermissionError> {
+    let default_organization_id = SettingsRepo::get(db)
+        .await
+        .ok()
+        .and_then(|settings| settings.default_organization_id);
+    if default_organization_id != Some(auth.user.organization_id) {
+        return Err(PermissionError::DefaultOrganizationOnly);
+    }
+    Ok(())
+}
+
+/// Rejects service accounts, e.g. for WebSockets that only make sense for humans. Has to be
+/// applied inside of `auth_middleware`.
+pub async fn human_users_only(
+    Extension(auth): Extension<AuthContext>,
+    req: Request,
+    next: Next,
+) -> Result<Response, PermissionError> {
+    if auth.user.kind == UserKind::Service {
+        return Err(PermissionError::ServiceAccountNotAllowed);
+    }
+    Ok(next.run(req).await)
+}
+
+/// Rejects impersonation sessions, for the credentials and identity of the impersonated user
+/// that an admin must not take over. Has to be applied inside of `auth_middleware`.
+pub async fn no_impersonation(
+    Extension(auth): Extension<AuthContext>,
+    req: Request,
+    next: Next,
+) -> Result<Response, PermissionError> {
+    if auth.impersonator_id.is_some() {
+        return Err(PermissionError::ImpersonationNotAllowed);
+    }
+    Ok(next.run(req).await)
+}
+
+/// Protects requests that are authenticated by the session cookie against cross-site request
+/// forgery. Unsafe methods have to repeat the CSRF token of the session in the `X-CSRF-Token`
+/// header. Browsers don't send bearer tokens on their own, so those requests are exempt.
+pub async fn csrf_middleware(req: Request, next: Next) -> Result<Response, PermissionError> {
+    if req.method().is_safe() {
+        return Ok(next.run(req).await);
+    }
+    if let Some(AuthCredential::SessionCookie(session_token)) =
+        AuthCredential::from_headers(req.headers())
+    {
+        let valid = req
+            .headers()
+            .get(CSRF_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|csrf| verify_csrf_token(&session_token, csrf));
+        if !valid {
+            return Err(PermissionError::InvalidCsrfToken);
+        }
     }
     Ok(next.run(req).await)
 }
+
+/// Only lets requests through whose user and token may use the given permission. Has to be
+/// applied inside of `auth_middleware`. Sessions with a pending password change are rejected,
+/// unless the route allows them. Every authenticated route declares its permission with this
+/// layer, or `PermissionLayer::authenticated` if it doesn't need one.
+#[derive(Clone)]
+pub struct PermissionLayer {
+    permission: Option<Permission>,
+    allow_password_change_required: bool,
+}
+
+impl PermissionLayer {
+    pub fn new(permission: Permission) -> Self {
+        Self {
+            permission: Some(permission),
+            allow_password_change_required: false,
+        }
+    }
+
+    /// For routes every authenticated user may use, e.g. to switch to another of their
+    /// organizations
+    pub fn authenticated() -> Self {
+        Self {
+            permission: None,
+            allow_password_change_required: false,
+        }
+    }
+
+    /// Lets sessions with a pending password change through, for the route that changes it
+    pub fn allow_password_change_required(mut self) -> Self {
+        self.allow_password_change_required = true;
+        self
+    }
+}
+
+impl<S> Layer<S> for PermissionLayer {
+    type Service = PermissionMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        PermissionMiddleware {
+            inner,
+            permission: self.permission,
+            allow_password_change_required: self.allow_password_change_required,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PermissionMiddleware<S> {
+    inner: S,
+    permission: Option<Permission>,
+    allow_password_change_required: bool,
+}
+
+impl<S> Service<Request> for PermissionMiddleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let result = match req.extensions().get::<AuthContext>() {
+            Some(auth) if auth.password_change_required && !self.allow_password_change_required => {
+                Err(PermissionError::PasswordChangeRequired)
+            }
+            Some(auth) => match self.permission {
+                Some(permission) => auth.require_permission(permission),
+                None => Ok(()),
+            },
+            None => Err(PermissionError::Unauthorized),
+        };
+        match result {
+            Ok(()) => Box::pin(self.inner.call(req)),
+            Err(error) => Box::pin(async move { Ok(error.into_response()) }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::auth::{Role, Token};
+    use crate::repo::organization::OrganizationRepo;
+    use crate::utils::{auth::AuthUser, testing};
+    use axum::{body::Body, middleware};
+    use chrono::Utc;
+    use sqlx::PgPool;
+    use tower::{service_fn, ServiceExt};
+    use uuid::Uuid;
+
+    fn auth(
+        token_type: TokenType,
+        permissions: Vec<Permission>,
+        scopes: &[Permission],
+    ) -> AuthContext {
+        let user_id = Uuid::new_v4();
+        AuthContext {
+            user: AuthUser {
+                id: user_id,
+                organization_id: Uuid::new_v4(),
+                role: Role::from(Role::AUTHOR),
+                kind: UserKind::Human,
+                permissions,
+                loaded: None,
+            },
+            token: Token {
+                id: 1,
+                name: None,
+                token_prefix: String::new(),
+                token_hash: vec![],
+                legacy_hash: false,
+                token_type,
+                expiration: None,
+                user_id,
+                session_id: None,
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+                scopes: scopes.iter().map(|p| String::from(*p)).collect(),
+                family_id: None,
+                used_at: None,
+                organization_id: None,
+            },
+            impersonator_id: None,
+            password_change_required: false,
+        }
+    }
+
+    async fn status(layer: PermissionLayer, auth: Option<AuthContext>) -> StatusCode {
+        let mut req = Request::new(Body::empty());
+        if let Some(auth) = auth {
+            req.extensions_mut().insert(auth);
+        }
+        let handler = service_fn(|_: Request| async {
+            Ok::<_, std::convert::Infallible>(StatusCode::OK.into_response())
+        });
+        layer.layer(handler).oneshot(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn requires_the_permission_of_the_route() {
+        let session = auth(TokenType::Session, vec![Permission::UsersRead], &[]);
+        let layer = || PermissionLayer::new(Permission::UsersRead);
+        assert_eq!(status(layer(), Some(session.clone())).await, StatusCode::OK);
+        let other = PermissionLayer::new(Permission::UsersWrite);
+        assert_eq!(status(other, Some(session)).await, StatusCode::FORBIDDEN);
+        assert_eq!(status(layer(), None).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn restricts_static_access_tokens_to_their_scopes() {
+        let permissions = vec![Permission::UsersRead, Permission::UsersWrite];
+        let layer = |permission| PermissionLayer::new(permission);
+        let token = auth(
+            TokenType::StaticAccess,
+            permissions.clone(),
+            &[Permission::UsersRead],
+        );
+        assert_eq!(
+            status(layer(Permission::UsersRead), Some(token.clone())).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(layer(Permission::UsersWrite), Some(token)).await,
+            StatusCode::FORBIDDEN
+        );
+        // A scope doesn't grant what the role of the owner doesn't
+        let token = auth(TokenType::StaticAccess, vec![], &[Permission::UsersRead]);
+        assert_eq!(
+            status(layer(Permission::UsersRead), Some(token)).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn blocks_sessions_with_a_pending_password_change() {
+        let mut session = auth(TokenType::Session, vec![Permission::ProfileWrite], &[]);
+        session.password_change_required = true;
+        let layer = PermissionLayer::new(Permission::ProfileWrite);
+        assert_eq!(
+            status(layer, Some(session.clone())).await,
+            StatusCode::FORBIDDEN
+        );
+        let layer = PermissionLayer::new(Permission::ProfileWrite).allow_password_change_required();
+        assert_eq!(status(layer, Some(session.clone())).await, StatusCode::OK);
+        assert_eq!(
+            status(PermissionLayer::authenticated(), Some(session)).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn lets_every_authenticated_user_through_routes_without_permission() {
+        let token = auth(TokenType::StaticAccess, vec![], &[]);
+        assert_eq!(
+            status(PermissionLayer::authenticated(), Some(token)).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(PermissionLayer::authenticated(), None).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    async fn middleware_status<F, Fut>(middleware: F, auth: AuthContext) -> StatusCode
+    where
+        F: FnMut(Extension<AuthContext>, Request, Next) -> Fut + Clone + Send + 'static,
+        Fut: std::future::Future<Output = Result<Response, PermissionError>> + Send + 'static,
+    {
+        let mut req = Request::new(Body::empty());
+        req.extensions_mut().insert(auth);
+        let handler = service_fn(|_: Request| async {
+            Ok::<_, std::convert::Infallible>(StatusCode::OK.into_response())
+        });
+        middleware::from_fn(middleware)
+            .layer(handler)
+            .oneshot(req)
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn blocks_service_accounts() {
+        let mut token = auth(TokenType::StaticAccess, Permission::ALL.to_vec(), &[]);
+        assert_eq!(
+            middleware_status(human_users_only, token.clone()).await,
+            StatusCode::OK
+        );
+        token.user.kind = UserKind::Service;
+        assert_eq!(
+            middleware_status(human_users_only, token).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn blocks_impersonation_sessions() {
+        let mut session = auth(TokenType::Session, Permission::ALL.to_vec(), &[]);
+        assert_eq!(
+            middleware_status(no_impersonation, session.clone()).await,
+            StatusCode::OK
+        );
+        session.impersonator_id = Some(Uuid::new_v4());
+        assert_eq!(
+            middleware_status(no_impersonation, session).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[sqlx::test]
+    async fn lets_only_the_default_organization_through(pool: PgPool) {
+        let conn = &mut pool.acquire().await.unwrap();
+        let organization = testing::setup(conn).await;
+        let other = OrganizationRepo::create_one("Other", conn).await.unwrap();
+        let mut admin = auth(TokenType::Session, Permission::ALL.to_vec(), &[]);
+        admin.user.organization_id = organization.id;
+        assert!(require_default_organization(&admin, conn).await.is_ok());
+        admin.user.organization_id = other.id;
+        assert!(matches!(
+            require_default_organization(&admin, conn).await,
+            Err(PermissionError::DefaultOrganizationOnly)
+        ));
+    }
+}
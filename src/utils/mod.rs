@@ -1,5 +1,9 @@
 pub mod auth;
+pub mod cookie;
 pub mod error;
 pub mod extractors;
 pub mod middlewares;
+pub mod password;
 pub mod response;
+#[cfg(test)]
+pub mod testing;
@@ -0,0 +1,140 @@
+use std::{num::NonZeroU32, sync::LazyLock};
+
+use argon2::{
+    password_hash::{phc::PasswordHash, PasswordHasher, PasswordVerifier},
+    Algorithm, Argon2, Params, Version,
+};
+use ring::pbkdf2;
+
+use crate::config::env_or;
+
+/// The algorithm of new hashes, every other algorithm is replaced on the next login
+const DEFAULT_ALGORITHM: &str = "argon2id";
+/// Hashes of the former `salt` and `hash` columns were migrated to this algorithm
+const PBKDF2_SHA512: &str = "pbkdf2-sha512";
+
+/// Argon2id with the cost from `ARGON2_MEMORY_KIB`, `ARGON2_ITERATIONS` and
+/// `ARGON2_PARALLELISM`, which default to the OWASP recommendation
+fn argon2() -> Argon2<'static> {
+    let params = Params::new(
+        env_or("ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST),
+        env_or("ARGON2_ITERATIONS", Params::DEFAULT_T_COST),
+        env_or("ARGON2_PARALLELISM", Params::DEFAULT_P_COST),
+        None,
+    )
+    .expect("Invalid Argon2 parameters");
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+}
+
+/// Hashes the password with a random salt and returns it as PHC string
+pub fn hash_password(password: &str) -> String {
+    argon2()
+        .hash_password(password.as_bytes())
+        .expect("Hashing the password failed")
+        .to_string()
+}
+
+/// Hashed once with the current parameters, so verifying against it takes as long as verifying
+/// against the hash of a user
+static DUMMY_HASH: LazyLock<String> = LazyLock::new(|| hash_password("dummy password"));
+
+/// Verifies the password against a dummy hash, so a login for an unknown email takes as long as
+/// one with a wrong password and doesn't tell whether the email exists
+pub fn verify_dummy_password(password: &str) {
+    let _ = verify_password(&DUMMY_HASH, password);
+}
+
+pub fn verify_password(password_hash: &str, password: &str) -> bool {
+    let Ok(parsed) = PasswordHash::new(password_hash) else {
+        return false;
+    };
+    match parsed.algorithm.as_str() {
+        "argon2id" | "argon2i" | "argon2d" => argon2()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        PBKDF2_SHA512 => {
+            let iterations = parsed.params.get_decimal("i").and_then(NonZeroU32::new);
+            match (iterations, parsed.salt, parsed.hash) {
+                (Some(iterations), Some(salt), Some(hash)) => pbkdf2::verify(
+                    pbkdf2::PBKDF2_HMAC_SHA512,
+                    iterations,
+                    &salt,
+                    password.as_bytes(),
+                    hash.as_bytes(),
+                )
+                .is_ok(),
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Whether the hash uses another algorithm or other parameters than new hashes
+pub fn needs_rehash(password_hash: &str) -> bool {
+    let Ok(parsed) = PasswordHash::new(password_hash) else {
+        return true;
+    };
+    if parsed.algorithm.as_str() != DEFAULT_ALGORITHM {
+        return true;
+    }
+    let current = argon2();
+    let current = current.params();
+    match Params::try_from(&parsed) {
+        Ok(params) => {
+            params.m_cost() != current.m_cost()
+                || params.t_cost() != current.t_cost()
+                || params.p_cost() != current.p_cost()
+        }
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// "long horse 9 battery" as the migration converted the `salt` and `hash` columns, with the
+    /// 32 byte salt and the iterations of the former hashing
+    const MIGRATED_HASH: &str = "$pbkdf2-sha512$i=600000,l=64$bGVnYWN5LXNhbHQtb2YtMzItYnl0ZXMtbGVuZ3RoISE$Tv3Fp72U+lZSpwT+iJF+Xp14HCKpD//NUW0HhhCEiIDwfmz5Y28wPnOtN15WCvGbsoOlFLO2m36cQx9LQXvAMg";
+
+    #[test]
+    fn verifies_new_hashes() {
+        let hash = hash_password("long horse 9 battery");
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password(&hash, "long horse 9 battery"));
+        assert!(!verify_password(&hash, "long horse 9 batter"));
+        assert!(!needs_rehash(&hash));
+    }
+
+    #[test]
+    fn verifies_migrated_pbkdf2_hashes() {
+        assert!(verify_password(MIGRATED_HASH, "long horse 9 battery"));
+        assert!(!verify_password(MIGRATED_HASH, "long horse 9 batter"));
+        assert!(needs_rehash(MIGRATED_HASH));
+    }
+
+    #[test]
+    fn rejects_pbkdf2_hashes_without_iterations() {
+        let hash = MIGRATED_HASH.replace("i=600000,", "");
+        assert!(!verify_password(&hash, "long horse 9 battery"));
+    }
+
+    #[test]
+    fn rehashes_argon2_hashes_with_other_parameters() {
+        let params = Params::new(1024, 1, 1, None).unwrap();
+        let hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password(b"long horse 9 battery")
+            .unwrap()
+            .to_string();
+        assert!(verify_password(&hash, "long horse 9 battery"));
+        assert!(needs_rehash(&hash));
+    }
+
+    #[test]
+    fn rejects_invalid_hashes() {
+        assert!(!verify_password("", "long horse 9 battery"));
+        assert!(!verify_password("$md5$abc$def", "long horse 9 battery"));
+        assert!(needs_rehash("not a hash"));
+    }
+}
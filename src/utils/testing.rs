@@ -0,0 +1,73 @@
+//! Fixtures for the tests that need a database. `#[sqlx::test]` runs every test in a new database
+//! with the migrations applied.
+
+use chrono::{Duration, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::{
+    config::system_user_uuid,
+    model::{
+        auth::{Role, SessionWithToken},
+        user::{User, UserCreateInput},
+        Organization,
+    },
+    repo::{organization::OrganizationRepo, session::SessionRepo, user::UserRepo},
+    service::setup::SetupService,
+    utils::{auth::set_test_token_hash_key, password::hash_password},
+};
+
+pub const PASSWORD: &str = "long horse 9 battery";
+
+/// Finishes the setup with a new default organization
+pub async fn setup(db: &mut PgConnection) -> Organization {
+    set_test_token_hash_key();
+    let organization = OrganizationRepo::create_one("Default", db).await.unwrap();
+    SetupService::finish_setup(organization.id, db)
+        .await
+        .unwrap();
+    organization
+}
+
+/// A user with a verified email and the password `PASSWORD`
+pub async fn create_user(
+    email: &str,
+    role: &str,
+    organization_id: Uuid,
+    db: &mut PgConnection,
+) -> User {
+    let user = UserRepo::create_one(
+        UserCreateInput {
+            email: email.to_string(),
+            password_hash: &hash_password(PASSWORD),
+            role: Some(Role::from(role)),
+            ..Default::default()
+        },
+        organization_id,
+        system_user_uuid(),
+        db,
+    )
+    .await
+    .unwrap();
+    UserRepo::mark_email_verified(user.id, db).await.unwrap()
+}
+
+/// A session of the user in the organization that lasts an hour
+pub async fn create_session(
+    user: &User,
+    organization_id: Uuid,
+    db: &mut PgConnection,
+) -> SessionWithToken {
+    SessionRepo::create_one_with_token(
+        user,
+        organization_id,
+        Some("127.0.0.1".parse().unwrap()),
+        "test".to_string(),
+        Utc::now() + Duration::hours(1),
+        None,
+        false,
+        db,
+    )
+    .await
+    .unwrap()
+}